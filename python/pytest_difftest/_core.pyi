@@ -1,5 +1,10 @@
 from __future__ import annotations
 
+from collections.abc import Callable
+from typing import Any, Literal
+
+SCHEMA_VERSION: int
+
 class Block:
     @property
     def start_line(self) -> int: ...
@@ -12,7 +17,21 @@ class Block:
     @property
     def block_type(self) -> str: ...
     @property
+    def qualified_name(self) -> str: ...
+    @property
     def body_start_line(self) -> int: ...
+    def contains_line(self, line: int) -> bool: ...
+    def as_dict(self) -> dict[str, Any]: ...
+    @classmethod
+    def from_dict(cls, data: dict[str, Any]) -> Block: ...
+    def __eq__(self, other: object) -> bool: ...
+    def __hash__(self) -> int: ...
+
+class BlockNode:
+    @property
+    def block(self) -> Block: ...
+    @property
+    def children(self) -> list[BlockNode]: ...
 
 class Fingerprint:
     @property
@@ -24,16 +43,108 @@ class Fingerprint:
     @property
     def mtime(self) -> float: ...
     @property
+    def mtime_exact(self) -> tuple[int, int] | None: ...
+    @property
     def blocks(self) -> list[Block] | None: ...
+    def as_dict(self) -> dict[str, Any]: ...
+    @classmethod
+    def from_dict(cls, data: dict[str, Any]) -> Fingerprint: ...
+    def to_json(self) -> str: ...
+    @staticmethod
+    def from_json(json: str) -> Fingerprint: ...
+
+class BlockChange:
+    @property
+    def kind(self) -> str: ...
+    @property
+    def name(self) -> str: ...
+    @property
+    def block_type(self) -> str: ...
+    @property
+    def start_line(self) -> int: ...
+    @property
+    def old_start_line(self) -> int | None: ...
+    @property
+    def old_checksum(self) -> int | None: ...
+    @property
+    def new_checksum(self) -> int | None: ...
 
 class ChangedFiles:
     @property
     def modified(self) -> list[str]: ...
     @property
     def changed_blocks(self) -> dict[str, list[int]]: ...
+    @property
+    def deleted(self) -> list[str]: ...
+    @property
+    def added(self) -> list[str]: ...
+    @property
+    def renamed(self) -> list[tuple[str, str]]: ...
+    @property
+    def conftest_affected(self) -> list[str]: ...
+    @property
+    def errored(self) -> list[tuple[str, str]]: ...
+    @property
+    def baseline_mismatch(self) -> bool: ...
+    @property
+    def warnings(self) -> list[str]: ...
+    @property
+    def unreferenced_changes(self) -> list[str]: ...
+    @property
+    def run_all(self) -> bool: ...
     def has_changes(self) -> bool: ...
     def total_changed_blocks(self) -> int: ...
 
+class ChangeSummary:
+    @property
+    def files_scanned(self) -> int: ...
+    @property
+    def files_modified(self) -> int: ...
+    @property
+    def files_added(self) -> int: ...
+    @property
+    def files_deleted(self) -> int: ...
+    @property
+    def files_renamed(self) -> int: ...
+    @property
+    def files_errored(self) -> int: ...
+    @property
+    def blocks_added(self) -> int: ...
+    @property
+    def blocks_removed(self) -> int: ...
+    @property
+    def blocks_modified(self) -> int: ...
+    @property
+    def warnings(self) -> list[str]: ...
+
+class BaselineStats:
+    @property
+    def count(self) -> int: ...
+    @property
+    def timings(self) -> list[tuple[str, float]] | None: ...
+
+class BaselineVerification:
+    @property
+    def matching(self) -> list[str]: ...
+    @property
+    def mismatched(self) -> list[str]: ...
+    @property
+    def missing(self) -> list[str]: ...
+    def is_consistent(self) -> bool: ...
+
+class FileInspection:
+    @property
+    def filename(self) -> str: ...
+    @property
+    def baseline_checksums(self) -> list[int] | None: ...
+    @property
+    def current_checksums(self) -> list[int]: ...
+    @property
+    def changed_checksums(self) -> list[int]: ...
+    @property
+    def blocks(self) -> list[Block]: ...
+    def has_changes(self) -> bool: ...
+
 class TestExecution:
     @property
     def test_name(self) -> str: ...
@@ -54,6 +165,10 @@ class ImportResult:
 
 class PytestDiffDatabase:
     def __init__(self, db_path: str) -> None: ...
+    @staticmethod
+    def in_memory() -> PytestDiffDatabase: ...
+    def is_in_memory(self) -> bool: ...
+    def dump_to(self, path: str) -> None: ...
     def save_test_execution(
         self,
         test_name: str,
@@ -61,34 +176,176 @@ class PytestDiffDatabase:
         duration: float,
         failed: bool,
         python_version: str = "3.12",
+        merge: bool = False,
     ) -> None: ...
+    def save_test_executions_batch(
+        self, executions: list[TestExecution], python_version: str = "3.12"
+    ) -> int: ...
     def get_affected_tests(self, changed_blocks: dict[str, list[int]]) -> list[str]: ...
+    def get_affected_tests_ranked(
+        self, changed_blocks: dict[str, list[int]]
+    ) -> list[tuple[str, int]]: ...
+    def tests_for_file(self, filename: str) -> list[str]: ...
+    def tests_for_block(self, filename: str, qualified_name: str) -> list[str]: ...
     def get_recorded_tests(self) -> list[str]: ...
+    def list_tests(self, python_version: str | None = None) -> list[str]: ...
+    def record_outcome(self, test_name: str, outcome: str) -> None: ...
+    def outcome_history(self, test_name: str) -> list[str]: ...
+    def flakiness_score(self, test_name: str) -> float: ...
+    def delete_test(self, test_name: str) -> int: ...
+    def prune_missing_tests(self, known: list[str]) -> int: ...
+    def get_test_stats(self, test_name: str) -> tuple[float, bool] | None: ...
+    def test_count(self) -> int: ...
     def get_fingerprint(self, filename: str) -> Fingerprint | None: ...
     def clear_cache(self) -> None: ...
     def get_stats(self) -> dict[str, int]: ...
+    def cache_stats(self) -> CacheStats: ...
     def save_baseline_fingerprint(self, fingerprint: Fingerprint) -> None: ...
     def get_baseline_fingerprint(self, filename: str) -> Fingerprint | None: ...
-    def clear_baseline(self) -> None: ...
+    def get_baseline_fingerprints_batch(
+        self, filenames: list[str]
+    ) -> dict[str, Fingerprint]: ...
+    def clear_baseline(self, scope_paths: list[str] | None = None) -> int: ...
     def import_baseline_from(self, source_db_path: str) -> ImportResult: ...
     def merge_baseline_from(self, source_db_path: str) -> ImportResult: ...
+    def export_json(self, path: str) -> None: ...
+    def import_json(self, path: str) -> ImportResult: ...
     def get_external_metadata(self, source_db_path: str, key: str) -> str | None: ...
     def set_metadata(self, key: str, value: str) -> None: ...
     def get_metadata(self, key: str) -> str | None: ...
+    def schema_version(self) -> int: ...
+    def vacuum(self) -> int: ...
+    def integrity_check(self) -> bool: ...
+    def last_integrity_error(self) -> str | None: ...
+    def reset(self) -> None: ...
+    def list_baseline_files(self) -> list[str]: ...
+    def diff_baselines(self, other: PytestDiffDatabase) -> ChangedFiles: ...
     def get_test_dependencies(self, test_name: str) -> list[str]: ...
     def get_file_dependents(self, filename: str) -> list[str]: ...
+    def checkpoint(self) -> None: ...
     def close(self) -> None: ...
 
+class CacheStats:
+    @property
+    def hits(self) -> int: ...
+    @property
+    def misses(self) -> int: ...
+    @property
+    def insertions(self) -> int: ...
+    @property
+    def evictions(self) -> int: ...
+    @property
+    def hit_rate(self) -> float: ...
+
+class CancelToken:
+    def __init__(self) -> None: ...
+    def cancel(self) -> None: ...
+    def is_cancelled(self) -> bool: ...
+
+class FingerprintOptions:
+    def __init__(
+        self,
+        normalize_line_endings: bool = True,
+        ignore_comments: bool = False,
+        ast_checksum: bool = False,
+        ignore_block_types: list[str] | None = None,
+        granularity: Literal["file", "block"] = "block",
+        refresh_mtimes: bool = False,
+        seed: int = 0,
+        checksum_algorithm: Literal["crc32", "xxhash64"] = "crc32",
+    ) -> None: ...
+    @property
+    def normalize_line_endings(self) -> bool: ...
+    @property
+    def ignore_comments(self) -> bool: ...
+    @property
+    def ast_checksum(self) -> bool: ...
+    @property
+    def ignore_block_types(self) -> list[str]: ...
+    @property
+    def granularity(self) -> Literal["file", "block"]: ...
+    @property
+    def refresh_mtimes(self) -> bool: ...
+    @property
+    def seed(self) -> int: ...
+    @property
+    def checksum_algorithm(self) -> Literal["crc32", "xxhash64"]: ...
+    def checksum_scheme(self) -> str: ...
+
+class ScanOptions:
+    def __init__(
+        self,
+        respect_gitignore: bool = False,
+        exclude_globs: list[str] | None = None,
+        max_file_bytes: int | None = None,
+        ignore_dirs: list[str] | None = None,
+        follow_symlinks: bool = False,
+        include_notebooks: bool = False,
+    ) -> None: ...
+    @property
+    def respect_gitignore(self) -> bool: ...
+    @property
+    def exclude_globs(self) -> list[str]: ...
+    @property
+    def max_file_bytes(self) -> int | None: ...
+    @property
+    def ignore_dirs(self) -> list[str]: ...
+    @property
+    def follow_symlinks(self) -> bool: ...
+    @property
+    def include_notebooks(self) -> bool: ...
+
 class FingerprintCache:
-    def __init__(self, max_size: int | None = None) -> None: ...
+    def __init__(
+        self,
+        max_size: int | None = None,
+        options: FingerprintOptions | None = None,
+        max_bytes: int | None = None,
+    ) -> None: ...
     def get_or_calculate(self, path: str) -> Fingerprint: ...
     def clear(self) -> None: ...
     def stats(self) -> tuple[int, int, float]: ...
+    def cache_stats(self) -> CacheStats: ...
     def size(self) -> int: ...
     def max_size(self) -> int: ...
+    def options(self) -> FingerprintOptions: ...
+    def max_bytes(self) -> int | None: ...
+    def size_bytes(self) -> int: ...
+    def save_to_disk(self, path: str) -> None: ...
+    def load_from_disk(self, path: str) -> int: ...
 
-def calculate_fingerprint(path: str, project_root: str | None = None) -> Fingerprint: ...
-def detect_changes(db_path: str, project_root: str, scope_paths: list[str]) -> ChangedFiles: ...
+def calculate_fingerprint(
+    path: str,
+    project_root: str | None = None,
+    options: FingerprintOptions | None = None,
+) -> Fingerprint: ...
+def diff_files(old_source: str, new_source: str) -> list[BlockChange]: ...
+def detect_changes(
+    db_path: str,
+    project_root: str,
+    scope_paths: list[str],
+    options: FingerprintOptions | None = None,
+    scan: ScanOptions | None = None,
+    project_roots: list[str] | None = None,
+    python_version: str | None = None,
+    report_unreferenced: bool = False,
+    cancel_token: CancelToken | None = None,
+    safe_mode: bool = False,
+) -> ChangedFiles: ...
+def summarize_changes(
+    db_path: str,
+    project_root: str,
+    scope_paths: list[str],
+    options: FingerprintOptions | None = None,
+    scan: ScanOptions | None = None,
+) -> ChangeSummary: ...
+def select_tests(
+    db_path: str,
+    project_root: str,
+    scope_paths: list[str],
+    options: FingerprintOptions | None = None,
+    scan: ScanOptions | None = None,
+) -> list[str]: ...
 def process_coverage_data(
     coverage_data: dict[str, list[int]],
     project_root: str,
@@ -96,12 +353,52 @@ def process_coverage_data(
     verbose: bool,
     scope_paths: list[str],
     cache: FingerprintCache | None = None,
+    exclude_globs: list[str] | None = None,
+    test_patterns: list[str] | None = None,
+    options: FingerprintOptions | None = None,
 ) -> list[Fingerprint]: ...
+def blocks_for_line(blocks: list[Block], line: int) -> Block | None: ...
+def inspect_file(
+    db_path: str,
+    path: str,
+    project_root: str | None = None,
+    options: FingerprintOptions | None = None,
+) -> FileInspection: ...
 def save_baseline(
     db_path: str,
     project_root: str,
     verbose: bool,
     scope_paths: list[str],
     force: bool = False,
-) -> int: ...
+    options: FingerprintOptions | None = None,
+    scan: ScanOptions | None = None,
+    project_roots: list[str] | None = None,
+    dry_run: bool = False,
+    collect_timings: bool = False,
+    python_version: str | None = None,
+    progress_callback: Callable[[int, int], object] | None = None,
+    cancel_token: CancelToken | None = None,
+) -> BaselineStats: ...
+def baseline_age(db_path: str) -> tuple[float | None, int]: ...
+def verify_baseline(
+    db_path: str, project_root: str, options: FingerprintOptions | None = None
+) -> BaselineVerification: ...
+def block_counts(
+    project_root: str,
+    scope_paths: list[str],
+    sort_by_count: bool = False,
+    options: FingerprintOptions | None = None,
+    scan: ScanOptions | None = None,
+) -> list[tuple[str, int]]: ...
+def update_baseline_file(
+    db_path: str,
+    path: str,
+    project_root: str | None = None,
+    options: FingerprintOptions | None = None,
+) -> Fingerprint: ...
 def parse_module(source: str) -> list[Block]: ...
+def parse_module_normalized(source: str, strip_comments: bool) -> list[Block]: ...
+def parse_module_tree(source: str) -> list[BlockNode]: ...
+def parse_module_lines(source: str) -> list[Block]: ...
+def compute_checksum(source: str, algorithm: Literal["crc32", "xxhash64"] = "crc32") -> int: ...
+def parse_module_lenient(source: str) -> list[Block]: ...
@@ -44,6 +44,12 @@ except ImportError:
     _core = None  # type: ignore[assignment]  # Allow import before building
 
 
+def log_change_warnings(changed: Any) -> None:
+    """Log the warnings change detection collected (skipped or unreadable files, etc.)"""
+    for warning in changed.warnings:
+        logger.warning("⚠ pytest-difftest: %s", warning)
+
+
 class PytestDiffPlugin:
     """Main plugin class for pytest-difftest"""
 
@@ -166,7 +172,10 @@ class PytestDiffPlugin:
         try:
             start = time.time()
             changed = _core.detect_changes(
-                str(self.db_path), str(get_rootdir(config)), self.scope_paths
+                str(self.db_path),
+                str(get_rootdir(config)),
+                self.scope_paths,
+                python_version=self.python_version,
             )
             recorded_tests = set(self.db.get_recorded_tests())
             known_test_files: set[str] = {nid.split("::")[0] for nid in recorded_tests}
@@ -187,8 +196,18 @@ class PytestDiffPlugin:
             if changed.has_changes():
                 affected_tests = set(self.db.get_affected_tests(changed.changed_blocks))
                 affected_test_files = {nid.split("::")[0] for nid in affected_tests}
-                # Include modified test files themselves (may contain new tests)
-                affected_test_files |= {f for f in changed.modified if self._is_test_file(f)}
+                # Include modified and new test files themselves (may contain new tests),
+                # test files under a changed conftest.py and test files that failed to parse
+                affected_test_files |= {
+                    f
+                    for f in [
+                        *changed.modified,
+                        *changed.added,
+                        *changed.conftest_affected,
+                        *(path for path, _ in changed.errored),
+                    ]
+                    if self._is_test_file(f)
+                }
 
             self._early_diff_data = {
                 "changed": changed,
@@ -453,8 +472,12 @@ class PytestDiffPlugin:
             if stats.get("test_count", 0) > 0:
                 try:
                     changed = _core.detect_changes(
-                        str(self.db_path), str(get_rootdir(config)), self.scope_paths
+                        str(self.db_path),
+                        str(get_rootdir(config)),
+                        self.scope_paths,
+                        python_version=self.python_version,
                     )
+                    log_change_warnings(changed)
 
                     # Find unrecorded tests (e.g. previously failed)
                     recorded_tests = set(self.db.get_recorded_tests())
@@ -464,8 +487,9 @@ class PytestDiffPlugin:
 
                     if changed.has_changes():
                         logger.info(
-                            "\n✓ pytest-difftest: Incremental baseline — %s modified files",
+                            "\n✓ pytest-difftest: Incremental baseline — %s modified, %s new files",
                             len(changed.modified),
+                            len(changed.added),
                         )
                         affected_tests = set(self.db.get_affected_tests(changed.changed_blocks))
                         affected_tests |= unrecorded_tests
@@ -524,10 +548,14 @@ class PytestDiffPlugin:
                 recorded_tests = self._early_diff_data["recorded_tests"]
             else:
                 changed = _core.detect_changes(
-                    str(self.db_path), str(get_rootdir(config)), self.scope_paths
+                    str(self.db_path),
+                    str(get_rootdir(config)),
+                    self.scope_paths,
+                    python_version=self.python_version,
                 )
                 assert self.db is not None
                 recorded_tests = set(self.db.get_recorded_tests())
+            log_change_warnings(changed)
 
             assert self.db is not None
 
@@ -538,17 +566,28 @@ class PytestDiffPlugin:
 
             if changed.has_changes():
                 logger.info(
-                    "\n✓ pytest-difftest: Detected %s modified files", len(changed.modified)
+                    "\n✓ pytest-difftest: Detected %s modified, %s new files",
+                    len(changed.modified),
+                    len(changed.added),
                 )
                 logger.info("  Changed blocks in %s files", len(changed.changed_blocks))
 
                 # Get affected tests from database
                 affected_tests = set(self.db.get_affected_tests(changed.changed_blocks))
 
-                # Also select tests living in modified files (new test files)
-                # changed.modified contains relative paths; resolve them against rootdir
+                # Also select tests living in modified, new or unparsable test files, or
+                # under a changed conftest.py. These are relative paths; resolve them
+                # against rootdir
                 rootdir = get_rootdir(config)
-                modified_abs = {str((rootdir / f).resolve()) for f in changed.modified}
+                modified_abs = {
+                    str((rootdir / f).resolve())
+                    for f in [
+                        *changed.modified,
+                        *changed.added,
+                        *changed.conftest_affected,
+                        *(path for path, _ in changed.errored),
+                    ]
+                }
                 for item in items:
                     if str(Path(item.fspath).resolve()) in modified_abs:
                         affected_tests.add(item.nodeid)
@@ -780,13 +819,15 @@ class PytestDiffPlugin:
 
         # Show cache statistics
         if self.fp_cache and self.verbose:
-            hits, misses, hit_rate = self.fp_cache.stats()
+            cache_stats = self.fp_cache.cache_stats()
             cache_size = self.fp_cache.size()
             logger.debug(
-                "Fingerprint cache stats: %s hits, %s misses, %.1f%% hit rate, %s cached files",
-                hits,
-                misses,
-                hit_rate * 100,
+                "Fingerprint cache stats: %s hits, %s misses, %s evictions, "
+                "cache hit rate: %.1f%%, %s cached files",
+                cache_stats.hits,
+                cache_stats.misses,
+                cache_stats.evictions,
+                cache_stats.hit_rate * 100,
                 cache_size,
             )
 
@@ -807,7 +848,8 @@ class PytestDiffPlugin:
                     self.verbose,
                     self.scope_paths,
                     self.force,
-                )
+                    python_version=self.python_version,
+                ).count
                 elapsed = time.time() - start
                 logger.debug("Baseline save completed in %.3fs", elapsed)
                 db_size = self._format_size(self.db_path.stat().st_size)
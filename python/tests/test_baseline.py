@@ -24,7 +24,7 @@ def test_baseline_revert_scenario():
         module_file.write_text(original_code)
         db_path = tmpdir / "pytest_difftest.db"
         scope_paths = [str(tmpdir)]
-        count = _core.save_baseline(str(db_path), str(tmpdir), False, scope_paths)
+        count = _core.save_baseline(str(db_path), str(tmpdir), False, scope_paths).count
         assert count == 1, "Should save baseline for 1 file"
 
         # Step 2: Verify no changes initially
@@ -71,7 +71,7 @@ def test_save_baseline_function():
         scope_paths = [str(tmpdir)]
 
         # Save baseline
-        count = _core.save_baseline(str(db_path), str(tmpdir), False, scope_paths)
+        count = _core.save_baseline(str(db_path), str(tmpdir), False, scope_paths).count
         assert count == 3, f"Should save baseline for 3 files, got {count}"
 
         # Open database and verify baselines were saved
@@ -4,6 +4,8 @@ Direct tests for the _core Rust module (fingerprint, cache, database).
 These tests use tmp_path (standard pytest) instead of pytester.
 """
 
+import pytest
+
 from pytest_difftest import _core
 
 
@@ -65,6 +67,21 @@ def test_fingerprint_cache_eviction(tmp_path):
     assert cache.max_size() == 2
 
 
+def test_fingerprint_cache_max_bytes(tmp_path):
+    """Cache with max_bytes keeps its approximate footprint within the budget."""
+    cache = _core.FingerprintCache(max_bytes=4096)
+    assert cache.max_bytes() == 4096
+
+    for i in range(50):
+        f = tmp_path / f"mod{i}.py"
+        f.write_text(f"def f{i}():\n    return {i}\n")
+        cache.get_or_calculate(str(f))
+
+    assert cache.size() < 50
+    assert cache.size_bytes() <= 4096
+    assert cache.cache_stats().evictions > 0
+
+
 def test_database_stats_empty(tmp_path):
     """New DB has test_count=0, file_count=0, baseline_count=0."""
     db_path = tmp_path / "test.db"
@@ -75,6 +92,40 @@ def test_database_stats_empty(tmp_path):
     assert stats["baseline_count"] == 0
 
 
+def test_in_memory_database_dump_to(tmp_path):
+    """An in-memory database works like a file one and persists only via dump_to."""
+    f = tmp_path / "module.py"
+    f.write_text("def foo(): pass\n")
+    fp = _core.calculate_fingerprint(str(f), str(tmp_path))
+
+    db = _core.PytestDiffDatabase.in_memory()
+    assert db.is_in_memory()
+    db.save_baseline_fingerprint(fp)
+    assert db.get_baseline_fingerprint("module.py").checksums == fp.checksums
+
+    dump_path = tmp_path / "dump.db"
+    db.dump_to(str(dump_path))
+    with pytest.raises(OSError):
+        db.dump_to(str(dump_path))
+
+    reopened = _core.PytestDiffDatabase(str(dump_path))
+    assert not reopened.is_in_memory()
+    assert reopened.list_baseline_files() == ["module.py"]
+
+
+def test_record_outcome_flakiness_score():
+    """Alternating pass/fail outcomes give the maximum flakiness score."""
+    db = _core.PytestDiffDatabase.in_memory()
+    for outcome in ["passed", "failed", "passed", "failed"]:
+        db.record_outcome("test_flaky", outcome)
+
+    assert db.outcome_history("test_flaky") == ["passed", "failed", "passed", "failed"]
+    assert db.flakiness_score("test_flaky") == 1.0
+    assert db.flakiness_score("test_never_run") == 0.0
+    with pytest.raises(ValueError):
+        db.record_outcome("test_flaky", "broken")
+
+
 def test_detect_changes_no_baseline(tmp_path):
     """Files with no baseline are detected as new/changed."""
     db_path = tmp_path / "test.db"
@@ -85,9 +136,10 @@ def test_detect_changes_no_baseline(tmp_path):
     f.write_text("def foo(): pass\n")
 
     changes = _core.detect_changes(str(db_path), str(tmp_path), [str(tmp_path)])
-    # New files (no baseline) should be detected as changed
+    # New files (no baseline) should be detected as added, not modified
     assert changes.has_changes()
-    assert len(changes.modified) == 1
+    assert len(changes.added) == 1
+    assert len(changes.modified) == 0
 
 
 def test_import_baseline_returns_import_result(tmp_path):
@@ -149,10 +201,10 @@ def test_detect_changes_returns_relative_paths(tmp_path):
     changes = _core.detect_changes(str(db_path), str(tmp_path), [str(tmp_path)])
     assert changes.has_changes()
     # All paths should be relative (not starting with /)
-    for path in changes.modified:
+    for path in changes.added:
         assert not path.startswith("/"), f"Expected relative path, got: {path}"
     # Should contain the relative path
-    assert "src/module.py" in changes.modified
+    assert "src/module.py" in changes.added
 
 
 def test_save_baseline_stores_relative_paths(tmp_path):
@@ -174,6 +226,166 @@ def test_save_baseline_stores_relative_paths(tmp_path):
     assert fp.filename == "src/module.py"
 
 
+def test_options_objects_apply_to_save_and_detect(tmp_path):
+    """FingerprintOptions and ScanOptions passed to save_baseline and detect_changes."""
+    db_path = tmp_path / "test.db"
+    (tmp_path / "module.py").write_text("def foo():\n    return 1\n")
+    (tmp_path / "build").mkdir()
+    (tmp_path / "build" / "generated.py").write_text("X = 1\n")
+
+    options = _core.FingerprintOptions(seed=7, ast_checksum=True)
+    scan = _core.ScanOptions(ignore_dirs=["build/"])
+    assert options.checksum_scheme() == "crc32+ast+seed=7"
+    assert scan.ignore_dirs == ["build"]
+
+    stats = _core.save_baseline(
+        str(db_path), str(tmp_path), False, [str(tmp_path)], options=options, scan=scan
+    )
+    assert stats.count == 1
+    assert stats.timings is None
+
+    changes = _core.detect_changes(
+        str(db_path), str(tmp_path), [str(tmp_path)], options=options, scan=scan
+    )
+    assert not changes.has_changes()
+    assert changes.warnings == []
+
+    # Detecting with options that change checksums is flagged
+    changes = _core.detect_changes(str(db_path), str(tmp_path), [str(tmp_path)], scan=scan)
+    assert any("crc32+ast+seed=7" in warning for warning in changes.warnings)
+
+
+def test_options_objects_reject_invalid_values():
+    """Bad granularity names and glob patterns raise ValueError."""
+    with pytest.raises(ValueError):
+        _core.FingerprintOptions(granularity="line")
+    with pytest.raises(ValueError):
+        _core.ScanOptions(exclude_globs=["src/["])
+
+
+def test_select_tests_uses_options(tmp_path):
+    """select_tests compares checksums with the options the baseline was saved with."""
+    db_path = tmp_path / "test.db"
+    f = tmp_path / "calc.py"
+    f.write_text("def add(a, b):\n    return a + b\n")
+    (tmp_path / "vendor").mkdir()
+    (tmp_path / "vendor" / "lib.py").write_text("X = 1\n")
+
+    options = _core.FingerprintOptions(ignore_comments=True)
+    scan = _core.ScanOptions(ignore_dirs=["vendor"])
+    _core.save_baseline(
+        str(db_path), str(tmp_path), False, [str(tmp_path)], options=options, scan=scan
+    )
+    fp = _core.calculate_fingerprint(str(f), str(tmp_path), options)
+    db = _core.PytestDiffDatabase(str(db_path))
+    db.save_test_execution("test_add", [fp], 0.1, False)
+    db.close()
+
+    def select():
+        return _core.select_tests(
+            str(db_path), str(tmp_path), [str(tmp_path)], options=options, scan=scan
+        )
+
+    f.write_text("def add(a, b):\n    # sum\n    return a + b\n")
+    assert select() == []
+
+    f.write_text("def add(a, b):\n    return b + a\n")
+    assert select() == ["test_add"]
+
+
+def test_seeded_baseline_round_trip(tmp_path):
+    """Every entry point reading a seeded baseline matches it when given the same options."""
+    db_path = tmp_path / "test.db"
+    f = tmp_path / "calc.py"
+    f.write_text("def add(a, b):\n    return a + b\n")
+
+    options = _core.FingerprintOptions(seed=42, ignore_comments=True)
+    _core.save_baseline(str(db_path), str(tmp_path), False, [str(tmp_path)], options=options)
+    fp = _core.calculate_fingerprint(str(f), str(tmp_path), options)
+    db = _core.PytestDiffDatabase(str(db_path))
+    db.save_test_execution("test_add", [fp], 0.1, False)
+    db.close()
+
+    root, scope = str(tmp_path), [str(tmp_path)]
+    assert _core.select_tests(str(db_path), root, scope, options=options) == []
+    assert _core.summarize_changes(str(db_path), root, scope, options=options).files_modified == 0
+
+    # A comment changes the file hash, so the seeded checksums get compared
+    f.write_text("# calculator\ndef add(a, b):\n    return a + b\n")
+    assert _core.select_tests(str(db_path), root, scope, options=options) == []
+    summary = _core.summarize_changes(str(db_path), root, scope, options=options)
+    assert (summary.blocks_added, summary.blocks_removed, summary.blocks_modified) == (0, 0, 0)
+    assert _core.inspect_file(str(db_path), "calc.py", root, options).changed_checksums == []
+
+    _core.update_baseline_file(str(db_path), "calc.py", root, options)
+    inspection = _core.inspect_file(str(db_path), "calc.py", root, options)
+    assert inspection.baseline_checksums == inspection.current_checksums
+
+
+def test_xxhash_baseline_round_trip(tmp_path):
+    """64-bit checksums survive the database and select tests like CRC32 ones."""
+    db_path = tmp_path / "test.db"
+    f = tmp_path / "calc.py"
+    f.write_text("def add(a, b):\n    return a + b\n")
+
+    options = _core.FingerprintOptions(checksum_algorithm="xxhash64")
+    assert options.checksum_scheme() == "xxhash64"
+    _core.save_baseline(str(db_path), str(tmp_path), False, [str(tmp_path)], options=options)
+    fp = _core.calculate_fingerprint(str(f), str(tmp_path), options)
+    assert _core.compute_checksum("def add(a, b):\n    return a + b", "xxhash64") in fp.checksums
+    db = _core.PytestDiffDatabase(str(db_path))
+    db.save_test_execution("test_add", [fp], 0.1, False)
+    db.close()
+
+    root, scope = str(tmp_path), [str(tmp_path)]
+    assert _core.select_tests(str(db_path), root, scope, options=options) == []
+
+    f.write_text("def add(a, b):\n    return b + a\n")
+    assert _core.select_tests(str(db_path), root, scope, options=options) == ["test_add"]
+    with pytest.raises(ValueError):
+        _core.FingerprintOptions(checksum_algorithm="md5")
+
+
+def test_save_baseline_collect_timings(tmp_path):
+    """collect_timings fills BaselineStats.timings for every file fingerprinted."""
+    db_path = tmp_path / "test.db"
+    (tmp_path / "a.py").write_text("A = 1\n")
+    (tmp_path / "b.py").write_text("B = 2\n")
+
+    stats = _core.save_baseline(
+        str(db_path), str(tmp_path), False, [str(tmp_path)], collect_timings=True
+    )
+    assert stats.count == 2
+    assert sorted(path for path, _ in stats.timings) == ["a.py", "b.py"]
+    assert all(seconds >= 0 for _, seconds in stats.timings)
+
+    # Unchanged files are not fingerprinted again
+    stats = _core.save_baseline(
+        str(db_path), str(tmp_path), False, [str(tmp_path)], collect_timings=True
+    )
+    assert (stats.count, stats.timings) == (2, [])
+
+
+def test_save_baseline_progress_callback(tmp_path):
+    """save_baseline reports (processed, total) progress to a callback."""
+    db_path = tmp_path / "test.db"
+    for i in range(60):
+        (tmp_path / f"module_{i}.py").write_text(f"X = {i}\n")
+
+    calls = []
+    _core.save_baseline(
+        str(db_path),
+        str(tmp_path),
+        False,
+        [str(tmp_path)],
+        progress_callback=lambda processed, total: calls.append((processed, total)),
+    )
+
+    assert calls
+    assert all(prev[0] < cur[0] for prev, cur in zip(calls, calls[1:]))
+    assert calls[-1] == (60, 60)
+
+
 def test_import_copies_test_execution_coverage(tmp_path):
     """Imported test execution data enables get_affected_tests."""
     source_path = tmp_path / "source.db"
@@ -194,3 +406,42 @@ def test_import_copies_test_execution_coverage(tmp_path):
     # get_affected_tests should find the imported test
     affected = target_db.get_affected_tests({fp.filename: list(fp.checksums)})
     assert "test_hello" in affected
+
+
+def test_fingerprint_dict_round_trip_with_blocks(tmp_path):
+    """as_dict/from_dict round-trips a fingerprint including its blocks."""
+    f = tmp_path / "module.py"
+    f.write_text("def hello():\n    return 'world'\n")
+    fp = _core.calculate_fingerprint(str(f))
+    assert fp.blocks
+
+    data = fp.as_dict()
+    assert data["filename"] == fp.filename
+    assert isinstance(data["blocks"][0], dict)
+
+    restored = _core.Fingerprint.from_dict(data)
+    assert restored.as_dict() == data
+    assert restored.blocks == fp.blocks
+
+
+def test_fingerprint_dict_round_trip_without_blocks():
+    """Fingerprints without blocks keep blocks=None through a dict."""
+    fp = _core.Fingerprint("a.py", [1, 2], "0" * 64, 1.5)
+
+    data = fp.as_dict()
+    assert data["blocks"] is None
+    assert data["mtime_exact"] is None
+
+    restored = _core.Fingerprint.from_dict(data)
+    assert restored.blocks is None
+    assert restored.as_dict() == data
+
+
+def test_fingerprint_from_dict_validates():
+    """from_dict raises KeyError for missing keys and TypeError for bad values."""
+    with pytest.raises(KeyError):
+        _core.Fingerprint.from_dict({"filename": "a.py"})
+    with pytest.raises(TypeError):
+        _core.Fingerprint.from_dict(
+            {"filename": "a.py", "checksums": "oops", "file_hash": "x", "mtime": 1.0}
+        )
@@ -1,71 +1,203 @@
-// In-memory cache for database operations
+// Building blocks of the in-memory fingerprint caches
 //
-// Provides fast lookups for frequently accessed data like fingerprints
-// and test mappings using LruCache for automatic eviction.
+// SizedLru is the LRU map behind the database's stored-fingerprint cache and
+// FingerprintCache; CacheCounters counts their lookups for `cache_stats()`.
 
 use lru::LruCache;
-use parking_lot::RwLock;
+use pyo3::prelude::*;
+use std::borrow::Borrow;
+use std::hash::Hash;
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::types::Fingerprint;
+use crate::types::{Block, Fingerprint};
 
-/// LRU cache for database queries
-pub struct Cache {
-    /// Cached file fingerprints: path -> fingerprint
-    fingerprints: RwLock<LruCache<PathBuf, Fingerprint>>,
+/// Snapshot of cache counters, exposed to Python
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    #[pyo3(get)]
+    pub hits: u64,
+    #[pyo3(get)]
+    pub misses: u64,
+    #[pyo3(get)]
+    pub insertions: u64,
+    #[pyo3(get)]
+    pub evictions: u64,
+}
+
+#[pymethods]
+impl CacheStats {
+    /// Fraction of lookups served from the cache (0.0 when nothing was looked up)
+    #[getter]
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total > 0 {
+            self.hits as f64 / total as f64
+        } else {
+            0.0
+        }
+    }
 
-    /// Cached test mappings: test_name -> list of checksums
-    test_mappings: RwLock<LruCache<String, Vec<i32>>>,
+    fn __repr__(&self) -> String {
+        format!(
+            "CacheStats(hits={}, misses={}, insertions={}, evictions={}, hit_rate={:.1}%)",
+            self.hits,
+            self.misses,
+            self.insertions,
+            self.evictions,
+            self.hit_rate() * 100.0
+        )
+    }
+}
+
+/// Lock-free hit/miss/insertion/eviction counters shared by the caches
+#[derive(Debug, Default)]
+pub(crate) struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
 }
 
-impl Cache {
-    /// Create a new cache with default size limit
-    pub fn new() -> Self {
-        Self::with_capacity(10_000)
+impl CacheCounters {
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an insertion that pushed out `evicted` other entries
+    pub(crate) fn record_insertion(&self, evicted: u64) {
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
     }
 
-    /// Create a new cache with specified capacity
-    pub fn with_capacity(max_size: usize) -> Self {
-        let cap = NonZeroUsize::new(max_size).unwrap_or(NonZeroUsize::new(1).unwrap());
+    pub(crate) fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.insertions.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Approximate in-memory footprint of a fingerprint, in bytes
+///
+/// Counts the struct itself plus its heap data (strings, checksums and blocks);
+/// allocator overhead is ignored.
+pub(crate) fn approx_fingerprint_bytes(fp: &Fingerprint) -> usize {
+    let blocks = fp.blocks.as_ref().map_or(0, |blocks| {
+        blocks
+            .iter()
+            .map(|b| {
+                std::mem::size_of::<Block>()
+                    + b.name.len()
+                    + b.qualified_name.len()
+                    + b.block_type.len()
+            })
+            .sum()
+    });
+    std::mem::size_of::<Fingerprint>()
+        + fp.filename.len()
+        + fp.file_hash.len()
+        + fp.checksums.len() * std::mem::size_of::<i64>()
+        + blocks
+}
+
+/// LRU map bounded by entry count and, optionally, by approximate size
+///
+/// Inserting past either limit evicts least-recently-used entries; an entry
+/// larger than the whole byte budget is not retained.
+pub(crate) struct SizedLru<K: Hash + Eq, V> {
+    entries: LruCache<K, V>,
+    bytes: usize,
+    /// Byte budget; `None` limits by entry count only
+    max_bytes: Option<usize>,
+    weigh: fn(&V) -> usize,
+}
+
+impl<K: Hash + Eq + Clone, V> SizedLru<K, V> {
+    pub(crate) fn new(
+        max_entries: usize,
+        max_bytes: Option<usize>,
+        weigh: fn(&V) -> usize,
+    ) -> Self {
+        let cap = NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::new(1).unwrap());
         Self {
-            fingerprints: RwLock::new(LruCache::new(cap)),
-            test_mappings: RwLock::new(LruCache::new(cap)),
+            entries: LruCache::new(cap),
+            bytes: 0,
+            max_bytes,
+            weigh,
         }
     }
 
-    /// Get a fingerprint from cache (promotes to most-recently-used)
-    pub fn get_fingerprint(&self, path: &PathBuf) -> Option<Fingerprint> {
-        self.fingerprints.write().get(path).cloned()
+    /// Look up an entry, promoting it to most-recently-used
+    pub(crate) fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.get(key)
     }
 
-    /// Insert a fingerprint into cache (auto-evicts LRU entry when full)
-    pub fn insert_fingerprint(&self, path: PathBuf, fp: Fingerprint) {
-        self.fingerprints.write().put(path, fp);
+    pub(crate) fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.contains(key)
     }
 
-    /// Get test mapping from cache (promotes to most-recently-used)
-    #[allow(dead_code)]
-    pub fn get_test_mapping(&self, test_name: &str) -> Option<Vec<i32>> {
-        self.test_mappings.write().get(test_name).cloned()
+    /// Insert an entry, returning how many other entries were evicted
+    pub(crate) fn push(&mut self, key: K, value: V) -> u64 {
+        self.bytes += (self.weigh)(&value);
+        let mut evicted = 0;
+        // `push` also returns the old entry when the key was already present
+        if let Some((old_key, old_value)) = self.entries.push(key.clone(), value) {
+            self.bytes -= (self.weigh)(&old_value);
+            if old_key != key {
+                evicted += 1;
+            }
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            while self.bytes > max_bytes {
+                let Some((_, old_value)) = self.entries.pop_lru() else {
+                    break;
+                };
+                self.bytes -= (self.weigh)(&old_value);
+                evicted += 1;
+            }
+        }
+        evicted
     }
 
-    /// Insert test mapping into cache (auto-evicts LRU entry when full)
-    #[allow(dead_code)]
-    pub fn insert_test_mapping(&self, test_name: String, checksums: Vec<i32>) {
-        self.test_mappings.write().put(test_name, checksums);
+    pub(crate) fn iter(&self) -> lru::Iter<'_, K, V> {
+        self.entries.iter()
     }
 
-    /// Clear all cached data
-    pub fn clear(&self) {
-        self.fingerprints.write().clear();
-        self.test_mappings.write().clear();
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
     }
-}
 
-impl Default for Cache {
-    fn default() -> Self {
-        Self::new()
+    /// Approximate memory held by the entries, in bytes
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
     }
 }
 
@@ -73,95 +205,159 @@ impl Default for Cache {
 mod tests {
     use super::*;
     use crate::types::Fingerprint;
-    use std::path::PathBuf;
 
-    #[test]
-    fn test_cache_fingerprint() {
-        let cache = Cache::new();
-        let path = PathBuf::from("test.py");
-
-        let fp = Fingerprint {
-            filename: "test.py".to_string(),
-            checksums: vec![123, 456],
-            file_hash: "abc".to_string(),
+    fn fp(name: &str) -> Fingerprint {
+        Fingerprint {
+            filename: name.to_string(),
+            checksums: vec![1],
+            file_hash: name.to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
-        };
+        }
+    }
 
-        cache.insert_fingerprint(path.clone(), fp.clone());
-        let retrieved = cache.get_fingerprint(&path).unwrap();
+    fn lru(max_entries: usize) -> SizedLru<String, Fingerprint> {
+        SizedLru::new(max_entries, None, approx_fingerprint_bytes)
+    }
+
+    #[test]
+    fn test_lru_get_and_clear() {
+        let mut store = lru(10);
+        store.push("test.py".to_string(), fp("test.py"));
+        assert_eq!(store.get("test.py").unwrap().filename, "test.py");
+        assert_eq!(store.bytes(), approx_fingerprint_bytes(&fp("test.py")));
 
-        assert_eq!(retrieved.filename, fp.filename);
-        assert_eq!(retrieved.checksums, fp.checksums);
+        store.clear();
+        assert!(store.get("test.py").is_none());
+        assert_eq!((store.len(), store.bytes()), (0, 0));
     }
 
     #[test]
-    fn test_cache_test_mapping() {
-        let cache = Cache::new();
+    fn test_lru_eviction_order() {
+        let mut store = lru(2);
+        store.push("a.py".to_string(), fp("a.py"));
+        store.push("b.py".to_string(), fp("b.py"));
 
-        cache.insert_test_mapping("test_foo".to_string(), vec![1, 2, 3]);
-        let retrieved = cache.get_test_mapping("test_foo").unwrap();
+        // Access a.py to make it most-recently-used
+        assert!(store.get("a.py").is_some());
+
+        // Insert c.py — should evict b.py (least recently used)
+        assert_eq!(store.push("c.py".to_string(), fp("c.py")), 1);
 
-        assert_eq!(retrieved, vec![1, 2, 3]);
+        assert!(store.contains("a.py"));
+        assert!(!store.contains("b.py")); // evicted
+        assert!(store.contains("c.py"));
     }
 
     #[test]
-    fn test_cache_clear() {
-        let cache = Cache::new();
-        let path = PathBuf::from("test.py");
-
-        let fp = Fingerprint {
-            filename: "test.py".to_string(),
-            checksums: vec![123],
-            file_hash: "abc".to_string(),
-            mtime: 1.0,
-            blocks: None,
-        };
+    fn test_lru_touched_entries_survive_eviction() {
+        let mut store = lru(4);
+        for name in ["a.py", "b.py", "c.py", "d.py"] {
+            store.push(name.to_string(), fp(name));
+        }
+
+        // Touch the two oldest entries so b.py and c.py become the coldest
+        assert!(store.get("a.py").is_some());
+        assert!(store.get("d.py").is_some());
+        assert!(store.get("a.py").is_some());
 
-        cache.insert_fingerprint(path.clone(), fp);
-        cache.clear();
+        // Two inserts over capacity evict the two least-recently-used entries
+        store.push("e.py".to_string(), fp("e.py"));
+        store.push("f.py".to_string(), fp("f.py"));
 
-        assert!(cache.get_fingerprint(&path).is_none());
+        for name in ["a.py", "d.py", "e.py", "f.py"] {
+            assert!(store.contains(name), "{name} evicted");
+        }
+        assert!(!store.contains("b.py"));
+        assert!(!store.contains("c.py"));
     }
 
     #[test]
-    fn test_lru_eviction_order() {
-        // Create a cache with capacity 2
-        let cache = Cache::with_capacity(2);
+    fn test_cache_counters() {
+        let counters = CacheCounters::default();
+        let mut store = lru(2);
 
-        let fp1 = Fingerprint {
-            filename: "a.py".to_string(),
-            checksums: vec![1],
-            file_hash: "h1".to_string(),
+        counters.record_miss();
+        counters.record_insertion(store.push("a.py".to_string(), fp("a.py")));
+        counters.record_insertion(store.push("b.py".to_string(), fp("b.py")));
+        // Re-inserting an existing key is not an eviction
+        counters.record_insertion(store.push("b.py".to_string(), fp("b.py")));
+        counters.record_hit();
+        counters.record_hit();
+        counters.record_insertion(store.push("c.py".to_string(), fp("c.py")));
+
+        let stats = counters.snapshot();
+        assert_eq!(
+            stats,
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                insertions: 4,
+                evictions: 1,
+            }
+        );
+        assert!((stats.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        counters.reset();
+        assert_eq!(counters.snapshot(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_byte_capacity_eviction() {
+        let fp = |name: &str, blocks: usize| Fingerprint {
+            filename: name.to_string(),
+            checksums: vec![0; blocks],
+            file_hash: "h".to_string(),
             mtime: 1.0,
-            blocks: None,
-        };
-        let fp2 = Fingerprint {
-            filename: "b.py".to_string(),
-            checksums: vec![2],
-            file_hash: "h2".to_string(),
-            mtime: 2.0,
-            blocks: None,
-        };
-        let fp3 = Fingerprint {
-            filename: "c.py".to_string(),
-            checksums: vec![3],
-            file_hash: "h3".to_string(),
-            mtime: 3.0,
-            blocks: None,
+            mtime_exact: None,
+            blocks: Some(
+                (0..blocks)
+                    .map(|i| Block {
+                        start_line: i + 1,
+                        end_line: i + 1,
+                        checksum: 0,
+                        name: format!("f{i}"),
+                        block_type: "function".to_string(),
+                        qualified_name: format!("f{i}"),
+                        body_start_line: i + 1,
+                    })
+                    .collect(),
+            ),
         };
+        let small = approx_fingerprint_bytes(&fp("s00.py", 1));
+        let large = approx_fingerprint_bytes(&fp("big0.py", 200));
+        let budget = large + 10 * small;
+        let mut store = SizedLru::new(1000, Some(budget), approx_fingerprint_bytes);
 
-        cache.insert_fingerprint(PathBuf::from("a.py"), fp1);
-        cache.insert_fingerprint(PathBuf::from("b.py"), fp2);
+        let mut evicted = 0;
+        for name in ["big0.py", "big1.py"] {
+            evicted += store.push(name.to_string(), fp(name, 200));
+        }
+        for i in 0..20 {
+            let name = format!("s{i:02}.py");
+            evicted += store.push(name.clone(), fp(&name, 1));
+            assert!(store.bytes() <= budget);
+        }
 
-        // Access a.py to make it most-recently-used
-        assert!(cache.get_fingerprint(&PathBuf::from("a.py")).is_some());
+        // Both large entries were the coldest and had to go to make room
+        assert!(!store.contains("big0.py"));
+        assert!(!store.contains("big1.py"));
+        // The most recent small entries all fit
+        for i in 10..20 {
+            assert!(store.contains(&format!("s{i:02}.py")));
+        }
+        assert!(evicted >= 2);
 
-        // Insert c.py — should evict b.py (least recently used)
-        cache.insert_fingerprint(PathBuf::from("c.py"), fp3);
+        // Replacing an entry is not an eviction and keeps the byte count exact
+        let before = store.bytes();
+        assert_eq!(store.push("s19.py".to_string(), fp("s19.py", 1)), 0);
+        assert_eq!(store.bytes(), before);
 
-        assert!(cache.get_fingerprint(&PathBuf::from("a.py")).is_some());
-        assert!(cache.get_fingerprint(&PathBuf::from("b.py")).is_none()); // evicted
-        assert!(cache.get_fingerprint(&PathBuf::from("c.py")).is_some());
+        // An entry larger than the whole budget is not retained
+        let mut tiny = SizedLru::new(1000, Some(small), approx_fingerprint_bytes);
+        assert_eq!(tiny.push("big0.py".to_string(), fp("big0.py", 200)), 1);
+        assert_eq!(tiny.bytes(), 0);
+        assert!(!tiny.contains("big0.py"));
     }
 }
@@ -0,0 +1,63 @@
+// Cooperative cancellation for long-running scans
+//
+// A CancelToken is shared between Python and the Rust workers: Python sets it
+// (e.g. from a signal handler or another thread) and the scans check it
+// between files, stopping early with whatever they have done so far.
+
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flag asking `save_baseline` or `detect_changes` to stop early
+///
+/// Clones share the same flag, so the token passed to a scan can be
+/// cancelled from Python while the scan runs.
+#[pyclass]
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl CancelToken {
+    #[new]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the scans using this token to stop
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `cancel` has been called
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("CancelToken(cancelled={})", self.is_cancelled())
+    }
+}
+
+/// Whether an optional token has been cancelled
+pub(crate) fn is_cancelled(token: Option<&CancelToken>) -> bool {
+    token.is_some_and(CancelToken::is_cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_the_flag() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!is_cancelled(Some(&token)));
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(is_cancelled(Some(&token)));
+        assert!(!is_cancelled(None));
+    }
+}
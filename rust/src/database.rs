@@ -10,17 +10,81 @@
 use anyhow::{Context, Result};
 use parking_lot::RwLock;
 use pyo3::prelude::*;
+use rusqlite::config::DbConfig;
 use rusqlite::{params, Connection, OptionalExtension};
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
 
-use crate::cache::Cache;
-use crate::types::Fingerprint;
+use crate::cache::{approx_fingerprint_bytes, CacheCounters, CacheStats, SizedLru};
+use crate::types::{ChangedFiles, Fingerprint, TestExecution};
+
+mod snapshot;
+
+/// Path that opens a private in-memory database
+const IN_MEMORY_PATH: &str = ":memory:";
 
 /// Default busy timeout in milliseconds for concurrent access
 const BUSY_TIMEOUT_MS: i32 = 30_000; // 30 seconds
 
+/// Stored fingerprints kept in memory by `get_fingerprint`
+const FINGERPRINT_CACHE_SIZE: usize = 10_000;
+
+/// Approximate memory budget of those fingerprints, so a few files with
+/// thousands of blocks can't make the entry limit meaningless
+const FINGERPRINT_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Filenames bound per `IN (...)` query, well below SQLite's variable limit
+const LOOKUP_CHUNK_SIZE: usize = 500;
+
+/// Most recent outcomes kept per test by `record_outcome`
+const OUTCOME_HISTORY_LEN: i64 = 20;
+
+/// Outcomes accepted by `record_outcome`, named like pytest's report outcomes
+const OUTCOMES: &[&str] = &["passed", "failed", "skipped"];
+
+/// Schema migrations, applied in order. Entry `i` upgrades a database from
+/// version `i` to `i + 1` (tracked in `PRAGMA user_version`). Databases created
+/// before versioning report version 0 and get the idempotent v1 schema.
+const MIGRATIONS: &[&str] = &[
+    include_str!("schema.sql"),
+    // v2: exact baseline mtimes; NULL for rows written before the upgrade
+    "ALTER TABLE baseline_fp ADD COLUMN mtime_secs INTEGER;
+     ALTER TABLE baseline_fp ADD COLUMN mtime_nanos INTEGER;",
+    // v3: `[qualified_name, checksum]` pairs per baseline file, for block-level queries
+    "ALTER TABLE baseline_fp ADD COLUMN block_names TEXT;",
+    // v4: recent pass/fail/skip outcomes per test, for flakiness tracking
+    "CREATE TABLE IF NOT EXISTS test_outcome (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         test_name TEXT NOT NULL,
+         outcome TEXT NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS ix_test_outcome_test_name ON test_outcome(test_name, id);",
+];
+
+/// Schema version written by this build
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Error raised when a database was written by a newer pytest-difftest
+#[derive(Debug)]
+pub struct SchemaVersionError {
+    pub found: i64,
+    pub supported: i64,
+}
+
+impl std::fmt::Display for SchemaVersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "database schema version {} is newer than the supported version {}; \
+             upgrade pytest-difftest or delete the database to rebuild the baseline",
+            self.found, self.supported
+        )
+    }
+}
+
+impl std::error::Error for SchemaVersionError {}
+
 /// Result of an import or merge operation
 #[pyclass]
 #[derive(Clone, Debug)]
@@ -38,11 +102,18 @@ pub struct ImportResult {
 /// - Prepared statement caching
 /// - Memory-mapped I/O
 /// - In-memory cache for frequently accessed data
+///
+/// Opening `":memory:"` gives a private in-memory database that supports every
+/// operation but lives only as long as the object: it is lost on close or
+/// process exit unless written to disk with `dump_to`.
 #[pyclass(unsendable)]
 pub struct PytestDiffDatabase {
     conn: Arc<RwLock<Connection>>,
-    cache: Arc<Cache>,
+    /// Stored fingerprints by filename, in front of `file_fp` lookups
+    fingerprint_cache: Arc<RwLock<SizedLru<String, Fingerprint>>>,
+    cache_counters: Arc<CacheCounters>,
     current_environment_id: Arc<RwLock<Option<i64>>>,
+    last_integrity_error: Arc<RwLock<Option<String>>>,
 }
 
 impl PytestDiffDatabase {
@@ -82,28 +153,66 @@ impl PytestDiffDatabase {
         )
         .context("Failed to set SQLite pragmas")?;
 
-        // Create schema
-        Self::create_schema(&conn)?;
+        // Create or upgrade schema
+        Self::run_migrations(&conn, MIGRATIONS)?;
 
         #[allow(clippy::arc_with_non_send_sync)]
         Ok(Self {
             conn: Arc::new(RwLock::new(conn)),
-            cache: Arc::new(Cache::new()),
+            fingerprint_cache: Arc::new(RwLock::new(SizedLru::new(
+                FINGERPRINT_CACHE_SIZE,
+                Some(FINGERPRINT_CACHE_MAX_BYTES),
+                approx_fingerprint_bytes,
+            ))),
+            cache_counters: Arc::new(CacheCounters::default()),
             current_environment_id: Arc::new(RwLock::new(None)),
+            last_integrity_error: Arc::new(RwLock::new(None)),
         })
     }
 
-    /// Create database schema if it doesn't exist
-    fn create_schema(conn: &Connection) -> Result<()> {
-        conn.execute_batch(include_str!("schema.sql"))
-            .context("Failed to create database schema")?;
+    /// Bring the schema up to date, one migration per transaction
+    ///
+    /// Refuses databases whose version is newer than `migrations` knows about.
+    fn run_migrations(conn: &Connection, migrations: &[&str]) -> Result<()> {
+        let supported = migrations.len() as i64;
+        let current: i64 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .context("Failed to read schema version")?;
+
+        if current > supported {
+            return Err(SchemaVersionError {
+                found: current,
+                supported,
+            }
+            .into());
+        }
+
+        for (version, sql) in migrations.iter().enumerate().skip(current as usize) {
+            let target = version as i64 + 1;
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch(sql)
+                .with_context(|| format!("Failed to migrate database schema to v{}", target))?;
+            tx.pragma_update(None, "user_version", target)?;
+            tx.commit()?;
+        }
+
         Ok(())
     }
 
+    fn schema_version_internal(&self) -> Result<i64> {
+        let conn = self.conn.read();
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .context("Failed to read schema version")
+    }
+
     /// Close database and checkpoint WAL (public Rust API)
     pub fn close_and_checkpoint(&self) -> Result<()> {
+        self.checkpoint_internal()
+    }
+
+    /// Merge the WAL into the main database file and truncate it to zero bytes
+    fn checkpoint_internal(&self) -> Result<()> {
         let conn = self.conn.write();
-        // Checkpoint WAL to merge changes into main database file
         conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
             .context("Failed to checkpoint WAL")?;
         Ok(())
@@ -218,6 +327,7 @@ impl PytestDiffDatabase {
                     filename: row.get(0)?,
                     checksums,
                     mtime: row.get(2)?,
+                    mtime_exact: None,
                     file_hash: row.get(3)?,
                     blocks: None,
                 })
@@ -230,10 +340,12 @@ impl PytestDiffDatabase {
     /// Get stored fingerprint for a file (if exists)
     fn get_fingerprint_internal(&self, filename: &str) -> Result<Option<Fingerprint>> {
         // Check cache first
-        let path = Path::new(filename).to_path_buf();
-        if let Some(cached) = self.cache.get_fingerprint(&path) {
-            return Ok(Some(cached));
+        let cached = self.fingerprint_cache.write().get(filename).cloned();
+        if cached.is_some() {
+            self.cache_counters.record_hit();
+            return Ok(cached);
         }
+        self.cache_counters.record_miss();
 
         let conn = self.conn.read();
 
@@ -253,6 +365,7 @@ impl PytestDiffDatabase {
                         filename: row.get(0)?,
                         checksums,
                         mtime: row.get(2)?,
+                        mtime_exact: None,
                         file_hash: row.get(3)?,
                         blocks: None,
                     })
@@ -263,11 +376,21 @@ impl PytestDiffDatabase {
 
         // Cache if found
         if let Some(ref fp) = result {
-            self.cache.insert_fingerprint(path, fp.clone());
+            let evicted = self
+                .fingerprint_cache
+                .write()
+                .push(filename.to_string(), fp.clone());
+            self.cache_counters.record_insertion(evicted);
         }
 
         Ok(result)
     }
+
+    /// Drop cached fingerprints and reset the cache counters
+    fn clear_fingerprint_cache(&self) {
+        self.fingerprint_cache.write().clear();
+        self.cache_counters.reset();
+    }
 }
 
 #[pymethods]
@@ -275,7 +398,35 @@ impl PytestDiffDatabase {
     #[new]
     fn new(path: &str) -> PyResult<Self> {
         Self::new_internal(path).map_err(|e| {
-            pyo3::exceptions::PyIOError::new_err(format!("Failed to open database: {}", e))
+            if e.downcast_ref::<SchemaVersionError>().is_some() {
+                pyo3::exceptions::PyRuntimeError::new_err(format!(
+                    "Failed to open database {}: {}",
+                    path, e
+                ))
+            } else {
+                pyo3::exceptions::PyIOError::new_err(format!("Failed to open database: {}", e))
+            }
+        })
+    }
+
+    /// Open a private in-memory database, the same as `PytestDiffDatabase(":memory:")`
+    #[staticmethod]
+    fn in_memory() -> PyResult<Self> {
+        Self::new(IN_MEMORY_PATH)
+    }
+
+    /// Whether the database lives in memory rather than in a file
+    fn is_in_memory(&self) -> bool {
+        self.is_in_memory_internal()
+    }
+
+    /// Write a copy of the database to a new file at `path`
+    ///
+    /// Works for any database, and is how an in-memory database is persisted.
+    /// Fails if `path` already exists.
+    fn dump_to(&self, path: &str) -> PyResult<()> {
+        self.dump_to_internal(path).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!("Failed to dump database: {}", e))
         })
     }
 
@@ -287,7 +438,9 @@ impl PytestDiffDatabase {
     /// * `duration` - Test execution time in seconds
     /// * `failed` - Whether the test failed
     /// * `python_version` - Python version string (e.g., "3.12.0")
-    #[pyo3(signature = (test_name, fingerprints, duration, failed, python_version = "3.12"))]
+    /// * `merge` - Union the checksums with the previously recorded ones instead of
+    ///   replacing them, so re-runs that take fewer branches keep earlier dependencies
+    #[pyo3(signature = (test_name, fingerprints, duration, failed, python_version = "3.12", merge = false))]
     fn save_test_execution(
         &mut self,
         test_name: &str,
@@ -295,13 +448,68 @@ impl PytestDiffDatabase {
         duration: f64,
         failed: bool,
         python_version: &str,
+        merge: bool,
     ) -> PyResult<()> {
-        self.save_test_execution_internal(test_name, fingerprints, duration, failed, python_version)
+        self.save_test_execution_internal(
+            test_name,
+            fingerprints,
+            duration,
+            failed,
+            python_version,
+            merge,
+        )
+        .map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to save test execution: {}",
+                e
+            ))
+        })
+    }
+
+    /// Save many test execution records in a single transaction
+    ///
+    /// Equivalent to calling `save_test_execution` for each execution, without
+    /// the per-call transaction overhead. Returns the number of executions saved.
+    #[pyo3(signature = (executions, python_version = "3.12"))]
+    fn save_test_executions_batch(
+        &mut self,
+        executions: Vec<TestExecution>,
+        python_version: &str,
+    ) -> PyResult<usize> {
+        self.save_test_executions_batch_internal(executions, python_version)
+            .map_err(|e| {
+                pyo3::exceptions::PyRuntimeError::new_err(format!(
+                    "Failed to save test executions: {}",
+                    e
+                ))
+            })
+    }
+
+    /// Get the tests that executed a specific function, method or class
+    ///
+    /// The block's checksum is looked up in the baseline fingerprint of the
+    /// file, so the baseline must have been saved by a version that records
+    /// block names.
+    ///
+    /// # Arguments
+    /// * `filename` - Path relative to the project root, as stored in fingerprints
+    /// * `qualified_name` - Dotted block name (e.g. "Calculator.add")
+    ///
+    /// # Returns
+    /// * Sorted list of test names, raising ValueError if the block is not in the baseline
+    fn tests_for_block(&self, filename: &str, qualified_name: &str) -> PyResult<Vec<String>> {
+        self.tests_for_block_internal(filename, qualified_name)
             .map_err(|e| {
                 pyo3::exceptions::PyRuntimeError::new_err(format!(
-                    "Failed to save test execution: {}",
+                    "Failed to get tests for block: {}",
                     e
                 ))
+            })?
+            .ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(format!(
+                    "No block '{}' in the baseline for {}",
+                    qualified_name, filename
+                ))
             })
     }
 
@@ -314,7 +522,7 @@ impl PytestDiffDatabase {
     /// * List of test names that should be run
     fn get_affected_tests(
         &self,
-        changed_blocks: HashMap<String, Vec<i32>>,
+        changed_blocks: HashMap<String, Vec<i64>>,
     ) -> PyResult<Vec<String>> {
         self.get_affected_tests_internal(changed_blocks)
             .map_err(|e| {
@@ -325,6 +533,46 @@ impl PytestDiffDatabase {
             })
     }
 
+    /// Get affected tests ordered by how much of the change they exercise
+    ///
+    /// Each test is paired with the number of changed checksums it depends on.
+    /// Tests are sorted by that count, descending; ties put tests whose last
+    /// run failed first, then sort by name.
+    ///
+    /// # Arguments
+    /// * `changed_blocks` - Map of filename -> list of changed checksums
+    fn get_affected_tests_ranked(
+        &self,
+        changed_blocks: HashMap<String, Vec<i64>>,
+    ) -> PyResult<Vec<(String, usize)>> {
+        self.get_affected_tests_ranked_internal(&changed_blocks)
+            .map_err(|e| {
+                pyo3::exceptions::PyRuntimeError::new_err(format!(
+                    "Failed to rank affected tests: {}",
+                    e
+                ))
+            })
+    }
+
+    /// Get the tests that executed any code in a file
+    ///
+    /// File-level counterpart to `get_affected_tests`: answers "what runs if I
+    /// touch this file?" without knowing which blocks will change.
+    ///
+    /// # Arguments
+    /// * `filename` - Path relative to the project root, as stored in fingerprints
+    ///
+    /// # Returns
+    /// * Sorted list of distinct test names
+    fn tests_for_file(&self, filename: &str) -> PyResult<Vec<String>> {
+        self.tests_for_file_internal(filename).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to get tests for file: {}",
+                e
+            ))
+        })
+    }
+
     /// Get all test names that have recorded executions in the current environment
     fn get_recorded_tests(&self) -> PyResult<Vec<String>> {
         self.get_recorded_tests_internal().map_err(|e| {
@@ -335,6 +583,93 @@ impl PytestDiffDatabase {
         })
     }
 
+    /// List every distinct recorded test name, sorted
+    ///
+    /// # Arguments
+    /// * `python_version` - Only list tests recorded under this Python version;
+    ///   all environments when None
+    #[pyo3(signature = (python_version = None))]
+    fn list_tests(&self, python_version: Option<&str>) -> PyResult<Vec<String>> {
+        self.list_tests_internal(python_version).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to list tests: {}", e))
+        })
+    }
+
+    /// Last recorded `(duration, failed)` of a test, or None if it was never recorded
+    ///
+    /// When the test ran in several environments, the most recent execution wins.
+    fn get_test_stats(&self, test_name: &str) -> PyResult<Option<(f64, bool)>> {
+        self.get_test_stats_internal(test_name).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to get test stats: {}", e))
+        })
+    }
+
+    /// Append an outcome ("passed", "failed" or "skipped") to a test's history
+    ///
+    /// Only the most recent 20 outcomes per test are kept.
+    fn record_outcome(&self, test_name: &str, outcome: &str) -> PyResult<()> {
+        if !OUTCOMES.contains(&outcome) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Unknown test outcome {:?}, expected one of {:?}",
+                outcome, OUTCOMES
+            )));
+        }
+        self.record_outcome_internal(test_name, outcome)
+            .map_err(|e| {
+                pyo3::exceptions::PyRuntimeError::new_err(format!(
+                    "Failed to record outcome: {}",
+                    e
+                ))
+            })
+    }
+
+    /// Recorded outcomes of a test, oldest first
+    fn outcome_history(&self, test_name: &str) -> PyResult<Vec<String>> {
+        self.outcome_history_internal(test_name).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to get outcome history: {}",
+                e
+            ))
+        })
+    }
+
+    /// Fraction of consecutive pass/fail outcomes that flip, from 0.0 (stable) to 1.0
+    ///
+    /// Skipped runs are ignored; tests with fewer than two pass/fail outcomes score 0.0.
+    fn flakiness_score(&self, test_name: &str) -> PyResult<f64> {
+        self.flakiness_score_internal(test_name).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to compute flakiness score: {}",
+                e
+            ))
+        })
+    }
+
+    /// Delete every recorded execution of a test, e.g. after it was renamed or removed
+    ///
+    /// Returns the number of test-to-fingerprint mapping rows deleted.
+    fn delete_test(&mut self, test_name: &str) -> PyResult<usize> {
+        self.delete_test_internal(test_name).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to delete test: {}", e))
+        })
+    }
+
+    /// Delete every recorded test whose name is not in `known`
+    ///
+    /// Returns the number of tests deleted.
+    fn prune_missing_tests(&mut self, known: Vec<String>) -> PyResult<usize> {
+        self.prune_missing_tests_internal(known).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to prune tests: {}", e))
+        })
+    }
+
+    /// Number of distinct recorded tests
+    fn test_count(&self) -> PyResult<usize> {
+        self.test_count_internal().map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to count tests: {}", e))
+        })
+    }
+
     /// Get stored fingerprint for a file
     fn get_fingerprint(&self, filename: &str) -> PyResult<Option<Fingerprint>> {
         self.get_fingerprint_internal(filename).map_err(|e| {
@@ -344,7 +679,7 @@ impl PytestDiffDatabase {
 
     /// Clear all cached data
     fn clear_cache(&self) -> PyResult<()> {
-        self.cache.clear();
+        self.clear_fingerprint_cache();
         Ok(())
     }
 
@@ -355,6 +690,11 @@ impl PytestDiffDatabase {
         })
     }
 
+    /// Hit/miss counters of the in-memory fingerprint cache
+    fn cache_stats(&self) -> CacheStats {
+        self.cache_counters.snapshot()
+    }
+
     /// Save baseline fingerprint for a file
     ///
     /// This stores the "known good" state that change detection compares against.
@@ -369,6 +709,22 @@ impl PytestDiffDatabase {
             })
     }
 
+    /// Get the baseline fingerprints of several files in as few queries as possible
+    ///
+    /// Files without a baseline are absent from the returned dict.
+    fn get_baseline_fingerprints_batch(
+        &self,
+        filenames: Vec<String>,
+    ) -> PyResult<HashMap<String, Fingerprint>> {
+        self.get_baseline_fingerprints_batch_internal(&filenames)
+            .map_err(|e| {
+                pyo3::exceptions::PyRuntimeError::new_err(format!(
+                    "Failed to get baseline fingerprints: {}",
+                    e
+                ))
+            })
+    }
+
     /// Get baseline fingerprint for a file
     fn get_baseline_fingerprint(&self, filename: &str) -> PyResult<Option<Fingerprint>> {
         self.get_baseline_fingerprint_internal(filename)
@@ -380,13 +736,74 @@ impl PytestDiffDatabase {
             })
     }
 
-    /// Clear all baseline fingerprints
-    fn clear_baseline(&mut self) -> PyResult<()> {
-        let conn = self.conn.write();
-        conn.execute("DELETE FROM baseline_fp", []).map_err(|e| {
-            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to clear baseline: {}", e))
-        })?;
-        Ok(())
+    /// Clear baseline fingerprints, all of them or only those under some paths
+    ///
+    /// # Arguments
+    /// * `scope_paths` - Files or directories relative to the project root, as
+    ///   stored in fingerprints (default: None, clearing the whole baseline)
+    ///
+    /// # Returns
+    /// * Number of baseline fingerprints removed
+    #[pyo3(signature = (scope_paths=None))]
+    fn clear_baseline(&mut self, scope_paths: Option<Vec<String>>) -> PyResult<usize> {
+        self.clear_baseline_internal(&scope_paths.unwrap_or_default())
+            .map_err(|e| {
+                pyo3::exceptions::PyRuntimeError::new_err(format!(
+                    "Failed to clear baseline: {}",
+                    e
+                ))
+            })
+    }
+
+    /// Delete fingerprints no test execution references, then compact the file
+    ///
+    /// Returns the number of bytes reclaimed.
+    fn vacuum(&mut self) -> PyResult<u64> {
+        self.vacuum_internal().map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to vacuum database: {}", e))
+        })
+    }
+
+    /// Run SQLite `integrity_check` and `foreign_key_check`
+    ///
+    /// Returns false if problems were found; the details are available from
+    /// `last_integrity_error()` until the next check.
+    fn integrity_check(&self) -> bool {
+        let problems = match self.integrity_problems_internal() {
+            Ok(problems) => problems,
+            Err(e) => vec![format!("{:#}", e)],
+        };
+        let ok = problems.is_empty();
+        *self.last_integrity_error.write() = (!ok).then(|| problems.join("\n"));
+        ok
+    }
+
+    /// Problems reported by the last failed `integrity_check()`, if any
+    fn last_integrity_error(&self) -> Option<String> {
+        self.last_integrity_error.read().clone()
+    }
+
+    /// Erase all data and recreate an empty schema
+    ///
+    /// Use when `integrity_check()` fails and the data cannot be repaired.
+    fn reset(&mut self) -> PyResult<()> {
+        self.reset_internal().map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to reset database: {}", e))
+        })
+    }
+
+    /// Write metadata, baselines, test executions and outcome history to a versioned JSON snapshot
+    fn export_json(&self, path: &str) -> PyResult<()> {
+        self.export_json_internal(path).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to export JSON: {}", e))
+        })
+    }
+
+    /// Populate an empty database from a JSON snapshot written by `export_json`
+    fn import_json(&mut self, path: &str) -> PyResult<ImportResult> {
+        self.import_json_internal(path).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to import JSON: {}", e))
+        })
     }
 
     /// Import baseline and test execution data from another database file using ATTACH DATABASE.
@@ -447,6 +864,38 @@ impl PytestDiffDatabase {
         })
     }
 
+    /// Schema version stored in the database (`PRAGMA user_version`)
+    fn schema_version(&self) -> PyResult<i64> {
+        self.schema_version_internal().map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to get schema version: {}",
+                e
+            ))
+        })
+    }
+
+    /// Get all filenames that have a baseline fingerprint, sorted
+    fn list_baseline_files(&self) -> PyResult<Vec<String>> {
+        self.list_baseline_files_internal().map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to list baseline files: {}",
+                e
+            ))
+        })
+    }
+
+    /// Compare this database's baseline with the baseline of `other`
+    ///
+    /// This baseline is the old side: files only in `other` are `added`, files
+    /// only here are `deleted`, and files whose hash differs are `modified`.
+    /// `changed_blocks` lists checksums of this baseline that `other` no longer
+    /// has, so they can be passed to this database's `get_affected_tests`.
+    fn diff_baselines(&self, other: &PytestDiffDatabase) -> PyResult<ChangedFiles> {
+        self.diff_baselines_internal(other).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to diff baselines: {}", e))
+        })
+    }
+
     /// Get filenames that a test depends on (via test_execution_file_fp)
     fn get_test_dependencies(&self, test_name: &str) -> PyResult<Vec<String>> {
         self.get_test_dependencies_internal(test_name).map_err(|e| {
@@ -467,31 +916,32 @@ impl PytestDiffDatabase {
         })
     }
 
+    /// Checkpoint the WAL into the main database file and truncate it
+    ///
+    /// Long sessions with many writes grow the -wal file until the last
+    /// connection closes; calling this periodically keeps it compact.
+    fn checkpoint(&self) -> PyResult<()> {
+        self.checkpoint_internal().map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to checkpoint WAL: {}", e))
+        })
+    }
+
     /// Close the database and checkpoint WAL to remove -wal and -shm files
     fn close(&self) -> PyResult<()> {
-        let conn = self.conn.write();
-        // Checkpoint WAL to merge it into main database file
-        // TRUNCATE mode will truncate the WAL file to zero bytes
-        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
-            .map_err(|e| {
-                pyo3::exceptions::PyRuntimeError::new_err(format!(
-                    "Failed to checkpoint WAL: {}",
-                    e
-                ))
-            })?;
-        Ok(())
+        self.checkpoint()
     }
 }
 
 // Internal implementation methods
 impl PytestDiffDatabase {
-    fn save_test_execution_internal(
+    pub(crate) fn save_test_execution_internal(
         &mut self,
         test_name: &str,
         fingerprints: Vec<Fingerprint>,
         duration: f64,
         failed: bool,
         python_version: &str,
+        merge: bool,
     ) -> Result<()> {
         // Get or create environment
         let env_id = self.get_or_create_environment("default", python_version)?;
@@ -501,38 +951,135 @@ impl PytestDiffDatabase {
         // Use BEGIN IMMEDIATE for fail-fast on write conflicts (pytest-xdist compatibility)
         let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
 
+        let execution = TestExecution {
+            test_name: test_name.to_string(),
+            duration,
+            failed,
+            forced: false,
+            fingerprints,
+        };
+        self.save_execution_in_tx(&tx, env_id, execution, merge)?;
+
+        tx.commit().context("Failed to commit transaction")?;
+
+        Ok(())
+    }
+
+    fn save_test_executions_batch_internal(
+        &mut self,
+        executions: Vec<TestExecution>,
+        python_version: &str,
+    ) -> Result<usize> {
+        let env_id = self.get_or_create_environment("default", python_version)?;
+
+        let mut conn = self.conn.write();
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+
+        let count = executions.len();
+        for execution in executions {
+            self.save_execution_in_tx(&tx, env_id, execution, false)?;
+        }
+
+        tx.commit().context("Failed to commit transaction")?;
+
+        Ok(count)
+    }
+
+    /// Replace a test's execution in one environment and link its fingerprints
+    ///
+    /// With `merge`, the previous run's checksums are folded into the new
+    /// fingerprints and files the new run did not touch stay linked.
+    fn save_execution_in_tx(
+        &self,
+        tx: &rusqlite::Transaction,
+        env_id: i64,
+        execution: TestExecution,
+        merge: bool,
+    ) -> Result<()> {
+        let TestExecution {
+            test_name,
+            duration,
+            failed,
+            forced,
+            mut fingerprints,
+        } = execution;
+
+        // Fold the previous run's checksums into the new fingerprints; files the
+        // new run did not touch keep their previous fingerprint
+        let mut kept_fingerprint_ids = Vec::new();
+        if merge {
+            let mut previous: HashMap<String, (i64, Vec<i64>)> = HashMap::new();
+            {
+                let mut stmt = tx.prepare_cached(
+                    "SELECT fp.id, fp.filename, fp.method_checksums
+                     FROM test_execution te
+                     JOIN test_execution_file_fp teff ON te.id = teff.test_execution_id
+                     JOIN file_fp fp ON teff.fingerprint_id = fp.id
+                     WHERE te.environment_id = ?1 AND te.test_name = ?2",
+                )?;
+                let rows = stmt.query_map(params![env_id, &test_name], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                })?;
+                for row in rows {
+                    let (id, filename, blob) = row?;
+                    previous.insert(filename, (id, deserialize_checksums(&blob)));
+                }
+            }
+
+            for fp in &mut fingerprints {
+                if let Some((_, old_checksums)) = previous.remove(&fp.filename) {
+                    let seen: HashSet<i64> = fp.checksums.iter().copied().collect();
+                    fp.checksums.extend(
+                        old_checksums
+                            .into_iter()
+                            .filter(|checksum| !seen.contains(checksum)),
+                    );
+                }
+            }
+            kept_fingerprint_ids.extend(previous.into_values().map(|(id, _)| id));
+        }
+
         // Delete previous executions for this test in this environment
         // This keeps the database from growing unbounded
-        tx.execute(
+        tx.prepare_cached(
             "DELETE FROM test_execution
              WHERE environment_id = ?1 AND test_name = ?2",
-            params![env_id, test_name],
-        )
+        )?
+        .execute(params![env_id, &test_name])
         .context("Failed to delete old test execution")?;
 
         // Insert test execution
-        tx.execute(
-            "INSERT INTO test_execution (environment_id, test_name, duration, failed, forced)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![env_id, test_name, duration, if failed { 1 } else { 0 }, 0],
-        )
-        .context("Failed to insert test execution")?;
-
-        let test_execution_id = tx.last_insert_rowid();
+        let test_execution_id = tx
+            .prepare_cached(
+                "INSERT INTO test_execution (environment_id, test_name, duration, failed, forced)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .insert(params![
+                env_id,
+                &test_name,
+                duration,
+                failed as i32,
+                forced as i32
+            ])
+            .context("Failed to insert test execution")?;
 
         // Insert fingerprints and link to test
-        for fp in fingerprints {
-            let fp_id = self.get_or_create_fingerprint_in_tx(&tx, &fp)?;
-
-            tx.execute(
-                "INSERT INTO test_execution_file_fp (test_execution_id, fingerprint_id)
-                 VALUES (?1, ?2)",
-                params![test_execution_id, fp_id],
-            )
-            .context("Failed to link test to fingerprint")?;
+        let mut fingerprint_ids = kept_fingerprint_ids;
+        for fp in &fingerprints {
+            fingerprint_ids.push(self.get_or_create_fingerprint_in_tx(tx, fp)?);
+        }
+        let mut link = tx.prepare_cached(
+            "INSERT INTO test_execution_file_fp (test_execution_id, fingerprint_id)
+             VALUES (?1, ?2)",
+        )?;
+        for fp_id in fingerprint_ids {
+            link.execute(params![test_execution_id, fp_id])
+                .context("Failed to link test to fingerprint")?;
         }
-
-        tx.commit().context("Failed to commit transaction")?;
 
         Ok(())
     }
@@ -544,10 +1091,13 @@ impl PytestDiffDatabase {
     ) -> Result<i64> {
         let checksums_blob = serialize_checksums(&fp.checksums);
 
+        // Batches look up the same statements once per file, so keep them cached
         let existing_id: Option<i64> = tx
-            .query_row(
+            .prepare_cached(
                 "SELECT id FROM file_fp
                  WHERE filename = ?1 AND fsha = ?2 AND method_checksums = ?3",
+            )?
+            .query_row(
                 params![&fp.filename, &fp.file_hash, checksums_blob],
                 |row| row.get(0),
             )
@@ -560,26 +1110,102 @@ impl PytestDiffDatabase {
             // No exact match - insert new fingerprint
             // We always insert new fingerprints to maintain history
             // Change detection relies on comparing current state vs stored state
-            tx.execute(
-                "INSERT INTO file_fp (filename, method_checksums, mtime, fsha)
-                 VALUES (?1, ?2, ?3, ?4)",
-                params![&fp.filename, checksums_blob, fp.mtime, &fp.file_hash],
-            )?;
-            Ok(tx.last_insert_rowid())
+            Ok(tx
+                .prepare_cached(
+                    "INSERT INTO file_fp (filename, method_checksums, mtime, fsha)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?
+                .insert(params![
+                    &fp.filename,
+                    checksums_blob,
+                    fp.mtime,
+                    &fp.file_hash
+                ])?)
         }
     }
 
-    fn get_affected_tests_internal(
+    pub(crate) fn get_affected_tests_internal(
         &self,
-        changed_blocks: HashMap<String, Vec<i32>>,
+        changed_blocks: HashMap<String, Vec<i64>>,
     ) -> Result<Vec<String>> {
-        if changed_blocks.is_empty() {
-            return Ok(vec![]);
-        }
+        let affected_tests: HashSet<String> = self
+            .affected_matches(&changed_blocks)?
+            .into_keys()
+            .map(|(test_name, _)| test_name)
+            .collect();
 
-        let conn = self.conn.read();
+        // Convert HashSet to sorted Vec for consistent ordering
+        let mut result: Vec<String> = affected_tests.into_iter().collect();
+        result.sort();
 
-        // Build a single query for all changed files (more efficient than N queries)
+        Ok(result)
+    }
+
+    /// Files among `changed_blocks` whose changed checksums some recorded test depends on
+    pub(crate) fn referenced_files_internal(
+        &self,
+        changed_blocks: &HashMap<String, Vec<i64>>,
+    ) -> Result<HashSet<String>> {
+        Ok(self
+            .affected_matches(changed_blocks)?
+            .into_keys()
+            .map(|(_, filename)| filename)
+            .collect())
+    }
+
+    fn get_affected_tests_ranked_internal(
+        &self,
+        changed_blocks: &HashMap<String, Vec<i64>>,
+    ) -> Result<Vec<(String, usize)>> {
+        let mut weights: HashMap<String, usize> = HashMap::new();
+        for ((test_name, _), checksums) in self.affected_matches(changed_blocks)? {
+            *weights.entry(test_name).or_default() += checksums.len();
+        }
+        if weights.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Latest outcome per test: later executions overwrite earlier ones
+        let mut last_failed: HashMap<String, bool> = HashMap::new();
+        {
+            let conn = self.conn.read();
+            let mut stmt =
+                conn.prepare("SELECT test_name, failed FROM test_execution ORDER BY id")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? != 0))
+            })?;
+            for row in rows {
+                let (test_name, failed) = row?;
+                last_failed.insert(test_name, failed);
+            }
+        }
+
+        let mut ranked: Vec<(String, usize)> = weights.into_iter().collect();
+        ranked.sort_by(|(a_name, a_weight), (b_name, b_weight)| {
+            let a_failed = last_failed.get(a_name).copied().unwrap_or(false);
+            let b_failed = last_failed.get(b_name).copied().unwrap_or(false);
+            b_weight
+                .cmp(a_weight)
+                .then(b_failed.cmp(&a_failed))
+                .then(a_name.cmp(b_name))
+        });
+        Ok(ranked)
+    }
+
+    /// Changed checksums of each file that a test used, keyed by `(test_name, filename)`
+    ///
+    /// Only pairs with at least one matching checksum are returned.
+    fn affected_matches(
+        &self,
+        changed_blocks: &HashMap<String, Vec<i64>>,
+    ) -> Result<HashMap<(String, String), HashSet<i64>>> {
+        if changed_blocks.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let conn = self.conn.read();
+
+        // Build a single query for all changed files (more efficient than N queries)
         let filenames: Vec<&str> = changed_blocks.keys().map(|s| s.as_str()).collect();
 
         // Create placeholders for IN clause: (?1, ?2, ?3, ...)
@@ -606,15 +1232,15 @@ impl PytestDiffDatabase {
             .collect();
 
         // Pre-compute changed checksums as HashSets for O(1) lookup
-        let changed_checksum_sets: HashMap<&str, HashSet<i32>> = changed_blocks
+        let changed_checksum_sets: HashMap<&str, HashSet<i64>> = changed_blocks
             .iter()
             .map(|(filename, checksums)| (filename.as_str(), checksums.iter().copied().collect()))
             .collect();
 
         // Cache deserialized blobs to avoid re-deserializing the same blob
-        let mut blob_cache: HashMap<Vec<u8>, Vec<i32>> = HashMap::new();
+        let mut blob_cache: HashMap<Vec<u8>, Vec<i64>> = HashMap::new();
 
-        let mut affected_tests: HashSet<String> = HashSet::new();
+        let mut affected: HashMap<(String, String), HashSet<i64>> = HashMap::new();
 
         let rows = stmt.query_map(params.as_slice(), |row| {
             let test_name: String = row.get(0)?;
@@ -631,19 +1257,23 @@ impl PytestDiffDatabase {
                 .entry(blob.clone())
                 .or_insert_with(|| deserialize_checksums(&blob));
 
-            // Check if any changed checksum for this file matches
+            // Collect the changed checksums of this file that the test used
             if let Some(changed_set) = changed_checksum_sets.get(filename.as_str()) {
-                if file_checksums.iter().any(|c| changed_set.contains(c)) {
-                    affected_tests.insert(test_name);
+                let matched: Vec<i64> = file_checksums
+                    .iter()
+                    .copied()
+                    .filter(|c| changed_set.contains(c))
+                    .collect();
+                if !matched.is_empty() {
+                    affected
+                        .entry((test_name, filename))
+                        .or_default()
+                        .extend(matched);
                 }
             }
         }
 
-        // Convert HashSet to sorted Vec for consistent ordering
-        let mut result: Vec<String> = affected_tests.into_iter().collect();
-        result.sort();
-
-        Ok(result)
+        Ok(affected)
     }
 
     fn get_recorded_tests_internal(&self) -> Result<Vec<String>> {
@@ -655,6 +1285,234 @@ impl PytestDiffDatabase {
         Ok(tests)
     }
 
+    fn clear_baseline_internal(&mut self, scope_paths: &[String]) -> Result<usize> {
+        let mut conn = self.conn.write();
+        if scope_paths.is_empty() {
+            return Ok(conn.execute("DELETE FROM baseline_fp", [])?);
+        }
+
+        let tx = conn.transaction()?;
+        let mut removed = 0;
+        for scope in scope_paths {
+            let scope = scope.replace('\\', "/");
+            let scope = scope.trim_end_matches('/');
+            // The file itself, or anything in the directory it names
+            removed += tx.execute(
+                "DELETE FROM baseline_fp
+                 WHERE filename = ?1 OR substr(filename, 1, length(?2)) = ?2",
+                params![scope, format!("{}/", scope)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    fn tests_for_file_internal(&self, filename: &str) -> Result<Vec<String>> {
+        let conn = self.conn.read();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT te.test_name
+             FROM test_execution te
+             JOIN test_execution_file_fp teff ON te.id = teff.test_execution_id
+             JOIN file_fp fp ON teff.fingerprint_id = fp.id
+             WHERE fp.filename = ?1
+             ORDER BY te.test_name",
+        )?;
+        let rows = stmt.query_map(params![filename], |row| row.get(0))?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Tests whose recorded checksums for `filename` include the named block,
+    /// or `None` when the baseline has no block with that name
+    fn tests_for_block_internal(
+        &self,
+        filename: &str,
+        qualified_name: &str,
+    ) -> Result<Option<Vec<String>>> {
+        let conn = self.conn.read();
+
+        let block_names: Option<String> = conn
+            .query_row(
+                "SELECT block_names FROM baseline_fp WHERE filename = ?1",
+                params![filename],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        let Some(block_names) = block_names else {
+            return Ok(None);
+        };
+        let block_names: Vec<(String, i64)> =
+            serde_json::from_str(&block_names).context("Invalid block names in baseline")?;
+        let checksums: HashSet<i64> = block_names
+            .into_iter()
+            .filter(|(name, _)| name == qualified_name)
+            .map(|(_, checksum)| checksum)
+            .collect();
+        if checksums.is_empty() {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT te.test_name, fp.method_checksums
+             FROM test_execution te
+             JOIN test_execution_file_fp teff ON te.id = teff.test_execution_id
+             JOIN file_fp fp ON teff.fingerprint_id = fp.id
+             WHERE fp.filename = ?1",
+        )?;
+        let rows = stmt.query_map(params![filename], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+
+        let mut tests = Vec::new();
+        for row in rows {
+            let (test_name, blob) = row?;
+            if deserialize_checksums(&blob)
+                .iter()
+                .any(|checksum| checksums.contains(checksum))
+            {
+                tests.push(test_name);
+            }
+        }
+        tests.sort();
+        tests.dedup();
+        Ok(Some(tests))
+    }
+
+    fn get_test_stats_internal(&self, test_name: &str) -> Result<Option<(f64, bool)>> {
+        let conn = self.conn.read();
+        conn.query_row(
+            "SELECT duration, failed FROM test_execution
+             WHERE test_name = ?1
+             ORDER BY id DESC
+             LIMIT 1",
+            params![test_name],
+            |row| Ok((row.get::<_, f64>(0)?, row.get::<_, i64>(1)? != 0)),
+        )
+        .optional()
+        .context("Failed to query test stats")
+    }
+
+    pub(crate) fn record_outcome_internal(&self, test_name: &str, outcome: &str) -> Result<()> {
+        if !OUTCOMES.contains(&outcome) {
+            anyhow::bail!("unknown test outcome {:?}", outcome);
+        }
+        let mut conn = self.conn.write();
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT INTO test_outcome (test_name, outcome) VALUES (?1, ?2)",
+            params![test_name, outcome],
+        )
+        .context("Failed to insert test outcome")?;
+        // Keep the history bounded: drop everything older than the newest N rows
+        tx.execute(
+            "DELETE FROM test_outcome
+             WHERE test_name = ?1 AND id NOT IN (
+                 SELECT id FROM test_outcome WHERE test_name = ?1
+                 ORDER BY id DESC LIMIT ?2
+             )",
+            params![test_name, OUTCOME_HISTORY_LEN],
+        )
+        .context("Failed to trim test outcome history")?;
+        tx.commit().context("Failed to commit transaction")?;
+        Ok(())
+    }
+
+    pub(crate) fn outcome_history_internal(&self, test_name: &str) -> Result<Vec<String>> {
+        let conn = self.conn.read();
+        let mut stmt = conn
+            .prepare_cached("SELECT outcome FROM test_outcome WHERE test_name = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![test_name], |row| row.get(0))?;
+        let outcomes: Vec<String> = rows.collect::<std::result::Result<_, _>>()?;
+        Ok(outcomes)
+    }
+
+    pub(crate) fn flakiness_score_internal(&self, test_name: &str) -> Result<f64> {
+        let results: Vec<bool> = self
+            .outcome_history_internal(test_name)?
+            .iter()
+            .filter(|outcome| *outcome != "skipped")
+            .map(|outcome| outcome == "failed")
+            .collect();
+        if results.len() < 2 {
+            return Ok(0.0);
+        }
+        let flips = results.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        Ok(flips as f64 / (results.len() - 1) as f64)
+    }
+
+    fn delete_test_internal(&mut self, test_name: &str) -> Result<usize> {
+        let mut conn = self.conn.write();
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let mappings = Self::delete_test_in_tx(&tx, test_name)?;
+        tx.commit().context("Failed to commit transaction")?;
+        Ok(mappings)
+    }
+
+    fn prune_missing_tests_internal(&mut self, known: Vec<String>) -> Result<usize> {
+        let known: HashSet<String> = known.into_iter().collect();
+        let stale: Vec<String> = self
+            .get_recorded_tests_internal()?
+            .into_iter()
+            .filter(|name| !known.contains(name))
+            .collect();
+
+        let mut conn = self.conn.write();
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        for name in &stale {
+            Self::delete_test_in_tx(&tx, name)?;
+        }
+        tx.commit().context("Failed to commit transaction")?;
+        Ok(stale.len())
+    }
+
+    /// Delete a test's executions and their fingerprint mappings, returning the mapping count
+    fn delete_test_in_tx(tx: &rusqlite::Transaction, test_name: &str) -> Result<usize> {
+        let mappings = tx
+            .execute(
+                "DELETE FROM test_execution_file_fp
+                 WHERE test_execution_id IN (SELECT id FROM test_execution WHERE test_name = ?1)",
+                params![test_name],
+            )
+            .context("Failed to delete test mappings")?;
+        tx.execute(
+            "DELETE FROM test_execution WHERE test_name = ?1",
+            params![test_name],
+        )
+        .context("Failed to delete test execution")?;
+        tx.execute(
+            "DELETE FROM test_outcome WHERE test_name = ?1",
+            params![test_name],
+        )
+        .context("Failed to delete test outcomes")?;
+        Ok(mappings)
+    }
+
+    fn list_tests_internal(&self, python_version: Option<&str>) -> Result<Vec<String>> {
+        let Some(python_version) = python_version else {
+            return self.get_recorded_tests_internal();
+        };
+        let conn = self.conn.read();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT te.test_name
+             FROM test_execution te
+             JOIN environment e ON e.id = te.environment_id
+             WHERE e.python_version = ?1
+             ORDER BY te.test_name",
+        )?;
+        let rows = stmt.query_map([python_version], |row| row.get(0))?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    fn test_count_internal(&self) -> Result<usize> {
+        let conn = self.conn.read();
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(DISTINCT test_name) FROM test_execution",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
     fn get_stats_internal(&self) -> Result<HashMap<String, i64>> {
         let conn = self.conn.read();
         let mut stats = HashMap::new();
@@ -683,15 +1541,99 @@ impl PytestDiffDatabase {
         Ok(stats)
     }
 
+    pub(crate) fn vacuum_internal(&mut self) -> Result<u64> {
+        let conn = self.conn.write();
+        let size_before = Self::database_size(&conn)?;
+
+        conn.execute(
+            "DELETE FROM file_fp
+             WHERE id NOT IN (SELECT fingerprint_id FROM test_execution_file_fp)",
+            [],
+        )
+        .context("Failed to delete orphaned fingerprints")?;
+        conn.execute_batch("VACUUM;")
+            .context("Failed to run VACUUM")?;
+
+        let size_after = Self::database_size(&conn)?;
+        drop(conn);
+        self.clear_fingerprint_cache();
+
+        Ok(size_before.saturating_sub(size_after))
+    }
+
+    pub(crate) fn integrity_problems_internal(&self) -> Result<Vec<String>> {
+        let conn = self.conn.read();
+        let mut problems = Vec::new();
+
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        for row in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let message = row?;
+            if message != "ok" {
+                problems.push(message);
+            }
+        }
+
+        let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+        let violations = stmt.query_map([], |row| {
+            Ok(format!(
+                "foreign key violation in {} (rowid {:?}) referencing {}",
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, String>(2)?
+            ))
+        })?;
+        for violation in violations {
+            problems.push(violation?);
+        }
+
+        Ok(problems)
+    }
+
+    pub(crate) fn reset_internal(&mut self) -> Result<()> {
+        let conn = self.conn.write();
+
+        // SQLite's documented way to empty a database, which also works when the
+        // file is too damaged for DROP TABLE
+        conn.set_db_config(DbConfig::SQLITE_DBCONFIG_RESET_DATABASE, true)?;
+        let vacuumed = conn.execute_batch("VACUUM;");
+        conn.set_db_config(DbConfig::SQLITE_DBCONFIG_RESET_DATABASE, false)?;
+        vacuumed.context("Failed to reset database file")?;
+
+        Self::run_migrations(&conn, MIGRATIONS)?;
+        drop(conn);
+
+        self.clear_fingerprint_cache();
+        *self.current_environment_id.write() = None;
+        *self.last_integrity_error.write() = None;
+        Ok(())
+    }
+
+    /// Logical database size in bytes (page_count * page_size)
+    fn database_size(conn: &Connection) -> Result<u64> {
+        let page_count: u64 = conn.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let page_size: u64 = conn.pragma_query_value(None, "page_size", |row| row.get(0))?;
+        Ok(page_count * page_size)
+    }
+
     pub fn save_baseline_fingerprint_internal(&mut self, fp: Fingerprint) -> Result<()> {
         let conn = self.conn.write();
         let checksums_blob = serialize_checksums(&fp.checksums);
 
         // Use INSERT OR REPLACE to update existing baseline
+        let (mtime_secs, mtime_nanos) = fp.mtime_exact.unzip();
         conn.execute(
-            "INSERT OR REPLACE INTO baseline_fp (filename, method_checksums, mtime, fsha)
-             VALUES (?1, ?2, ?3, ?4)",
-            params![&fp.filename, checksums_blob, fp.mtime, &fp.file_hash],
+            "INSERT OR REPLACE INTO baseline_fp
+                 (filename, method_checksums, mtime, fsha, mtime_secs, mtime_nanos, block_names)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                &fp.filename,
+                checksums_blob,
+                fp.mtime,
+                &fp.file_hash,
+                mtime_secs,
+                mtime_nanos,
+                block_names_json(&fp)
+            ],
         )
         .context("Failed to save baseline fingerprint")?;
 
@@ -712,10 +1654,30 @@ impl PytestDiffDatabase {
         for fp in fingerprints {
             let checksums_blob = serialize_checksums(&fp.checksums);
 
+            let (mtime_secs, mtime_nanos) = fp.mtime_exact.unzip();
+            // Upsert: updates the existing row in place, so saving the same file
+            // twice (or re-baselining) leaves one row per file
             tx.execute(
-                "INSERT OR REPLACE INTO baseline_fp (filename, method_checksums, mtime, fsha)
-                 VALUES (?1, ?2, ?3, ?4)",
-                params![&fp.filename, checksums_blob, fp.mtime, &fp.file_hash],
+                "INSERT INTO baseline_fp
+                     (filename, method_checksums, mtime, fsha, mtime_secs, mtime_nanos, block_names)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(filename) DO UPDATE SET
+                     method_checksums = excluded.method_checksums,
+                     mtime = excluded.mtime,
+                     fsha = excluded.fsha,
+                     mtime_secs = excluded.mtime_secs,
+                     mtime_nanos = excluded.mtime_nanos,
+                     block_names = excluded.block_names,
+                     created_at = CURRENT_TIMESTAMP",
+                params![
+                    &fp.filename,
+                    checksums_blob,
+                    fp.mtime,
+                    &fp.file_hash,
+                    mtime_secs,
+                    mtime_nanos,
+                    block_names_json(&fp)
+                ],
             )
             .context("Failed to save baseline fingerprint in batch")?;
 
@@ -728,6 +1690,30 @@ impl PytestDiffDatabase {
         Ok(count)
     }
 
+    /// Set the stored mtime of baseline files whose content is unchanged
+    ///
+    /// `mtimes` holds `(filename, (seconds, nanoseconds))` pairs; hashes and
+    /// checksums are left as they are. Returns the number of rows updated.
+    pub(crate) fn update_baseline_mtimes(&self, mtimes: &[(String, (u64, u32))]) -> Result<usize> {
+        let mut conn = self.conn.write();
+        let tx = conn.transaction()?;
+        let mut count = 0;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE baseline_fp SET mtime = ?2, mtime_secs = ?3, mtime_nanos = ?4
+                 WHERE filename = ?1",
+            )?;
+            for (filename, (secs, nanos)) in mtimes {
+                let mtime = std::time::Duration::new(*secs, *nanos).as_secs_f64();
+                count += stmt
+                    .execute(params![filename, mtime, secs, nanos])
+                    .context("Failed to update baseline mtime")?;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
     /// Check if a table exists in the attached source database.
     /// Used for backward compatibility with older databases that may not have
     /// test execution tables.
@@ -1120,7 +2106,7 @@ impl PytestDiffDatabase {
         result
     }
 
-    fn set_metadata_internal(&self, key: &str, value: &str) -> Result<()> {
+    pub(crate) fn set_metadata_internal(&self, key: &str, value: &str) -> Result<()> {
         let conn = self.conn.write();
         conn.execute(
             "INSERT OR REPLACE INTO metadata (dataid, data) VALUES (?1, ?2)",
@@ -1130,7 +2116,7 @@ impl PytestDiffDatabase {
         Ok(())
     }
 
-    fn get_metadata_internal(&self, key: &str) -> Result<Option<String>> {
+    pub(crate) fn get_metadata_internal(&self, key: &str) -> Result<Option<String>> {
         let conn = self.conn.read();
         conn.query_row(
             "SELECT data FROM metadata WHERE dataid = ?1",
@@ -1141,6 +2127,87 @@ impl PytestDiffDatabase {
         .context("Failed to get metadata")
     }
 
+    pub(crate) fn list_baseline_files_internal(&self) -> Result<Vec<String>> {
+        let conn = self.conn.read();
+        let mut stmt = conn.prepare("SELECT filename FROM baseline_fp ORDER BY filename")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        let files: Vec<String> = rows.collect::<std::result::Result<_, _>>()?;
+        Ok(files)
+    }
+
+    pub(crate) fn is_in_memory_internal(&self) -> bool {
+        self.conn.read().path().is_none_or(str::is_empty)
+    }
+
+    pub(crate) fn dump_to_internal(&self, path: &str) -> Result<()> {
+        let target = Path::new(path);
+        if target.exists() {
+            anyhow::bail!("{} already exists", path);
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        self.conn
+            .read()
+            .execute("VACUUM INTO ?1", params![path])
+            .with_context(|| format!("Failed to write database copy to {}", path))?;
+        Ok(())
+    }
+
+    pub(crate) fn diff_baselines_internal(&self, other: &Self) -> Result<ChangedFiles> {
+        let old = self.get_all_baseline_fingerprints()?;
+        let new = other.get_all_baseline_fingerprints()?;
+
+        let mut modified = Vec::new();
+        let mut deleted = Vec::new();
+        let mut changed_blocks = HashMap::new();
+        for (filename, old_fp) in &old {
+            let removed: Vec<i64> = match new.get(filename) {
+                Some(new_fp) if new_fp.file_hash == old_fp.file_hash => continue,
+                Some(new_fp) => {
+                    modified.push(filename.clone());
+                    let kept: HashSet<i64> = new_fp.checksums.iter().copied().collect();
+                    old_fp
+                        .checksums
+                        .iter()
+                        .copied()
+                        .filter(|checksum| !kept.contains(checksum))
+                        .collect()
+                }
+                None => {
+                    deleted.push(filename.clone());
+                    old_fp.checksums.clone()
+                }
+            };
+            if !removed.is_empty() {
+                changed_blocks.insert(filename.clone(), removed);
+            }
+        }
+        let mut added: Vec<String> = new
+            .keys()
+            .filter(|filename| !old.contains_key(*filename))
+            .cloned()
+            .collect();
+        modified.sort();
+        deleted.sort();
+        added.sort();
+
+        Ok(ChangedFiles {
+            modified,
+            changed_blocks,
+            deleted,
+            added,
+            renamed: Vec::new(),
+            conftest_affected: Vec::new(),
+            errored: Vec::new(),
+            baseline_mismatch: false,
+            warnings: Vec::new(),
+            unreferenced_changes: Vec::new(),
+            run_all: false,
+        })
+    }
+
     fn get_test_dependencies_internal(&self, test_name: &str) -> Result<Vec<String>> {
         let conn = self.conn.read();
         let mut stmt = conn.prepare(
@@ -1175,7 +2242,7 @@ impl PytestDiffDatabase {
         let conn = self.conn.read();
 
         conn.query_row(
-            "SELECT filename, method_checksums, mtime, fsha
+            "SELECT filename, method_checksums, mtime, fsha, mtime_secs, mtime_nanos
              FROM baseline_fp
              WHERE filename = ?1",
             params![filename],
@@ -1187,6 +2254,7 @@ impl PytestDiffDatabase {
                     filename: row.get(0)?,
                     checksums,
                     mtime: row.get(2)?,
+                    mtime_exact: read_mtime_exact(row, 4)?,
                     file_hash: row.get(3)?,
                     blocks: None,
                 })
@@ -1196,14 +2264,55 @@ impl PytestDiffDatabase {
         .context("Failed to query baseline fingerprint")
     }
 
+    /// Get the baseline fingerprints of `filenames`, keyed by filename
+    ///
+    /// One `IN (...)` query per `LOOKUP_CHUNK_SIZE` filenames instead of one
+    /// query per file. Filenames without a baseline are left out.
+    pub fn get_baseline_fingerprints_batch_internal(
+        &self,
+        filenames: &[String],
+    ) -> Result<HashMap<String, Fingerprint>> {
+        let conn = self.conn.read();
+        let mut fingerprints = HashMap::with_capacity(filenames.len());
+
+        for chunk in filenames.chunks(LOOKUP_CHUNK_SIZE) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT filename, method_checksums, mtime, fsha, mtime_secs, mtime_nanos
+                 FROM baseline_fp
+                 WHERE filename IN ({})",
+                placeholders
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(chunk), |row| {
+                let checksums_blob: Vec<u8> = row.get(1)?;
+                Ok(Fingerprint {
+                    filename: row.get(0)?,
+                    checksums: deserialize_checksums(&checksums_blob),
+                    mtime: row.get(2)?,
+                    mtime_exact: read_mtime_exact(row, 4)?,
+                    file_hash: row.get(3)?,
+                    blocks: None,
+                })
+            })?;
+            for fingerprint in rows {
+                let fingerprint = fingerprint.context("Failed to read baseline fingerprint")?;
+                fingerprints.insert(fingerprint.filename.clone(), fingerprint);
+            }
+        }
+
+        Ok(fingerprints)
+    }
+
     /// Get all baseline fingerprints in a single query
     ///
     /// Returns a HashMap of filename -> Fingerprint for efficient lookup
     pub fn get_all_baseline_fingerprints(&self) -> Result<HashMap<String, Fingerprint>> {
         let conn = self.conn.read();
 
-        let mut stmt =
-            conn.prepare("SELECT filename, method_checksums, mtime, fsha FROM baseline_fp")?;
+        let mut stmt = conn.prepare(
+            "SELECT filename, method_checksums, mtime, fsha, mtime_secs, mtime_nanos
+             FROM baseline_fp",
+        )?;
 
         let fingerprints = stmt
             .query_map([], |row| {
@@ -1217,6 +2326,7 @@ impl PytestDiffDatabase {
                         filename,
                         checksums,
                         mtime: row.get(2)?,
+                        mtime_exact: read_mtime_exact(row, 4)?,
                         file_hash: row.get(3)?,
                         blocks: None,
                     },
@@ -1227,17 +2337,100 @@ impl PytestDiffDatabase {
 
         Ok(fingerprints)
     }
+
+    /// Baseline fingerprints stored in the database file at `path`, without writing to it
+    ///
+    /// The file is opened read-only and immutable, so no schema migration runs and
+    /// no WAL or shared-memory file is created. Only columns every schema version
+    /// has are read, so `mtime_exact` is left empty. A missing file has no baseline.
+    pub(crate) fn read_baseline_fingerprints(path: &str) -> Result<HashMap<String, Fingerprint>> {
+        if !Path::new(path).exists() {
+            return Ok(HashMap::new());
+        }
+
+        // `?`, `#` and `%` would otherwise be read as URI syntax
+        let escaped = path
+            .replace('%', "%25")
+            .replace('?', "%3f")
+            .replace('#', "%23");
+        let conn = Connection::open_with_flags(
+            format!("file:{}?immutable=1", escaped),
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_URI,
+        )
+        .with_context(|| format!("Failed to open database read-only: {}", path))?;
+
+        let mut stmt =
+            conn.prepare("SELECT filename, method_checksums, mtime, fsha FROM baseline_fp")?;
+        let fingerprints = stmt
+            .query_map([], |row| {
+                let filename: String = row.get(0)?;
+                Ok((
+                    filename.clone(),
+                    Fingerprint {
+                        filename,
+                        checksums: deserialize_checksums(&row.get::<_, Vec<u8>>(1)?),
+                        mtime: row.get(2)?,
+                        mtime_exact: None,
+                        file_hash: row.get(3)?,
+                        blocks: None,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(fingerprints)
+    }
 }
 
-/// Serialize checksums (Vec<i32>) to blob
-fn serialize_checksums(checksums: &[i32]) -> Vec<u8> {
-    checksums.iter().flat_map(|c| c.to_le_bytes()).collect()
+/// Read the nullable `(mtime_secs, mtime_nanos)` column pair starting at `idx`
+fn read_mtime_exact(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<Option<(u64, u32)>> {
+    let secs: Option<u64> = row.get(idx)?;
+    let nanos: Option<u32> = row.get(idx + 1)?;
+    Ok(secs.zip(nanos))
 }
 
-/// Deserialize checksums from blob to Vec<i32>
-fn deserialize_checksums(blob: &[u8]) -> Vec<i32> {
-    blob.chunks_exact(4)
-        .map(|chunk| i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+/// Leading byte of a checksum blob holding 8-byte entries
+///
+/// Blobs of 4-byte entries have a length that is a multiple of 4, while a
+/// marked blob has length `1 + 8n`, so the two layouts can't be confused.
+const WIDE_CHECKSUMS_MARKER: u8 = 0x08;
+
+/// Serialize checksums (Vec<i64>) to blob
+///
+/// CRC32 checksums fit in an i32 and are stored as 4-byte little-endian
+/// entries, the testmon layout. Blobs holding any wider checksum (xxHash64)
+/// start with `WIDE_CHECKSUMS_MARKER` followed by 8-byte entries.
+fn serialize_checksums(checksums: &[i64]) -> Vec<u8> {
+    if checksums.iter().all(|&c| i32::try_from(c).is_ok()) {
+        return checksums
+            .iter()
+            .flat_map(|&c| (c as i32).to_le_bytes())
+            .collect();
+    }
+    std::iter::once(WIDE_CHECKSUMS_MARKER)
+        .chain(checksums.iter().flat_map(|c| c.to_le_bytes()))
+        .collect()
+}
+
+/// `[qualified_name, checksum]` pairs of a fingerprint's blocks as JSON, if it has blocks
+fn block_names_json(fp: &Fingerprint) -> Option<String> {
+    let blocks = fp.blocks.as_ref()?;
+    let pairs: Vec<(&str, i64)> = blocks
+        .iter()
+        .map(|b| (b.qualified_name.as_str(), b.checksum))
+        .collect();
+    serde_json::to_string(&pairs).ok()
+}
+
+/// Deserialize checksums from blob to Vec<i64>
+fn deserialize_checksums(blob: &[u8]) -> Vec<i64> {
+    if blob.len() % 4 == 1 && blob[0] == WIDE_CHECKSUMS_MARKER {
+        return blob[1..]
+            .chunks_exact(8)
+            .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+    }
+    blob.chunks_exact(4)
+        .map(|chunk| i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as i64)
         .collect()
 }
 
@@ -1253,6 +2446,323 @@ mod tests {
         assert!(db.is_ok());
     }
 
+    fn baseline_round_trip(db: &mut PytestDiffDatabase) {
+        let fps = vec![
+            Fingerprint {
+                filename: "src/a.py".to_string(),
+                checksums: vec![1, -2, 3],
+                file_hash: "hash_a".to_string(),
+                mtime: 10.5,
+                mtime_exact: None,
+                blocks: None,
+            },
+            Fingerprint {
+                filename: "src/b.py".to_string(),
+                checksums: vec![],
+                file_hash: "hash_b".to_string(),
+                mtime: 20.0,
+                mtime_exact: None,
+                blocks: None,
+            },
+        ];
+        db.save_baseline_fingerprints_batch(fps).unwrap();
+
+        let a = db
+            .get_baseline_fingerprint_rust("src/a.py")
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.checksums, vec![1, -2, 3]);
+        assert_eq!(a.file_hash, "hash_a");
+        assert_eq!(a.mtime, 10.5);
+        assert!(db
+            .get_baseline_fingerprint_rust("missing.py")
+            .unwrap()
+            .is_none());
+
+        let all = db.get_all_baseline_fingerprints().unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all["src/b.py"].checksums.is_empty());
+    }
+
+    #[test]
+    fn test_baseline_round_trip_in_memory() {
+        let mut db = PytestDiffDatabase::open(":memory:").unwrap();
+        baseline_round_trip(&mut db);
+    }
+
+    #[test]
+    fn test_baseline_round_trip_tempfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.db");
+        let path = path.to_str().unwrap();
+
+        let mut db = PytestDiffDatabase::open(path).unwrap();
+        {
+            let conn = db.conn.read();
+            let mode: String = conn
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap();
+            let synchronous: i64 = conn
+                .query_row("PRAGMA synchronous", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(mode, "wal");
+            assert_eq!(synchronous, 1); // NORMAL
+        }
+        baseline_round_trip(&mut db);
+        db.close_and_checkpoint().unwrap();
+        drop(db);
+
+        // Data survives reopening the file
+        let reopened = PytestDiffDatabase::open(path).unwrap();
+        assert_eq!(reopened.get_all_baseline_fingerprints().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_checkpoint_truncates_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.db");
+        let wal_path = dir.path().join("wal.db-wal");
+
+        let db = PytestDiffDatabase::open(path.to_str().unwrap()).unwrap();
+        for i in 0..200 {
+            db.get_or_create_fingerprint(&Fingerprint {
+                filename: format!("file_{}.py", i),
+                checksums: (0..50).collect(),
+                file_hash: format!("hash_{}", i),
+                mtime: 1.0,
+                mtime_exact: None,
+                blocks: None,
+            })
+            .unwrap();
+        }
+        assert!(std::fs::metadata(&wal_path).unwrap().len() > 0);
+
+        db.checkpoint_internal().unwrap();
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+
+        // Writes after a checkpoint still land and are readable
+        db.set_metadata_internal("key", "value").unwrap();
+        assert_eq!(
+            db.get_metadata_internal("key").unwrap().as_deref(),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn test_new_database_has_current_schema_version() {
+        let db = PytestDiffDatabase::open(":memory:").unwrap();
+        assert_eq!(db.schema_version_internal().unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migration_preserves_existing_data() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let path = temp_db.path().to_str().unwrap();
+
+        // A v1 database with a baseline entry
+        let mut db = PytestDiffDatabase::open(path).unwrap();
+        db.save_baseline_fingerprint_internal(Fingerprint {
+            filename: "module.py".to_string(),
+            checksums: vec![7, 8],
+            file_hash: "hash".to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        })
+        .unwrap();
+        drop(db);
+
+        // Upgrade with an extra migration on top of the current ones
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push("ALTER TABLE baseline_fp ADD COLUMN note TEXT;");
+        let conn = Connection::open(path).unwrap();
+        PytestDiffDatabase::run_migrations(&conn, &migrations).unwrap();
+
+        let version: i64 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION + 1);
+        let (checksums, note): (Vec<u8>, Option<String>) = conn
+            .query_row(
+                "SELECT method_checksums, note FROM baseline_fp WHERE filename = 'module.py'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(deserialize_checksums(&checksums), vec![7, 8]);
+        assert!(note.is_none());
+
+        // Running again is a no-op
+        PytestDiffDatabase::run_migrations(&conn, &migrations).unwrap();
+    }
+
+    #[test]
+    fn test_unversioned_database_is_migrated() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let path = temp_db.path().to_str().unwrap();
+
+        // Databases created before versioning have the tables but user_version = 0
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(include_str!("schema.sql")).unwrap();
+        conn.execute(
+            "INSERT INTO metadata (dataid, data) VALUES ('commit', 'abc')",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = PytestDiffDatabase::open(path).unwrap();
+        assert_eq!(db.schema_version_internal().unwrap(), SCHEMA_VERSION);
+        assert_eq!(
+            db.get_metadata_internal("commit").unwrap(),
+            Some("abc".to_string())
+        );
+    }
+
+    #[test]
+    fn test_newer_schema_version_is_rejected() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let path = temp_db.path().to_str().unwrap();
+        let conn = Connection::open(path).unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        drop(conn);
+
+        let err = PytestDiffDatabase::open(path).err().unwrap();
+        let version_err = err.downcast_ref::<SchemaVersionError>().unwrap();
+        assert_eq!(version_err.found, SCHEMA_VERSION + 1);
+        assert_eq!(version_err.supported, SCHEMA_VERSION);
+        assert!(err.to_string().contains("newer than the supported version"));
+    }
+
+    #[test]
+    fn test_get_fingerprint_is_cached_until_cleared() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        let fp = Fingerprint {
+            filename: "cached.py".to_string(),
+            checksums: vec![123, 456],
+            file_hash: "abc".to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+        db.save_test_execution_internal("test_cached", vec![fp.clone()], 0.1, false, "3.12", false)
+            .unwrap();
+
+        assert!(db.get_fingerprint_internal("missing.py").unwrap().is_none());
+        for _ in 0..2 {
+            let cached = db.get_fingerprint_internal("cached.py").unwrap().unwrap();
+            assert_eq!(cached.checksums, vec![123, 456]);
+        }
+        // The cache tracks the entry's size against its byte budget
+        assert_eq!(
+            db.fingerprint_cache.read().bytes(),
+            approx_fingerprint_bytes(&fp)
+        );
+        assert_eq!(
+            db.cache_counters.snapshot(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                insertions: 1,
+                evictions: 0,
+            }
+        );
+
+        db.clear_fingerprint_cache();
+        assert_eq!(db.cache_counters.snapshot(), CacheStats::default());
+        assert_eq!(db.fingerprint_cache.read().len(), 0);
+    }
+
+    #[test]
+    fn test_vacuum_removes_orphaned_fingerprints() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+
+        let fp = Fingerprint {
+            filename: "used.py".to_string(),
+            checksums: vec![1, 2],
+            file_hash: "used".to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+        db.save_test_execution_internal("test_used", vec![fp], 0.1, false, "3.12", false)
+            .unwrap();
+        // Many orphaned fingerprints, e.g. left behind by older file versions
+        for i in 0..500 {
+            db.get_or_create_fingerprint(&Fingerprint {
+                filename: format!("stale_{}.py", i),
+                checksums: (0..100).collect(),
+                file_hash: format!("stale_{}", i),
+                mtime: 1.0,
+                mtime_exact: None,
+                blocks: None,
+            })
+            .unwrap();
+        }
+
+        let reclaimed = db.vacuum_internal().unwrap();
+
+        let stats = db.get_stats_internal().unwrap();
+        assert_eq!(stats["fingerprint_count"], 1);
+        assert_eq!(stats["test_count"], 1);
+        assert!(reclaimed > 0);
+
+        let mut changed = HashMap::new();
+        changed.insert("used.py".to_string(), vec![2]);
+        assert_eq!(
+            db.get_affected_tests_internal(changed).unwrap(),
+            vec!["test_used"]
+        );
+    }
+
+    #[test]
+    fn test_integrity_check_healthy_database() {
+        let db = PytestDiffDatabase::open(":memory:").unwrap();
+        assert!(db.integrity_check());
+        assert!(db.last_integrity_error().is_none());
+    }
+
+    #[test]
+    fn test_integrity_check_detects_corruption() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let temp_db = NamedTempFile::new().unwrap();
+        let path = temp_db.path().to_str().unwrap();
+
+        let mut db = PytestDiffDatabase::open(path).unwrap();
+        let fps = (0..200)
+            .map(|i| Fingerprint {
+                filename: format!("module_{}.py", i),
+                checksums: (0..50).collect(),
+                file_hash: format!("hash_{}", i),
+                mtime: 1.0,
+                mtime_exact: None,
+                blocks: None,
+            })
+            .collect();
+        db.save_baseline_fingerprints_batch(fps).unwrap();
+        db.close_and_checkpoint().unwrap();
+        drop(db);
+
+        // Overwrite data pages (keeping the header page intact) with garbage
+        let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(4096 * 3)).unwrap();
+        file.write_all(&[0xAB; 4096 * 2]).unwrap();
+        drop(file);
+
+        let mut db = PytestDiffDatabase::open(path).unwrap();
+        assert!(!db.integrity_check());
+        assert!(db.last_integrity_error().is_some());
+
+        db.reset_internal().unwrap();
+        assert!(db.integrity_check());
+        assert_eq!(db.get_stats_internal().unwrap()["baseline_count"], 0);
+        assert_eq!(db.schema_version_internal().unwrap(), SCHEMA_VERSION);
+    }
+
     #[test]
     fn test_save_and_retrieve_fingerprint() {
         let temp_db = NamedTempFile::new().unwrap();
@@ -1263,6 +2773,7 @@ mod tests {
             checksums: vec![123, 456, 789],
             file_hash: "abc123".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
 
@@ -1284,10 +2795,11 @@ mod tests {
             checksums: vec![123],
             file_hash: "abc".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
 
-        db.save_test_execution_internal("test_example", vec![fp], 0.5, false, "3.12")
+        db.save_test_execution_internal("test_example", vec![fp], 0.5, false, "3.12", false)
             .unwrap();
 
         let stats = db.get_stats_internal().unwrap();
@@ -1295,6 +2807,38 @@ mod tests {
         assert_eq!(stats["file_count"], 1);
     }
 
+    #[test]
+    fn test_outcome_history_and_flakiness_score() {
+        let mut db = PytestDiffDatabase::open(":memory:").unwrap();
+
+        // Alternating pass/fail flips on every run; skips do not count
+        for outcome in ["passed", "failed", "skipped", "passed", "failed", "passed"] {
+            db.record_outcome_internal("test_flaky", outcome).unwrap();
+        }
+        assert_eq!(db.flakiness_score_internal("test_flaky").unwrap(), 1.0);
+
+        db.record_outcome_internal("test_mixed", "passed").unwrap();
+        db.record_outcome_internal("test_mixed", "passed").unwrap();
+        db.record_outcome_internal("test_mixed", "failed").unwrap();
+        assert_eq!(db.flakiness_score_internal("test_mixed").unwrap(), 0.5);
+        assert_eq!(db.flakiness_score_internal("test_unknown").unwrap(), 0.0);
+        assert!(db.record_outcome_internal("test_mixed", "xfail").is_err());
+
+        // History is bounded to the most recent outcomes
+        for _ in 0..OUTCOME_HISTORY_LEN {
+            db.record_outcome_internal("test_flaky", "passed").unwrap();
+        }
+        let history = db.outcome_history_internal("test_flaky").unwrap();
+        assert_eq!(history.len(), OUTCOME_HISTORY_LEN as usize);
+        assert_eq!(db.flakiness_score_internal("test_flaky").unwrap(), 0.0);
+
+        db.delete_test_internal("test_flaky").unwrap();
+        assert!(db
+            .outcome_history_internal("test_flaky")
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn test_checksum_serialization() {
         let checksums = vec![123, -456, 789, -1];
@@ -1302,6 +2846,20 @@ mod tests {
         let deserialized = deserialize_checksums(&blob);
 
         assert_eq!(checksums, deserialized);
+        // CRC32 checksums keep the 4-byte testmon layout
+        assert_eq!(blob.len(), 16);
+    }
+
+    #[test]
+    fn test_wide_checksum_serialization() {
+        let checksums = vec![i64::MAX, -456, i64::from(i32::MIN) - 1];
+        let blob = serialize_checksums(&checksums);
+        assert_eq!(blob.len(), 1 + 8 * checksums.len());
+        assert_eq!(deserialize_checksums(&blob), checksums);
+
+        // A 4-byte blob whose first byte equals the marker is still read as 4-byte entries
+        let narrow = vec![i64::from(WIDE_CHECKSUMS_MARKER), 2];
+        assert_eq!(deserialize_checksums(&serialize_checksums(&narrow)), narrow);
     }
 
     #[test]
@@ -1316,6 +2874,7 @@ mod tests {
             checksums: vec![10, 20, 30],
             file_hash: "hash_foo".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
         let fp2 = Fingerprint {
@@ -1323,6 +2882,7 @@ mod tests {
             checksums: vec![40, 50],
             file_hash: "hash_bar".to_string(),
             mtime: 2.0,
+            mtime_exact: None,
             blocks: None,
         };
 
@@ -1404,6 +2964,7 @@ mod tests {
             checksums: vec![10, 20],
             file_hash: "hash_foo".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
         source_db.save_baseline_fingerprint_internal(fp).unwrap();
@@ -1447,6 +3008,7 @@ mod tests {
             checksums: vec![10, 20, 30],
             file_hash: "hash_foo".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
         source1_db.save_baseline_fingerprint_internal(fp1).unwrap();
@@ -1462,6 +3024,7 @@ mod tests {
             checksums: vec![40, 50],
             file_hash: "hash_bar".to_string(),
             mtime: 2.0,
+            mtime_exact: None,
             blocks: None,
         };
         source2_db.save_baseline_fingerprint_internal(fp2).unwrap();
@@ -1524,6 +3087,7 @@ mod tests {
             checksums: vec![10, 20],
             file_hash: "hash_old".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
         source1_db.save_baseline_fingerprint_internal(fp1).unwrap();
@@ -1539,6 +3103,7 @@ mod tests {
             checksums: vec![30, 40, 50],
             file_hash: "hash_new".to_string(),
             mtime: 2.0,
+            mtime_exact: None,
             blocks: None,
         };
         source2_db.save_baseline_fingerprint_internal(fp2).unwrap();
@@ -1579,12 +3144,13 @@ mod tests {
             checksums: vec![100, 200],
             file_hash: "hash1".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
 
-        db.save_test_execution_internal("test_one", vec![fp.clone()], 0.1, false, "3.12")
+        db.save_test_execution_internal("test_one", vec![fp.clone()], 0.1, false, "3.12", false)
             .unwrap();
-        db.save_test_execution_internal("test_two", vec![fp], 0.2, false, "3.12")
+        db.save_test_execution_internal("test_two", vec![fp], 0.2, false, "3.12", false)
             .unwrap();
 
         let mut changed = HashMap::new();
@@ -1596,6 +3162,755 @@ mod tests {
         assert!(affected.contains(&"test_two".to_string()));
     }
 
+    #[test]
+    fn test_get_affected_tests_ranked() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+
+        let fp = |filename: &str, checksums: Vec<i64>| Fingerprint {
+            filename: filename.to_string(),
+            checksums,
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+        let mut record = |name: &str, fps: Vec<Fingerprint>, failed: bool| {
+            db.save_test_execution_internal(name, fps, 0.1, failed, "3.12", false)
+                .unwrap();
+        };
+        record(
+            "test_wide",
+            vec![fp("module.py", vec![100, 200, 300])],
+            false,
+        );
+        record("test_b", vec![fp("module.py", vec![100, 200, 400])], false);
+        record("test_a", vec![fp("module.py", vec![200, 300])], false);
+        record(
+            "test_failing",
+            vec![fp("module.py", vec![100]), fp("other.py", vec![900])],
+            true,
+        );
+        record("test_untouched", vec![fp("module.py", vec![400])], false);
+
+        let changed: HashMap<String, Vec<i64>> = [
+            ("module.py".to_string(), vec![100, 200, 300]),
+            ("other.py".to_string(), vec![900]),
+        ]
+        .into_iter()
+        .collect();
+
+        let ranked = db.get_affected_tests_ranked_internal(&changed).unwrap();
+        assert_eq!(
+            ranked,
+            vec![
+                ("test_wide".to_string(), 3),
+                ("test_failing".to_string(), 2),
+                ("test_a".to_string(), 2),
+                ("test_b".to_string(), 2),
+            ]
+        );
+        assert!(db
+            .get_affected_tests_ranked_internal(&HashMap::new())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_list_tests_and_count() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        assert!(db.get_recorded_tests_internal().unwrap().is_empty());
+        assert_eq!(db.test_count_internal().unwrap(), 0);
+
+        let fp = Fingerprint {
+            filename: "module.py".to_string(),
+            checksums: vec![100],
+            file_hash: "hash1".to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+        for name in ["test_b", "test_a", "test_c"] {
+            db.save_test_execution_internal(name, vec![fp.clone()], 0.1, false, "3.12", false)
+                .unwrap();
+        }
+        // Re-recording a test replaces its execution
+        db.save_test_execution_internal("test_a", vec![fp.clone()], 0.2, true, "3.12", false)
+            .unwrap();
+        // Another environment (a connection is bound to one) adds rows
+        let mut other = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        other
+            .save_test_execution_internal("test_a", vec![fp], 0.2, false, "3.13", false)
+            .unwrap();
+
+        assert_eq!(
+            db.list_tests_internal(None).unwrap(),
+            vec!["test_a", "test_b", "test_c"]
+        );
+        assert_eq!(
+            db.list_tests_internal(Some("3.13")).unwrap(),
+            vec!["test_a"]
+        );
+        assert!(db.list_tests_internal(Some("3.11")).unwrap().is_empty());
+        assert_eq!(db.test_count_internal().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_save_test_executions_batch() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        let fp = |filename: &str, checksum: i64| Fingerprint {
+            filename: filename.to_string(),
+            checksums: vec![checksum],
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+        let execution = |name: &str, fingerprints: Vec<Fingerprint>| TestExecution {
+            test_name: name.to_string(),
+            duration: 0.5,
+            failed: name == "test_c",
+            forced: false,
+            fingerprints,
+        };
+
+        let executions = vec![
+            execution("test_a", vec![fp("a.py", 1), fp("shared.py", 9)]),
+            execution("test_b", vec![fp("b.py", 2), fp("shared.py", 9)]),
+            execution("test_c", vec![]),
+        ];
+        assert_eq!(
+            db.save_test_executions_batch_internal(executions, "3.12")
+                .unwrap(),
+            3
+        );
+
+        assert_eq!(db.test_count_internal().unwrap(), 3);
+        assert_eq!(
+            db.get_test_stats_internal("test_c").unwrap(),
+            Some((0.5, true))
+        );
+
+        let mut changed = HashMap::new();
+        changed.insert("shared.py".to_string(), vec![9]);
+        let mut affected = db.get_affected_tests_internal(changed).unwrap();
+        affected.sort();
+        assert_eq!(affected, vec!["test_a", "test_b"]);
+
+        let mut changed = HashMap::new();
+        changed.insert("b.py".to_string(), vec![2]);
+        assert_eq!(
+            db.get_affected_tests_internal(changed).unwrap(),
+            vec!["test_b"]
+        );
+
+        // The shared fingerprint row is reused, and re-saving replaces old executions
+        let conn = db.conn.read();
+        let fp_rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM file_fp", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(fp_rows, 3);
+        drop(conn);
+
+        db.save_test_executions_batch_internal(
+            vec![execution("test_a", vec![fp("a.py", 1)])],
+            "3.12",
+        )
+        .unwrap();
+        let conn = db.conn.read();
+        let mappings: i64 = conn
+            .query_row("SELECT COUNT(*) FROM test_execution_file_fp", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(mappings, 3);
+    }
+
+    #[test]
+    fn test_clear_baseline_scoped() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        for filename in [
+            "services/api/app.py",
+            "services/api/views/users.py",
+            "services/api_client.py",
+            "services/worker/jobs.py",
+            "setup.py",
+        ] {
+            db.save_baseline_fingerprint_internal(Fingerprint {
+                filename: filename.to_string(),
+                checksums: vec![1],
+                file_hash: "hash".to_string(),
+                mtime: 1.0,
+                mtime_exact: None,
+                blocks: None,
+            })
+            .unwrap();
+        }
+
+        // A directory prefix must not match a sibling that merely shares its name
+        assert_eq!(
+            db.clear_baseline_internal(&["services/api/".to_string(), "setup.py".to_string()])
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            db.list_baseline_files_internal().unwrap(),
+            vec!["services/api_client.py", "services/worker/jobs.py"]
+        );
+        assert_eq!(
+            db.clear_baseline_internal(&["missing".to_string()])
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_baseline_batch_upserts_duplicate_filenames() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        let fp = |hash: &str| Fingerprint {
+            filename: "src/a.py".to_string(),
+            checksums: vec![1],
+            file_hash: hash.to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+
+        db.save_baseline_fingerprints_batch(vec![fp("first"), fp("second")])
+            .unwrap();
+        db.save_baseline_fingerprints_batch(vec![fp("third")])
+            .unwrap();
+
+        let (rows, id): (i64, i64) = db
+            .conn
+            .read()
+            .query_row("SELECT COUNT(*), MAX(id) FROM baseline_fp", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((rows, id), (1, 1));
+        let stored = db
+            .get_baseline_fingerprint_rust("src/a.py")
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.file_hash, "third");
+    }
+
+    #[test]
+    fn test_diff_baselines() {
+        let main_db = NamedTempFile::new().unwrap();
+        let branch_db = NamedTempFile::new().unwrap();
+        let mut main = PytestDiffDatabase::new_internal(main_db.path().to_str().unwrap()).unwrap();
+        let mut branch =
+            PytestDiffDatabase::new_internal(branch_db.path().to_str().unwrap()).unwrap();
+        let fp = |filename: &str, checksums: Vec<i64>, hash: &str| Fingerprint {
+            filename: filename.to_string(),
+            checksums,
+            file_hash: hash.to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+
+        main.save_baseline_fingerprints_batch(vec![
+            fp("same.py", vec![1], "same"),
+            fp("edited.py", vec![10, 11, 12], "before"),
+            fp("removed.py", vec![20, 21], "gone"),
+        ])
+        .unwrap();
+        branch
+            .save_baseline_fingerprints_batch(vec![
+                fp("same.py", vec![1], "same"),
+                fp("edited.py", vec![10, 13, 12], "after"),
+                fp("new.py", vec![30], "new"),
+            ])
+            .unwrap();
+
+        let diff = main.diff_baselines_internal(&branch).unwrap();
+        assert_eq!(diff.modified, vec!["edited.py"]);
+        assert_eq!(diff.deleted, vec!["removed.py"]);
+        assert_eq!(diff.added, vec!["new.py"]);
+        assert_eq!(diff.changed_blocks.len(), 2);
+        assert_eq!(diff.changed_blocks["edited.py"], vec![11]);
+        assert_eq!(diff.changed_blocks["removed.py"], vec![20, 21]);
+
+        let identical = main.diff_baselines_internal(&main).unwrap();
+        assert!(identical.modified.is_empty() && identical.changed_blocks.is_empty());
+    }
+
+    #[test]
+    fn test_get_baseline_fingerprints_batch_matches_single_lookups() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        // More files than one chunk holds
+        let fps = (0..LOOKUP_CHUNK_SIZE + 20)
+            .map(|i| Fingerprint {
+                filename: format!("pkg/module_{}.py", i),
+                checksums: vec![i as i64, -(i as i64)],
+                file_hash: format!("hash_{}", i),
+                mtime: i as f64,
+                mtime_exact: Some((i as u64, 7)),
+                blocks: None,
+            })
+            .collect();
+        db.save_baseline_fingerprints_batch(fps).unwrap();
+
+        let mut filenames: Vec<String> = (0..LOOKUP_CHUNK_SIZE + 20)
+            .step_by(3)
+            .map(|i| format!("pkg/module_{}.py", i))
+            .collect();
+        filenames.push("pkg/missing.py".to_string());
+        filenames.push("pkg/module_0.py".to_string());
+
+        let batch = db
+            .get_baseline_fingerprints_batch_internal(&filenames)
+            .unwrap();
+        assert_eq!(batch.len(), filenames.len() - 2);
+        for filename in &filenames {
+            let single = db.get_baseline_fingerprint_internal(filename).unwrap();
+            let batched = batch.get(filename);
+            assert_eq!(batched.is_some(), single.is_some(), "{}", filename);
+            if let (Some(batched), Some(single)) = (batched, single) {
+                assert_eq!(batched.checksums, single.checksums);
+                assert_eq!(batched.file_hash, single.file_hash);
+                assert_eq!(batched.mtime, single.mtime);
+                assert_eq!(batched.mtime_exact, single.mtime_exact);
+            }
+        }
+        assert!(db
+            .get_baseline_fingerprints_batch_internal(&[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_database_dump_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = PytestDiffDatabase::open(IN_MEMORY_PATH).unwrap();
+        assert!(db.is_in_memory_internal());
+        db.save_baseline_fingerprint_internal(Fingerprint {
+            filename: "src/a.py".to_string(),
+            checksums: vec![1, -2],
+            file_hash: "hash".to_string(),
+            mtime: 1.0,
+            mtime_exact: Some((1, 0)),
+            blocks: None,
+        })
+        .unwrap();
+        db.save_test_execution_internal(
+            "test_a",
+            vec![Fingerprint {
+                filename: "src/a.py".to_string(),
+                checksums: vec![-2],
+                file_hash: "hash".to_string(),
+                mtime: 1.0,
+                mtime_exact: None,
+                blocks: None,
+            }],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.set_metadata_internal("commit", "abc123").unwrap();
+        assert_eq!(
+            db.get_baseline_fingerprint_internal("src/a.py")
+                .unwrap()
+                .unwrap()
+                .checksums,
+            vec![1, -2]
+        );
+
+        let dump_path = dir.path().join("nested").join("dump.db");
+        let dump_path = dump_path.to_str().unwrap();
+        db.dump_to_internal(dump_path).unwrap();
+        // The target is never overwritten
+        assert!(db.dump_to_internal(dump_path).is_err());
+        drop(db);
+
+        let reopened = PytestDiffDatabase::open(dump_path).unwrap();
+        assert!(!reopened.is_in_memory_internal());
+        let stored = reopened
+            .get_baseline_fingerprint_internal("src/a.py")
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.checksums, vec![1, -2]);
+        assert_eq!(stored.mtime_exact, Some((1, 0)));
+        assert_eq!(
+            reopened.get_metadata_internal("commit").unwrap().as_deref(),
+            Some("abc123")
+        );
+        let changed = HashMap::from([("src/a.py".to_string(), vec![-2])]);
+        assert_eq!(
+            reopened.get_affected_tests_internal(changed).unwrap(),
+            vec!["test_a"]
+        );
+    }
+
+    #[test]
+    fn test_clear_baseline_everything() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        for filename in ["a.py", "pkg/b.py"] {
+            db.save_baseline_fingerprint_internal(Fingerprint {
+                filename: filename.to_string(),
+                checksums: vec![1],
+                file_hash: "hash".to_string(),
+                mtime: 1.0,
+                mtime_exact: None,
+                blocks: None,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(db.clear_baseline_internal(&[]).unwrap(), 2);
+        assert!(db.list_baseline_files_internal().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tests_for_file() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        let fp = |filename: &str, checksum: i64| Fingerprint {
+            filename: filename.to_string(),
+            checksums: vec![checksum],
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+
+        db.save_test_execution_internal(
+            "test_refund",
+            vec![fp("payments.py", 1)],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.save_test_execution_internal(
+            "test_charge",
+            vec![fp("payments.py", 2), fp("models.py", 3)],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.save_test_execution_internal(
+            "test_user",
+            vec![fp("users.py", 4)],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            db.tests_for_file_internal("payments.py").unwrap(),
+            vec!["test_charge", "test_refund"]
+        );
+        assert_eq!(
+            db.tests_for_file_internal("users.py").unwrap(),
+            vec!["test_user"]
+        );
+        assert!(db.tests_for_file_internal("missing.py").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tests_for_block() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+
+        let source = "class Calculator:\n    def add(self, a, b):\n        return a + b\n\n    def sub(self, a, b):\n        return a - b\n";
+        let blocks = crate::parser::parse_module_internal(source).unwrap();
+        let checksum_of = |name: &str| {
+            blocks
+                .iter()
+                .find(|b| b.qualified_name == name)
+                .unwrap()
+                .checksum
+        };
+        let fp = |checksums: Vec<i64>, blocks: Option<Vec<crate::types::Block>>| Fingerprint {
+            filename: "calc.py".to_string(),
+            checksums,
+            file_hash: "hash_calc".to_string(),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks,
+        };
+
+        let all_checksums: Vec<i64> = blocks.iter().map(|b| b.checksum).collect();
+        db.save_baseline_fingerprint_internal(fp(all_checksums, Some(blocks.clone())))
+            .unwrap();
+
+        db.save_test_execution_internal(
+            "test_add",
+            vec![fp(
+                vec![checksum_of("Calculator"), checksum_of("Calculator.add")],
+                None,
+            )],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.save_test_execution_internal(
+            "test_sub",
+            vec![fp(
+                vec![checksum_of("Calculator"), checksum_of("Calculator.sub")],
+                None,
+            )],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            db.tests_for_block_internal("calc.py", "Calculator.add")
+                .unwrap(),
+            Some(vec!["test_add".to_string()])
+        );
+        assert_eq!(
+            db.tests_for_block_internal("calc.py", "Calculator")
+                .unwrap(),
+            Some(vec!["test_add".to_string(), "test_sub".to_string()])
+        );
+        assert_eq!(
+            db.tests_for_block_internal("calc.py", "Calculator.mul")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            db.tests_for_block_internal("other.py", "Calculator.add")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_save_test_execution_merge() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        let fp = |filename: &str, checksums: Vec<i64>| Fingerprint {
+            filename: filename.to_string(),
+            checksums,
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+        let affected = |db: &PytestDiffDatabase, filename: &str, checksum: i64| {
+            let mut changed = HashMap::new();
+            changed.insert(filename.to_string(), vec![checksum]);
+            db.get_affected_tests_internal(changed).unwrap()
+        };
+
+        db.save_test_execution_internal(
+            "test_flaky",
+            vec![fp("app.py", vec![1, 2]), fp("retry.py", vec![7])],
+            0.1,
+            true,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        // The re-run skipped the branch in block 2 and never reached retry.py
+        db.save_test_execution_internal(
+            "test_flaky",
+            vec![fp("app.py", vec![1, 3])],
+            0.1,
+            false,
+            "3.12",
+            true,
+        )
+        .unwrap();
+
+        for (filename, checksum) in [("app.py", 1), ("app.py", 2), ("app.py", 3), ("retry.py", 7)] {
+            assert_eq!(affected(&db, filename, checksum), vec!["test_flaky"]);
+        }
+        assert_eq!(
+            db.get_test_stats_internal("test_flaky").unwrap(),
+            Some((0.1, false))
+        );
+
+        // Without merge the latest run replaces the dependency set
+        db.save_test_execution_internal(
+            "test_flaky",
+            vec![fp("app.py", vec![1])],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        assert!(affected(&db, "app.py", 2).is_empty());
+        assert!(affected(&db, "retry.py", 7).is_empty());
+        assert_eq!(affected(&db, "app.py", 1), vec!["test_flaky"]);
+    }
+
+    #[test]
+    fn test_get_test_stats() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        assert_eq!(db.get_test_stats_internal("test_slow").unwrap(), None);
+
+        db.save_test_execution_internal("test_slow", vec![], 2.5, true, "3.12", false)
+            .unwrap();
+        db.save_test_execution_internal("test_fast", vec![], 0.01, false, "3.12", false)
+            .unwrap();
+        assert_eq!(
+            db.get_test_stats_internal("test_slow").unwrap(),
+            Some((2.5, true))
+        );
+        assert_eq!(
+            db.get_test_stats_internal("test_fast").unwrap(),
+            Some((0.01, false))
+        );
+
+        // The latest run replaces the previous one
+        db.save_test_execution_internal("test_slow", vec![], 1.5, false, "3.12", false)
+            .unwrap();
+        assert_eq!(
+            db.get_test_stats_internal("test_slow").unwrap(),
+            Some((1.5, false))
+        );
+    }
+
+    #[test]
+    fn test_delete_test() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        let fp = |filename: &str| Fingerprint {
+            filename: filename.to_string(),
+            checksums: vec![100],
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+
+        db.save_test_execution_internal(
+            "test_old",
+            vec![fp("a.py"), fp("b.py")],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.save_test_execution_internal("test_kept", vec![fp("a.py")], 0.1, false, "3.12", false)
+            .unwrap();
+
+        assert_eq!(db.delete_test_internal("test_old").unwrap(), 2);
+        assert_eq!(db.delete_test_internal("test_old").unwrap(), 0);
+        assert_eq!(db.get_recorded_tests_internal().unwrap(), vec!["test_kept"]);
+
+        let mut changed = HashMap::new();
+        changed.insert("a.py".to_string(), vec![100]);
+        assert_eq!(
+            db.get_affected_tests_internal(changed).unwrap(),
+            vec!["test_kept"]
+        );
+    }
+
+    #[test]
+    fn test_prune_missing_tests() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+        for name in ["test_a", "test_b", "test_c", "test_d"] {
+            db.save_test_execution_internal(name, vec![], 0.1, false, "3.12", false)
+                .unwrap();
+        }
+
+        let known = vec![
+            "test_b".to_string(),
+            "test_d".to_string(),
+            "test_new".to_string(),
+        ];
+        assert_eq!(db.prune_missing_tests_internal(known).unwrap(), 2);
+        assert_eq!(
+            db.get_recorded_tests_internal().unwrap(),
+            vec!["test_b", "test_d"]
+        );
+    }
+
+    #[test]
+    fn test_get_affected_tests_selects_only_dependents() {
+        let temp_db = NamedTempFile::new().unwrap();
+        let mut db = PytestDiffDatabase::new_internal(temp_db.path().to_str().unwrap()).unwrap();
+
+        let fp = |filename: &str, checksums: Vec<i64>| Fingerprint {
+            filename: filename.to_string(),
+            checksums,
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.0,
+            mtime_exact: None,
+            blocks: None,
+        };
+
+        // test_add uses add(), test_sub uses sub(), test_report uses report.py only
+        db.save_test_execution_internal(
+            "test_add",
+            vec![fp("calc.py", vec![1, 10])],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.save_test_execution_internal(
+            "test_sub",
+            vec![fp("calc.py", vec![1, 20])],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+        db.save_test_execution_internal(
+            "test_report",
+            vec![fp("report.py", vec![10])],
+            0.1,
+            false,
+            "3.12",
+            false,
+        )
+        .unwrap();
+
+        let affected = |changes: Vec<(&str, Vec<i64>)>| {
+            let changed = changes
+                .into_iter()
+                .map(|(f, c)| (f.to_string(), c))
+                .collect();
+            db.get_affected_tests_internal(changed).unwrap()
+        };
+
+        // Changing add() selects only test_add, even though report.py shares checksum 10
+        assert_eq!(affected(vec![("calc.py", vec![10])]), vec!["test_add"]);
+        // Changing the module block selects both calc tests, sorted
+        assert_eq!(
+            affected(vec![("calc.py", vec![1])]),
+            vec!["test_add", "test_sub"]
+        );
+        // Changes across files are de-duplicated
+        assert_eq!(
+            affected(vec![("calc.py", vec![1, 20]), ("report.py", vec![10])]),
+            vec!["test_add", "test_report", "test_sub"]
+        );
+        assert!(affected(vec![("calc.py", vec![99])]).is_empty());
+    }
+
     #[test]
     fn test_import_baseline_copies_test_executions() {
         // Create source database with test execution data
@@ -1608,14 +3923,15 @@ mod tests {
             checksums: vec![100, 200],
             file_hash: "hash1".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
 
         source_db
-            .save_test_execution_internal("test_one", vec![fp.clone()], 0.1, false, "3.12")
+            .save_test_execution_internal("test_one", vec![fp.clone()], 0.1, false, "3.12", false)
             .unwrap();
         source_db
-            .save_test_execution_internal("test_two", vec![fp], 0.2, false, "3.12")
+            .save_test_execution_internal("test_two", vec![fp], 0.2, false, "3.12", false)
             .unwrap();
         source_db
             .save_baseline_fingerprint_internal(Fingerprint {
@@ -1623,6 +3939,7 @@ mod tests {
                 checksums: vec![100, 200],
                 file_hash: "hash1".to_string(),
                 mtime: 1.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
@@ -1661,10 +3978,11 @@ mod tests {
             checksums: vec![100],
             file_hash: "hash_a".to_string(),
             mtime: 1.0,
+            mtime_exact: None,
             blocks: None,
         };
         source1_db
-            .save_test_execution_internal("test_alpha", vec![fp1], 0.1, false, "3.12")
+            .save_test_execution_internal("test_alpha", vec![fp1], 0.1, false, "3.12", false)
             .unwrap();
         source1_db.close_and_checkpoint().unwrap();
 
@@ -1678,10 +3996,11 @@ mod tests {
             checksums: vec![200],
             file_hash: "hash_b".to_string(),
             mtime: 2.0,
+            mtime_exact: None,
             blocks: None,
         };
         source2_db
-            .save_test_execution_internal("test_beta", vec![fp2], 0.2, false, "3.12")
+            .save_test_execution_internal("test_beta", vec![fp2], 0.2, false, "3.12", false)
             .unwrap();
         source2_db.close_and_checkpoint().unwrap();
 
@@ -1727,6 +4046,7 @@ mod tests {
                 checksums: vec![42],
                 file_hash: "hash42".to_string(),
                 mtime: 1.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
@@ -1761,6 +4081,7 @@ mod tests {
                 checksums: vec![42],
                 file_hash: "hash42".to_string(),
                 mtime: 1.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
@@ -1797,6 +4118,7 @@ mod tests {
                 checksums: vec![1],
                 file_hash: "h1".to_string(),
                 mtime: 1.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
@@ -1818,6 +4140,7 @@ mod tests {
                 checksums: vec![2],
                 file_hash: "h2".to_string(),
                 mtime: 2.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
@@ -1866,6 +4189,7 @@ mod tests {
                 checksums: vec![1],
                 file_hash: "h1".to_string(),
                 mtime: 1.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
@@ -1883,6 +4207,7 @@ mod tests {
                 checksums: vec![2],
                 file_hash: "h2".to_string(),
                 mtime: 2.0,
+                mtime_exact: None,
                 blocks: None,
             })
             .unwrap();
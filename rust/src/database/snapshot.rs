@@ -0,0 +1,449 @@
+// Portable JSON snapshots of the database
+//
+// A snapshot holds metadata, baseline fingerprints, test executions (with
+// the file fingerprints they depend on) and test outcome history so selection
+// state can be cached or inspected without shipping the SQLite file.
+
+use anyhow::{Context, Result};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    block_names_json, deserialize_checksums, serialize_checksums, ImportResult, PytestDiffDatabase,
+};
+use crate::types::Fingerprint;
+
+/// Snapshot format version written by this build; bump on incompatible changes
+///
+/// Version 2 added `test_outcomes`; version 1 snapshots import with no outcomes.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    format_version: u32,
+    schema_version: i64,
+    metadata: BTreeMap<String, String>,
+    baseline: Vec<Fingerprint>,
+    /// File fingerprints referenced by test executions, keyed by snapshot-local id
+    fingerprints: BTreeMap<i64, Fingerprint>,
+    test_executions: Vec<TestExecutionRecord>,
+    /// Recorded outcomes, oldest first
+    #[serde(default)]
+    test_outcomes: Vec<TestOutcomeRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestExecutionRecord {
+    test_name: String,
+    environment: String,
+    python_version: String,
+    duration: Option<f64>,
+    failed: bool,
+    forced: bool,
+    fingerprint_ids: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TestOutcomeRecord {
+    test_name: String,
+    outcome: String,
+}
+
+impl PytestDiffDatabase {
+    pub(crate) fn export_json_internal(&self, path: &str) -> Result<()> {
+        let snapshot = self.read_snapshot()?;
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create snapshot file: {}", path))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), &snapshot)
+            .context("Failed to write snapshot")?;
+        Ok(())
+    }
+
+    pub(crate) fn import_json_internal(&mut self, path: &str) -> Result<ImportResult> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open snapshot file: {}", path))?;
+        let snapshot: Snapshot = serde_json::from_reader(std::io::BufReader::new(file))
+            .context("Failed to parse snapshot")?;
+
+        if snapshot.format_version > SNAPSHOT_FORMAT_VERSION {
+            anyhow::bail!(
+                "snapshot format version {} is newer than the supported version {}",
+                snapshot.format_version,
+                SNAPSHOT_FORMAT_VERSION
+            );
+        }
+
+        let stats = self.get_stats_internal()?;
+        if stats["baseline_count"] > 0 || stats["test_count"] > 0 || stats["fingerprint_count"] > 0
+        {
+            anyhow::bail!("snapshots can only be imported into an empty database");
+        }
+
+        let result = self.write_snapshot(&snapshot)?;
+        self.clear_fingerprint_cache();
+        *self.current_environment_id.write() = None;
+        Ok(result)
+    }
+
+    fn read_snapshot(&self) -> Result<Snapshot> {
+        let mut baseline: Vec<Fingerprint> = self
+            .get_all_baseline_fingerprints()?
+            .into_values()
+            .collect();
+        baseline.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        let conn = self.conn.read();
+
+        let metadata = {
+            let mut stmt = conn.prepare("SELECT dataid, data FROM metadata")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<std::result::Result<_, _>>()?
+        };
+
+        let fingerprints = {
+            let mut stmt = conn.prepare(
+                "SELECT id, filename, method_checksums, mtime, fsha FROM file_fp
+                 WHERE id IN (SELECT fingerprint_id FROM test_execution_file_fp)",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    Fingerprint {
+                        filename: row.get(1)?,
+                        checksums: deserialize_checksums(&row.get::<_, Vec<u8>>(2)?),
+                        mtime: row.get(3)?,
+                        mtime_exact: None,
+                        file_hash: row.get(4)?,
+                        blocks: None,
+                    },
+                ))
+            })?;
+            rows.collect::<std::result::Result<_, _>>()?
+        };
+
+        let mut links: HashMap<i64, Vec<i64>> = HashMap::new();
+        {
+            let mut stmt = conn.prepare(
+                "SELECT test_execution_id, fingerprint_id FROM test_execution_file_fp
+                 ORDER BY test_execution_id, fingerprint_id",
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (test_execution_id, fingerprint_id) = row?;
+                links
+                    .entry(test_execution_id)
+                    .or_default()
+                    .push(fingerprint_id);
+            }
+        }
+
+        let test_executions = {
+            let mut stmt = conn.prepare(
+                "SELECT te.id, te.test_name, e.environment_name, e.python_version,
+                        te.duration, te.failed, te.forced
+                 FROM test_execution te
+                 JOIN environment e ON te.environment_id = e.id
+                 ORDER BY te.test_name, te.id",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let id: i64 = row.get(0)?;
+                Ok(TestExecutionRecord {
+                    test_name: row.get(1)?,
+                    environment: row.get(2)?,
+                    python_version: row.get(3)?,
+                    duration: row.get(4)?,
+                    failed: row.get::<_, Option<i64>>(5)?.unwrap_or(0) != 0,
+                    forced: row.get::<_, Option<i64>>(6)?.unwrap_or(0) != 0,
+                    fingerprint_ids: links.remove(&id).unwrap_or_default(),
+                })
+            })?;
+            rows.collect::<std::result::Result<_, _>>()?
+        };
+
+        let test_outcomes = {
+            let mut stmt =
+                conn.prepare("SELECT test_name, outcome FROM test_outcome ORDER BY id")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(TestOutcomeRecord {
+                    test_name: row.get(0)?,
+                    outcome: row.get(1)?,
+                })
+            })?;
+            rows.collect::<std::result::Result<_, _>>()?
+        };
+
+        Ok(Snapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            schema_version: super::SCHEMA_VERSION,
+            metadata,
+            baseline,
+            fingerprints,
+            test_executions,
+            test_outcomes,
+        })
+    }
+
+    fn write_snapshot(&mut self, snapshot: &Snapshot) -> Result<ImportResult> {
+        let mut conn = self.conn.write();
+        let tx = conn.transaction()?;
+
+        for (key, value) in &snapshot.metadata {
+            tx.execute(
+                "INSERT OR REPLACE INTO metadata (dataid, data) VALUES (?1, ?2)",
+                params![key, value],
+            )?;
+        }
+
+        for fp in &snapshot.baseline {
+            let (mtime_secs, mtime_nanos) = fp.mtime_exact.unzip();
+            tx.execute(
+                "INSERT OR REPLACE INTO baseline_fp
+                     (filename, method_checksums, mtime, fsha, mtime_secs, mtime_nanos, block_names)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    &fp.filename,
+                    serialize_checksums(&fp.checksums),
+                    fp.mtime,
+                    &fp.file_hash,
+                    mtime_secs,
+                    mtime_nanos,
+                    block_names_json(fp)
+                ],
+            )
+            .context("Failed to import baseline fingerprint")?;
+        }
+
+        // Snapshot ids are remapped to the ids assigned by this database
+        let mut fingerprint_ids: HashMap<i64, i64> = HashMap::new();
+        for (snapshot_id, fp) in &snapshot.fingerprints {
+            let id = self.get_or_create_fingerprint_in_tx(&tx, fp)?;
+            fingerprint_ids.insert(*snapshot_id, id);
+        }
+
+        let mut environment_ids: HashMap<(&str, &str), i64> = HashMap::new();
+        for record in &snapshot.test_executions {
+            let key = (record.environment.as_str(), record.python_version.as_str());
+            let env_id = match environment_ids.get(&key) {
+                Some(id) => *id,
+                None => {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO environment
+                             (environment_name, system_packages, python_version)
+                         VALUES (?1, '', ?2)",
+                        params![key.0, key.1],
+                    )?;
+                    let id: i64 = tx.query_row(
+                        "SELECT id FROM environment
+                         WHERE environment_name = ?1 AND python_version = ?2",
+                        params![key.0, key.1],
+                        |row| row.get(0),
+                    )?;
+                    environment_ids.insert(key, id);
+                    id
+                }
+            };
+
+            tx.execute(
+                "INSERT INTO test_execution (environment_id, test_name, duration, failed, forced)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    env_id,
+                    &record.test_name,
+                    record.duration,
+                    record.failed as i64,
+                    record.forced as i64
+                ],
+            )
+            .context("Failed to import test execution")?;
+            let test_execution_id = tx.last_insert_rowid();
+
+            for snapshot_id in &record.fingerprint_ids {
+                let fingerprint_id = fingerprint_ids.get(snapshot_id).with_context(|| {
+                    format!(
+                        "test {} references unknown fingerprint {}",
+                        record.test_name, snapshot_id
+                    )
+                })?;
+                tx.execute(
+                    "INSERT OR IGNORE INTO test_execution_file_fp
+                         (test_execution_id, fingerprint_id)
+                     VALUES (?1, ?2)",
+                    params![test_execution_id, fingerprint_id],
+                )?;
+            }
+        }
+
+        for record in &snapshot.test_outcomes {
+            tx.execute(
+                "INSERT INTO test_outcome (test_name, outcome) VALUES (?1, ?2)",
+                params![&record.test_name, &record.outcome],
+            )
+            .context("Failed to import test outcome")?;
+        }
+
+        tx.commit()?;
+
+        Ok(ImportResult {
+            baseline_count: snapshot.baseline.len(),
+            test_execution_count: snapshot.test_executions.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(filename: &str, checksums: Vec<i64>) -> Fingerprint {
+        Fingerprint {
+            filename: filename.to_string(),
+            checksums,
+            file_hash: format!("hash_{}", filename),
+            mtime: 1.5,
+            mtime_exact: None,
+            blocks: None,
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot.json");
+        let snapshot_path = snapshot_path.to_str().unwrap();
+
+        let mut source = PytestDiffDatabase::open(":memory:").unwrap();
+        source
+            .save_baseline_fingerprints_batch(vec![
+                fingerprint("a.py", vec![1, 2, 3]),
+                fingerprint("b.py", vec![-4]),
+            ])
+            .unwrap();
+        source
+            .save_test_execution_internal(
+                "test_a",
+                vec![fingerprint("a.py", vec![1, 2])],
+                0.25,
+                false,
+                "3.12",
+                false,
+            )
+            .unwrap();
+        source
+            .save_test_execution_internal(
+                "test_b",
+                vec![
+                    fingerprint("a.py", vec![1, 2]),
+                    fingerprint("b.py", vec![-4]),
+                ],
+                0.5,
+                true,
+                "3.12",
+                false,
+            )
+            .unwrap();
+        for outcome in ["passed", "failed", "passed"] {
+            source.record_outcome_internal("test_b", outcome).unwrap();
+        }
+        source.set_metadata_internal("commit", "abc123").unwrap();
+        source.export_json_internal(snapshot_path).unwrap();
+
+        let mut target = PytestDiffDatabase::open(":memory:").unwrap();
+        let result = target.import_json_internal(snapshot_path).unwrap();
+        assert_eq!(result.baseline_count, 2);
+        assert_eq!(result.test_execution_count, 2);
+
+        for filename in ["a.py", "b.py"] {
+            let expected = source
+                .get_baseline_fingerprint_rust(filename)
+                .unwrap()
+                .unwrap();
+            let actual = target
+                .get_baseline_fingerprint_rust(filename)
+                .unwrap()
+                .unwrap();
+            assert_eq!(actual.checksums, expected.checksums);
+            assert_eq!(actual.file_hash, expected.file_hash);
+            assert_eq!(actual.mtime, expected.mtime);
+        }
+
+        let mut changed = HashMap::new();
+        changed.insert("b.py".to_string(), vec![-4]);
+        assert_eq!(
+            target.get_affected_tests_internal(changed).unwrap(),
+            vec!["test_b"]
+        );
+        assert_eq!(
+            target.get_test_dependencies_internal("test_b").unwrap(),
+            vec!["a.py", "b.py"]
+        );
+        assert_eq!(
+            target.get_metadata_internal("commit").unwrap(),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            target.outcome_history_internal("test_b").unwrap(),
+            vec!["passed", "failed", "passed"]
+        );
+        assert_eq!(target.flakiness_score_internal("test_b").unwrap(), 1.0);
+        // Shared fingerprints are stored once
+        assert_eq!(target.get_stats_internal().unwrap()["fingerprint_count"], 2);
+    }
+
+    #[test]
+    fn test_import_json_rejects_non_empty_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot.json");
+        let snapshot_path = snapshot_path.to_str().unwrap();
+
+        let mut db = PytestDiffDatabase::open(":memory:").unwrap();
+        db.save_baseline_fingerprint_internal(fingerprint("a.py", vec![1]))
+            .unwrap();
+        db.export_json_internal(snapshot_path).unwrap();
+
+        let err = db.import_json_internal(snapshot_path).unwrap_err();
+        assert!(err.to_string().contains("empty database"));
+    }
+
+    #[test]
+    fn test_import_json_accepts_snapshot_without_outcomes() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot.json");
+        std::fs::write(
+            &snapshot_path,
+            r#"{"format_version": 1, "schema_version": 3, "metadata": {"commit": "abc123"},
+                "baseline": [], "fingerprints": {}, "test_executions": []}"#,
+        )
+        .unwrap();
+
+        let mut db = PytestDiffDatabase::open(":memory:").unwrap();
+        db.import_json_internal(snapshot_path.to_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            db.get_metadata_internal("commit").unwrap(),
+            Some("abc123".to_string())
+        );
+        assert!(db.outcome_history_internal("test_a").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_import_json_rejects_newer_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot.json");
+        std::fs::write(
+            &snapshot_path,
+            format!(
+                r#"{{"format_version": {}, "schema_version": 1, "metadata": {{}},
+                    "baseline": [], "fingerprints": {{}}, "test_executions": []}}"#,
+                SNAPSHOT_FORMAT_VERSION + 1
+            ),
+        )
+        .unwrap();
+
+        let mut db = PytestDiffDatabase::open(":memory:").unwrap();
+        let err = db
+            .import_json_internal(snapshot_path.to_str().unwrap())
+            .unwrap_err();
+        assert!(err.to_string().contains("newer than the supported version"));
+    }
+}
@@ -7,37 +7,210 @@
 // - Processing coverage data with concurrent block filtering
 
 use anyhow::{Context, Result};
+use globset::GlobSet;
+use ignore::WalkBuilder;
 use pyo3::prelude::*;
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::UNIX_EPOCH;
 use walkdir::WalkDir;
 
+use crate::cancel::{is_cancelled, CancelToken};
 use crate::database::PytestDiffDatabase;
-use crate::parser::parse_module_internal;
-use crate::types::{Block, ChangedFiles, Fingerprint};
+use crate::notebook::{is_notebook, parse_notebook};
+use crate::options::{build_glob_set, FingerprintOptions, Granularity, ScanOptions};
+use crate::parser::{
+    parse_module_internal, parse_module_with_options, seed_checksum, ParseOptions,
+};
+use crate::types::{
+    BaselineStats, BaselineVerification, Block, BlockChange, ChangeSummary, ChangedFiles,
+    FileInspection, Fingerprint,
+};
+
+/// Metadata key holding the Unix time `save_baseline` last completed
+const BASELINE_SAVED_AT_KEY: &str = "baseline_saved_at";
+
+/// Metadata keys describing the environment that produced the baseline; the
+/// checksum algorithm key holds `FingerprintOptions::checksum_scheme`
+const CHECKSUM_ALGORITHM_KEY: &str = "checksum_algorithm";
+const CRATE_VERSION_KEY: &str = "crate_version";
+const PYTHON_VERSION_KEY: &str = "python_version";
+
+/// Files larger than this are hashed in chunks instead of being read whole
+const STREAMING_HASH_THRESHOLD: u64 = 1024 * 1024;
+
+/// Read size used when streaming a file into the hasher
+const HASH_CHUNK_BYTES: usize = 64 * 1024;
+
+/// In `safe_mode`, more errored files than this make the result untrustworthy
+const SAFE_MODE_MAX_ERRORED: usize = 10;
 
 /// Convert an absolute path to a relative path by stripping the project root prefix.
 /// Falls back to the original path if it doesn't start with project_root.
+/// The result always uses `/` separators, whichever style the inputs used.
 fn make_relative(abs_path: &str, project_root: &str) -> String {
-    Path::new(abs_path)
-        .strip_prefix(project_root)
+    let abs_path = to_slash(abs_path);
+    Path::new(&abs_path)
+        .strip_prefix(to_slash(project_root))
         .map(|p| p.to_string_lossy().to_string())
-        .unwrap_or_else(|_| abs_path.to_string())
+        .unwrap_or(abs_path)
+}
+
+/// Use `/` as the path separator, so filenames stored on Windows and Unix match
+fn to_slash(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// `to_slash` for `Path` values
+fn slash_path(path: &Path) -> PathBuf {
+    PathBuf::from(to_slash(&path.to_string_lossy()))
+}
+
+/// Key stored baselines by `/`-separated filename (older Windows baselines used `\`)
+fn slash_keys(baselines: HashMap<String, Fingerprint>) -> HashMap<String, Fingerprint> {
+    baselines
+        .into_iter()
+        .map(|(filename, fp)| (to_slash(&filename), fp))
+        .collect()
+}
+
+/// Scope limiting which test files are considered
+///
+/// Plain entries are path prefixes (a directory matches everything under it).
+/// Entries containing glob metacharacters are matched against the absolute
+/// path, the path relative to the project root and the file name, so
+/// `test_api_*.py` selects matching files in any directory.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Scope {
+    prefixes: Vec<PathBuf>,
+    globs: Option<GlobSet>,
+}
+
+impl Scope {
+    pub(crate) fn new(scope_paths: &[String]) -> Result<Self> {
+        let (patterns, paths): (Vec<String>, Vec<String>) = scope_paths
+            .iter()
+            .cloned()
+            .partition(|p| p.contains(['*', '?', '[', '{']));
+        let prefixes = paths
+            .iter()
+            .map(|p| {
+                let path = PathBuf::from(p);
+                std::fs::canonicalize(&path).unwrap_or(path)
+            })
+            .collect();
+        let globs = if patterns.is_empty() {
+            None
+        } else {
+            let slashed: Vec<String> = patterns.iter().map(|p| to_slash(p)).collect();
+            Some(build_glob_set(&slashed, "scope")?)
+        };
+        Ok(Self { prefixes, globs })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.prefixes.is_empty() && self.globs.is_none()
+    }
+
+    /// Whether a file is in scope; `rel_path` is relative to the project root
+    fn contains(&self, abs_path: &Path, rel_path: Option<&Path>) -> bool {
+        if self
+            .prefixes
+            .iter()
+            .any(|prefix| abs_path.starts_with(prefix))
+        {
+            return true;
+        }
+        self.globs.as_ref().is_some_and(|globs| {
+            globs.is_match(slash_path(abs_path))
+                || rel_path.is_some_and(|rel| globs.is_match(slash_path(rel)))
+                || abs_path
+                    .file_name()
+                    .is_some_and(|name| globs.is_match(name))
+        })
+    }
+}
+
+/// Filters applied to files reported by coverage
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CoverageFilter {
+    /// Skip files whose path relative to the project root matches one of these globs
+    pub exclude: GlobSet,
+    /// Globs identifying test files; `None` uses the built-in conventions
+    pub test_patterns: Option<GlobSet>,
+}
+
+/// Whether a file is a test file
+///
+/// Custom patterns are matched against the project-relative path and the
+/// file name, so both `check_*.py` and `spec/**` work. Without patterns, a
+/// file is a test if it is named `test_*.py` / `*_test.py` or sits in a
+/// `tests` or `test` directory.
+fn is_test_file(path: &Path, rel_path: &Path, patterns: Option<&GlobSet>) -> bool {
+    match patterns {
+        Some(patterns) => {
+            patterns.is_match(rel_path)
+                || path.file_name().is_some_and(|name| patterns.is_match(name))
+        }
+        None => is_default_test_file(path),
+    }
+}
+
+fn is_default_test_file(path: &Path) -> bool {
+    // Use Path components for cross-platform compatibility (works on both / and \)
+    let filename = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let is_test_filename = filename.starts_with("test_") || filename.ends_with("_test.py");
+
+    // Check if any parent directory is named "tests" or "test"
+    let in_tests_dir = path.components().any(|c| {
+        if let std::path::Component::Normal(name) = c {
+            let name_str = name.to_string_lossy();
+            name_str == "tests" || name_str == "test"
+        } else {
+            false
+        }
+    });
+
+    is_test_filename || in_tests_dir
+}
+
+/// Check a project-relative path against the exclude set
+///
+/// Parent directories are checked too, so `migrations` excludes everything below it.
+fn is_excluded(rel_path: &Path, exclude: &GlobSet) -> bool {
+    !exclude.is_empty()
+        && rel_path
+            .ancestors()
+            .any(|p| !p.as_os_str().is_empty() && exclude.is_match(p))
+}
+
+/// Size of `path` if it exceeds `limit`
+fn oversized(path: &Path, limit: Option<u64>) -> Option<u64> {
+    let limit = limit?;
+    let len = std::fs::metadata(path).ok()?.len();
+    (len > limit).then_some(len)
 }
 
 /// Calculate fingerprint for a single Python file
 ///
 /// # Arguments
 /// * `path` - Path to the Python file
+/// * `project_root` - Optional root used to make the filename relative
+/// * `options` - `FingerprintOptions` to fingerprint with (default: `FingerprintOptions()`)
 ///
 /// # Returns
 /// * Fingerprint containing blocks, checksums, hash, and mtime
 #[pyfunction]
-#[pyo3(signature = (path, project_root=None))]
-pub fn calculate_fingerprint(path: &str, project_root: Option<&str>) -> PyResult<Fingerprint> {
-    let mut fingerprint = calculate_fingerprint_internal(path).map_err(|e| {
+#[pyo3(signature = (path, project_root=None, options=None))]
+pub fn calculate_fingerprint(
+    path: &str,
+    project_root: Option<&str>,
+    options: Option<FingerprintOptions>,
+) -> PyResult<Fingerprint> {
+    let options = options.unwrap_or_default();
+    let mut fingerprint = calculate_fingerprint_with_options(path, &options).map_err(|e| {
         pyo3::exceptions::PyIOError::new_err(format!("Failed to calculate fingerprint: {}", e))
     })?;
 
@@ -48,40 +221,300 @@ pub fn calculate_fingerprint(path: &str, project_root: Option<&str>) -> PyResult
     Ok(fingerprint)
 }
 
+#[cfg(test)]
 pub(crate) fn calculate_fingerprint_internal(path: &str) -> Result<Fingerprint> {
+    calculate_fingerprint_with_options(path, &FingerprintOptions::default())
+}
+
+pub(crate) fn calculate_fingerprint_with_options(
+    path: &str,
+    options: &FingerprintOptions,
+) -> Result<Fingerprint> {
+    calculate_fingerprint_noting(path, options).map(|(fingerprint, _)| fingerprint)
+}
+
+/// `calculate_fingerprint_with_options`, also returning the note from `decode_source`
+fn calculate_fingerprint_noting(
+    path: &str,
+    options: &FingerprintOptions,
+) -> Result<(Fingerprint, Option<String>)> {
+    let (bytes, file_hash) = read_source_and_hash(path, options)?;
+    fingerprint_from_source(path, &bytes, file_hash, options)
+}
+
+/// Read a source file and compute its file hash, without parsing it
+pub(crate) fn read_source_and_hash(
+    path: &str,
+    options: &FingerprintOptions,
+) -> Result<(Vec<u8>, String)> {
     let path = Path::new(path);
 
-    // Read file content
-    let content = std::fs::read_to_string(path)
+    // Read raw bytes; the hash covers the bytes on disk, parsing uses the decoded text
+    let bytes = read_source_bytes(path, options)
         .with_context(|| format!("Failed to read file: {}", path.display()))?;
 
     // Calculate file-level hash using Blake3 (fast!)
-    let file_hash = blake3::hash(content.as_bytes()).to_hex().to_string();
+    let file_hash = blake3::hash(&bytes).to_hex().to_string();
+
+    Ok((bytes, file_hash))
+}
+
+/// Parse source bytes read from `path` into a fingerprint
+///
+/// Also returns the note from `decode_source` when the bytes had to be decoded lossily.
+pub(crate) fn fingerprint_from_source(
+    path: &str,
+    bytes: &[u8],
+    file_hash: String,
+    options: &FingerprintOptions,
+) -> Result<(Fingerprint, Option<String>)> {
+    let (content, note) = decode_source(bytes, Path::new(path));
 
     // Parse and extract blocks
-    let blocks = parse_module_internal(&content)
+    let blocks = parse_source(&content, Path::new(path), options)
         .map_err(|e| anyhow::anyhow!("Failed to parse Python file: {}", e))?;
 
     // Extract checksums
-    let checksums: Vec<i32> = blocks.iter().map(|b| b.checksum).collect();
+    let checksums: Vec<i64> = blocks.iter().map(|b| b.checksum).collect();
+
+    let fingerprint = Fingerprint {
+        filename: String::new(),
+        checksums,
+        file_hash,
+        mtime: 0.0,
+        mtime_exact: None,
+        blocks: Some(blocks),
+    };
+    Ok((relocate_fingerprint(fingerprint, path)?, note))
+}
+
+/// Point a fingerprint at `path`, taking the filename and modification time from it
+///
+/// Lets a fingerprint computed for one file be reused for another with identical content.
+pub(crate) fn relocate_fingerprint(
+    mut fingerprint: Fingerprint,
+    path: &str,
+) -> Result<Fingerprint> {
+    let path = Path::new(path);
 
     // Get modification time
     let metadata = std::fs::metadata(path)
         .with_context(|| format!("Failed to get metadata for: {}", path.display()))?;
-    let mtime = metadata
+    let since_epoch = metadata
         .modified()
         .with_context(|| "Failed to get modification time")?
         .duration_since(UNIX_EPOCH)
-        .with_context(|| "Invalid modification time")?
-        .as_secs_f64();
+        .with_context(|| "Invalid modification time")?;
 
-    Ok(Fingerprint {
-        filename: path.to_string_lossy().to_string(),
-        checksums,
-        file_hash,
-        mtime,
-        blocks: Some(blocks),
-    })
+    fingerprint.filename = to_slash(&path.to_string_lossy());
+    fingerprint.mtime = since_epoch.as_secs_f64();
+    fingerprint.mtime_exact = Some((since_epoch.as_secs(), since_epoch.subsec_nanos()));
+    Ok(fingerprint)
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of `open_source` calls on this thread, for asserting read counts
+    static SOURCE_OPENS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Open a source file for reading; every source read goes through here
+fn open_source(path: &Path) -> std::io::Result<std::fs::File> {
+    #[cfg(test)]
+    SOURCE_OPENS.with(|opens| opens.set(opens.get() + 1));
+    std::fs::File::open(path)
+}
+
+/// Read a source file, applying line-ending normalization if enabled
+fn read_source_bytes(path: &Path, options: &FingerprintOptions) -> std::io::Result<Vec<u8>> {
+    read_open_source(&mut open_source(path)?, options)
+}
+
+/// `read_source_bytes` from an already open file, starting at its current position
+fn read_open_source(
+    file: &mut std::fs::File,
+    options: &FingerprintOptions,
+) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    if options.normalize_line_endings {
+        Ok(normalize_crlf(bytes))
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Blake3 hash of a file as stored in `Fingerprint::file_hash`
+///
+/// Large files are streamed so their content is never held in memory at once.
+pub(crate) fn file_hash(path: &Path, options: &FingerprintOptions) -> std::io::Result<String> {
+    if std::fs::metadata(path)?.len() > STREAMING_HASH_THRESHOLD {
+        return stream_file_hash(path, options);
+    }
+    let bytes = read_source_bytes(path, options)?;
+    Ok(blake3::hash(&bytes).to_hex().to_string())
+}
+
+/// `file_hash` computed chunk by chunk, normalizing CRLF across chunk boundaries
+fn stream_file_hash(path: &Path, options: &FingerprintOptions) -> std::io::Result<String> {
+    stream_open_file_hash(&mut open_source(path)?, options)
+}
+
+/// `stream_file_hash` over an already open file, reading it to the end
+fn stream_open_file_hash(
+    file: &mut std::fs::File,
+    options: &FingerprintOptions,
+) -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; HASH_CHUNK_BYTES];
+    // A chunk-final '\r' is held back until we know whether '\n' follows
+    let mut pending_cr = false;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let chunk = &buf[..n];
+        if !options.normalize_line_endings {
+            hasher.update(chunk);
+            continue;
+        }
+
+        if pending_cr && chunk[0] != b'\n' {
+            hasher.update(b"\r");
+        }
+        pending_cr = chunk[n - 1] == b'\r';
+        let body = if pending_cr { &chunk[..n - 1] } else { chunk };
+
+        let mut segment_start = 0;
+        for i in 0..body.len().saturating_sub(1) {
+            if body[i] == b'\r' && body[i + 1] == b'\n' {
+                hasher.update(&body[segment_start..i]);
+                segment_start = i + 1;
+            }
+        }
+        hasher.update(&body[segment_start..]);
+    }
+    if pending_cr {
+        hasher.update(b"\r");
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Replace every `\r\n` with `\n`, leaving lone `\r` untouched
+fn normalize_crlf(bytes: Vec<u8>) -> Vec<u8> {
+    if !bytes.windows(2).any(|w| w == b"\r\n") {
+        return bytes;
+    }
+
+    let mut normalized = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter().peekable();
+    while let Some(&b) = iter.next() {
+        if b == b'\r' && iter.peek() == Some(&&b'\n') {
+            continue;
+        }
+        normalized.push(b);
+    }
+    normalized
+}
+
+/// Decode Python source bytes to text the way the interpreter would
+///
+/// A UTF-8 BOM is stripped and a PEP 263 coding declaration on the first two lines
+/// selects the codec (UTF-8, Latin-1 or ASCII). Unsupported codecs, and bytes that
+/// are invalid for the declared codec or for UTF-8, fall back to lossy UTF-8 rather
+/// than dropping the file; the returned note says which, for the caller to report.
+fn decode_source(bytes: &[u8], path: &Path) -> (String, Option<String>) {
+    if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        return (String::from_utf8_lossy(rest).into_owned(), None);
+    }
+
+    if let Some(label) = coding_declaration(bytes) {
+        match normalize_codec(&label).as_str() {
+            "utf8" => {}
+            "latin1" | "iso88591" | "l1" | "cp819" => {
+                // Latin-1 maps every byte to the code point of the same value
+                return (bytes.iter().map(|&b| b as char).collect(), None);
+            }
+            "ascii" | "usascii" => {
+                if bytes.is_ascii() {
+                    return (String::from_utf8_lossy(bytes).into_owned(), None);
+                }
+                let note = format!(
+                    "{} declares encoding '{}' but contains non-ASCII bytes, decoding as lossy UTF-8",
+                    path.display(),
+                    label
+                );
+                return (String::from_utf8_lossy(bytes).into_owned(), Some(note));
+            }
+            _ => {
+                let note = format!(
+                    "Unsupported encoding '{}' in {}, decoding as lossy UTF-8",
+                    label,
+                    path.display()
+                );
+                return (String::from_utf8_lossy(bytes).into_owned(), Some(note));
+            }
+        }
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => {
+            let note = format!(
+                "{} is not valid UTF-8, decoding as lossy UTF-8",
+                path.display()
+            );
+            (String::from_utf8_lossy(bytes).into_owned(), Some(note))
+        }
+    }
+}
+
+/// Extract the encoding name from a PEP 263 declaration on the first two lines
+///
+/// Matches `^[ \t\f]*#.*?coding[:=][ \t]*([-\w.]+)`. The second line is only
+/// considered when the first is blank or a comment, as in CPython's tokenizer.
+fn coding_declaration(bytes: &[u8]) -> Option<String> {
+    for line in bytes.split(|&b| b == b'\n').take(2) {
+        let trimmed = line.trim_ascii_start();
+        if !trimmed.starts_with(b"#") {
+            if trimmed.is_empty() {
+                continue;
+            }
+            return None;
+        }
+
+        let line = String::from_utf8_lossy(trimmed);
+        if let Some(pos) = line.find("coding") {
+            let rest = &line[pos + "coding".len()..];
+            if let Some(rest) = rest.strip_prefix([':', '=']) {
+                let name: String = rest
+                    .trim_start_matches([' ', '\t'])
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                    .collect();
+                if !name.is_empty() {
+                    return Some(name);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Normalize a codec name so spellings like `Latin-1`, `latin_1` and `LATIN1` compare equal
+fn normalize_codec(label: &str) -> String {
+    label
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | '.'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
 }
 
 /// Save baseline fingerprints for all Python files in a project
@@ -95,40 +528,165 @@ pub(crate) fn calculate_fingerprint_internal(path: &str) -> Result<Fingerprint>
 /// * `verbose` - Whether to print debug information
 /// * `scope_paths` - List of directory paths to limit the scope (e.g., ["tests/unit/"])
 /// * `force` - Force recomputation of all fingerprints, even for unchanged files
+/// * `options` - `FingerprintOptions` to fingerprint with; pass the same options to
+///   `detect_changes` and the other functions reading this baseline
+///   (default: `FingerprintOptions()`)
+/// * `scan` - `ScanOptions` selecting the files to fingerprint (default: `ScanOptions()`)
+/// * `project_roots` - Source roots to scan instead of `project_root` alone, for
+///   monorepos; filenames stay relative to `project_root` (default: None)
+/// * `dry_run` - Scan and fingerprint without creating or writing to the database (default: false)
+/// * `collect_timings` - Also report per-file fingerprinting times (default: false)
+/// * `python_version` - Python version of the running interpreter, recorded in the
+///   baseline metadata (default: None)
+/// * `progress_callback` - Called with `(processed, total)` every 50 files and once
+///   all files are fingerprinted; calls never overlap (default: None)
+/// * `cancel_token` - `CancelToken` that stops fingerprinting early when cancelled;
+///   fingerprints computed so far are still saved (default: None)
 ///
 /// # Returns
-/// * Number of files added to baseline
+/// * `BaselineStats` with the number of files added to the baseline (or that
+///   would be, with `dry_run`) and, with `collect_timings`, `(path, seconds)` for
+///   every file that was fingerprinted (unchanged files are skipped)
 #[pyfunction]
-#[pyo3(signature = (db_path, project_root, verbose, scope_paths, force=false))]
+#[pyo3(signature = (
+    db_path,
+    project_root,
+    verbose,
+    scope_paths,
+    force=false,
+    options=None,
+    scan=None,
+    project_roots=None,
+    dry_run=false,
+    collect_timings=false,
+    python_version=None,
+    progress_callback=None,
+    cancel_token=None,
+))]
+#[allow(clippy::too_many_arguments)]
 pub fn save_baseline(
+    py: Python<'_>,
     db_path: &str,
     project_root: &str,
     verbose: bool,
     scope_paths: Vec<String>,
     force: bool,
-) -> PyResult<usize> {
-    let count = save_baseline_internal(db_path, project_root, verbose, scope_paths, force)
+    options: Option<FingerprintOptions>,
+    scan: Option<ScanOptions>,
+    project_roots: Option<Vec<String>>,
+    dry_run: bool,
+    collect_timings: bool,
+    python_version: Option<String>,
+    progress_callback: Option<Py<PyAny>>,
+    cancel_token: Option<CancelToken>,
+) -> PyResult<BaselineStats> {
+    let options = options.unwrap_or_default();
+    let scan = scan.unwrap_or_default();
+    let project_roots = project_roots.unwrap_or_else(|| vec![project_root.to_string()]);
+    // Fingerprinting runs on rayon threads, which need the GIL to call back
+    let report = progress_callback.map(|callback| {
+        move |processed: usize, total: usize| {
+            Python::with_gil(|py| {
+                if let Err(err) = callback.call1(py, (processed, total)) {
+                    err.write_unraisable(py, Some(callback.bind(py)));
+                }
+            })
+        }
+    });
+    let progress = report.as_ref().map(|report| report as &ProgressFn<'_>);
+    let run = py
+        .allow_threads(|| {
+            save_baseline_roots_internal(
+                db_path,
+                project_root,
+                &project_roots,
+                verbose,
+                scope_paths,
+                force,
+                dry_run,
+                python_version.as_deref(),
+                &options,
+                &scan,
+                progress,
+                cancel_token.as_ref(),
+            )
+        })
         .map_err(|e| {
             pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to save baseline: {}", e))
         })?;
 
-    Ok(count)
+    Ok(BaselineStats {
+        count: run.count,
+        timings: collect_timings.then_some(run.timings),
+    })
+}
+
+/// Receives `(processed, total)` file counts while a baseline is being saved
+type ProgressFn<'a> = dyn Fn(usize, usize) + Sync + 'a;
+
+/// Outcome of a `save_baseline` run
+struct BaselineRun {
+    /// Files covered by the baseline (unchanged plus updated)
+    count: usize,
+    /// `(path, seconds)` spent fingerprinting each file that was (re)computed
+    timings: Vec<(String, f64)>,
 }
 
+#[cfg(test)]
 fn save_baseline_internal(
     db_path: &str,
     project_root: &str,
     verbose: bool,
     scope_paths: Vec<String>,
     force: bool,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
 ) -> Result<usize> {
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    save_baseline_roots_internal(
+        db_path,
+        project_root,
+        &[project_root.to_string()],
+        verbose,
+        scope_paths,
+        force,
+        false,
+        None,
+        options,
+        scan,
+        None,
+        None,
+    )
+    .map(|run| run.count)
+}
+
+/// `save_baseline` scanning several source roots
+///
+/// Stored filenames are relative to `project_root`, which need not be one
+/// of `project_roots`. With `dry_run` the database is only read, through
+/// `read_baseline_fingerprints`, so a missing one is not created.
+#[allow(clippy::too_many_arguments)]
+fn save_baseline_roots_internal(
+    db_path: &str,
+    project_root: &str,
+    project_roots: &[String],
+    verbose: bool,
+    scope_paths: Vec<String>,
+    force: bool,
+    dry_run: bool,
+    python_version: Option<&str>,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
+    progress: Option<&ProgressFn<'_>>,
+    cancel: Option<&CancelToken>,
+) -> Result<BaselineRun> {
     use std::sync::Arc;
     use std::time::Instant;
 
     let start = Instant::now();
-    let mut db = PytestDiffDatabase::open(db_path)?;
-    if verbose {
+    let db = (!dry_run)
+        .then(|| PytestDiffDatabase::open(db_path))
+        .transpose()?;
+    if verbose && db.is_some() {
         eprintln!(
             "[rust] Database opened in {:.3}s",
             start.elapsed().as_secs_f64()
@@ -136,8 +694,12 @@ fn save_baseline_internal(
     }
 
     let find_start = Instant::now();
-    let python_files = find_python_files(project_root, &scope_paths)?;
+    let mut skipped = Vec::new();
+    let python_files = find_python_files_in_roots(project_roots, &scope_paths, scan, &mut skipped)?;
     if verbose {
+        for warning in &skipped {
+            eprintln!("⚠ pytest-difftest: {}", warning);
+        }
         eprintln!(
             "pytest-difftest: Scanning {} Python files... ({:.3}s)",
             python_files.len(),
@@ -147,7 +709,10 @@ fn save_baseline_internal(
 
     // Load ALL existing baselines in a single query (much faster than N queries)
     let baseline_start = Instant::now();
-    let existing_baselines = db.get_all_baseline_fingerprints()?;
+    let existing_baselines = slash_keys(match &db {
+        Some(db) => db.get_all_baseline_fingerprints()?,
+        None => PytestDiffDatabase::read_baseline_fingerprints(db_path)?,
+    });
 
     if verbose {
         eprintln!(
@@ -163,6 +728,8 @@ fn save_baseline_internal(
     // Progress counters for parallel processing
     let progress_counter = Arc::new(AtomicUsize::new(0));
     let skipped_unchanged = Arc::new(AtomicUsize::new(0));
+    // Serializes progress callbacks and keeps the reported count increasing
+    let last_reported = parking_lot::Mutex::new(0);
 
     if verbose {
         let mode = if force { "force" } else { "incremental" };
@@ -171,11 +738,14 @@ fn save_baseline_internal(
 
     // PARALLEL: Calculate fingerprints, skipping unchanged files (unless force=true)
     let fp_calc_start = Instant::now();
-    let fingerprints: Vec<(String, Option<Fingerprint>)> = python_files
+    let fingerprints: Vec<(String, Option<Fingerprint>, Option<f64>)> = python_files
         .par_iter()
         .map(|path| {
             let path_str = path.to_string_lossy().to_string();
             let rel_path = make_relative(&path_str, project_root);
+            if is_cancelled(cancel) {
+                return (rel_path, None, None);
+            }
 
             // Update progress counter
             let count = progress_counter.fetch_add(1, Ordering::Relaxed) + 1;
@@ -188,19 +758,28 @@ fn save_baseline_internal(
                     count as f64 / total_files as f64 * 100.0
                 );
             }
+            if let Some(progress) = progress {
+                if count.is_multiple_of(50) || count == total_files {
+                    let mut last = last_reported.lock();
+                    if count > *last {
+                        *last = count;
+                        progress(count, total_files);
+                    }
+                }
+            }
 
             // Check if we can skip this file (hash unchanged) - only when not forcing
             // Lookup by relative path since baselines are stored with relative paths
             if !force {
                 if let Some(existing) = existing_baselines.get(&rel_path) {
                     // Compute Blake3 hash (cheap: ~1ms for typical file)
-                    if let Ok(content) = std::fs::read_to_string(path) {
-                        let current_hash = blake3::hash(content.as_bytes()).to_hex().to_string();
+                    if let Ok(bytes) = read_source_bytes(path, options) {
+                        let current_hash = blake3::hash(&bytes).to_hex().to_string();
 
                         if current_hash == existing.file_hash {
                             // Hash matches - file content unchanged, skip expensive AST parsing
                             skipped_unchanged.fetch_add(1, Ordering::Relaxed);
-                            return (rel_path, None); // None means "keep existing"
+                            return (rel_path, None, None); // None means "keep existing"
                         }
                     }
                 }
@@ -208,7 +787,8 @@ fn save_baseline_internal(
 
             // File is new or changed (or force=true) - compute full fingerprint
             let fp_start = Instant::now();
-            let result = calculate_fingerprint_internal(&path_str);
+            let result = calculate_fingerprint_noting(&path_str, options);
+            let elapsed = fp_start.elapsed().as_secs_f64();
 
             // Log slow files
             if verbose && fp_start.elapsed().as_millis() > 100 {
@@ -220,16 +800,19 @@ fn save_baseline_internal(
             }
 
             match result {
-                Ok(mut fp) => {
+                Ok((mut fp, note)) => {
+                    if let Some(note) = note.filter(|_| verbose) {
+                        eprintln!("⚠ pytest-difftest: {}", note);
+                    }
                     // Store relative path in the fingerprint
                     fp.filename = rel_path.clone();
-                    (rel_path, Some(fp))
+                    (rel_path, Some(fp), Some(elapsed))
                 }
                 Err(e) => {
                     if verbose {
                         eprintln!("[rust]   Skipping {}: {}", rel_path, e);
                     }
-                    (rel_path, None)
+                    (rel_path, None, None)
                 }
             }
         })
@@ -250,16 +833,50 @@ fn save_baseline_internal(
     // SEQUENTIAL: Save only changed fingerprints to database
     let db_save_start = Instant::now();
     let mut fingerprints_to_save = Vec::new();
+    let mut timings = Vec::new();
 
-    for (_path_str, maybe_fp) in fingerprints {
+    for (path_str, maybe_fp, elapsed) in fingerprints {
         if let Some(fp) = maybe_fp {
             fingerprints_to_save.push(fp);
         }
+        if let Some(elapsed) = elapsed {
+            timings.push((path_str, elapsed));
+        }
         // If None and we have existing baseline, it's already in DB (unchanged)
         // If None and no existing baseline, it was an error (already logged)
     }
 
+    // Overlapping roots or symlinks can reach one file under two paths that
+    // map to the same relative filename; keep the last fingerprint computed
+    let mut seen = HashSet::new();
+    fingerprints_to_save.reverse();
+    fingerprints_to_save.retain(|fp| seen.insert(fp.filename.clone()));
+    fingerprints_to_save.reverse();
+
+    if verbose {
+        for fp in &fingerprints_to_save {
+            for warning in checksum_collision_warnings(fp) {
+                eprintln!("⚠ pytest-difftest: {}", warning);
+            }
+        }
+    }
+
     let changed_count = fingerprints_to_save.len();
+    let Some(mut db) = db else {
+        if verbose {
+            eprintln!(
+                "pytest-difftest: Dry run, {} fingerprints would be written ({} unchanged) in {:.3}s",
+                changed_count,
+                unchanged_count,
+                processing_start.elapsed().as_secs_f64()
+            );
+        }
+        return Ok(BaselineRun {
+            count: unchanged_count + changed_count,
+            timings,
+        });
+    };
+
     let count = if changed_count > 0 {
         if verbose {
             eprint!(
@@ -292,609 +909,4079 @@ fn save_baseline_internal(
         );
     }
 
+    // A cancelled run only covers part of the tree, so it doesn't count as
+    // a fresh baseline
+    if is_cancelled(cancel) {
+        if verbose {
+            eprintln!("pytest-difftest: Baseline save was cancelled");
+        }
+    } else {
+        let saved_at = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs_f64();
+        db.set_metadata_internal(BASELINE_SAVED_AT_KEY, &saved_at.to_string())?;
+        record_baseline_environment(&db, python_version, options)?;
+    }
+
     // Checkpoint WAL to remove -wal and -shm files
     db.close_and_checkpoint()?;
 
     // Return total baseline count (unchanged + updated)
-    Ok(unchanged_count + count)
+    Ok(BaselineRun {
+        count: unchanged_count + count,
+        timings,
+    })
 }
 
-/// Detect changes between current filesystem state and database
+/// Describe blocks of a file that share a checksum despite having different names
 ///
-/// Uses three-level change detection for optimal performance:
-/// 1. mtime check (fastest - file modification time)
-/// 2. file hash check (fast - blake3 hash of entire file)
-/// 3. block checksum comparison (precise - per-function/class checksums)
+/// Checksums are truncated CRC32 values, so distinct blocks can collide; a
+/// change to one of them then goes unnoticed whenever the other still exists.
+/// Blocks with the same qualified name (e.g. identical redefinitions) are not
+/// reported, since their source can legitimately be equal.
+fn checksum_collision_warnings(fp: &Fingerprint) -> Vec<String> {
+    let Some(blocks) = &fp.blocks else {
+        return Vec::new();
+    };
+
+    let mut names_by_checksum: HashMap<i64, Vec<&str>> = HashMap::new();
+    for block in blocks {
+        let names = names_by_checksum.entry(block.checksum).or_default();
+        if !names.contains(&block.qualified_name.as_str()) {
+            names.push(&block.qualified_name);
+        }
+    }
+
+    let mut warnings: Vec<String> = names_by_checksum
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|(checksum, names)| {
+            format!(
+                "Checksum collision in {}: {} share checksum {}; changes to one may go unnoticed",
+                fp.filename,
+                names.join(", "),
+                checksum
+            )
+        })
+        .collect();
+    warnings.sort();
+    warnings
+}
+
+/// Refresh the baseline of a single file without rescanning the project
+///
+/// Creates the baseline entry if the file has none, otherwise replaces its
+/// mtime, hash and checksums. Other baseline entries are left untouched.
 ///
 /// # Arguments
 /// * `db_path` - Path to the pytest-difftest database
-/// * `project_root` - Root directory of the project
-/// * `scope_paths` - List of directory paths to limit the scope (e.g., ["tests/unit/"])
+/// * `path` - File to refresh (relative paths are resolved against `project_root`)
+/// * `project_root` - Root directory used to store the filename relative to it
+/// * `options` - `FingerprintOptions` the baseline was saved with
+///   (default: `FingerprintOptions()`)
 ///
 /// # Returns
-/// * ChangedFiles containing list of modified files and changed blocks
+/// * The fingerprint that was stored
 #[pyfunction]
-pub fn detect_changes(
+#[pyo3(signature = (db_path, path, project_root=None, options=None))]
+pub fn update_baseline_file(
     db_path: &str,
-    project_root: &str,
-    scope_paths: Vec<String>,
-) -> PyResult<ChangedFiles> {
-    let changes = detect_changes_internal(db_path, project_root, scope_paths).map_err(|e| {
-        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to detect changes: {}", e))
-    })?;
-
-    Ok(changes)
+    path: &str,
+    project_root: Option<&str>,
+    options: Option<FingerprintOptions>,
+) -> PyResult<Fingerprint> {
+    let options = options.unwrap_or_default();
+    update_baseline_file_internal(db_path, path, project_root, &options).map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to update baseline: {}", e))
+    })
 }
 
-fn detect_changes_internal(
+fn update_baseline_file_internal(
     db_path: &str,
-    project_root: &str,
-    scope_paths: Vec<String>,
-) -> Result<ChangedFiles> {
-    // Open database
-    let db = PytestDiffDatabase::open(db_path)?;
-
-    // Find all Python files in the project
-    let python_files = find_python_files(project_root, &scope_paths)?;
+    path: &str,
+    project_root: Option<&str>,
+    options: &FingerprintOptions,
+) -> Result<Fingerprint> {
+    let abs_path = match project_root {
+        Some(root) if Path::new(path).is_relative() => Path::new(root).join(path),
+        _ => PathBuf::from(path),
+    };
 
-    // Load ALL baselines in a single query (much faster than N queries)
-    let baselines = db.get_all_baseline_fingerprints()?;
+    let mut fingerprint = calculate_fingerprint_with_options(&abs_path.to_string_lossy(), options)?;
+    if let Some(root) = project_root {
+        fingerprint.filename = make_relative(&fingerprint.filename, root);
+    }
+    // Blocks are not stored in the baseline table
+    fingerprint.blocks = None;
 
-    // Process files in PARALLEL using rayon
-    // Now that we have all baselines in memory, we don't need DB access per file
-    let changed_entries: Vec<_> = python_files
-        .par_iter()
-        .filter_map(|path| {
-            let abs_path = path.to_string_lossy().to_string();
-            let rel_path = make_relative(&abs_path, project_root);
-            match check_file_changed_with_baseline(&baselines, path, &rel_path) {
-                Ok(Some(change)) => Some(change),
-                Ok(None) => None,
-                Err(_) => None,
-            }
-        })
-        .collect();
+    let mut db = PytestDiffDatabase::open(db_path)?;
+    db.save_baseline_fingerprint_internal(fingerprint.clone())?;
 
-    // Separate modified files from changed blocks
-    let mut modified = Vec::new();
-    let mut changed_blocks = HashMap::new();
+    Ok(fingerprint)
+}
 
-    for (file, blocks) in changed_entries {
-        modified.push(file.clone());
-        if !blocks.is_empty() {
-            changed_blocks.insert(file, blocks);
-        }
-    }
-
-    Ok(ChangedFiles {
-        modified,
-        changed_blocks,
+/// Compare a single file with its baseline, reporting checksums in detail
+///
+/// The diagnostic counterpart of change detection: instead of a verdict it
+/// returns the stored and current checksums, the changed set detection would
+/// report, and the blocks of a fresh parse. Nothing is written.
+///
+/// # Arguments
+/// * `db_path` - Path to the pytest-difftest database
+/// * `path` - File to inspect (relative paths are resolved against `project_root`)
+/// * `project_root` - Root directory the baseline filenames are relative to
+/// * `options` - `FingerprintOptions` the baseline was saved with, so the current
+///   checksums are comparable (default: `FingerprintOptions()`)
+///
+/// # Returns
+/// * A FileInspection for the file
+#[pyfunction]
+#[pyo3(signature = (db_path, path, project_root=None, options=None))]
+pub fn inspect_file(
+    db_path: &str,
+    path: &str,
+    project_root: Option<&str>,
+    options: Option<FingerprintOptions>,
+) -> PyResult<FileInspection> {
+    let options = options.unwrap_or_default();
+    inspect_file_internal(db_path, path, project_root, &options).map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to inspect file: {}", e))
     })
 }
 
-/// Check if a file has changed using three-level detection (with pre-loaded baseline)
-///
-/// This version takes a pre-loaded HashMap of baselines for parallel processing.
-/// `rel_filename` is the path relative to project root, used for DB lookups and return values.
-/// `path` is the absolute path, used for file I/O operations.
-fn check_file_changed_with_baseline(
-    baselines: &HashMap<String, Fingerprint>,
-    path: &Path,
-    rel_filename: &str,
-) -> Result<Option<(String, Vec<i32>)>> {
-    // Get baseline fingerprint from pre-loaded map (keyed by relative path)
-    let stored_fp = match baselines.get(rel_filename) {
-        Some(fp) => fp,
-        None => {
-            // No baseline for this file - it's new, treat as changed
-            // Parse to get checksums so new tests in this file can be selected
-            let current_fp = calculate_fingerprint_internal(path.to_string_lossy().as_ref())?;
-            let checksums = current_fp.checksums.clone();
-            return Ok(Some((rel_filename.to_string(), checksums)));
-        }
+fn inspect_file_internal(
+    db_path: &str,
+    path: &str,
+    project_root: Option<&str>,
+    options: &FingerprintOptions,
+) -> Result<FileInspection> {
+    let abs_path = match project_root {
+        Some(root) if Path::new(path).is_relative() => Path::new(root).join(path),
+        _ => PathBuf::from(path),
     };
 
-    // Level 1: mtime check (fastest)
-    let metadata = std::fs::metadata(path)?;
-    let current_mtime = metadata
-        .modified()?
-        .duration_since(UNIX_EPOCH)?
-        .as_secs_f64();
-
-    if (current_mtime - stored_fp.mtime).abs() < 0.001 {
-        // mtime unchanged - file definitely not modified
-        return Ok(None);
-    }
+    let fingerprint = calculate_fingerprint_with_options(&abs_path.to_string_lossy(), options)?;
+    let filename = match project_root {
+        Some(root) => make_relative(&fingerprint.filename, root),
+        None => fingerprint.filename.clone(),
+    };
 
-    // Level 2: file hash check (fast)
-    let content = std::fs::read_to_string(path)?;
-    let current_hash = blake3::hash(content.as_bytes()).to_hex().to_string();
+    let db = PytestDiffDatabase::open(db_path)?;
+    let baseline_checksums = db
+        .get_baseline_fingerprint_rust(&filename)?
+        .map(|stored| stored.checksums);
 
-    if current_hash == stored_fp.file_hash {
-        // Hash unchanged - content is identical (mtime changed but not content)
-        return Ok(None);
-    }
+    let current_checksums = fingerprint.checksums;
+    let changed_checksums = match &baseline_checksums {
+        Some(stored) => find_changed_checksums(stored, &current_checksums),
+        None => current_checksums.clone(),
+    };
 
-    // Level 3: block checksum comparison (precise)
-    let current_blocks = parse_module_internal(&content)
-        .map_err(|e| anyhow::anyhow!("Parse error in {}: {}", rel_filename, e))?;
+    Ok(FileInspection {
+        filename,
+        baseline_checksums,
+        current_checksums,
+        changed_checksums,
+        blocks: fingerprint.blocks.unwrap_or_default(),
+    })
+}
 
-    let current_checksums: Vec<i32> = current_blocks.iter().map(|b| b.checksum).collect();
+/// Re-hash every baseline file and compare it with the stored file hash
+///
+/// Only file hashes are compared, nothing is parsed, and the baseline is
+/// not modified.
+///
+/// # Arguments
+/// * `db_path` - Path to the pytest-difftest database
+/// * `project_root` - Root directory the baseline filenames are relative to
+/// * `options` - `FingerprintOptions`; `normalize_line_endings` must match the value
+///   the baseline was saved with (default: `FingerprintOptions()`)
+///
+/// # Returns
+/// * BaselineVerification listing `matching`, `mismatched` and `missing` files, sorted
+#[pyfunction]
+#[pyo3(signature = (db_path, project_root, options=None))]
+pub fn verify_baseline(
+    db_path: &str,
+    project_root: &str,
+    options: Option<FingerprintOptions>,
+) -> PyResult<BaselineVerification> {
+    let options = options.unwrap_or_default();
+    verify_baseline_internal(db_path, project_root, &options).map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to verify baseline: {}", e))
+    })
+}
 
-    if current_checksums == stored_fp.checksums {
-        // Checksums unchanged - semantically equivalent (e.g., only whitespace/comments changed)
-        return Ok(None);
-    }
+fn verify_baseline_internal(
+    db_path: &str,
+    project_root: &str,
+    options: &FingerprintOptions,
+) -> Result<BaselineVerification> {
+    let baselines = PytestDiffDatabase::open(db_path)?.get_all_baseline_fingerprints()?;
 
-    // Find which specific blocks changed
-    let changed_checksums = find_changed_checksums(&stored_fp.checksums, &current_checksums);
+    let mut results: Vec<(String, Option<bool>)> = baselines
+        .into_par_iter()
+        .map(|(filename, stored)| {
+            let path = Path::new(project_root).join(&filename);
+            let matches = if !path.exists() {
+                None
+            } else {
+                // An unreadable file can't be shown to match
+                let current = read_source_and_hash(&path.to_string_lossy(), options);
+                Some(current.is_ok_and(|(_, hash)| hash == stored.file_hash))
+            };
+            (filename, matches)
+        })
+        .collect();
+    results.sort();
 
-    Ok(Some((rel_filename.to_string(), changed_checksums)))
+    let mut verification = BaselineVerification::default();
+    for (filename, matches) in results {
+        match matches {
+            Some(true) => verification.matching.push(filename),
+            Some(false) => verification.mismatched.push(filename),
+            None => verification.missing.push(filename),
+        }
+    }
+    Ok(verification)
 }
 
-/// Find all Python files in a directory
+/// Number of blocks in each Python file of a project, for metrics
 ///
-/// Scope paths only apply to test files - source files are always included.
-/// This ensures that when running a subset of tests, we still track all source
-/// file dependencies.
-fn find_python_files(root: &str, scope_paths: &[String]) -> Result<Vec<PathBuf>> {
-    let mut files = Vec::new();
+/// Files are parsed in parallel; the database is not touched. Files that fail
+/// to read or parse are left out.
+///
+/// # Arguments
+/// * `project_root` - Root directory to scan
+/// * `scope_paths` - Directory paths limiting which test files are scanned, as in
+///   `save_baseline`
+/// * `sort_by_count` - Order by block count, largest first, instead of by
+///   filename (default: false)
+/// * `options` - `FingerprintOptions` to parse with; `ignore_block_types` leaves
+///   those blocks out of the counts (default: `FingerprintOptions()`)
+/// * `scan` - `ScanOptions` selecting the files to count (default: `ScanOptions()`)
+///
+/// # Returns
+/// * `(filename, block_count)` pairs, filenames relative to `project_root`
+#[pyfunction]
+#[pyo3(signature = (project_root, scope_paths, sort_by_count=false, options=None, scan=None))]
+pub fn block_counts(
+    project_root: &str,
+    scope_paths: Vec<String>,
+    sort_by_count: bool,
+    options: Option<FingerprintOptions>,
+    scan: Option<ScanOptions>,
+) -> PyResult<Vec<(String, usize)>> {
+    block_counts_internal(
+        project_root,
+        &scope_paths,
+        sort_by_count,
+        &options.unwrap_or_default(),
+        &scan.unwrap_or_default(),
+    )
+    .map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to count blocks: {}", e))
+    })
+}
 
-    // Convert root to absolute path
-    let root_path = std::fs::canonicalize(root).unwrap_or_else(|_| PathBuf::from(root));
+fn block_counts_internal(
+    project_root: &str,
+    scope_paths: &[String],
+    sort_by_count: bool,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
+) -> Result<Vec<(String, usize)>> {
+    let python_files =
+        find_python_files_with_warnings(project_root, scope_paths, scan, &mut Vec::new())?;
 
-    // Convert scope paths to absolute PathBufs for comparison
-    let scope_paths_abs: Vec<PathBuf> = scope_paths
-        .iter()
-        .map(|p| {
-            let path = PathBuf::from(p);
-            std::fs::canonicalize(&path).unwrap_or(path)
+    let mut counts: Vec<(String, usize)> = python_files
+        .par_iter()
+        .filter_map(|path| {
+            let bytes = read_source_bytes(path, options).ok()?;
+            let (content, _) = decode_source(&bytes, path);
+            let blocks = parse_source(&content, path, options).ok()?;
+            Some((
+                make_relative(&path.to_string_lossy(), project_root),
+                blocks.len(),
+            ))
         })
         .collect();
 
-    for entry in WalkDir::new(&root_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_entry(|e| {
-            // Skip hidden directories and common non-source directories
-            let name = e.file_name().to_string_lossy();
-            if name.starts_with('.') || name == "__pycache__" || name == "node_modules" {
-                return false;
-            }
-            // Skip Python virtual environments (identified by pyvenv.cfg marker)
-            if e.file_type().is_dir() && e.path().join("pyvenv.cfg").exists() {
-                return false;
-            }
-            true
-        })
-    {
-        let entry = entry?;
-        let path = entry.path();
+    if sort_by_count {
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    } else {
+        counts.sort();
+    }
+    Ok(counts)
+}
 
-        // Only include .py files
-        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("py") {
-            // Store absolute path
-            let abs_path = if path.is_absolute() {
-                path.to_path_buf()
-            } else {
-                std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
-            };
+/// When the baseline was last saved and how many files it covers
+///
+/// # Arguments
+/// * `db_path` - Path to the pytest-difftest database
+///
+/// # Returns
+/// * `(saved_at, file_count)`: `saved_at` is a Unix timestamp, or None if the
+///   baseline was never saved by `save_baseline` (e.g. created by an older version)
+#[pyfunction]
+pub fn baseline_age(db_path: &str) -> PyResult<(Option<f64>, usize)> {
+    baseline_age_internal(db_path).map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to read baseline age: {}", e))
+    })
+}
 
-            // Determine if this is a test file
-            let filename = abs_path.file_name().and_then(|s| s.to_str()).unwrap_or("");
-            let is_test_filename = filename.starts_with("test_") || filename.ends_with("_test.py");
-            let in_tests_dir = abs_path.components().any(|c| {
-                if let std::path::Component::Normal(name) = c {
-                    let name_str = name.to_string_lossy();
-                    name_str == "tests" || name_str == "test"
-                } else {
-                    false
-                }
-            });
-            let is_test_file = is_test_filename || in_tests_dir;
+fn baseline_age_internal(db_path: &str) -> Result<(Option<f64>, usize)> {
+    let db = PytestDiffDatabase::open(db_path)?;
+    let saved_at = db
+        .get_metadata_internal(BASELINE_SAVED_AT_KEY)?
+        .and_then(|value| value.parse().ok());
+    let file_count = db.list_baseline_files_internal()?.len();
+    Ok((saved_at, file_count))
+}
 
-            // Scope paths only apply to test files
-            // Source files are always included
-            if is_test_file && !scope_paths_abs.is_empty() {
-                let in_scope = scope_paths_abs
-                    .iter()
-                    .any(|scope| abs_path.starts_with(scope));
-                if !in_scope {
-                    continue; // Skip test files outside scope
-                }
-            }
+/// Store the checksum scheme, crate version and Python version behind a baseline
+fn record_baseline_environment(
+    db: &PytestDiffDatabase,
+    python_version: Option<&str>,
+    options: &FingerprintOptions,
+) -> Result<()> {
+    db.set_metadata_internal(CHECKSUM_ALGORITHM_KEY, &options.checksum_scheme())?;
+    db.set_metadata_internal(CRATE_VERSION_KEY, env!("CARGO_PKG_VERSION"))?;
+    if let Some(version) = python_version {
+        db.set_metadata_internal(PYTHON_VERSION_KEY, version)?;
+    }
+    Ok(())
+}
 
-            files.push(abs_path);
+/// Differences between the environment that saved the baseline and this one
+///
+/// The checksum scheme catches both a different algorithm and options (e.g. `seed`
+/// or `ast_checksum`) that differ from the ones the baseline was saved with.
+/// Baselines from versions that did not record their environment produce no warnings.
+/// Python versions are compared on major.minor only, so patch upgrades stay quiet.
+fn baseline_environment_warnings(
+    db: &PytestDiffDatabase,
+    python_version: Option<&str>,
+    options: &FingerprintOptions,
+) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+
+    let current = options.checksum_scheme();
+    if let Some(stored) = db.get_metadata_internal(CHECKSUM_ALGORITHM_KEY)? {
+        if stored != current {
+            warnings.push(format!(
+                "Baseline checksums were computed with '{}' but this run uses '{}'; every block may look changed until the baseline is saved again",
+                stored, current
+            ));
         }
     }
 
-    Ok(files)
+    if let (Some(stored), Some(current)) = (
+        db.get_metadata_internal(PYTHON_VERSION_KEY)?,
+        python_version,
+    ) {
+        if minor_version(&stored) != minor_version(current) {
+            warnings.push(format!(
+                "Baseline was saved with Python {} but this run uses Python {}; parse results may differ",
+                stored, current
+            ));
+        }
+    }
+
+    Ok(warnings)
 }
 
-/// Find which OLD checksums were removed/modified (these indicate blocks that changed)
+/// `major.minor` part of a Python version string
+fn minor_version(version: &str) -> &str {
+    match version.match_indices('.').nth(1) {
+        Some((i, _)) => &version[..i],
+        None => version,
+    }
+}
+
+/// Number of `files` (relative to `project_root`) a scan with `scope_paths` would find
 ///
-/// Returns the OLD checksums that are no longer present in the new version.
-/// These are the checksums that tests may have used, so any test that used
-/// these blocks should be re-run to verify the changes.
-fn find_changed_checksums(old_checksums: &[i32], new_checksums: &[i32]) -> Vec<i32> {
-    let new_set: std::collections::HashSet<i32> = new_checksums.iter().copied().collect();
+/// Scope paths only narrow test files, as in `find_python_files_with_warnings`.
+fn count_in_scope<'a>(
+    files: impl Iterator<Item = &'a String>,
+    project_root: &str,
+    scope_paths: &[String],
+) -> Result<usize> {
+    let scope = Scope::new(scope_paths)?;
+    if scope.is_empty() {
+        return Ok(files.count());
+    }
+    let root = std::fs::canonicalize(project_root).unwrap_or_else(|_| PathBuf::from(project_root));
+    Ok(files
+        .filter(|file| {
+            let abs_path = root.join(file);
+            !is_default_test_file(&abs_path) || scope.contains(&abs_path, Some(Path::new(file)))
+        })
+        .count())
+}
 
-    // Return OLD checksums that are no longer in the new version
-    // These represent blocks that were removed or modified
-    old_checksums
-        .iter()
-        .copied()
-        .filter(|checksum| !new_set.contains(checksum))
-        .collect()
+/// Whether the scanned and baseline file counts differ by more than half of
+/// the larger one. An empty baseline is a first run, not a mismatch.
+fn baseline_counts_diverge(baseline_count: usize, scanned_count: usize) -> bool {
+    baseline_count > 0
+        && baseline_count.abs_diff(scanned_count) * 2 > baseline_count.max(scanned_count)
 }
 
-/// Process coverage data and return filtered fingerprints
+/// Detect changes between current filesystem state and database
 ///
-/// This function does the heavy lifting of coverage processing in Rust with
-/// parallel processing for better performance. It:
-/// 1. Filters files (only .py files in project, excludes other test files)
-/// 2. Calculates fingerprints for each file (using cache if provided)
-/// 3. Filters blocks to only those that were executed (block-level granularity)
-/// 4. Returns fingerprints with only executed blocks
+/// Uses three-level change detection for optimal performance:
+/// 1. mtime check (fastest - file modification time)
+/// 2. file hash check (fast - blake3 hash of entire file)
+/// 3. block checksum comparison (precise - per-function/class checksums)
 ///
 /// # Arguments
-/// * `coverage_data` - Map of filename -> list of executed line numbers
+/// * `db_path` - Path to the pytest-difftest database
 /// * `project_root` - Root directory of the project
-/// * `test_file` - Path to the current test file (to filter out other test files)
-/// * `verbose` - Whether to print debug information
 /// * `scope_paths` - List of directory paths to limit the scope (e.g., ["tests/unit/"])
-/// * `cache` - Optional FingerprintCache to avoid re-parsing files
+/// * `options` - `FingerprintOptions`; the options that change checksums must match
+///   the ones the baseline was saved with. Its `granularity` and `refresh_mtimes`
+///   apply to detection only (default: `FingerprintOptions()`)
+/// * `scan` - `ScanOptions` selecting the files to check (default: `ScanOptions()`)
+/// * `project_roots` - Source roots to scan instead of `project_root` alone, for
+///   monorepos; filenames stay relative to `project_root` (default: None)
+/// * `python_version` - Python version of the running interpreter, compared with
+///   the one recorded in the baseline (default: None)
+/// * `report_unreferenced` - Also list modified or deleted files that no recorded
+///   test depends on in `unreferenced_changes` (default: false)
+/// * `cancel_token` - `CancelToken` that stops the scan early when cancelled; files
+///   not checked by then are listed in `warnings` (default: None)
+/// * `safe_mode` - Set `run_all` instead of returning a result that can't be trusted:
+///   no baseline, a database that fails to open or its integrity check, more than
+///   10 errored files, a failed or cancelled scan (default: false)
 ///
 /// # Returns
-/// * List of Fingerprint objects with only executed blocks
+/// * ChangedFiles containing modified, added and deleted files and changed blocks
 #[pyfunction]
-#[pyo3(signature = (coverage_data, project_root, test_file, verbose, scope_paths, cache=None))]
-pub fn process_coverage_data(
-    coverage_data: HashMap<String, Vec<usize>>,
+#[pyo3(signature = (
+    db_path,
+    project_root,
+    scope_paths,
+    options=None,
+    scan=None,
+    project_roots=None,
+    python_version=None,
+    report_unreferenced=false,
+    cancel_token=None,
+    safe_mode=false,
+))]
+#[allow(clippy::too_many_arguments)]
+pub fn detect_changes(
+    py: Python<'_>,
+    db_path: &str,
     project_root: &str,
-    test_file: &str,
-    verbose: bool,
     scope_paths: Vec<String>,
-    cache: Option<&crate::fingerprint_cache::FingerprintCache>,
-) -> PyResult<Vec<Fingerprint>> {
-    let fingerprints = process_coverage_data_internal(
-        coverage_data,
+    options: Option<FingerprintOptions>,
+    scan: Option<ScanOptions>,
+    project_roots: Option<Vec<String>>,
+    python_version: Option<String>,
+    report_unreferenced: bool,
+    cancel_token: Option<CancelToken>,
+    safe_mode: bool,
+) -> PyResult<ChangedFiles> {
+    let options = options.unwrap_or_default();
+    let scan = scan.unwrap_or_default();
+    let project_roots = project_roots.unwrap_or_else(|| vec![project_root.to_string()]);
+    let run_scan = || {
+        detect_changes_scan(
+            db_path,
+            project_root,
+            &project_roots,
+            scope_paths,
+            python_version.as_deref(),
+            &options,
+            &scan,
+            cancel_token.as_ref(),
+        )
+    };
+    // Released so another thread can cancel the scan while it runs
+    let mut changes = py
+        .allow_threads(|| {
+            if safe_mode {
+                Ok(safe_mode_changes(db_path, run_scan, cancel_token.as_ref()))
+            } else {
+                run_scan().map(|(changes, _)| changes)
+            }
+        })
+        .map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to detect changes: {}", e))
+        })?;
+
+    if report_unreferenced && !changes.run_all {
+        changes.unreferenced_changes = unreferenced_changes(db_path, &changes).map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to cross-reference test mappings: {}",
+                e
+            ))
+        })?;
+    }
+
+    Ok(changes)
+}
+
+/// Run `scan` for `safe_mode`, flagging `run_all` whenever its result is ambiguous
+///
+/// The database is checked first, so a missing baseline or a broken database
+/// doesn't cost a scan. Never fails: errors become a `run_all` result.
+fn safe_mode_changes(
+    db_path: &str,
+    scan: impl FnOnce() -> Result<(ChangedFiles, usize)>,
+    cancel: Option<&CancelToken>,
+) -> ChangedFiles {
+    let run_all = |reason: String| ChangedFiles {
+        run_all: true,
+        warnings: vec![reason],
+        ..ChangedFiles::default()
+    };
+
+    let db_problem = PytestDiffDatabase::open(db_path).and_then(|db| {
+        if let Some(problem) = db.integrity_problems_internal()?.into_iter().next() {
+            return Ok(Some(format!(
+                "Database integrity check failed: {}",
+                problem
+            )));
+        }
+        let no_baseline = db.list_baseline_files_internal()?.is_empty();
+        Ok(no_baseline.then(|| "No baseline has been saved".to_string()))
+    });
+    match db_problem {
+        Ok(Some(reason)) => return run_all(reason),
+        Err(e) => return run_all(format!("Could not read the database: {}", e)),
+        Ok(None) => {}
+    }
+
+    let mut changes = match scan() {
+        Ok((changes, _)) => changes,
+        Err(e) => return run_all(format!("Change detection failed: {}", e)),
+    };
+    if changes.errored.len() > SAFE_MODE_MAX_ERRORED {
+        changes.warnings.push(format!(
+            "{} files could not be checked",
+            changes.errored.len()
+        ));
+        changes.run_all = true;
+    }
+    // The cancellation warning was already added by the scan
+    if is_cancelled(cancel) {
+        changes.run_all = true;
+    }
+    changes
+}
+
+fn detect_changes_internal(
+    db_path: &str,
+    project_root: &str,
+    scope_paths: Vec<String>,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
+) -> Result<ChangedFiles> {
+    let roots = [project_root.to_string()];
+    let (changes, _) = detect_changes_scan(
+        db_path,
         project_root,
-        test_file,
-        verbose,
+        &roots,
         scope_paths,
-        cache,
-    )
-    .map_err(|e| {
-        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to process coverage data: {}", e))
-    })?;
-
-    Ok(fingerprints)
+        None,
+        options,
+        scan,
+        None,
+    )?;
+    Ok(changes)
 }
 
-fn process_coverage_data_internal(
-    coverage_data: HashMap<String, Vec<usize>>,
+/// Detect changes across `project_roots`, also returning the number of files scanned
+///
+/// Filenames are relative to `project_root`, as in `save_baseline_roots_internal`.
+#[allow(clippy::too_many_arguments)]
+fn detect_changes_scan(
+    db_path: &str,
     project_root: &str,
-    test_file: &str,
-    verbose: bool,
+    project_roots: &[String],
     scope_paths: Vec<String>,
-    cache: Option<&crate::fingerprint_cache::FingerprintCache>,
-) -> Result<Vec<Fingerprint>> {
-    let project_root_path = Path::new(project_root);
-    let test_file_path = Path::new(test_file);
+    python_version: Option<&str>,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
+    cancel: Option<&CancelToken>,
+) -> Result<(ChangedFiles, usize)> {
+    // Open database
+    let db = PytestDiffDatabase::open(db_path)?;
 
-    // Convert scope paths to absolute PathBufs for comparison
-    // If scope_paths is empty, use project_root as the default scope
-    let scope_paths_abs: Vec<PathBuf> = if scope_paths.is_empty() {
-        vec![std::fs::canonicalize(project_root_path)
-            .unwrap_or_else(|_| project_root_path.to_path_buf())]
-    } else {
-        scope_paths
-            .iter()
-            .map(|p| {
-                let path = PathBuf::from(p);
-                std::fs::canonicalize(&path).unwrap_or(path)
-            })
-            .collect()
-    };
+    // Find all Python files in the project
+    let mut warnings = Vec::new();
+    let python_files =
+        find_python_files_in_roots(project_roots, &scope_paths, scan, &mut warnings)?;
 
-    // Process files in parallel with rayon
-    let fingerprints: Vec<Fingerprint> = coverage_data
-        .par_iter()
-        .filter_map(|(filename, executed_lines)| {
-            let filepath = Path::new(filename);
+    // Load ALL baselines in a single query (much faster than N queries)
+    let baselines = slash_keys(db.get_all_baseline_fingerprints()?);
 
-            // 1. File filtering - only include relevant Python files
-            if !should_process_file(
-                filepath,
-                project_root_path,
-                test_file_path,
-                &scope_paths_abs,
-            ) {
-                return None;
-            }
-
-            // 2. Calculate fingerprint with all blocks (use cache if available)
-            let fp = match cache {
-                Some(c) => match c.get_or_calculate_internal(filename) {
-                    Ok(fp) => fp,
-                    Err(e) => {
-                        if verbose {
-                            eprintln!(
-                                "⚠ pytest-difftest: Could not fingerprint {}: {}",
-                                filename, e
-                            );
-                        }
-                        return None;
-                    }
-                },
-                None => match calculate_fingerprint_internal(filename) {
-                    Ok(fp) => fp,
-                    Err(e) => {
-                        if verbose {
-                            eprintln!(
-                                "⚠ pytest-difftest: Could not fingerprint {}: {}",
-                                filename, e
-                            );
-                        }
-                        return None;
-                    }
-                },
-            };
+    warnings.extend(baseline_environment_warnings(&db, python_version, options)?);
 
-            // 3. Filter blocks to only those that were executed
-            let blocks = match &fp.blocks {
-                Some(blocks) => blocks,
-                None => return Some(fp), // No blocks info - use full fingerprint
-            };
+    // A baseline from a different checkout makes nearly everything look changed.
+    // Only baseline files the scope lets the scan find are comparable.
+    let baseline_in_scope = count_in_scope(baselines.keys(), project_root, &scope_paths)?;
+    let baseline_mismatch = baseline_counts_diverge(baseline_in_scope, python_files.len());
+    if baseline_mismatch {
+        warnings.push(format!(
+            "Scanned {} files but the baseline has {} in scope; it may come from a different checkout",
+            python_files.len(),
+            baseline_in_scope
+        ));
+    }
 
-            let executed_lines_set: HashSet<usize> = executed_lines.iter().copied().collect();
-            let executed_blocks = filter_executed_blocks_rust(blocks, &executed_lines_set);
+    // Baseline files that were not found by the scan and are gone from disk.
+    // The existence check keeps out-of-scope test files from being reported.
+    let scanned: HashSet<String> = python_files
+        .iter()
+        .map(|path| make_relative(&path.to_string_lossy(), project_root))
+        .collect();
+    let mut deleted: Vec<String> = db
+        .list_baseline_files_internal()?
+        .iter()
+        .map(|file| to_slash(file))
+        .filter(|file| !scanned.contains(file) && !Path::new(project_root).join(file).exists())
+        .collect();
 
-            if executed_blocks.is_empty() {
-                if verbose {
-                    eprintln!("[DEBUG] {}: No blocks executed (skipping)", filename);
-                }
+    // Process files in PARALLEL using rayon
+    // Now that we have all baselines in memory, we don't need DB access per file
+    let unchecked = AtomicUsize::new(0);
+    let touched = TouchedFiles::default();
+    let touched_sink = options.refresh_mtimes.then_some(&touched);
+    let notes = DecodeNotes::default();
+    let results: Vec<_> = python_files
+        .par_iter()
+        .filter_map(|path| {
+            if is_cancelled(cancel) {
+                unchecked.fetch_add(1, Ordering::Relaxed);
                 return None;
             }
-
-            if verbose {
-                eprintln!(
-                    "[DEBUG] {}: {}/{} blocks executed",
-                    filename,
-                    executed_blocks.len(),
-                    blocks.len()
-                );
+            let abs_path = path.to_string_lossy().to_string();
+            let rel_path = make_relative(&abs_path, project_root);
+            match check_file_changed_with_baseline(
+                &baselines,
+                path,
+                &rel_path,
+                options,
+                touched_sink,
+                Some(&notes),
+            ) {
+                Ok(Some(change)) => Some(Ok(change)),
+                Ok(None) => None,
+                // One unreadable file must not hide the rest of the scan
+                Err(e) => Some(Err((rel_path, format!("{:#}", e)))),
             }
-
-            // 4. Create filtered fingerprint with only executed blocks
-            let filtered_checksums: Vec<i32> = executed_blocks.iter().map(|b| b.checksum).collect();
-
-            Some(Fingerprint {
-                filename: make_relative(&fp.filename, project_root),
-                checksums: filtered_checksums,
-                file_hash: fp.file_hash,
-                mtime: fp.mtime,
-                blocks: None, // Don't need to store full blocks in DB
-            })
         })
         .collect();
+    let mut changed_entries = Vec::new();
+    let mut errored = Vec::new();
+    for result in results {
+        match result {
+            Ok(change) => changed_entries.push(change),
+            Err(error) => errored.push(error),
+        }
+    }
+    errored.sort();
+    for (file, error) in &errored {
+        warnings.push(format!("Could not check {}: {}", file, error));
+    }
+    let mut notes = notes.into_inner();
+    notes.sort();
+    warnings.extend(notes);
 
-    Ok(fingerprints)
-}
-
-/// Check if a file should be processed based on filtering rules
-fn should_process_file(
-    filepath: &Path,
-    project_root: &Path,
-    test_file: &Path,
-    scope_paths: &[PathBuf],
-) -> bool {
-    // Must be a .py file
-    if filepath.extension().and_then(|s| s.to_str()) != Some("py") {
-        return false;
+    let touched = touched.into_inner();
+    if !touched.is_empty() {
+        db.update_baseline_mtimes(&touched)?;
     }
 
-    // Must be in the project root (use Path methods for cross-platform compatibility)
-    if !filepath.starts_with(project_root) {
-        return false;
+    let unchecked = unchecked.into_inner();
+    if unchecked > 0 {
+        warnings.push(format!(
+            "Change detection was cancelled; {} of {} files were not checked",
+            unchecked,
+            python_files.len()
+        ));
     }
 
-    // Determine if this is a test file
-    // Use Path components for cross-platform compatibility (works on both / and \)
-    let filename = filepath.file_name().and_then(|s| s.to_str()).unwrap_or("");
-    let is_test_filename = filename.starts_with("test_") || filename.ends_with("_test.py");
+    // Separate modified and added files from changed blocks
+    let mut modified = Vec::new();
+    let mut added = Vec::new();
+    let mut changed_blocks = HashMap::new();
 
-    // Check if any parent directory is named "tests" or "test"
-    let in_tests_dir = filepath.components().any(|c| {
-        if let std::path::Component::Normal(name) = c {
-            let name_str = name.to_string_lossy();
-            name_str == "tests" || name_str == "test"
+    for (file, blocks) in changed_entries {
+        if baselines.contains_key(&file) {
+            modified.push(file.clone());
         } else {
-            false
+            added.push(file.clone());
         }
-    });
+        if !blocks.is_empty() {
+            changed_blocks.insert(file, blocks);
+        }
+    }
 
-    let is_test_file = is_test_filename || in_tests_dir;
-    let is_current_test_file = filepath == test_file;
+    // A new file with the exact content of a deleted one is a rename. The new
+    // path has no recorded tests, but tests still importing the old path break,
+    // so the old path contributes its blocks like a deleted file
+    let renamed = match_renames(&mut added, &mut deleted, &baselines, project_root, options);
+    for (_, new) in &renamed {
+        changed_blocks.remove(new);
+    }
 
-    // Scope paths only apply to test files, not source files
-    // Source files that are dependencies should always be tracked
-    if is_test_file {
-        // For test files: only include the current test file being executed
-        // This prevents coverage contamination where test collection
-        // causes all tests to depend on all test files
-        if !is_current_test_file {
-            return false;
+    // Every block of a deleted (or renamed) file is gone, so tests that used any
+    // of them are affected
+    for file in deleted.iter().chain(renamed.iter().map(|(old, _)| old)) {
+        if let Some(stored_fp) = baselines.get(file) {
+            if !stored_fp.checksums.is_empty() {
+                changed_blocks.insert(file.clone(), stored_fp.checksums.clone());
+            }
         }
-        // For the current test file, check scope (if running a subset of tests)
-        if !scope_paths.is_empty() {
-            let in_scope = scope_paths.iter().any(|scope| filepath.starts_with(scope));
-            if !in_scope {
-                return false;
+    }
+
+    // A file we could not read or parse may have changed anywhere, so treat
+    // every block it had in the baseline as changed
+    for (file, _) in &errored {
+        if let Some(stored_fp) = baselines.get(file) {
+            if !stored_fp.checksums.is_empty() {
+                changed_blocks.insert(file.clone(), stored_fp.checksums.clone());
             }
         }
     }
-    // Source files are always included (if they're in project root)
 
-    true
+    // File granularity reports which files changed, never which blocks
+    if options.granularity == Granularity::File {
+        changed_blocks.clear();
+    }
+
+    let conftest_affected = conftest_affected_files(
+        scanned.iter(),
+        modified.iter().chain(&added).chain(&deleted),
+    );
+
+    let changes = ChangedFiles {
+        modified,
+        changed_blocks,
+        deleted,
+        added,
+        renamed,
+        conftest_affected,
+        errored,
+        baseline_mismatch,
+        warnings,
+        unreferenced_changes: Vec::new(),
+        run_all: false,
+    };
+    Ok((changes, python_files.len()))
 }
 
-/// Filter blocks to only those where at least one line was executed
+/// Modified and deleted files whose changed blocks no recorded test executed
+fn unreferenced_changes(db_path: &str, changes: &ChangedFiles) -> Result<Vec<String>> {
+    let db = PytestDiffDatabase::open(db_path)?;
+    let referenced = db.referenced_files_internal(&changes.changed_blocks)?;
+    let mut files: Vec<String> = changes
+        .modified
+        .iter()
+        .chain(&changes.deleted)
+        .filter(|file| !referenced.contains(*file))
+        .cloned()
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Select the tests affected by changes since the baseline, in one call
 ///
-/// This implements block-level granularity in Rust for performance:
-/// - Only blocks that were actually executed are tracked as dependencies
-/// - If function_a() is never called, changing it won't re-run this test
+/// Runs change detection and looks up the tests that executed a changed block,
+/// without passing the intermediate `ChangedFiles` through Python.
 ///
 /// # Arguments
-/// * `blocks` - List of Block objects with start_line/end_line
-/// * `executed_lines` - Set of line numbers that were executed
+/// * `db_path` - Path to the database file
+/// * `project_root` - Project root directory
+/// * `scope_paths` - List of paths to check (empty = check all)
+/// * `options` - `FingerprintOptions`, as for `detect_changes` (default: `FingerprintOptions()`)
+/// * `scan` - `ScanOptions`, as for `detect_changes` (default: `ScanOptions()`)
 ///
 /// # Returns
-/// * Vec of Block objects that were executed
-fn filter_executed_blocks_rust(blocks: &[Block], executed_lines: &HashSet<usize>) -> Vec<Block> {
-    blocks
-        .iter()
-        .filter(|block| {
-            // Check if any line in this block's BODY was executed.
-            // We use body_start_line instead of start_line to skip decorators
-            // and `def`/`class` signature lines, which Python executes at import
-            // time. This prevents false positives where importing a module makes
-            // all functions appear "executed".
-            (block.body_start_line..=block.end_line).any(|line| executed_lines.contains(&line))
-        })
-        .cloned()
-        .collect()
+/// * Sorted names of the affected tests
+#[pyfunction]
+#[pyo3(signature = (db_path, project_root, scope_paths, options=None, scan=None))]
+pub fn select_tests(
+    db_path: &str,
+    project_root: &str,
+    scope_paths: Vec<String>,
+    options: Option<FingerprintOptions>,
+    scan: Option<ScanOptions>,
+) -> PyResult<Vec<String>> {
+    select_tests_internal(
+        db_path,
+        project_root,
+        scope_paths,
+        &options.unwrap_or_default(),
+        &scan.unwrap_or_default(),
+    )
+    .map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to select tests: {}", e))
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+fn select_tests_internal(
+    db_path: &str,
+    project_root: &str,
+    scope_paths: Vec<String>,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
+) -> Result<Vec<String>> {
+    let changes = detect_changes_internal(db_path, project_root, scope_paths, options, scan)?;
+    let db = PytestDiffDatabase::open(db_path)?;
+    db.get_affected_tests_internal(changes.changed_blocks)
+}
 
-    #[test]
-    fn test_calculate_fingerprint() {
-        let mut file = NamedTempFile::new().unwrap();
-        writeln!(file, "def foo(): pass").unwrap();
-        file.flush().unwrap();
+/// Summarize what changed in the project since the baseline
+///
+/// Runs the same scan as `detect_changes` and reduces it to counts, so
+/// callers (e.g. CI reports) don't have to derive them from the raw lists.
+///
+/// # Arguments
+/// * `db_path` - Path to the database file
+/// * `project_root` - Project root directory
+/// * `scope_paths` - List of paths to check (empty = check all)
+/// * `options` - `FingerprintOptions`, as for `detect_changes` (default: `FingerprintOptions()`)
+/// * `scan` - `ScanOptions`, as for `detect_changes` (default: `ScanOptions()`)
+///
+/// # Returns
+/// * ChangeSummary with file and block counts. A modified file that can't be
+///   fingerprinted again counts as errored and is listed in `warnings`.
+#[pyfunction]
+#[pyo3(signature = (db_path, project_root, scope_paths, options=None, scan=None))]
+pub fn summarize_changes(
+    db_path: &str,
+    project_root: &str,
+    scope_paths: Vec<String>,
+    options: Option<FingerprintOptions>,
+    scan: Option<ScanOptions>,
+) -> PyResult<ChangeSummary> {
+    summarize_changes_internal(
+        db_path,
+        project_root,
+        scope_paths,
+        &options.unwrap_or_default(),
+        &scan.unwrap_or_default(),
+    )
+    .map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to summarize changes: {}", e))
+    })
+}
 
-        let path = file.path().to_str().unwrap();
-        let fingerprint = calculate_fingerprint_internal(path).unwrap();
+fn summarize_changes_internal(
+    db_path: &str,
+    project_root: &str,
+    scope_paths: Vec<String>,
+    options: &FingerprintOptions,
+    scan: &ScanOptions,
+) -> Result<ChangeSummary> {
+    let roots = [project_root.to_string()];
+    let (changes, files_scanned) = detect_changes_scan(
+        db_path,
+        project_root,
+        &roots,
+        scope_paths,
+        None,
+        options,
+        scan,
+        None,
+    )?;
+    let baselines = slash_keys(PytestDiffDatabase::open(db_path)?.get_all_baseline_fingerprints()?);
 
-        assert_eq!(fingerprint.filename, path);
-        assert_eq!(fingerprint.checksums.len(), 2); // module + function
-        assert!(!fingerprint.file_hash.is_empty());
-        assert!(fingerprint.mtime > 0.0);
+    let mut summary = ChangeSummary {
+        files_scanned,
+        files_modified: changes.modified.len(),
+        files_added: changes.added.len(),
+        files_deleted: changes.deleted.len(),
+        files_renamed: changes.renamed.len(),
+        files_errored: changes.errored.len(),
+        warnings: changes.warnings,
+        ..Default::default()
+    };
+
+    // The current checksums of modified files aren't part of ChangedFiles
+    // (only the vanished ones are), so fingerprint them again. The file may
+    // have changed since the scan, or never been parsed with `granularity="file"`.
+    for file in &changes.modified {
+        let old = baselines
+            .get(file)
+            .map(|fp| fp.checksums.as_slice())
+            .unwrap_or_default();
+        let path = Path::new(project_root).join(file);
+        let new = match calculate_fingerprint_with_options(path.to_string_lossy().as_ref(), options)
+        {
+            Ok(new) => new,
+            Err(e) => {
+                summary.files_errored += 1;
+                summary
+                    .warnings
+                    .push(format!("Could not count the blocks of {}: {}", file, e));
+                continue;
+            }
+        };
+        let (added, removed, modified) = count_block_changes(old, &new.checksums);
+        summary.blocks_added += added;
+        summary.blocks_removed += removed;
+        summary.blocks_modified += modified;
+    }
+    for file in &changes.added {
+        summary.blocks_added += changes.changed_blocks.get(file).map_or(0, Vec::len);
+    }
+    for file in &changes.deleted {
+        summary.blocks_removed += baselines.get(file).map_or(0, |fp| fp.checksums.len());
     }
 
-    #[test]
-    fn test_fingerprint_hash_stability() {
-        let mut file = NamedTempFile::new().unwrap();
-        let source = "def add(a, b):\n    return a + b\n";
-        writeln!(file, "{}", source).unwrap();
-        file.flush().unwrap();
+    Ok(summary)
+}
 
-        let path = file.path().to_str().unwrap();
+/// Count added, removed and modified blocks between two checksum lists
+///
+/// Checksums are compared as multisets. Without block names a modification
+/// looks like one checksum vanishing and another appearing, so such pairs
+/// count as modified and the remainder as added or removed.
+fn count_block_changes(old_checksums: &[i64], new_checksums: &[i64]) -> (usize, usize, usize) {
+    let mut balance: HashMap<i64, isize> = HashMap::new();
+    for checksum in old_checksums {
+        *balance.entry(*checksum).or_default() -= 1;
+    }
+    for checksum in new_checksums {
+        *balance.entry(*checksum).or_default() += 1;
+    }
+    let appeared: usize = balance
+        .values()
+        .filter(|n| **n > 0)
+        .map(|n| *n as usize)
+        .sum();
+    let vanished: usize = balance
+        .values()
+        .filter(|n| **n < 0)
+        .map(|n| n.unsigned_abs())
+        .sum();
+    let modified = appeared.min(vanished);
+    (appeared - modified, vanished - modified, modified)
+}
 
-        let fp1 = calculate_fingerprint_internal(path).unwrap();
-        let fp2 = calculate_fingerprint_internal(path).unwrap();
+/// Files living under the directory of any changed `conftest.py`
+///
+/// pytest applies a conftest's fixtures and hooks to its whole directory
+/// subtree, so those files are affected even if none of their blocks changed.
+/// Paths are project-relative with `/` separators; the changed conftests
+/// themselves are not included.
+fn conftest_affected_files<'a>(
+    scanned: impl Iterator<Item = &'a String>,
+    changed: impl Iterator<Item = &'a String>,
+) -> Vec<String> {
+    let changed_conftests: Vec<&Path> = changed
+        .map(Path::new)
+        .filter(|path| path.file_name().is_some_and(|name| name == "conftest.py"))
+        .collect();
+    if changed_conftests.is_empty() {
+        return Vec::new();
+    }
 
-        assert_eq!(fp1.file_hash, fp2.file_hash);
-        assert_eq!(fp1.checksums, fp2.checksums);
+    let mut affected: Vec<String> = scanned
+        .filter(|file| {
+            let file = Path::new(file.as_str());
+            changed_conftests.iter().any(|conftest| {
+                file != *conftest && conftest.parent().is_some_and(|dir| file.starts_with(dir))
+            })
+        })
+        .cloned()
+        .collect();
+    affected.sort();
+    affected
+}
+
+/// Pair added files with deleted ones whose baseline has the same content hash
+///
+/// Matched files are removed from `added` and `deleted` and returned as
+/// `(old_path, new_path)` pairs.
+fn match_renames(
+    added: &mut Vec<String>,
+    deleted: &mut Vec<String>,
+    baselines: &HashMap<String, Fingerprint>,
+    project_root: &str,
+    options: &FingerprintOptions,
+) -> Vec<(String, String)> {
+    if added.is_empty() || deleted.is_empty() {
+        return Vec::new();
     }
 
-    #[test]
-    fn test_filter_executed_blocks_only_def_line_not_executed() {
-        // Simulates import-time coverage: only the `def` line (line 2) is covered,
-        // but the body starts at line 3. The function should NOT be considered executed.
-        let blocks = vec![Block {
-            start_line: 2,
-            end_line: 4,
-            checksum: 111,
-            name: "get_active_announcements".to_string(),
-            block_type: "function".to_string(),
-            body_start_line: 3,
-        }];
-        // Only the def line (2) was executed (import-time registration)
-        let executed_lines: HashSet<usize> = [2].into_iter().collect();
-        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
-        assert!(
-            result.is_empty(),
-            "Function with only def line covered should NOT be considered executed"
-        );
+    let mut deleted_by_hash: HashMap<&str, Vec<String>> = HashMap::new();
+    for file in deleted.iter() {
+        if let Some(stored_fp) = baselines.get(file) {
+            deleted_by_hash
+                .entry(stored_fp.file_hash.as_str())
+                .or_default()
+                .push(file.clone());
+        }
     }
 
-    #[test]
-    fn test_filter_executed_blocks_body_line_executed() {
-        // When a body line is covered, the function IS considered executed.
+    let mut renamed = Vec::new();
+    added.retain(|new| {
+        let old = file_hash(&Path::new(project_root).join(new), options)
+            .ok()
+            .and_then(|hash| deleted_by_hash.get_mut(hash.as_str())?.pop());
+        match old {
+            Some(old) => {
+                renamed.push((old, new.clone()));
+                false
+            }
+            None => true,
+        }
+    });
+    deleted.retain(|file| !renamed.iter().any(|(old, _)| old == file));
+    renamed.sort();
+    renamed
+}
+
+/// `(baseline filename, (seconds, nanoseconds))` of files touched without an edit
+type TouchedFiles = parking_lot::Mutex<Vec<(String, (u64, u32))>>;
+
+/// Notes from `decode_source` about files decoded as lossy UTF-8
+type DecodeNotes = parking_lot::Mutex<Vec<String>>;
+
+/// Check if a file has changed using three-level detection (with pre-loaded baseline)
+///
+/// This version takes a pre-loaded HashMap of baselines for parallel processing.
+/// `rel_filename` is the path relative to project root, used for DB lookups and return values.
+/// `path` is the absolute path, used for file I/O operations.
+/// Files whose mtime changed but whose hash didn't are added to `touched`, as
+/// `(baseline filename, current mtime)`, when it is given.
+fn check_file_changed_with_baseline(
+    baselines: &HashMap<String, Fingerprint>,
+    path: &Path,
+    rel_filename: &str,
+    options: &FingerprintOptions,
+    touched: Option<&TouchedFiles>,
+    notes: Option<&DecodeNotes>,
+) -> Result<Option<(String, Vec<i64>)>> {
+    let note = |note: Option<String>| {
+        if let (Some(notes), Some(note)) = (notes, note) {
+            notes.lock().push(note);
+        }
+    };
+
+    // Get baseline fingerprint from pre-loaded map (keyed by relative path)
+    let stored_fp = match baselines.get(rel_filename) {
+        Some(fp) => fp,
+        None if options.granularity == Granularity::File => {
+            return Ok(Some((rel_filename.to_string(), Vec::new())));
+        }
+        None => {
+            // No baseline for this file - it's new, treat as changed
+            // Parse to get checksums so new tests in this file can be selected
+            let (current_fp, decode_note) =
+                calculate_fingerprint_noting(path.to_string_lossy().as_ref(), options)?;
+            note(decode_note);
+            let checksums = current_fp.checksums.clone();
+            return Ok(Some((rel_filename.to_string(), checksums)));
+        }
+    };
+
+    // Level 1: mtime check (fastest)
+    let metadata = std::fs::metadata(path)?;
+    let current = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    let mtime_unchanged = match stored_fp.mtime_exact {
+        Some(stored) => stored == (current.as_secs(), current.subsec_nanos()),
+        // Baselines written before exact mtimes were stored
+        None => (current.as_secs_f64() - stored_fp.mtime).abs() < 0.001,
+    };
+
+    if mtime_unchanged {
+        // mtime unchanged - file definitely not modified
+        return Ok(None);
+    }
+
+    // Level 2: file hash check (fast). The file is opened once: small files are
+    // read whole and the bytes reused for parsing; large ones are streamed and
+    // only read whole, from the same handle, if the hash shows they changed.
+    let mut file = open_source(path)?;
+    let (current_hash, bytes) = if metadata.len() > STREAMING_HASH_THRESHOLD {
+        (stream_open_file_hash(&mut file, options)?, None)
+    } else {
+        let bytes = read_open_source(&mut file, options)?;
+        (blake3::hash(&bytes).to_hex().to_string(), Some(bytes))
+    };
+
+    if current_hash == stored_fp.file_hash {
+        // Hash unchanged - content is identical (mtime changed but not content)
+        if let Some(touched) = touched {
+            let mtime = (current.as_secs(), current.subsec_nanos());
+            touched.lock().push((stored_fp.filename.clone(), mtime));
+        }
+        return Ok(None);
+    }
+    if options.granularity == Granularity::File {
+        return Ok(Some((rel_filename.to_string(), Vec::new())));
+    }
+
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        None => {
+            use std::io::Seek;
+            file.rewind()?;
+            read_open_source(&mut file, options)?
+        }
+    };
+
+    let (content, decode_note) = decode_source(&bytes, path);
+    note(decode_note);
+
+    // Level 3: block checksum comparison (precise)
+    let current_blocks = parse_source(&content, path, options)
+        .map_err(|e| anyhow::anyhow!("Parse error in {}: {}", rel_filename, e))?;
+
+    let current_checksums: Vec<i64> = current_blocks.iter().map(|b| b.checksum).collect();
+
+    if same_checksums_ignoring_order(&current_checksums, &stored_fp.checksums) {
+        // Checksums unchanged - semantically equivalent (e.g., only whitespace/comments
+        // changed, or blocks were reordered). Redefinitions of one name are the
+        // exception: the last one wins, so their order matters.
+        let redefined = redefined_checksums(&current_blocks);
+        let redefinition_order = |checksums: &[i64]| -> Vec<i64> {
+            checksums
+                .iter()
+                .copied()
+                .filter(|c| redefined.contains(c))
+                .collect()
+        };
+        if redefinition_order(&current_checksums) == redefinition_order(&stored_fp.checksums) {
+            return Ok(None);
+        }
+        let mut changed_checksums: Vec<i64> = redefined.into_iter().collect();
+        changed_checksums.sort_unstable();
+        return Ok(Some((rel_filename.to_string(), changed_checksums)));
+    }
+
+    // Find which specific blocks changed
+    let changed_checksums = find_changed_checksums(&stored_fp.checksums, &current_checksums);
+
+    Ok(Some((rel_filename.to_string(), changed_checksums)))
+}
+
+/// Find all Python files under any of several roots
+///
+/// Roots may overlap; each file is returned once, keyed by canonical path.
+/// Files skipped along the way are described in `warnings`.
+fn find_python_files_in_roots(
+    roots: &[String],
+    scope_paths: &[String],
+    scan: &ScanOptions,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for root in roots {
+        for path in find_python_files_with_warnings(root, scope_paths, scan, warnings)? {
+            let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if seen.insert(canonical) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Find all Python files in a directory
+///
+/// Scope paths only apply to test files - source files are always included.
+/// This ensures that when running a subset of tests, we still track all source
+/// file dependencies.
+#[cfg(test)]
+fn find_python_files(
+    root: &str,
+    scope_paths: &[String],
+    scan: &ScanOptions,
+) -> Result<Vec<PathBuf>> {
+    find_python_files_with_warnings(root, scope_paths, scan, &mut Vec::new())
+}
+
+/// `find_python_files`, describing skipped files in `warnings`
+fn find_python_files_with_warnings(
+    root: &str,
+    scope_paths: &[String],
+    scan: &ScanOptions,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    // Convert root to absolute path
+    let root_path = std::fs::canonicalize(root).unwrap_or_else(|_| PathBuf::from(root));
+
+    let scope = Scope::new(scope_paths)?;
+
+    // Through symlinks the same file can show up under several paths
+    let mut seen = HashSet::new();
+
+    for path in walk_project(&root_path, scan)? {
+        let path = path.as_path();
+
+        // Only include Python sources (.py and .pyi stubs)
+        if path.is_file() && (is_python_source(path) || scan.include_notebooks && is_notebook(path))
+        {
+            // Store absolute path
+            let abs_path = if path.is_absolute() {
+                path.to_path_buf()
+            } else {
+                std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+            };
+
+            let is_test_file = is_default_test_file(&abs_path);
+
+            let rel_path = abs_path.strip_prefix(&root_path).ok();
+            if rel_path.is_some_and(|rel| is_excluded(rel, &scan.exclude)) {
+                continue;
+            }
+
+            // Scope paths only apply to test files
+            // Source files are always included
+            if is_test_file && !scope.is_empty() && !scope.contains(&abs_path, rel_path) {
+                continue; // Skip test files outside scope
+            }
+
+            if let Some(len) = oversized(&abs_path, scan.max_file_bytes) {
+                let warning = format!(
+                    "Skipping {} ({} bytes exceeds max_file_bytes)",
+                    abs_path.display(),
+                    len
+                );
+                warnings.push(warning);
+                continue;
+            }
+
+            if scan.follow_symlinks {
+                let canonical =
+                    std::fs::canonicalize(&abs_path).unwrap_or_else(|_| abs_path.clone());
+                if !seen.insert(canonical) {
+                    continue;
+                }
+            }
+
+            files.push(abs_path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// List every path under `root`, pruning directories that never hold project sources
+///
+/// With `respect_gitignore`, nested `.gitignore` files below `root` are honored
+/// as well, even when `root` is not inside a git repository.
+///
+/// With `follow_symlinks`, entries are visited in file-name order so the path
+/// kept for a file reachable through several links is stable across runs.
+/// Symlink cycles are detected by the walkers and skipped.
+fn walk_project(root: &Path, scan: &ScanOptions) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    if scan.respect_gitignore {
+        let ignore_dirs = scan.ignore_dirs.clone();
+        let mut builder = WalkBuilder::new(root);
+        builder
+            .standard_filters(false)
+            .git_ignore(true)
+            .require_git(false)
+            .follow_links(scan.follow_symlinks)
+            .filter_entry(move |e| !is_excluded_dir_entry(e.path(), e.file_type(), &ignore_dirs));
+        if scan.follow_symlinks {
+            builder.sort_by_file_name(|a, b| a.cmp(b));
+        }
+        for entry in builder.build() {
+            match entry {
+                Ok(entry) => paths.push(entry.into_path()),
+                Err(e) if is_symlink_loop(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    } else {
+        let mut walker = WalkDir::new(root).follow_links(scan.follow_symlinks);
+        if scan.follow_symlinks {
+            walker = walker.sort_by_file_name();
+        }
+        for entry in walker.into_iter().filter_entry(|e| {
+            !is_excluded_dir_entry(e.path(), Some(e.file_type()), &scan.ignore_dirs)
+        }) {
+            match entry {
+                Ok(entry) => paths.push(entry.into_path()),
+                Err(e) if e.loop_ancestor().is_some() => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Whether a gitignore-aware walk error is a symlink cycle
+fn is_symlink_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_symlink_loop(err),
+        ignore::Error::Partial(errs) => errs.iter().all(is_symlink_loop),
+        _ => false,
+    }
+}
+
+/// Skip hidden entries, common non-source directories and virtual environments
+///
+/// Directories named in `ignore_dirs` are skipped as well.
+fn is_excluded_dir_entry(
+    path: &Path,
+    file_type: Option<std::fs::FileType>,
+    ignore_dirs: &[String],
+) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    if name.starts_with('.') || name == "__pycache__" || name == "node_modules" {
+        return true;
+    }
+    let is_dir = file_type.is_some_and(|t| t.is_dir());
+    if is_dir && ignore_dirs.iter().any(|d| *d == name) {
+        return true;
+    }
+    // Python virtual environments are identified by their pyvenv.cfg marker
+    is_dir && path.join("pyvenv.cfg").exists()
+}
+
+/// Parse decoded file content into blocks, extracting code cells from notebooks
+fn parse_source(content: &str, path: &Path, options: &FingerprintOptions) -> Result<Vec<Block>> {
+    let parse_options = ParseOptions {
+        strip_comments: options.ignore_comments,
+        ast_checksum: options.ast_checksum,
+        algorithm: options.checksum_algorithm,
+    };
+    let mut blocks = if is_notebook(path) {
+        parse_notebook(content, &parse_options)?
+    } else {
+        parse_module_with_options(content, &parse_options)?
+    };
+    blocks.retain(|block| !options.ignore_block_types.contains(&block.block_type));
+    for block in &mut blocks {
+        block.checksum = seed_checksum(block.checksum, options.seed, options.checksum_algorithm);
+    }
+    Ok(blocks)
+}
+
+/// Check if a path has a Python source extension (`.py` or `.pyi` stub)
+fn is_python_source(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|s| s.to_str()),
+        Some("py") | Some("pyi")
+    )
+}
+
+/// Whether two checksum lists hold the same checksums, counting duplicates
+fn same_checksums_ignoring_order(a: &[i64], b: &[i64]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+    a.sort_unstable();
+    b.sort_unstable();
+    a == b
+}
+
+/// Checksums of blocks whose qualified name is defined more than once
+fn redefined_checksums(blocks: &[Block]) -> HashSet<i64> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for block in blocks {
+        *counts.entry(block.qualified_name.as_str()).or_default() += 1;
+    }
+    blocks
+        .iter()
+        .filter(|block| counts[block.qualified_name.as_str()] > 1)
+        .map(|block| block.checksum)
+        .collect()
+}
+
+/// Find which OLD checksums were removed/modified (these indicate blocks that changed)
+///
+/// Returns the OLD checksums that are no longer present in the new version.
+/// These are the checksums that tests may have used, so any test that used
+/// these blocks should be re-run to verify the changes.
+fn find_changed_checksums(old_checksums: &[i64], new_checksums: &[i64]) -> Vec<i64> {
+    let new_set: std::collections::HashSet<i64> = new_checksums.iter().copied().collect();
+
+    // Return OLD checksums that are no longer in the new version
+    // These represent blocks that were removed or modified
+    old_checksums
+        .iter()
+        .copied()
+        .filter(|checksum| !new_set.contains(checksum))
+        .collect()
+}
+
+/// Block-level diff of two versions of a Python source file
+///
+/// Parses both sources and reports each added, removed or modified block
+/// (matched by qualified name). No filesystem or database access.
+///
+/// # Arguments
+/// * `old_source` - Source code of the previous version
+/// * `new_source` - Source code of the current version
+///
+/// # Returns
+/// * List of BlockChange objects, raising SyntaxError if either source fails to parse
+#[pyfunction]
+pub fn diff_files(old_source: &str, new_source: &str) -> PyResult<Vec<BlockChange>> {
+    diff_files_internal(old_source, new_source).map_err(|e| {
+        pyo3::exceptions::PySyntaxError::new_err(format!("Failed to parse Python code: {}", e))
+    })
+}
+
+pub(crate) fn diff_files_internal(old_source: &str, new_source: &str) -> Result<Vec<BlockChange>> {
+    let old_blocks = parse_module_internal(old_source)?;
+    let new_blocks = parse_module_internal(new_source)?;
+    Ok(diff_blocks(&old_blocks, &new_blocks))
+}
+
+/// Compare two versions' blocks and describe what was added, removed or modified
+///
+/// Blocks are paired by qualified name; when several blocks share a name
+/// (e.g. redefinitions or repeated imports) they are paired in source order.
+/// Added and modified changes follow the new file's order, then removals.
+///
+/// Line blocks are named after their line number, so they are paired by
+/// checksum first: a line whose content only moved is reported as `"moved"`
+/// rather than as a modification of both its old and new line.
+pub(crate) fn diff_blocks(old_blocks: &[Block], new_blocks: &[Block]) -> Vec<BlockChange> {
+    let (old_lines, old_blocks): (Vec<&Block>, Vec<&Block>) =
+        old_blocks.iter().partition(|b| b.block_type == "line");
+    let (new_lines, new_blocks): (Vec<&Block>, Vec<&Block>) =
+        new_blocks.iter().partition(|b| b.block_type == "line");
+
+    let mut changes = diff_named_blocks(&old_blocks, &new_blocks);
+    changes.extend(diff_line_blocks(&old_lines, &new_lines));
+    changes
+}
+
+/// `diff_blocks` for blocks paired by qualified name
+fn diff_named_blocks(old_blocks: &[&Block], new_blocks: &[&Block]) -> Vec<BlockChange> {
+    let mut unmatched_old: HashMap<&str, std::collections::VecDeque<&Block>> = HashMap::new();
+    for block in old_blocks {
+        unmatched_old
+            .entry(block.qualified_name.as_str())
+            .or_default()
+            .push_back(block);
+    }
+
+    let mut changes = Vec::new();
+    for new in new_blocks {
+        let old = unmatched_old
+            .get_mut(new.qualified_name.as_str())
+            .and_then(|candidates| candidates.pop_front());
+        let kind = match old {
+            None => "added",
+            Some(old) if old.checksum != new.checksum => "modified",
+            Some(_) => continue,
+        };
+        changes.push(block_change(kind, old, new));
+    }
+
+    // Whatever was not paired no longer exists in the new version
+    let mut removed: Vec<&Block> = unmatched_old.into_values().flatten().collect();
+    removed.sort_by_key(|b| b.start_line);
+    changes.extend(removed.into_iter().map(removed_block_change));
+    changes
+}
+
+/// `diff_blocks` for line blocks: paired by checksum, then by line number
+fn diff_line_blocks(old_lines: &[&Block], new_lines: &[&Block]) -> Vec<BlockChange> {
+    let mut by_checksum: HashMap<i64, std::collections::VecDeque<&Block>> = HashMap::new();
+    for block in old_lines {
+        by_checksum
+            .entry(block.checksum)
+            .or_default()
+            .push_back(block);
+    }
+
+    let mut changes = Vec::new();
+    let mut unmatched_new = Vec::new();
+    for new in new_lines {
+        match by_checksum
+            .get_mut(&new.checksum)
+            .and_then(|candidates| candidates.pop_front())
+        {
+            Some(old) if old.start_line != new.start_line => {
+                changes.push(block_change("moved", Some(old), new))
+            }
+            Some(_) => {}
+            None => unmatched_new.push(*new),
+        }
+    }
+
+    // Lines left over on both sides at the same position were edited in place
+    let mut unmatched_old: HashMap<usize, &Block> = by_checksum
+        .into_values()
+        .flatten()
+        .map(|b| (b.start_line, b))
+        .collect();
+    for new in unmatched_new {
+        let old = unmatched_old.remove(&new.start_line);
+        let kind = if old.is_some() { "modified" } else { "added" };
+        changes.push(block_change(kind, old, new));
+    }
+    changes.sort_by_key(|c| c.start_line);
+
+    let mut removed: Vec<&Block> = unmatched_old.into_values().collect();
+    removed.sort_by_key(|b| b.start_line);
+    changes.extend(removed.into_iter().map(removed_block_change));
+    changes
+}
+
+fn block_change(kind: &str, old: Option<&Block>, new: &Block) -> BlockChange {
+    BlockChange {
+        kind: kind.to_string(),
+        name: new.qualified_name.clone(),
+        block_type: new.block_type.clone(),
+        start_line: new.start_line,
+        old_start_line: old.map(|b| b.start_line),
+        old_checksum: old.map(|b| b.checksum),
+        new_checksum: Some(new.checksum),
+    }
+}
+
+fn removed_block_change(old: &Block) -> BlockChange {
+    BlockChange {
+        kind: "removed".to_string(),
+        name: old.qualified_name.clone(),
+        block_type: old.block_type.clone(),
+        start_line: old.start_line,
+        old_start_line: Some(old.start_line),
+        old_checksum: Some(old.checksum),
+        new_checksum: None,
+    }
+}
+
+/// Process coverage data and return filtered fingerprints
+///
+/// This function does the heavy lifting of coverage processing in Rust with
+/// parallel processing for better performance. It:
+/// 1. Filters files (only .py files in project, excludes other test files)
+/// 2. Calculates fingerprints for each file (using cache if provided)
+/// 3. Filters blocks to only those that were executed (block-level granularity)
+/// 4. Returns fingerprints with only executed blocks
+///
+/// # Arguments
+/// * `coverage_data` - Map of filename -> list of executed line numbers
+/// * `project_root` - Root directory of the project
+/// * `test_file` - Path to the current test file (to filter out other test files)
+/// * `verbose` - Whether to print debug information
+/// * `scope_paths` - List of directory paths to limit the scope (e.g., ["tests/unit/"])
+/// * `cache` - Optional FingerprintCache to avoid re-parsing files
+/// * `exclude_globs` - Glob patterns (relative to `project_root`) of files to skip
+/// * `test_patterns` - Globs identifying test files, matched against the
+///   project-relative path or the file name (default: `test_*.py`, `*_test.py`,
+///   and files under `tests/` or `test/`)
+/// * `options` - `FingerprintOptions` used when no cache is given, matching the
+///   baseline's; a cache uses its own options (default: `FingerprintOptions()`)
+///
+/// # Returns
+/// * List of Fingerprint objects with only executed blocks
+#[pyfunction]
+#[pyo3(signature = (
+    coverage_data,
+    project_root,
+    test_file,
+    verbose,
+    scope_paths,
+    cache=None,
+    exclude_globs=None,
+    test_patterns=None,
+    options=None,
+))]
+#[allow(clippy::too_many_arguments)]
+pub fn process_coverage_data(
+    coverage_data: HashMap<String, Vec<usize>>,
+    project_root: &str,
+    test_file: &str,
+    verbose: bool,
+    scope_paths: Vec<String>,
+    cache: Option<&crate::fingerprint_cache::FingerprintCache>,
+    exclude_globs: Option<Vec<String>>,
+    test_patterns: Option<Vec<String>>,
+    options: Option<FingerprintOptions>,
+) -> PyResult<Vec<Fingerprint>> {
+    let options = options.unwrap_or_default();
+    let glob_set = |patterns: Vec<String>, kind| {
+        build_glob_set(&patterns, kind)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{:#}", e)))
+    };
+    let filter = CoverageFilter {
+        exclude: glob_set(exclude_globs.unwrap_or_default(), "exclude")?,
+        test_patterns: test_patterns
+            .map(|patterns| glob_set(patterns, "test"))
+            .transpose()?,
+    };
+    let fingerprints = process_coverage_data_internal(
+        coverage_data,
+        project_root,
+        test_file,
+        verbose,
+        scope_paths,
+        cache,
+        &filter,
+        &options,
+    )
+    .map_err(|e| {
+        pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to process coverage data: {}", e))
+    })?;
+
+    Ok(fingerprints)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_coverage_data_internal(
+    coverage_data: HashMap<String, Vec<usize>>,
+    project_root: &str,
+    test_file: &str,
+    verbose: bool,
+    scope_paths: Vec<String>,
+    cache: Option<&crate::fingerprint_cache::FingerprintCache>,
+    filter: &CoverageFilter,
+    options: &FingerprintOptions,
+) -> Result<Vec<Fingerprint>> {
+    let project_root_path = Path::new(project_root);
+    let test_file_path = Path::new(test_file);
+
+    // If scope_paths is empty, use project_root as the default scope
+    let scope = if scope_paths.is_empty() {
+        Scope::new(&[project_root.to_string()])?
+    } else {
+        Scope::new(&scope_paths)?
+    };
+
+    // Process files in parallel with rayon
+    let fingerprints: Vec<Fingerprint> = coverage_data
+        .par_iter()
+        .filter_map(|(filename, executed_lines)| {
+            let filepath = Path::new(filename);
+
+            // 1. File filtering - only include relevant Python files
+            if !should_process_file(filepath, project_root_path, test_file_path, &scope, filter) {
+                return None;
+            }
+
+            // 2. Calculate fingerprint with all blocks (use cache if available)
+            let fp = match cache {
+                Some(c) => match c.get_or_calculate_internal(filename) {
+                    Ok(fp) => fp,
+                    Err(e) => {
+                        if verbose {
+                            eprintln!(
+                                "⚠ pytest-difftest: Could not fingerprint {}: {}",
+                                filename, e
+                            );
+                        }
+                        return None;
+                    }
+                },
+                None => match calculate_fingerprint_with_options(filename, options) {
+                    Ok(fp) => fp,
+                    Err(e) => {
+                        if verbose {
+                            eprintln!(
+                                "⚠ pytest-difftest: Could not fingerprint {}: {}",
+                                filename, e
+                            );
+                        }
+                        return None;
+                    }
+                },
+            };
+
+            // 3. Filter blocks to only those that were executed
+            let blocks = match &fp.blocks {
+                Some(blocks) => blocks,
+                None => return Some(fp), // No blocks info - use full fingerprint
+            };
+
+            let executed_lines_set: HashSet<usize> = executed_lines.iter().copied().collect();
+            let executed_blocks = filter_executed_blocks_rust(blocks, &executed_lines_set);
+
+            if executed_blocks.is_empty() {
+                if verbose {
+                    eprintln!("[DEBUG] {}: No blocks executed (skipping)", filename);
+                }
+                return None;
+            }
+
+            if verbose {
+                eprintln!(
+                    "[DEBUG] {}: {}/{} blocks executed",
+                    filename,
+                    executed_blocks.len(),
+                    blocks.len()
+                );
+            }
+
+            // 4. Create filtered fingerprint with only executed blocks
+            let filtered_checksums: Vec<i64> = executed_blocks.iter().map(|b| b.checksum).collect();
+
+            Some(Fingerprint {
+                filename: make_relative(&fp.filename, project_root),
+                checksums: filtered_checksums,
+                file_hash: fp.file_hash,
+                mtime: fp.mtime,
+                mtime_exact: fp.mtime_exact,
+                blocks: None, // Don't need to store full blocks in DB
+            })
+        })
+        .collect();
+
+    Ok(fingerprints)
+}
+
+/// Check if a file should be processed based on filtering rules
+fn should_process_file(
+    filepath: &Path,
+    project_root: &Path,
+    test_file: &Path,
+    scope: &Scope,
+    filter: &CoverageFilter,
+) -> bool {
+    // Must be a Python source file
+    if !is_python_source(filepath) {
+        return false;
+    }
+
+    // Compare `/`-separated forms so either path style matches the other
+    let filepath = &slash_path(filepath);
+    let project_root = &slash_path(project_root);
+    let test_file = &slash_path(test_file);
+
+    // Must be in the project root (use Path methods for cross-platform compatibility)
+    let Ok(rel_path) = filepath.strip_prefix(project_root) else {
+        return false;
+    };
+
+    if is_excluded(rel_path, &filter.exclude) {
+        return false;
+    }
+
+    let is_test_file = is_test_file(filepath, rel_path, filter.test_patterns.as_ref());
+    let is_current_test_file = filepath == test_file;
+
+    // Scope paths only apply to test files, not source files
+    // Source files that are dependencies should always be tracked
+    if is_test_file {
+        // For test files: only include the current test file being executed
+        // This prevents coverage contamination where test collection
+        // causes all tests to depend on all test files
+        if !is_current_test_file {
+            return false;
+        }
+        // For the current test file, check scope (if running a subset of tests)
+        if !scope.is_empty() && !scope.contains(filepath, Some(rel_path)) {
+            return false;
+        }
+    }
+    // Source files are always included (if they're in project root)
+
+    true
+}
+
+/// Find the innermost block containing a line
+///
+/// Of the blocks whose `start_line..=end_line` range includes `line`, returns
+/// the one with the smallest range, e.g. a nested function rather than its
+/// enclosing function, class or module. Useful to map a traceback or coverage
+/// line back to the function it belongs to.
+///
+/// # Arguments
+/// * `blocks` - Blocks of one file, as returned by `parse_module`
+/// * `line` - Line number (1-indexed)
+///
+/// # Returns
+/// * The innermost containing block, or None if no block contains the line
+#[pyfunction]
+pub fn blocks_for_line(blocks: Vec<Block>, line: usize) -> Option<Block> {
+    innermost_block(&blocks, line).cloned()
+}
+
+fn innermost_block(blocks: &[Block], line: usize) -> Option<&Block> {
+    // On equal ranges the later block wins: nested blocks follow their parents
+    blocks
+        .iter()
+        .filter(|block| block.contains_line(line))
+        .rev()
+        .min_by_key(|block| block.end_line - block.start_line)
+}
+
+/// Filter blocks to only those where at least one line was executed
+///
+/// This implements block-level granularity in Rust for performance:
+/// - Only blocks that were actually executed are tracked as dependencies
+/// - If function_a() is never called, changing it won't re-run this test
+///
+/// # Arguments
+/// * `blocks` - List of Block objects with start_line/end_line
+/// * `executed_lines` - Set of line numbers that were executed
+///
+/// # Returns
+/// * Vec of Block objects that were executed
+fn filter_executed_blocks_rust(blocks: &[Block], executed_lines: &HashSet<usize>) -> Vec<Block> {
+    blocks
+        .iter()
+        .filter(|block| {
+            // Check if any line in this block's BODY was executed.
+            // We use body_start_line instead of start_line to skip decorators
+            // and `def`/`class` signature lines, which Python executes at import
+            // time. This prevents false positives where importing a module makes
+            // all functions appear "executed".
+            (block.body_start_line..=block.end_line).any(|line| executed_lines.contains(&line))
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::build_exclude_set;
+    use crate::parser::ChecksumAlgorithm;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_innermost_block_prefers_nested_functions() {
+        let source = "class Outer:\n    def method(self):\n        def helper():\n            return 1\n        return helper()\n\nX = 1\n";
+        let blocks = parse_module_internal(source).unwrap();
+        let name_at = |line| innermost_block(&blocks, line).map(|b| b.qualified_name.as_str());
+
+        assert_eq!(name_at(1), Some("Outer"));
+        assert_eq!(name_at(2), Some("Outer.method"));
+        assert_eq!(name_at(4), Some("Outer.method.helper"));
+        assert_eq!(name_at(5), Some("Outer.method"));
+        assert_eq!(name_at(7), Some("<module>"));
+        assert_eq!(name_at(100), None);
+    }
+
+    #[test]
+    fn test_calculate_fingerprint() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "def foo(): pass").unwrap();
+        file.flush().unwrap();
+
+        let path = file.path().to_str().unwrap();
+        let fingerprint = calculate_fingerprint_internal(path).unwrap();
+
+        assert_eq!(fingerprint.filename, path);
+        assert_eq!(fingerprint.checksums.len(), 2); // module + function
+        assert!(!fingerprint.file_hash.is_empty());
+        assert!(fingerprint.mtime > 0.0);
+    }
+
+    #[test]
+    fn test_calculate_fingerprint_seed_namespaces_checksums() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "def add(a, b):\n    return a + b").unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let checksums = |seed| {
+            let options = FingerprintOptions {
+                seed,
+                ..FingerprintOptions::default()
+            };
+            calculate_fingerprint_with_options(path, &options)
+                .unwrap()
+                .checksums
+        };
+        let unseeded = calculate_fingerprint_internal(path).unwrap().checksums;
+        assert_eq!(checksums(0), unseeded);
+        assert_eq!(checksums(1), checksums(1));
+
+        let (first, second) = (checksums(1), checksums(2));
+        for checksum in &first {
+            assert!(!second.contains(checksum) && !unseeded.contains(checksum));
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_hash_stability() {
+        let mut file = NamedTempFile::new().unwrap();
+        let source = "def add(a, b):\n    return a + b\n";
+        writeln!(file, "{}", source).unwrap();
+        file.flush().unwrap();
+
+        let path = file.path().to_str().unwrap();
+
+        let fp1 = calculate_fingerprint_internal(path).unwrap();
+        let fp2 = calculate_fingerprint_internal(path).unwrap();
+
+        assert_eq!(fp1.file_hash, fp2.file_hash);
+        assert_eq!(fp1.checksums, fp2.checksums);
+    }
+
+    #[test]
+    fn test_filter_executed_blocks_with_multibyte_source() {
+        let source = "# 日本語 🎉🎉\nNAME = \"ñandú ✓\"\n\ndef grüße():\n    return \"👋\"\n\ndef café():\n    return \"☕\"\n";
+        let blocks = parse_module_internal(source).unwrap();
+
+        // Coverage reports physical lines: line 5 is the body of grüße
+        let executed_lines: HashSet<usize> = [1, 2, 4, 5, 7].into_iter().collect();
+        let names: Vec<String> = filter_executed_blocks_rust(&blocks, &executed_lines)
+            .into_iter()
+            .map(|b| b.name)
+            .collect();
+        assert_eq!(names, vec!["<module>", "grüße"]);
+    }
+
+    #[test]
+    fn test_filter_executed_blocks_only_def_line_not_executed() {
+        // Simulates import-time coverage: only the `def` line (line 2) is covered,
+        // but the body starts at line 3. The function should NOT be considered executed.
+        let blocks = vec![Block {
+            start_line: 2,
+            end_line: 4,
+            checksum: 111,
+            name: "get_active_announcements".to_string(),
+            qualified_name: "get_active_announcements".to_string(),
+            block_type: "function".to_string(),
+            body_start_line: 3,
+        }];
+        // Only the def line (2) was executed (import-time registration)
+        let executed_lines: HashSet<usize> = [2].into_iter().collect();
+        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
+        assert!(
+            result.is_empty(),
+            "Function with only def line covered should NOT be considered executed"
+        );
+    }
+
+    #[test]
+    fn test_filter_executed_blocks_body_line_executed() {
+        // When a body line is covered, the function IS considered executed.
+        let blocks = vec![Block {
+            start_line: 2,
+            end_line: 4,
+            checksum: 111,
+            name: "get_active_announcements".to_string(),
+            qualified_name: "get_active_announcements".to_string(),
+            block_type: "function".to_string(),
+            body_start_line: 3,
+        }];
+        // Body line 3 was executed (function was actually called)
+        let executed_lines: HashSet<usize> = [2, 3].into_iter().collect();
+        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
+        assert_eq!(
+            result.len(),
+            1,
+            "Function with body line covered should be considered executed"
+        );
+        assert_eq!(result[0].name, "get_active_announcements");
+    }
+
+    #[test]
+    fn test_filter_executed_blocks_decorator_not_counted() {
+        // Decorator on line 1, def on line 2, body starts line 3.
+        // Only decorator + def lines covered → not executed.
+        let blocks = vec![Block {
+            start_line: 1,
+            end_line: 5,
+            checksum: 222,
+            name: "decorated_func".to_string(),
+            qualified_name: "decorated_func".to_string(),
+            block_type: "function".to_string(),
+            body_start_line: 3,
+        }];
+        let executed_lines: HashSet<usize> = [1, 2].into_iter().collect();
+        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
+        assert!(
+            result.is_empty(),
+            "Decorator + def line coverage should not count as executed"
+        );
+    }
+
+    #[test]
+    fn test_filter_executed_blocks_class_def_line_counted() {
+        // Class body_start_line = class def line (skipping decorator).
+        // The class def line IS executed at import time, so covering it counts.
         let blocks = vec![Block {
-            start_line: 2,
-            end_line: 4,
-            checksum: 111,
-            name: "get_active_announcements".to_string(),
+            start_line: 1, // decorator line
+            end_line: 10,
+            checksum: 333,
+            name: "MyClass".to_string(),
+            qualified_name: "MyClass".to_string(),
+            block_type: "class".to_string(),
+            body_start_line: 2, // class def line
+        }];
+        // Only decorator line covered → not executed
+        let executed_lines: HashSet<usize> = [1].into_iter().collect();
+        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
+        assert!(
+            result.is_empty(),
+            "Decorated class with only decorator covered should NOT be executed"
+        );
+
+        // Class def line covered → executed
+        let executed_lines: HashSet<usize> = [1, 2].into_iter().collect();
+        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
+        assert_eq!(
+            result.len(),
+            1,
+            "Class with def line covered should be considered executed"
+        );
+    }
+
+    #[test]
+    fn test_calculate_fingerprint_latin1_declaration() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"# -*- coding: latin-1 -*-\ndef greet():\n    return '\xe9t\xe9'\n")
+            .unwrap();
+        file.flush().unwrap();
+
+        let fingerprint = calculate_fingerprint_internal(file.path().to_str().unwrap()).unwrap();
+        let blocks = fingerprint.blocks.unwrap();
+
+        assert_eq!(blocks.len(), 2); // module + function
+        assert_eq!(blocks[1].name, "greet");
+    }
+
+    #[test]
+    fn test_decode_source_notes() {
+        let path = Path::new("legacy.py");
+        let note = |bytes: &[u8]| decode_source(bytes, path).1;
+
+        // A supported encoding with bytes invalid for it is not "unsupported"
+        let ascii_with_latin1 = note(b"# -*- coding: ascii -*-\nname = 'caf\xe9'\n").unwrap();
+        assert_eq!(
+            ascii_with_latin1,
+            "legacy.py declares encoding 'ascii' but contains non-ASCII bytes, decoding as lossy UTF-8"
+        );
+        assert!(note(b"# -*- coding: ascii -*-\nname = 'cafe'\n").is_none());
+
+        let unsupported = note(b"# -*- coding: cp1252 -*-\nname = 'caf\xe9'\n").unwrap();
+        assert_eq!(
+            unsupported,
+            "Unsupported encoding 'cp1252' in legacy.py, decoding as lossy UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_calculate_fingerprint_strips_bom() {
+        let source = "def foo():\n    return 1\n";
+        let mut plain = NamedTempFile::new().unwrap();
+        plain.write_all(source.as_bytes()).unwrap();
+        let mut with_bom = NamedTempFile::new().unwrap();
+        with_bom.write_all(b"\xEF\xBB\xBF").unwrap();
+        with_bom.write_all(source.as_bytes()).unwrap();
+
+        let fp_plain = calculate_fingerprint_internal(plain.path().to_str().unwrap()).unwrap();
+        let fp_bom = calculate_fingerprint_internal(with_bom.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(fp_plain.checksums, fp_bom.checksums);
+        assert_ne!(fp_plain.file_hash, fp_bom.file_hash);
+    }
+
+    #[test]
+    fn test_calculate_fingerprint_invalid_utf8_falls_back_to_lossy() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"def foo():\n    return '\xff'\n").unwrap();
+        file.flush().unwrap();
+
+        let fingerprint = calculate_fingerprint_internal(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(fingerprint.checksums.len(), 2);
+    }
+
+    #[test]
+    fn test_detect_changes_warns_about_lossy_decoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let db_path = root.join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        PytestDiffDatabase::open(db_path).unwrap();
+
+        std::fs::write(root.join("binary.py"), b"def foo():\n    return '\xff'\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(changes.added, vec!["binary.py"]);
+        assert!(changes
+            .warnings
+            .iter()
+            .any(|w| w.contains("binary.py is not valid UTF-8")));
+    }
+
+    #[test]
+    fn test_crlf_and_lf_fingerprint_identically() {
+        let mut lf = NamedTempFile::new().unwrap();
+        lf.write_all(b"class A:\n    def f(self):\n        return 1\n")
+            .unwrap();
+        let mut crlf = NamedTempFile::new().unwrap();
+        crlf.write_all(b"class A:\r\n    def f(self):\r\n        return 1\r\n")
+            .unwrap();
+
+        let fp_lf = calculate_fingerprint_internal(lf.path().to_str().unwrap()).unwrap();
+        let fp_crlf = calculate_fingerprint_internal(crlf.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(fp_lf.file_hash, fp_crlf.file_hash);
+        assert_eq!(fp_lf.checksums, fp_crlf.checksums);
+
+        // Literal bytes are hashed when normalization is disabled
+        let literal = FingerprintOptions {
+            normalize_line_endings: false,
+            ..FingerprintOptions::default()
+        };
+        let fp_raw =
+            calculate_fingerprint_with_options(crlf.path().to_str().unwrap(), &literal).unwrap();
+        assert_ne!(fp_lf.file_hash, fp_raw.file_hash);
+    }
+
+    #[test]
+    fn test_normalize_crlf_keeps_lone_cr() {
+        assert_eq!(normalize_crlf(b"a\r\nb\rc\n".to_vec()), b"a\nb\rc\n");
+        assert_eq!(normalize_crlf(b"a\nb\n".to_vec()), b"a\nb\n");
+    }
+
+    #[test]
+    fn test_coding_declaration() {
+        assert_eq!(
+            coding_declaration(b"# -*- coding: latin-1 -*-\n"),
+            Some("latin-1".to_string())
+        );
+        assert_eq!(
+            coding_declaration(b"#!/usr/bin/env python\n# vim: set fileencoding=cp1252 :\n"),
+            Some("cp1252".to_string())
+        );
+        assert_eq!(coding_declaration(b"import os\n# coding: latin-1\n"), None);
+        assert_eq!(normalize_codec("ISO-8859-1"), "iso88591");
+        assert_eq!(normalize_codec("latin_1"), "latin1");
+    }
+
+    #[test]
+    fn test_detect_changes_reports_deleted_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("keep.py"), "def keep():\n    return 1\n").unwrap();
+        std::fs::write(root.join("gone.py"), "def gone():\n    return 2\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        std::fs::remove_file(root.join("gone.py")).unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(changes.deleted, vec!["gone.py"]);
+        assert!(changes.modified.is_empty());
+        assert_eq!(changes.changed_blocks["gone.py"].len(), 2); // module + function
+    }
+
+    #[test]
+    fn test_unreferenced_changes_lists_untested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("tested.py"), "def tested():\n    return 1\n").unwrap();
+        std::fs::write(root.join("untested.py"), "def untested():\n    return 2\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        let mut fp =
+            calculate_fingerprint_internal(root.join("tested.py").to_str().unwrap()).unwrap();
+        fp.filename = "tested.py".to_string();
+        let mut db = PytestDiffDatabase::open(db_path).unwrap();
+        db.save_test_execution_internal("test_tested", vec![fp], 0.1, false, "3.12", false)
+            .unwrap();
+        drop(db);
+
+        std::fs::write(root.join("tested.py"), "def tested():\n    return 10\n").unwrap();
+        std::fs::write(root.join("untested.py"), "def untested():\n    return 20\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert_eq!(changes.modified, vec!["tested.py", "untested.py"]);
+        assert!(changes.unreferenced_changes.is_empty());
+
+        assert_eq!(
+            unreferenced_changes(db_path, &changes).unwrap(),
+            vec!["untested.py"]
+        );
+    }
+
+    #[test]
+    fn test_ignore_comments_controls_comment_only_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        let root_str = root.to_str().unwrap();
+        let module = root.join("calc.py");
+
+        let detect = |ignore_comments: bool, edit: &str| {
+            let options = FingerprintOptions {
+                ignore_comments,
+                ..FingerprintOptions::default()
+            };
+            let db_path = dir.path().join(format!("{}.db", ignore_comments));
+            let db_path = db_path.to_str().unwrap();
+            std::fs::write(&module, "def add(a, b):\n    # sum\n    return a + b\n").unwrap();
+            save_baseline_internal(
+                db_path,
+                root_str,
+                false,
+                vec![],
+                true,
+                &options,
+                &ScanOptions::default(),
+            )
+            .unwrap();
+            std::fs::write(&module, edit).unwrap();
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap()
+                .modified
+        };
+
+        let comment_edit = "def add(a, b):\n    # add both operands\n    return a + b\n";
+        assert_eq!(detect(false, comment_edit), vec!["calc.py"]);
+        assert!(detect(true, comment_edit).is_empty());
+
+        let logic_edit = "def add(a, b):\n    # sum\n    return a - b\n";
+        assert_eq!(detect(true, logic_edit), vec!["calc.py"]);
+    }
+
+    #[test]
+    fn test_xxhash_checksums_round_trip_through_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        let root_str = root.to_str().unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let options = FingerprintOptions {
+            checksum_algorithm: ChecksumAlgorithm::XxHash64,
+            ..FingerprintOptions::default()
+        };
+        std::fs::write(root.join("calc.py"), "def add(a, b):\n    return a + b\n").unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            true,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        std::fs::write(root.join("calc.py"), "def add(a, b):\n    return b + a\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert_eq!(changes.modified, vec!["calc.py"]);
+        // The baseline checksum of `add` kept all 64 bits
+        let old_add = crate::parser::calculate_checksum_with(
+            "def add(a, b):\n    return a + b",
+            ChecksumAlgorithm::XxHash64,
+        );
+        assert!(i32::try_from(old_add).is_err());
+        assert_eq!(changes.changed_blocks["calc.py"], vec![old_add]);
+    }
+
+    #[test]
+    fn test_block_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        let root_str = root.to_str().unwrap();
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        std::fs::create_dir_all(root.join("tests")).unwrap();
+        // module + import + f + C + C.m
+        std::fs::write(
+            root.join("pkg/shapes.py"),
+            "import os\n\ndef f():\n    pass\n\nclass C:\n    def m(self):\n        pass\n",
+        )
+        .unwrap();
+        // module only
+        std::fs::write(root.join("consts.py"), "X = 1\n").unwrap();
+        // module + test_x
+        std::fs::write(root.join("tests/test_x.py"), "def test_x():\n    pass\n").unwrap();
+        // Unparseable files are left out
+        std::fs::write(root.join("broken.py"), "def (:\n").unwrap();
+
+        let counts = |sort_by_count, options: &FingerprintOptions, scan: &ScanOptions| {
+            block_counts_internal(root_str, &[], sort_by_count, options, scan).unwrap()
+        };
+        let defaults = (FingerprintOptions::default(), ScanOptions::default());
+        assert_eq!(
+            counts(false, &defaults.0, &defaults.1),
+            vec![
+                ("consts.py".to_string(), 1),
+                ("pkg/shapes.py".to_string(), 5),
+                ("tests/test_x.py".to_string(), 2),
+            ]
+        );
+        let by_count: Vec<String> = counts(true, &defaults.0, &defaults.1)
+            .into_iter()
+            .map(|(file, _)| file)
+            .collect();
+        assert_eq!(
+            by_count,
+            vec!["pkg/shapes.py", "tests/test_x.py", "consts.py"]
+        );
+
+        // Ignored block types aren't counted, and excluded files aren't scanned
+        let options = FingerprintOptions {
+            ignore_block_types: vec!["module".to_string(), "import".to_string()],
+            ..FingerprintOptions::default()
+        };
+        let scan = ScanOptions::new(false, &["tests/".to_string()]).unwrap();
+        assert_eq!(
+            counts(false, &options, &scan),
+            vec![
+                ("consts.py".to_string(), 0),
+                ("pkg/shapes.py".to_string(), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignore_block_types_excludes_main_guard() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "def run():\n    return 1\n\nif __name__ == \"__main__\":\n    run()\n"
+        )
+        .unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let all = calculate_fingerprint_with_options(path, &FingerprintOptions::default()).unwrap();
+        let options = FingerprintOptions {
+            ignore_block_types: vec!["main".to_string()],
+            ..FingerprintOptions::default()
+        };
+        let without_main = calculate_fingerprint_with_options(path, &options).unwrap();
+
+        assert_eq!(all.checksums.len(), 3); // module + run + main
+        assert_eq!(without_main.checksums.len(), 2);
+        assert!(without_main
+            .blocks
+            .unwrap()
+            .iter()
+            .all(|b| b.block_type != "main"));
+    }
+
+    #[test]
+    fn test_ignore_block_types_skips_module_level_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        let root_str = root.to_str().unwrap();
+        let module = root.join("calc.py");
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let options = FingerprintOptions {
+            ignore_block_types: vec!["module".to_string()],
+            ..FingerprintOptions::default()
+        };
+
+        std::fs::write(&module, "RATE = 1\n\ndef add(a, b):\n    return a + b\n").unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            true,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let stored = PytestDiffDatabase::open(db_path)
+            .unwrap()
+            .get_baseline_fingerprint_rust("calc.py")
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.checksums.len(), 1);
+
+        std::fs::write(&module, "RATE = 2\n\ndef add(a, b):\n    return a + b\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert!(changes.modified.is_empty());
+        assert!(changes.changed_blocks.is_empty());
+
+        std::fs::write(&module, "RATE = 2\n\ndef add(a, b):\n    return a - b\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert_eq!(changes.modified, vec!["calc.py"]);
+        assert_eq!(changes.changed_blocks["calc.py"].len(), 1);
+    }
+
+    #[test]
+    fn test_safe_mode_runs_all_without_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("calc.py"), "def add(a, b):\n    return a + b\n").unwrap();
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+
+        let changes = safe_mode_changes(db_path, || panic!("no scan without a baseline"), None);
+        assert!(changes.run_all);
+        assert_eq!(changes.warnings, vec!["No baseline has been saved"]);
+
+        // With a baseline, an unchanged tree is trusted
+        let options = FingerprintOptions::default();
+        let root_str = root.to_str().unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            true,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let changes = safe_mode_changes(
+            db_path,
+            || {
+                let roots = [root_str.to_string()];
+                detect_changes_scan(
+                    db_path,
+                    root_str,
+                    &roots,
+                    vec![],
+                    None,
+                    &options,
+                    &ScanOptions::default(),
+                    None,
+                )
+            },
+            None,
+        );
+        assert!(!changes.run_all);
+        assert!(changes.modified.is_empty());
+    }
+
+    #[test]
+    fn test_safe_mode_runs_all_with_corrupted_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        std::fs::write(&db_path, vec![0xAB; 8192]).unwrap();
+
+        let changes = safe_mode_changes(
+            db_path.to_str().unwrap(),
+            || panic!("no scan with a corrupted database"),
+            None,
+        );
+        assert!(changes.run_all);
+        assert!(changes.warnings[0].starts_with("Could not read the database"));
+    }
+
+    #[test]
+    fn test_verify_baseline_sorts_files_into_buckets() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        for name in ["a.py", "pkg/b.py", "edited.py", "deleted.py"] {
+            std::fs::write(root.join(name), "def f():\n    return 1\n").unwrap();
+        }
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            true,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // A touch alone is not a mismatch; only content counts
+        let touched = root.join("a.py");
+        std::fs::File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))
+            .unwrap();
+        std::fs::write(root.join("edited.py"), "def f():\n    return 2\n").unwrap();
+        std::fs::remove_file(root.join("deleted.py")).unwrap();
+
+        let verification = verify_baseline_internal(db_path, root_str, &options).unwrap();
+        assert_eq!(verification.matching, vec!["a.py", "pkg/b.py"]);
+        assert_eq!(verification.mismatched, vec!["edited.py"]);
+        assert_eq!(verification.missing, vec!["deleted.py"]);
+        assert!(!verification.is_consistent());
+    }
+
+    #[test]
+    fn test_file_granularity_skips_block_comparison() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("calc.py"),
+            "def add(a, b):\n    return a + b\n\ndef sub(a, b):\n    return a - b\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("shapes.py"), "def area(r):\n    return r * r\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // A real edit, and a whitespace edit that leaves every block checksum intact
+        std::fs::write(
+            root.join("calc.py"),
+            "def add(a, b):\n    return a + b + 0\n\ndef sub(a, b):\n    return a - b\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("shapes.py"),
+            "def area(r):\n    return r * r\n\n\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("new.py"), "X = 1\n").unwrap();
+
+        let detect = |granularity| {
+            let options = FingerprintOptions {
+                granularity,
+                ..FingerprintOptions::default()
+            };
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap()
+        };
+
+        let block = detect(Granularity::Block);
+        assert_eq!(block.modified, vec!["calc.py"]);
+        assert_eq!(block.changed_blocks["calc.py"].len(), 1);
+
+        let file = detect(Granularity::File);
+        assert_eq!(file.modified, vec!["calc.py", "shapes.py"]);
+        assert_eq!(file.added, block.added);
+        assert!(file.changed_blocks.is_empty());
+
+        assert!(Granularity::from_name("line").is_err());
+    }
+
+    #[test]
+    fn test_detect_changes_reports_added_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("old.py"), "def old():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        std::fs::write(root.join("new.py"), "def new():\n    return 2\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(changes.added, vec!["new.py"]);
+        assert!(changes.modified.is_empty());
+        assert!(changes.deleted.is_empty());
+    }
+
+    #[test]
+    fn test_detect_changes_sub_millisecond_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("mod.py");
+        std::fs::write(&file, "def f():\n    return 1\n").unwrap();
+        let baseline_time = UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 100_000);
+        let set_mtime = |when| {
+            std::fs::File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(when)
+                .unwrap()
+        };
+        set_mtime(baseline_time);
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let stored = PytestDiffDatabase::open(db_path)
+            .unwrap()
+            .get_all_baseline_fingerprints()
+            .unwrap();
+        assert_eq!(stored["mod.py"].mtime_exact, Some((1_700_000_000, 100_000)));
+
+        // Same second, 200µs later: within the old float tolerance, but the
+        // exact mtime differs so the hash fallback catches the edit
+        std::fs::write(&file, "def f():\n    return 2\n").unwrap();
+        set_mtime(baseline_time + std::time::Duration::from_micros(200));
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(changes.modified, vec!["mod.py"]);
+    }
+
+    #[test]
+    fn test_refresh_mtimes_updates_touched_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("mod.py");
+        std::fs::write(&file, "def f():\n    return 1\n").unwrap();
+        let set_mtime = |secs| {
+            std::fs::File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + std::time::Duration::new(secs, 500))
+                .unwrap()
+        };
+        set_mtime(1_700_000_000);
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions {
+            refresh_mtimes: true,
+            ..FingerprintOptions::default()
+        };
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let stored_mtime = || {
+            PytestDiffDatabase::open(db_path)
+                .unwrap()
+                .get_baseline_fingerprint_rust("mod.py")
+                .unwrap()
+                .unwrap()
+                .mtime_exact
+        };
+
+        // Touched without an edit: not a change, and the baseline catches up
+        set_mtime(1_700_000_100);
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert!(changes.modified.is_empty());
+        assert_eq!(stored_mtime(), Some((1_700_000_100, 500)));
+
+        // Without the option the stored mtime stays stale
+        set_mtime(1_700_000_200);
+        detect_changes_internal(
+            db_path,
+            root_str,
+            vec![],
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(stored_mtime(), Some((1_700_000_100, 500)));
+    }
+
+    #[test]
+    fn test_detect_changes_flags_conftest_subtree() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        let write = |rel: &str, body: &str| {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, body).unwrap();
+        };
+        write("conftest.py", "import pytest\n");
+        write("tests/api/conftest.py", "import pytest\n");
+        write("tests/api/test_users.py", "def test_users():\n    pass\n");
+        write(
+            "tests/api/v2/test_orders.py",
+            "def test_orders():\n    pass\n",
+        );
+        write("tests/ui/test_pages.py", "def test_pages():\n    pass\n");
+        write("src/app.py", "def app():\n    pass\n");
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        write(
+            "tests/api/conftest.py",
+            "import pytest\n\n\n@pytest.fixture\ndef client():\n    return 1\n",
+        );
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(changes.modified, vec!["tests/api/conftest.py"]);
+        assert_eq!(
+            changes.conftest_affected,
+            vec!["tests/api/test_users.py", "tests/api/v2/test_orders.py"]
+        );
+    }
+
+    #[test]
+    fn test_detect_changes_collects_errored_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("broken.py"), "def broken():\n    return 1\n").unwrap();
+        std::fs::write(root.join("good.py"), "def good():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        std::fs::write(root.join("broken.py"), "def broken(:\n    return 1\n").unwrap();
+        std::fs::write(root.join("good.py"), "def good():\n    return 2\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(changes.errored.len(), 1);
+        assert_eq!(changes.errored[0].0, "broken.py");
+        assert_eq!(changes.modified, vec!["good.py"]);
+        // The unparsable file's baseline blocks count as changed
+        assert!(changes.changed_blocks.contains_key("broken.py"));
+        assert!(changes.changed_blocks.contains_key("good.py"));
+        assert_eq!(changes.warnings.len(), 1);
+        assert!(changes.warnings[0].starts_with("Could not check broken.py: "));
+    }
+
+    #[test]
+    fn test_detect_changes_warns_about_skipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("small.py"), "X = 1\n").unwrap();
+        std::fs::write(root.join("generated.py"), "X = 1\n".repeat(100)).unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let scan = ScanOptions {
+            max_file_bytes: Some(100),
+            ..ScanOptions::default()
+        };
+        let changes = detect_changes_internal(
+            db_path,
+            root_str,
+            vec![],
+            &FingerprintOptions::default(),
+            &scan,
+        )
+        .unwrap();
+
+        assert_eq!(changes.added, vec!["small.py"]);
+        assert_eq!(changes.warnings.len(), 1);
+        assert!(changes.warnings[0].starts_with("Skipping "));
+        assert!(changes.warnings[0].contains("generated.py (600 bytes exceeds max_file_bytes)"));
+    }
+
+    #[test]
+    fn test_summarize_changes_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("a.py"),
+            "def f():\n    return 1\n\n\ndef g():\n    return 2\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("b.py"), "def b():\n    return 1\n").unwrap();
+        std::fs::write(root.join("c.py"), "def c():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // a.py: f edited, h added (the module block changes too); c.py deleted;
+        // d.py added; b.py untouched
+        std::fs::write(
+            root.join("a.py"),
+            "def f():\n    return 10\n\n\ndef g():\n    return 2\n\n\ndef h():\n    return 3\n",
+        )
+        .unwrap();
+        std::fs::remove_file(root.join("c.py")).unwrap();
+        std::fs::write(root.join("d.py"), "def d():\n    return 1\n").unwrap();
+
+        let summary = summarize_changes_internal(
+            db_path,
+            root_str,
+            vec![],
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            summary,
+            ChangeSummary {
+                files_scanned: 3,
+                files_modified: 1,
+                files_added: 1,
+                files_deleted: 1,
+                files_renamed: 0,
+                files_errored: 0,
+                // h in a.py, plus <module> and d in d.py
+                blocks_added: 3,
+                // <module> and c in c.py
+                blocks_removed: 2,
+                // <module> and f in a.py
+                blocks_modified: 2,
+                warnings: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_summarize_changes_reports_unparsable_modified_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def f():\n    return 1\n").unwrap();
+        std::fs::write(root.join("b.py"), "def g():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // File granularity reports a.py without parsing it, so only the
+        // summary finds out it no longer parses
+        std::fs::write(root.join("a.py"), "def f(:\n").unwrap();
+        std::fs::write(root.join("b.py"), "def g():\n    return 2\n").unwrap();
+        let options = FingerprintOptions {
+            granularity: Granularity::File,
+            ..FingerprintOptions::default()
+        };
+        let summary = summarize_changes_internal(
+            db_path,
+            root_str,
+            vec![],
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(summary.files_modified, 2);
+        assert_eq!(summary.files_errored, 1);
+        assert_eq!(summary.blocks_modified, 1);
+        assert_eq!(summary.warnings.len(), 1);
+        assert!(summary.warnings[0].contains("a.py"));
+    }
+
+    #[test]
+    fn test_count_block_changes() {
+        assert_eq!(count_block_changes(&[1, 2, 3], &[1, 2, 3]), (0, 0, 0));
+        assert_eq!(count_block_changes(&[1, 2], &[1, 4, 5]), (1, 0, 1));
+        assert_eq!(count_block_changes(&[1, 2, 3], &[1]), (0, 2, 0));
+        assert_eq!(count_block_changes(&[7, 7], &[7]), (0, 1, 0));
+    }
+
+    #[test]
+    fn test_save_baseline_overlapping_scopes_store_one_row_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        let tests = root.join("tests");
+        std::fs::create_dir_all(&tests).unwrap();
+        std::fs::write(root.join("app.py"), "def app():\n    return 1\n").unwrap();
+        std::fs::write(tests.join("test_app.py"), "def test_app():\n    pass\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let tests_str = tests.to_string_lossy().to_string();
+        for _ in 0..2 {
+            let count = save_baseline_roots_internal(
+                db_path,
+                root_str,
+                &[root_str.to_string(), tests_str.clone()],
+                false,
+                vec![root_str.to_string(), tests_str.clone()],
+                true,
+                false,
+                None,
+                &FingerprintOptions::default(),
+                &ScanOptions::default(),
+                None,
+                None,
+            )
+            .unwrap()
+            .count;
+            assert_eq!(count, 2);
+        }
+
+        let db = PytestDiffDatabase::open(db_path).unwrap();
+        assert_eq!(
+            db.list_baseline_files_internal().unwrap(),
+            vec!["app.py", "tests/test_app.py"]
+        );
+    }
+
+    #[test]
+    fn test_save_baseline_multiple_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        let backend = root.join("backend");
+        let shared = root.join("libs").join("shared");
+        std::fs::create_dir_all(&backend).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(backend.join("api.py"), "def api():\n    return 1\n").unwrap();
+        std::fs::write(shared.join("util.py"), "def util():\n    return 1\n").unwrap();
+        // Outside both roots, so not scanned
+        std::fs::write(root.join("setup.py"), "print('setup')\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let roots = [
+            backend.to_string_lossy().to_string(),
+            shared.to_string_lossy().to_string(),
+            // Overlapping roots must not count files twice
+            backend.to_string_lossy().to_string(),
+        ];
+        let options = FingerprintOptions::default();
+        let count = save_baseline_roots_internal(
+            db_path,
+            root_str,
+            &roots,
+            false,
+            vec![],
+            false,
+            false,
+            None,
+            &options,
+            &ScanOptions::default(),
+            None,
+            None,
+        )
+        .unwrap()
+        .count;
+        assert_eq!(count, 2);
+
+        let db = PytestDiffDatabase::open(db_path).unwrap();
+        let mut files = db.list_baseline_files_internal().unwrap();
+        files.sort();
+        assert_eq!(files, vec!["backend/api.py", "libs/shared/util.py"]);
+        drop(db);
+
+        std::fs::write(shared.join("util.py"), "def util():\n    return 2\n").unwrap();
+        let (changes, scanned) = detect_changes_scan(
+            db_path,
+            root_str,
+            &roots,
+            vec![],
+            None,
+            &options,
+            &ScanOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(scanned, 2);
+        assert_eq!(changes.modified, vec!["libs/shared/util.py"]);
+        assert!(changes.deleted.is_empty());
+    }
+
+    #[test]
+    fn test_stream_file_hash_matches_one_shot_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.py");
+        // Odd line length so CRLF pairs straddle chunk boundaries
+        let mut content = Vec::new();
+        let mut i = 0;
+        while content.len() as u64 <= STREAMING_HASH_THRESHOLD {
+            content.extend_from_slice(format!("value_{} = {}\r\n", i, i).as_bytes());
+            i += 1;
+        }
+        content.extend_from_slice(b"tail = 1\r");
+        std::fs::write(&path, &content).unwrap();
+
+        for normalize_line_endings in [true, false] {
+            let options = FingerprintOptions {
+                normalize_line_endings,
+                ..FingerprintOptions::default()
+            };
+            let one_shot = blake3::hash(&read_source_bytes(&path, &options).unwrap())
+                .to_hex()
+                .to_string();
+            assert_eq!(stream_file_hash(&path, &options).unwrap(), one_shot);
+            assert_eq!(file_hash(&path, &options).unwrap(), one_shot);
+        }
+    }
+
+    #[test]
+    fn test_stream_file_hash_crlf_split_across_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("split.py");
+        let options = FingerprintOptions::default();
+        // Put '\r' as the last byte of the first chunk in several layouts
+        for tail in [&b"\r\n"[..], b"\r\r\n", b"\rx", b"\r"] {
+            let mut content = vec![b'#'; HASH_CHUNK_BYTES - 1];
+            content.extend_from_slice(tail);
+            std::fs::write(&path, &content).unwrap();
+            let one_shot = blake3::hash(&normalize_crlf(content)).to_hex().to_string();
+            assert_eq!(stream_file_hash(&path, &options).unwrap(), one_shot);
+        }
+    }
+
+    #[test]
+    fn test_check_file_changed_opens_file_at_most_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = FingerprintOptions::default();
+        let opens_during = |f: &dyn Fn()| {
+            let before = SOURCE_OPENS.with(|opens| opens.get());
+            f();
+            SOURCE_OPENS.with(|opens| opens.get()) - before
+        };
+
+        let small = dir.path().join("small.py");
+        std::fs::write(&small, "def f():\n    return 1\n").unwrap();
+        // One large constant keeps parsing cheap while exceeding the streaming threshold
+        let padding = "x".repeat(STREAMING_HASH_THRESHOLD as usize);
+        let big_source = format!("DATA = '{}'\n\n\ndef f():\n    return 1\n", padding);
+        let big = dir.path().join("big.py");
+        std::fs::write(&big, &big_source).unwrap();
+
+        let mut baselines = HashMap::new();
+        for (name, path) in [("small.py", &small), ("big.py", &big)] {
+            let fp = calculate_fingerprint_with_options(path.to_str().unwrap(), &options).unwrap();
+            baselines.insert(name.to_string(), fp);
+        }
+        let check = |path: &Path, name: &str| {
+            check_file_changed_with_baseline(&baselines, path, name, &options, None, None).unwrap()
+        };
+
+        // Unchanged mtime: answered from metadata alone
+        assert_eq!(
+            opens_during(&|| assert!(check(&small, "small.py").is_none())),
+            0
+        );
+
+        // Changed files, small and streamed, are opened exactly once
+        std::fs::write(&small, "def f():\n    return 2\n").unwrap();
+        assert_eq!(
+            opens_during(&|| assert!(check(&small, "small.py").is_some())),
+            1
+        );
+        std::fs::write(&big, big_source.replacen("return 1", "return 2", 1)).unwrap();
+        assert_eq!(
+            opens_during(&|| assert!(check(&big, "big.py").is_some())),
+            1
+        );
+
+        // Touched but identical: hashed once and not parsed
+        std::fs::write(&small, "def f():\n    return 1\n").unwrap();
+        let touched = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&small)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        assert_eq!(
+            opens_during(&|| assert!(check(&small, "small.py").is_none())),
+            1
+        );
+    }
+
+    #[test]
+    fn test_detect_changes_flags_baseline_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        assert_eq!(baseline_age_internal(db_path).unwrap(), (None, 0));
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        let (saved_at, file_count) = baseline_age_internal(db_path).unwrap();
+        assert!(saved_at.is_some());
+        assert_eq!(file_count, 1);
+
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert!(!changes.baseline_mismatch);
+
+        // The tree now has far more files than the baseline
+        for i in 0..4 {
+            std::fs::write(root.join(format!("m{}.py", i)), "x = 1\n").unwrap();
+        }
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert!(changes.baseline_mismatch);
+        assert!(changes.warnings[0].contains("different checkout"));
+    }
+
+    #[test]
+    fn test_scoped_detect_changes_is_not_a_baseline_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("tests/unit")).unwrap();
+        std::fs::create_dir_all(root.join("tests/integration")).unwrap();
+        std::fs::write(root.join("app.py"), "def a():\n    return 1\n").unwrap();
+        std::fs::write(
+            root.join("tests/unit/test_app.py"),
+            "def test_a():\n    pass\n",
+        )
+        .unwrap();
+        for i in 0..6 {
+            std::fs::write(
+                root.join(format!("tests/integration/test_{}.py", i)),
+                "def test_x():\n    pass\n",
+            )
+            .unwrap();
+        }
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // A run scoped to one directory scans 2 of the 8 baseline files
+        let scope = vec![root.join("tests/unit").to_string_lossy().into_owned()];
+        let changes =
+            detect_changes_internal(db_path, root_str, scope, &options, &ScanOptions::default())
+                .unwrap();
+        assert!(!changes.baseline_mismatch);
+        assert!(changes.warnings.is_empty());
+        assert!(changes.deleted.is_empty());
+    }
+
+    #[test]
+    fn test_baseline_counts_diverge() {
+        assert!(!baseline_counts_diverge(0, 100));
+        assert!(!baseline_counts_diverge(100, 100));
+        assert!(!baseline_counts_diverge(100, 60));
+        assert!(baseline_counts_diverge(100, 40));
+        assert!(baseline_counts_diverge(10, 100));
+    }
+
+    #[test]
+    fn test_baseline_environment_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let roots = [root_str.to_string()];
+        let options = FingerprintOptions::default();
+        let scan = ScanOptions::default();
+        save_baseline_roots_internal(
+            db_path,
+            root_str,
+            &roots,
+            false,
+            vec![],
+            false,
+            false,
+            Some("3.12.1"),
+            &options,
+            &scan,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let db = PytestDiffDatabase::open(db_path).unwrap();
+        assert_eq!(
+            db.get_metadata_internal(CHECKSUM_ALGORITHM_KEY).unwrap(),
+            Some("crc32".to_string())
+        );
+        assert_eq!(
+            db.get_metadata_internal(CRATE_VERSION_KEY).unwrap(),
+            Some(env!("CARGO_PKG_VERSION").to_string())
+        );
+        assert_eq!(
+            db.get_metadata_internal(PYTHON_VERSION_KEY).unwrap(),
+            Some("3.12.1".to_string())
+        );
+        drop(db);
+
+        let warnings = |python_version, options: &FingerprintOptions| {
+            detect_changes_scan(
+                db_path,
+                root_str,
+                &roots,
+                vec![],
+                python_version,
+                options,
+                &scan,
+                None,
+            )
+            .unwrap()
+            .0
+            .warnings
+        };
+        // Same algorithm, and a patch release of the same Python
+        assert!(warnings(Some("3.12.4"), &options).is_empty());
+        assert!(warnings(None, &options).is_empty());
+        assert_eq!(warnings(Some("3.13.0"), &options).len(), 1);
+
+        // Detection with options that change checksums
+        let seeded = FingerprintOptions {
+            seed: 7,
+            ..FingerprintOptions::default()
+        };
+        let seeded_warnings = warnings(Some("3.12.1"), &seeded);
+        assert_eq!(seeded_warnings.len(), 1);
+        assert!(seeded_warnings[0].contains("'crc32' but this run uses 'crc32+seed=7'"));
+        let xxhash = FingerprintOptions {
+            checksum_algorithm: ChecksumAlgorithm::XxHash64,
+            ..FingerprintOptions::default()
+        };
+        assert!(warnings(Some("3.12.1"), &xxhash)[0].contains("this run uses 'xxhash64'"));
+
+        // A baseline produced under another algorithm
+        let db = PytestDiffDatabase::open(db_path).unwrap();
+        db.set_metadata_internal(CHECKSUM_ALGORITHM_KEY, "xxhash64")
+            .unwrap();
+        drop(db);
+        let warnings = warnings(Some("3.12.1"), &options);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'xxhash64'"));
+    }
+
+    #[test]
+    fn test_minor_version() {
+        assert_eq!(minor_version("3.12.1"), "3.12");
+        assert_eq!(minor_version("3.12"), "3.12");
+        assert_eq!(minor_version("3"), "3");
+    }
+
+    #[test]
+    fn test_save_baseline_dry_run_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+        std::fs::write(root.join("pkg/b.py"), "def b():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let count = save_baseline_roots_internal(
+            db_path,
+            root_str,
+            &[root_str.to_string()],
+            false,
+            vec![],
+            false,
+            true,
+            None,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+            None,
+            None,
+        )
+        .unwrap()
+        .count;
+
+        assert_eq!(count, 2);
+        assert!(!Path::new(db_path).exists());
+    }
+
+    #[test]
+    fn test_save_baseline_dry_run_leaves_existing_database_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let before = std::fs::read(db_path).unwrap();
+
+        std::fs::write(root.join("b.py"), "def b():\n    return 2\n").unwrap();
+        let run = save_baseline_roots_internal(
+            db_path,
+            root_str,
+            &[root_str.to_string()],
+            false,
+            vec![],
+            false,
+            true,
+            None,
+            &options,
+            &ScanOptions::default(),
+            None,
+            None,
+        )
+        .unwrap();
+
+        // a.py is unchanged and only b.py would be fingerprinted
+        assert_eq!(run.count, 2);
+        assert_eq!(run.timings.len(), 1);
+        assert_eq!(std::fs::read(db_path).unwrap(), before);
+        assert!(!Path::new(&format!("{}-wal", db_path)).exists());
+    }
+
+    #[test]
+    fn test_save_baseline_collects_timings() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+        std::fs::write(root.join("b.py"), "def b():\n    return 1\n").unwrap();
+        std::fs::write(root.join("broken.py"), "def broken(:\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let run = |force| {
+            save_baseline_roots_internal(
+                db_path,
+                root_str,
+                &[root_str.to_string()],
+                false,
+                vec![],
+                force,
+                false,
+                None,
+                &FingerprintOptions::default(),
+                &ScanOptions::default(),
+                None,
+                None,
+            )
+            .unwrap()
+        };
+
+        // Every successfully fingerprinted file has a timing; the broken one doesn't
+        let first = run(false);
+        assert_eq!(first.count, 2);
+        let mut paths: Vec<&str> = first.timings.iter().map(|(p, _)| p.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["a.py", "b.py"]);
+        assert!(first.timings.iter().all(|(_, seconds)| *seconds >= 0.0));
+
+        // Unchanged files are skipped, so they aren't timed
+        std::fs::write(root.join("b.py"), "def b():\n    return 2\n").unwrap();
+        let second = run(false);
+        assert_eq!(second.count, 2);
+        assert_eq!(second.timings.len(), 1);
+        assert_eq!(second.timings[0].0, "b.py");
+    }
+
+    #[test]
+    fn test_save_baseline_reports_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        for i in 0..120 {
+            std::fs::write(root.join(format!("m{}.py", i)), format!("X = {}\n", i)).unwrap();
+        }
+
+        let db_path = dir.path().join("test.db");
+        let root_str = root.to_str().unwrap();
+        let calls = parking_lot::Mutex::new(Vec::new());
+        let record = |processed, total| calls.lock().push((processed, total));
+        save_baseline_roots_internal(
+            db_path.to_str().unwrap(),
+            root_str,
+            &[root_str.to_string()],
+            false,
+            vec![],
+            false,
+            false,
+            None,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+            Some(&record),
+            None,
+        )
+        .unwrap();
+
+        let calls = calls.into_inner();
+        assert!(!calls.is_empty());
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(calls.iter().all(|&(_, total)| total == 120));
+        assert_eq!(calls.last(), Some(&(120, 120)));
+    }
+
+    #[test]
+    fn test_save_baseline_stops_when_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        for i in 0..1000 {
+            std::fs::write(root.join(format!("m{}.py", i)), format!("X = {}\n", i)).unwrap();
+        }
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let token = CancelToken::new();
+        let cancel_at_first_report = |_, _| token.cancel();
+        let run = save_baseline_roots_internal(
+            db_path,
+            root_str,
+            &[root_str.to_string()],
+            false,
+            vec![],
+            false,
+            false,
+            None,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+            Some(&cancel_at_first_report),
+            Some(&token),
+        )
+        .unwrap();
+
+        // Files fingerprinted before the cancellation are kept, the rest are skipped
+        assert!(run.count >= 50 && run.count < 1000, "count = {}", run.count);
+        let db = PytestDiffDatabase::open(db_path).unwrap();
+        assert_eq!(db.list_baseline_files_internal().unwrap().len(), run.count);
+        assert_eq!(
+            db.get_metadata_internal(BASELINE_SAVED_AT_KEY).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_changes_stops_when_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "X = 1\n").unwrap();
+        std::fs::write(root.join("b.py"), "Y = 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let token = CancelToken::new();
+        token.cancel();
+        let (changes, scanned) = detect_changes_scan(
+            db_path,
+            root_str,
+            &[root_str.to_string()],
+            vec![],
+            None,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+            Some(&token),
+        )
+        .unwrap();
+
+        assert_eq!(scanned, 2);
+        assert!(changes.added.is_empty());
+        assert_eq!(
+            changes.warnings,
+            vec!["Change detection was cancelled; 2 of 2 files were not checked"]
+        );
+    }
+
+    #[test]
+    fn test_checksum_collision_warnings() {
+        let block = |name: &str, checksum| Block {
+            start_line: 1,
+            end_line: 2,
+            checksum,
+            name: name.to_string(),
             block_type: "function".to_string(),
-            body_start_line: 3,
-        }];
-        // Body line 3 was executed (function was actually called)
-        let executed_lines: HashSet<usize> = [2, 3].into_iter().collect();
-        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
+            qualified_name: name.to_string(),
+            body_start_line: 2,
+        };
+        let fingerprint = |blocks: Vec<Block>| Fingerprint {
+            filename: "calc.py".to_string(),
+            checksums: blocks.iter().map(|b| b.checksum).collect(),
+            file_hash: String::new(),
+            mtime: 0.0,
+            mtime_exact: None,
+            blocks: Some(blocks),
+        };
+
+        // Stubbed checksums: add and sub collide, mul is distinct
+        let colliding = fingerprint(vec![block("add", 7), block("sub", 7), block("mul", 8)]);
+        let warnings = checksum_collision_warnings(&colliding);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("calc.py"));
+        assert!(warnings[0].contains("add, sub"));
+
+        // Equal checksums under the same name are identical redefinitions, not collisions
+        let redefined = fingerprint(vec![block("add", 7), block("add", 7)]);
+        assert!(checksum_collision_warnings(&redefined).is_empty());
+
+        // A real parse has no collisions
+        let blocks =
+            parse_module_internal("def add():\n    return 1\n\n\ndef sub():\n    return 2\n")
+                .unwrap();
+        assert!(checksum_collision_warnings(&fingerprint(blocks)).is_empty());
+    }
+
+    #[test]
+    fn test_conftest_affected_files_root_conftest() {
+        let scanned: Vec<String> = ["conftest.py", "src/app.py", "tests/test_app.py"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let changed = ["conftest.py".to_string()];
         assert_eq!(
-            result.len(),
-            1,
-            "Function with body line covered should be considered executed"
+            conftest_affected_files(scanned.iter(), changed.iter()),
+            vec!["src/app.py", "tests/test_app.py"]
         );
-        assert_eq!(result[0].name, "get_active_announcements");
+        let changed = ["src/app.py".to_string()];
+        assert!(conftest_affected_files(scanned.iter(), changed.iter()).is_empty());
+    }
+
+    #[test]
+    fn test_detect_changes_reports_renamed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("utils.py"), "def util():\n    return 1\n").unwrap();
+        std::fs::write(root.join("other.py"), "def other():\n    return 2\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // Move one file unchanged, and replace the other with different content
+        std::fs::rename(root.join("utils.py"), root.join("helpers.py")).unwrap();
+        std::fs::remove_file(root.join("other.py")).unwrap();
+        std::fs::write(root.join("fresh.py"), "def fresh():\n    return 3\n").unwrap();
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(
+            changes.renamed,
+            vec![("utils.py".to_string(), "helpers.py".to_string())]
+        );
+        assert_eq!(changes.added, vec!["fresh.py"]);
+        assert_eq!(changes.deleted, vec!["other.py"]);
+        // Imports of the old path break, so its blocks still count as changed
+        assert_eq!(changes.changed_blocks["utils.py"].len(), 2);
+        assert!(!changes.changed_blocks.contains_key("helpers.py"));
+    }
+
+    #[test]
+    fn test_select_tests_after_rename_selects_tests_of_old_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("utils.py"), "def util():\n    return 1\n").unwrap();
+        std::fs::write(root.join("other.py"), "def other():\n    return 2\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        let mut db = PytestDiffDatabase::open(db_path).unwrap();
+        for (test, file) in [("test_util", "utils.py"), ("test_other", "other.py")] {
+            let mut fp = calculate_fingerprint_internal(root.join(file).to_str().unwrap()).unwrap();
+            fp.filename = file.to_string();
+            db.save_test_execution_internal(test, vec![fp], 0.1, false, "3.12", false)
+                .unwrap();
+        }
+        drop(db);
+
+        // test_util still imports `utils`, which no longer exists
+        std::fs::rename(root.join("utils.py"), root.join("helpers.py")).unwrap();
+        let selected =
+            select_tests_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+
+        assert_eq!(selected, vec!["test_util"]);
+    }
+
+    #[test]
+    fn test_inspect_file_reports_edited_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        let original = "def add(a, b):\n    return a + b\n\ndef sub(a, b):\n    return a - b\n";
+        std::fs::write(root.join("calc.py"), original).unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let old_blocks = parse_module_internal(original).unwrap();
+        let old_sub = old_blocks
+            .iter()
+            .find(|b| b.name == "sub")
+            .unwrap()
+            .checksum;
+
+        std::fs::write(
+            root.join("calc.py"),
+            "def add(a, b):\n    return a + b\n\ndef sub(a, b):\n    return b - a\n",
+        )
+        .unwrap();
+        let options = FingerprintOptions::default();
+        let inspection =
+            inspect_file_internal(db_path, "calc.py", Some(root_str), &options).unwrap();
+
+        assert_eq!(inspection.filename, "calc.py");
+        let stored: Vec<i64> = old_blocks.iter().map(|b| b.checksum).collect();
+        assert_eq!(inspection.baseline_checksums, Some(stored));
+        assert_eq!(inspection.changed_checksums, vec![old_sub]);
+        assert!(inspection.has_changes());
+        let names: Vec<&str> = inspection.blocks.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["<module>", "add", "sub"]);
+        let checksums: Vec<i64> = inspection.blocks.iter().map(|b| b.checksum).collect();
+        assert_eq!(inspection.current_checksums, checksums);
+
+        // Without a baseline every current checksum counts as changed
+        std::fs::write(root.join("new.py"), "X = 1\n").unwrap();
+        let fresh = inspect_file_internal(db_path, "new.py", Some(root_str), &options).unwrap();
+        assert_eq!(fresh.baseline_checksums, None);
+        assert_eq!(fresh.changed_checksums, fresh.current_checksums);
+    }
+
+    #[test]
+    fn test_inspect_file_uses_baseline_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("calc.py"), "def add(a, b):\n    return a + b\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions {
+            ignore_comments: true,
+            seed: 7,
+            ..FingerprintOptions::default()
+        };
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // A comment-only edit is not a change under the baseline's options
+        std::fs::write(
+            root.join("calc.py"),
+            "def add(a, b):\n    # sum\n    return a + b\n",
+        )
+        .unwrap();
+        let inspection =
+            inspect_file_internal(db_path, "calc.py", Some(root_str), &options).unwrap();
+        assert_eq!(
+            inspection.baseline_checksums.as_ref(),
+            Some(&inspection.current_checksums)
+        );
+        assert!(inspection.changed_checksums.is_empty());
+
+        // Default options compute checksums the seeded baseline doesn't know
+        let unseeded = inspect_file_internal(
+            db_path,
+            "calc.py",
+            Some(root_str),
+            &FingerprintOptions::default(),
+        )
+        .unwrap();
+        assert!(!unseeded.changed_checksums.is_empty());
+    }
+
+    #[test]
+    fn test_select_tests_returns_tests_of_edited_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("calc.py"),
+            "def add(a, b):\n    return a + b\n\ndef sub(a, b):\n    return a - b\n",
+        )
+        .unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions::default();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        // Each test executed the module and one of the functions
+        let full = calculate_fingerprint_internal(root.join("calc.py").to_str().unwrap()).unwrap();
+        let executed = |name: &str| {
+            let blocks: Vec<Block> = full
+                .blocks
+                .clone()
+                .unwrap()
+                .into_iter()
+                .filter(|b| b.name == "<module>" || b.name == name)
+                .collect();
+            Fingerprint {
+                filename: "calc.py".to_string(),
+                checksums: blocks.iter().map(|b| b.checksum).collect(),
+                blocks: Some(blocks),
+                ..full.clone()
+            }
+        };
+        let mut db = PytestDiffDatabase::open(db_path).unwrap();
+        for (test, function) in [("test_add", "add"), ("test_sub", "sub")] {
+            db.save_test_execution_internal(
+                test,
+                vec![executed(function)],
+                0.1,
+                false,
+                "3.12",
+                false,
+            )
+            .unwrap();
+        }
+        drop(db);
+
+        let select = || {
+            select_tests_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap()
+        };
+        assert!(select().is_empty());
+
+        std::fs::write(
+            root.join("calc.py"),
+            "def add(a, b):\n    return a + b\n\ndef sub(a, b):\n    return b - a\n",
+        )
+        .unwrap();
+        assert_eq!(select(), vec!["test_sub"]);
+    }
+
+    #[test]
+    fn test_update_baseline_file_only_touches_that_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+        std::fs::write(root.join("b.py"), "def b():\n    return 2\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+        let before = PytestDiffDatabase::open(db_path)
+            .unwrap()
+            .get_all_baseline_fingerprints()
+            .unwrap();
+
+        std::fs::write(root.join("a.py"), "def a():\n    return 10\n").unwrap();
+        std::fs::write(root.join("c.py"), "def c():\n    return 3\n").unwrap();
+        let options = FingerprintOptions::default();
+        let updated =
+            update_baseline_file_internal(db_path, "a.py", Some(root_str), &options).unwrap();
+        update_baseline_file_internal(db_path, "c.py", Some(root_str), &options).unwrap();
+
+        let after = PytestDiffDatabase::open(db_path)
+            .unwrap()
+            .get_all_baseline_fingerprints()
+            .unwrap();
+        assert_eq!(updated.filename, "a.py");
+        assert_ne!(after["a.py"].checksums, before["a.py"].checksums);
+        assert_eq!(after["a.py"].checksums, updated.checksums);
+        assert_eq!(after["b.py"].checksums, before["b.py"].checksums);
+        assert_eq!(after["b.py"].file_hash, before["b.py"].file_hash);
+        assert!(after.contains_key("c.py"));
+        assert_eq!(after.len(), 3);
     }
 
     #[test]
-    fn test_filter_executed_blocks_decorator_not_counted() {
-        // Decorator on line 1, def on line 2, body starts line 3.
-        // Only decorator + def lines covered → not executed.
-        let blocks = vec![Block {
-            start_line: 1,
-            end_line: 5,
-            checksum: 222,
-            name: "decorated_func".to_string(),
-            block_type: "function".to_string(),
-            body_start_line: 3,
-        }];
-        let executed_lines: HashSet<usize> = [1, 2].into_iter().collect();
-        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
-        assert!(
-            result.is_empty(),
-            "Decorator + def line coverage should not count as executed"
-        );
+    fn test_update_baseline_file_uses_baseline_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.py"), "def a():\n    return 1\n").unwrap();
+
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let root_str = root.to_str().unwrap();
+        let options = FingerprintOptions {
+            ast_checksum: true,
+            seed: 7,
+            ..FingerprintOptions::default()
+        };
+        save_baseline_internal(
+            db_path,
+            root_str,
+            false,
+            vec![],
+            false,
+            &options,
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        std::fs::write(root.join("a.py"), "def a():\n    return 10\n").unwrap();
+        let updated =
+            update_baseline_file_internal(db_path, "a.py", Some(root_str), &options).unwrap();
+        let expected =
+            calculate_fingerprint_with_options(root.join("a.py").to_str().unwrap(), &options)
+                .unwrap();
+        assert_eq!(updated.checksums, expected.checksums);
+
+        // The refreshed entry matches what detection computes with the same options
+        let changes =
+            detect_changes_internal(db_path, root_str, vec![], &options, &ScanOptions::default())
+                .unwrap();
+        assert!(changes.modified.is_empty(), "{:?}", changes.modified);
+        assert!(changes.changed_blocks.is_empty());
     }
 
     #[test]
-    fn test_filter_executed_blocks_class_def_line_counted() {
-        // Class body_start_line = class def line (skipping decorator).
-        // The class def line IS executed at import time, so covering it counts.
-        let blocks = vec![Block {
-            start_line: 1, // decorator line
-            end_line: 10,
-            checksum: 333,
-            name: "MyClass".to_string(),
-            block_type: "class".to_string(),
-            body_start_line: 2, // class def line
-        }];
-        // Only decorator line covered → not executed
-        let executed_lines: HashSet<usize> = [1].into_iter().collect();
-        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
-        assert!(
-            result.is_empty(),
-            "Decorated class with only decorator covered should NOT be executed"
-        );
+    fn test_process_coverage_data_end_to_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("tests")).unwrap();
+        let calc = root.join("src").join("calc.py");
+        let test_calc = root.join("tests").join("test_calc.py");
+        let test_other = root.join("tests").join("test_other.py");
+        std::fs::write(
+            &calc,
+            "def add(a, b):\n    return a + b\n\ndef sub(a, b):\n    return a - b\n",
+        )
+        .unwrap();
+        std::fs::write(&test_calc, "def test_add():\n    assert True\n").unwrap();
+        std::fs::write(&test_other, "def test_other():\n    assert True\n").unwrap();
 
-        // Class def line covered → executed
-        let executed_lines: HashSet<usize> = [1, 2].into_iter().collect();
-        let result = filter_executed_blocks_rust(&blocks, &executed_lines);
-        assert_eq!(
-            result.len(),
-            1,
-            "Class with def line covered should be considered executed"
-        );
+        let path = |p: &PathBuf| p.to_string_lossy().to_string();
+        let coverage_data: HashMap<String, Vec<usize>> = [
+            (path(&calc), vec![1, 2, 4]), // add() body ran, sub() body did not
+            (path(&test_calc), vec![1, 2]),
+            (path(&test_other), vec![1]), // another test file: filtered out
+            ("/elsewhere/lib.py".to_string(), vec![1]), // outside project: filtered out
+        ]
+        .into_iter()
+        .collect();
+
+        let root_str = root.to_str().unwrap();
+        let cache = crate::fingerprint_cache::FingerprintCache::new(None, None, None);
+        for cache in [None, Some(&cache)] {
+            let mut fingerprints = process_coverage_data_internal(
+                coverage_data.clone(),
+                root_str,
+                &path(&test_calc),
+                false,
+                vec![],
+                cache,
+                &CoverageFilter::default(),
+                &FingerprintOptions::default(),
+            )
+            .unwrap();
+            fingerprints.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+            let filenames: Vec<&str> = fingerprints.iter().map(|f| f.filename.as_str()).collect();
+            assert_eq!(filenames, vec!["src/calc.py", "tests/test_calc.py"]);
+
+            let full = calculate_fingerprint_internal(&path(&calc)).unwrap();
+            // module + add(), but not sub()
+            assert_eq!(fingerprints[0].checksums, full.checksums[..2].to_vec());
+            assert!(fingerprints[0].blocks.is_none());
+        }
     }
 
     #[test]
@@ -916,7 +5003,8 @@ mod tests {
         std::fs::write(venv_dir.join("pyvenv.cfg"), "home = /usr/bin").unwrap();
         std::fs::write(venv_dir.join("lib").join("site.py"), "pass").unwrap();
 
-        let files = find_python_files(root.to_str().unwrap(), &[]).unwrap();
+        let files =
+            find_python_files(root.to_str().unwrap(), &[], &ScanOptions::default()).unwrap();
         let filenames: Vec<String> = files
             .iter()
             .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
@@ -932,6 +5020,370 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_find_python_files_includes_stubs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("api.py"), "def get(): return 1\n").unwrap();
+        std::fs::write(root.join("api.pyi"), "def get() -> int: ...\n").unwrap();
+        std::fs::write(root.join("notes.txt"), "not python").unwrap();
+
+        let files =
+            find_python_files(root.to_str().unwrap(), &[], &ScanOptions::default()).unwrap();
+        let mut filenames: Vec<String> = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        filenames.sort();
+
+        assert_eq!(filenames, vec!["api.py", "api.pyi"]);
+    }
+
+    #[test]
+    fn test_find_python_files_respects_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("generated")).unwrap();
+        std::fs::create_dir_all(root.join("pkg").join("build")).unwrap();
+        std::fs::write(root.join(".gitignore"), "generated/\n").unwrap();
+        std::fs::write(root.join("pkg").join(".gitignore"), "build/\n").unwrap();
+        std::fs::write(root.join("app.py"), "pass").unwrap();
+        std::fs::write(root.join("generated").join("schema.py"), "pass").unwrap();
+        std::fs::write(root.join("pkg").join("core.py"), "pass").unwrap();
+        std::fs::write(root.join("pkg").join("build").join("out.py"), "pass").unwrap();
+
+        let file_names = |scan: &ScanOptions| {
+            let mut names: Vec<String> = find_python_files(root.to_str().unwrap(), &[], scan)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let gitignore = ScanOptions {
+            respect_gitignore: true,
+            ..Default::default()
+        };
+        assert_eq!(file_names(&gitignore), vec!["app.py", "core.py"]);
+        // Opt-in only: default scan still returns ignored files
+        assert_eq!(
+            file_names(&ScanOptions::default()),
+            vec!["app.py", "core.py", "out.py", "schema.py"]
+        );
+    }
+
+    #[test]
+    fn test_find_python_files_max_file_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("small.py"), "x = 1\n").unwrap();
+        std::fs::write(root.join("generated.py"), "x = 1\n".repeat(100)).unwrap();
+
+        let file_names = |scan: &ScanOptions| {
+            let mut names: Vec<String> = find_python_files(root.to_str().unwrap(), &[], scan)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let limited = ScanOptions {
+            max_file_bytes: Some(64),
+            ..Default::default()
+        };
+        assert_eq!(file_names(&limited), vec!["small.py"]);
+        // No limit by default
+        assert_eq!(
+            file_names(&ScanOptions::default()),
+            vec!["generated.py", "small.py"]
+        );
+    }
+
+    #[test]
+    fn test_find_python_files_ignore_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        for rel in [
+            "app.py",
+            "vendor/six.py",
+            "vendor/lib/inner.py",
+            "pkg/vendor.py",
+        ] {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "x = 1\n").unwrap();
+        }
+
+        let file_names = |scan: &ScanOptions| {
+            let mut names: Vec<String> = find_python_files(root.to_str().unwrap(), &[], scan)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let ignoring = ScanOptions {
+            ignore_dirs: vec!["vendor".to_string()],
+            ..Default::default()
+        };
+        // Only the directory is pruned; a file with the same stem is kept
+        assert_eq!(file_names(&ignoring), vec!["app.py", "vendor.py"]);
+        assert_eq!(
+            file_names(&ScanOptions::default()),
+            vec!["app.py", "inner.py", "six.py", "vendor.py"]
+        );
+        let with_gitignore = ScanOptions {
+            respect_gitignore: true,
+            ..ignoring
+        };
+        assert_eq!(file_names(&with_gitignore), vec!["app.py", "vendor.py"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_python_files_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = std::fs::canonicalize(dir.path()).unwrap();
+        let root = base.join("project");
+        let shared = base.join("shared");
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(root.join("app.py"), "x = 1\n").unwrap();
+        std::fs::write(shared.join("common.py"), "x = 1\n").unwrap();
+        // Two links to the same shared package, and a cycle back to the root
+        std::os::unix::fs::symlink(&shared, root.join("shared")).unwrap();
+        std::os::unix::fs::symlink(&shared, root.join("pkg").join("shared_alias")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("pkg").join("loop")).unwrap();
+
+        let rel_paths = |scan: &ScanOptions| {
+            let mut paths: Vec<String> = find_python_files(root.to_str().unwrap(), &[], scan)
+                .unwrap()
+                .iter()
+                .map(|p| p.strip_prefix(&root).unwrap().to_string_lossy().to_string())
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        // Not followed by default
+        assert_eq!(rel_paths(&ScanOptions::default()), vec!["app.py"]);
+
+        let following = ScanOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let expected = vec!["app.py", "pkg/shared_alias/common.py"];
+        assert_eq!(rel_paths(&following), expected);
+
+        let with_gitignore = ScanOptions {
+            respect_gitignore: true,
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        assert_eq!(rel_paths(&with_gitignore), expected);
+    }
+
+    #[test]
+    fn test_find_python_files_include_notebooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("app.py"), "x = 1\n").unwrap();
+        let notebook = root.join("analysis.ipynb");
+        std::fs::write(
+            &notebook,
+            r#"{"cells": [{"cell_type": "code", "source": ["def f():\n", "    return 1"]}]}"#,
+        )
+        .unwrap();
+
+        let file_names = |scan: &ScanOptions| {
+            let mut names: Vec<String> = find_python_files(root.to_str().unwrap(), &[], scan)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(file_names(&ScanOptions::default()), vec!["app.py"]);
+        let with_notebooks = ScanOptions {
+            include_notebooks: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            file_names(&with_notebooks),
+            vec!["analysis.ipynb", "app.py"]
+        );
+
+        let fp = calculate_fingerprint_internal(notebook.to_str().unwrap()).unwrap();
+        let blocks = fp.blocks.unwrap();
+        assert!(blocks
+            .iter()
+            .any(|b| b.name == "f" && b.block_type == "function"));
+        assert_eq!(blocks.iter().filter(|b| b.block_type == "cell").count(), 1);
+    }
+
+    #[test]
+    fn test_find_python_files_glob_and_directory_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        for rel in [
+            "src/app.py",
+            "tests/unit/test_models.py",
+            "tests/integration/test_api_users.py",
+            "tests/integration/test_db.py",
+            "tests/e2e/test_api_smoke.py",
+        ] {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "x = 1\n").unwrap();
+        }
+
+        let scope = [
+            root.join("tests/unit").to_string_lossy().to_string(),
+            "test_api_*.py".to_string(),
+        ];
+        let mut files: Vec<String> =
+            find_python_files(root.to_str().unwrap(), &scope, &ScanOptions::default())
+                .unwrap()
+                .iter()
+                .map(|p| make_relative(&p.to_string_lossy(), root.to_str().unwrap()))
+                .collect();
+        files.sort();
+
+        assert_eq!(
+            files,
+            vec![
+                "src/app.py",
+                "tests/e2e/test_api_smoke.py",
+                "tests/integration/test_api_users.py",
+                "tests/unit/test_models.py",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_python_files_exclude_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("app").join("migrations")).unwrap();
+        std::fs::create_dir_all(root.join("proto")).unwrap();
+        std::fs::write(root.join("app").join("models.py"), "pass").unwrap();
+        std::fs::write(root.join("app").join("migrations").join("0001.py"), "pass").unwrap();
+        std::fs::write(root.join("proto").join("user_pb2.py"), "pass").unwrap();
+        std::fs::write(root.join("proto").join("client.py"), "pass").unwrap();
+
+        let scan = ScanOptions::new(
+            false,
+            &["*_pb2.py".to_string(), "app/migrations/".to_string()],
+        )
+        .unwrap();
+        let mut filenames: Vec<String> = find_python_files(root.to_str().unwrap(), &[], &scan)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        filenames.sort();
+
+        assert_eq!(filenames, vec!["client.py", "models.py"]);
+    }
+
+    #[test]
+    fn test_should_process_file_exclude_globs() {
+        let root = Path::new("/project");
+        let test_file = Path::new("/project/tests/test_app.py");
+        let filter = CoverageFilter {
+            exclude: build_exclude_set(&["*_pb2.py".to_string(), "legacy".to_string()]).unwrap(),
+            ..Default::default()
+        };
+
+        let check = |p: &str| {
+            should_process_file(Path::new(p), root, test_file, &Scope::default(), &filter)
+        };
+
+        assert!(check("/project/src/app.py"));
+        assert!(!check("/project/src/user_pb2.py"));
+        assert!(!check("/project/legacy/old.py"));
+        assert!(check("/project/src/legacy_support.py"));
+    }
+
+    #[test]
+    fn test_should_process_file_glob_scope() {
+        let root = Path::new("/project");
+        let filter = CoverageFilter::default();
+        let scope = Scope::new(&[
+            "/project/tests/unit".to_string(),
+            "test_api_*.py".to_string(),
+        ])
+        .unwrap();
+
+        let check =
+            |p: &str| should_process_file(Path::new(p), root, Path::new(p), &scope, &filter);
+
+        // The running test file must be in the directory scope or match the glob
+        assert!(check("/project/tests/unit/test_models.py"));
+        assert!(check("/project/tests/integration/test_api_users.py"));
+        assert!(!check("/project/tests/integration/test_db.py"));
+        // Source files ignore the scope
+        assert!(check("/project/src/app.py"));
+    }
+
+    #[test]
+    fn test_should_process_file_custom_test_patterns() {
+        let root = Path::new("/project");
+        let test_file = Path::new("/project/checks/check_app.py");
+        let filter = CoverageFilter {
+            test_patterns: Some(build_glob_set(&["check_*.py".to_string()], "test").unwrap()),
+            ..Default::default()
+        };
+
+        let check = |p: &str| {
+            should_process_file(Path::new(p), root, test_file, &Scope::default(), &filter)
+        };
+
+        // Only the running check file is kept among `check_*.py` files
+        assert!(check("/project/checks/check_app.py"));
+        assert!(!check("/project/checks/check_other.py"));
+        // With custom patterns, the default conventions no longer apply
+        assert!(check("/project/tests/helpers.py"));
+        assert!(check("/project/src/test_utils.py"));
+    }
+
+    #[test]
+    fn test_should_process_file_custom_test_directory() {
+        let root = Path::new("/project");
+        let test_file = Path::new("/project/spec/models_spec.py");
+        let filter = CoverageFilter {
+            test_patterns: Some(build_glob_set(&["spec/**".to_string()], "test").unwrap()),
+            ..Default::default()
+        };
+
+        let check = |p: &str| {
+            should_process_file(Path::new(p), root, test_file, &Scope::default(), &filter)
+        };
+
+        assert!(check("/project/spec/models_spec.py"));
+        assert!(!check("/project/spec/views_spec.py"));
+        assert!(!check("/project/spec/support/factories.py"));
+        // `testing/` helpers are ordinary source files
+        assert!(check("/project/testing/fixtures.py"));
+        assert!(check("/project/src/models.py"));
+    }
+
+    #[test]
+    fn test_build_exclude_set_rejects_invalid_pattern() {
+        assert!(build_exclude_set(&["src/[".to_string()]).is_err());
+    }
+
     #[test]
     fn test_make_relative() {
         // Standard case: path under project root
@@ -958,4 +5410,306 @@ mod tests {
             "src/main.py"
         );
     }
+
+    #[test]
+    fn test_make_relative_windows_separators() {
+        assert_eq!(
+            make_relative("C:\\proj\\pkg\\mod.py", "C:\\proj"),
+            "pkg/mod.py"
+        );
+        // Mixed styles between the path and the root still match
+        assert_eq!(
+            make_relative("C:/proj/pkg\\mod.py", "C:\\proj"),
+            "pkg/mod.py"
+        );
+        assert_eq!(
+            make_relative("C:\\proj\\pkg\\mod.py", "C:/proj/"),
+            "pkg/mod.py"
+        );
+        // Fallback is normalized too
+        assert_eq!(
+            make_relative("D:\\other\\x.py", "C:\\proj"),
+            "D:/other/x.py"
+        );
+    }
+
+    #[test]
+    fn test_should_process_file_windows_separators() {
+        let root = Path::new("C:\\project");
+        let test_file = Path::new("C:/project/tests/test_app.py");
+        let filter = CoverageFilter::default();
+
+        let check = |p: &str| {
+            should_process_file(Path::new(p), root, test_file, &Scope::default(), &filter)
+        };
+
+        assert!(check("C:\\project\\src\\app.py"));
+        assert!(check("C:/project/src/app.py"));
+        // The current test file matches in either style; other test files never do
+        assert!(check("C:\\project\\tests\\test_app.py"));
+        assert!(!check("C:\\project\\tests\\test_other.py"));
+        assert!(!check("C:\\project\\src\\tests\\helpers.py"));
+        assert!(!check("D:\\elsewhere\\app.py"));
+    }
+
+    #[test]
+    fn test_detect_changes_matches_backslash_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap().join("project");
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        let file = root.join("pkg").join("mod.py");
+        std::fs::write(&file, "def f():\n    return 1\n").unwrap();
+
+        // A baseline written on Windows before filenames were normalized
+        let db_path = dir.path().join("test.db");
+        let db_path = db_path.to_str().unwrap();
+        let mut fp = calculate_fingerprint_internal(file.to_str().unwrap()).unwrap();
+        fp.filename = "pkg\\mod.py".to_string();
+        PytestDiffDatabase::open(db_path)
+            .unwrap()
+            .save_baseline_fingerprint_internal(fp)
+            .unwrap();
+
+        let changes = detect_changes_internal(
+            db_path,
+            root.to_str().unwrap(),
+            vec![],
+            &FingerprintOptions::default(),
+            &ScanOptions::default(),
+        )
+        .unwrap();
+
+        assert!(changes.added.is_empty());
+        assert!(changes.deleted.is_empty());
+        assert!(changes.modified.is_empty());
+    }
+
+    fn kinds(changes: &[BlockChange]) -> Vec<(&str, &str)> {
+        changes
+            .iter()
+            .map(|c| (c.kind.as_str(), c.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_blocks_edited_function() {
+        let old = parse_module_internal("def add(a, b):\n    return a + b\n").unwrap();
+        let new = parse_module_internal("def add(a, b):\n    return b + a\n").unwrap();
+
+        let changes = diff_blocks(&old, &new);
+
+        assert_eq!(kinds(&changes), vec![("modified", "add")]);
+        let add = &changes[0];
+        assert_eq!(add.block_type, "function");
+        assert_eq!(add.start_line, 1);
+        assert_ne!(add.old_checksum, add.new_checksum);
+    }
+
+    #[test]
+    fn test_diff_blocks_deleted_function() {
+        let old = parse_module_internal(
+            "class Calculator:\n    def add(self):\n        return 1\n\n    def sub(self):\n        return 2\n",
+        )
+        .unwrap();
+        let new =
+            parse_module_internal("class Calculator:\n    def add(self):\n        return 1\n")
+                .unwrap();
+
+        let changes = diff_blocks(&old, &new);
+        let removed: Vec<_> = changes.iter().filter(|c| c.kind == "removed").collect();
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].name, "Calculator.sub");
+        assert_eq!(removed[0].start_line, 5);
+        assert_eq!(removed[0].new_checksum, None);
+        assert!(!changes.iter().any(|c| c.name == "Calculator.add"));
+    }
+
+    #[test]
+    fn test_diff_blocks_renamed_function() {
+        let old = parse_module_internal("def helper():\n    return 1\n").unwrap();
+        let new = parse_module_internal("def assist():\n    return 1\n").unwrap();
+
+        let changes: Vec<_> = diff_blocks(&old, &new)
+            .into_iter()
+            .filter(|c| c.block_type == "function")
+            .collect();
+
+        assert_eq!(
+            kinds(&changes),
+            vec![("added", "assist"), ("removed", "helper")]
+        );
+    }
+
+    #[test]
+    fn test_diff_files_edit_and_addition() {
+        let old = "def area(r):\n    return 3.14 * r * r\n";
+        let new = "def area(r):\n    return 3.14159 * r * r\n\n\ndef perimeter(r):\n    return 2 * 3.14159 * r\n";
+
+        let changes = diff_files_internal(old, new).unwrap();
+
+        // Adding a top-level definition also changes the module block
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                ("modified", "<module>"),
+                ("modified", "area"),
+                ("added", "perimeter")
+            ]
+        );
+        assert_eq!(changes[2].start_line, 5);
+        assert_eq!(changes[2].old_checksum, None);
+    }
+
+    #[test]
+    fn test_diff_files_invalid_source() {
+        assert!(diff_files_internal("def ok():\n    pass\n", "def broken(:\n").is_err());
+    }
+
+    #[test]
+    fn test_reordered_functions_are_unchanged() {
+        let old =
+            "import os\n\n\ndef first():\n    return 1\n\n\ndef second():\n    return os.sep\n";
+        let new =
+            "import os\n\n\ndef second():\n    return os.sep\n\n\ndef first():\n    return 1\n";
+        let old_blocks = parse_module_internal(old).unwrap();
+        let new_blocks = parse_module_internal(new).unwrap();
+
+        let old_checksums: Vec<i64> = old_blocks.iter().map(|b| b.checksum).collect();
+        let new_checksums: Vec<i64> = new_blocks.iter().map(|b| b.checksum).collect();
+        assert!(find_changed_checksums(&old_checksums, &new_checksums).is_empty());
+        assert!(diff_blocks(&old_blocks, &new_blocks).is_empty());
+
+        // Moving a definition across a module-level statement is a module change
+        let across =
+            "def second():\n    return os.sep\n\n\nimport os\n\n\ndef first():\n    return 1\n";
+        assert_eq!(
+            kinds(&diff_files_internal(old, across).unwrap()),
+            vec![("modified", "<module>")]
+        );
+    }
+
+    #[test]
+    fn test_check_file_changed_ignores_block_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ops.py");
+        std::fs::write(
+            &path,
+            "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n",
+        )
+        .unwrap();
+        let mut stored = calculate_fingerprint_internal(path.to_str().unwrap()).unwrap();
+        // Force past the mtime check
+        stored.mtime = 0.0;
+        stored.mtime_exact = None;
+        let baselines = HashMap::from([("ops.py".to_string(), stored)]);
+        let options = FingerprintOptions::default();
+
+        std::fs::write(
+            &path,
+            "def sub(a, b):\n    return a - b\n\n\ndef add(a, b):\n    return a + b\n",
+        )
+        .unwrap();
+        assert_eq!(
+            check_file_changed_with_baseline(&baselines, &path, "ops.py", &options, None, None)
+                .unwrap(),
+            None
+        );
+
+        // A genuine edit still reports the old checksum of the edited block
+        std::fs::write(
+            &path,
+            "def sub(a, b):\n    return b - a\n\n\ndef add(a, b):\n    return a + b\n",
+        )
+        .unwrap();
+        let (_, changed) =
+            check_file_changed_with_baseline(&baselines, &path, "ops.py", &options, None, None)
+                .unwrap()
+                .unwrap();
+        assert_eq!(changed.len(), 1);
+    }
+
+    #[test]
+    fn test_check_file_changed_detects_swapped_redefinitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compat.py");
+        let first = "def f():\n    return 1\n";
+        let second = "def f():\n    return 2\n";
+        let helper = "def g():\n    return 0\n";
+        std::fs::write(&path, format!("{first}\n\n{second}\n\n{helper}")).unwrap();
+        let mut stored = calculate_fingerprint_internal(path.to_str().unwrap()).unwrap();
+        stored.mtime = 0.0;
+        stored.mtime_exact = None;
+        let baselines = HashMap::from([("compat.py".to_string(), stored.clone())]);
+        let options = FingerprintOptions::default();
+        let f_checksums: Vec<i64> = stored
+            .blocks
+            .as_ref()
+            .unwrap()
+            .iter()
+            .filter(|b| b.name == "f")
+            .map(|b| b.checksum)
+            .collect();
+
+        // The second `def f` wins at import time, so swapping them is a change
+        std::fs::write(&path, format!("{second}\n\n{first}\n\n{helper}")).unwrap();
+        let (_, mut changed) =
+            check_file_changed_with_baseline(&baselines, &path, "compat.py", &options, None, None)
+                .unwrap()
+                .unwrap();
+        changed.sort_unstable();
+        let mut expected = f_checksums.clone();
+        expected.sort_unstable();
+        assert_eq!(changed, expected);
+
+        // Moving another block around the redefinitions is still only a reorder
+        std::fs::write(&path, format!("{helper}\n\n{first}\n\n{second}")).unwrap();
+        assert_eq!(
+            check_file_changed_with_baseline(&baselines, &path, "compat.py", &options, None, None)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_same_checksums_ignoring_order() {
+        assert!(same_checksums_ignoring_order(&[1, 2, 2], &[2, 1, 2]));
+        assert!(!same_checksums_ignoring_order(&[1, 2, 2], &[1, 1, 2]));
+        assert!(!same_checksums_ignoring_order(&[1, 2], &[1, 2, 2]));
+    }
+
+    #[test]
+    fn test_diff_line_blocks_reports_moves() {
+        let old = crate::parser::extract_line_blocks("a = 1\nb = 2\nc = 3\n");
+        let new = crate::parser::extract_line_blocks("b = 2\na = 1\nc = 30\nd = 4\n");
+
+        let changes = diff_blocks(&old, &new);
+
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                ("moved", "<line 1>"),
+                ("moved", "<line 2>"),
+                ("modified", "<line 3>"),
+                ("added", "<line 4>")
+            ]
+        );
+        assert_eq!(changes[0].old_start_line, Some(2));
+        assert_eq!(changes[0].old_checksum, changes[0].new_checksum);
+        assert_eq!(changes[1].old_start_line, Some(1));
+        assert_ne!(changes[2].old_checksum, changes[2].new_checksum);
+
+        // Pure reordering changes no line checksum
+        let old_checksums: Vec<i64> = old.iter().map(|b| b.checksum).collect();
+        let reordered = crate::parser::extract_line_blocks("c = 3\nb = 2\na = 1\n");
+        let reordered: Vec<i64> = reordered.iter().map(|b| b.checksum).collect();
+        assert!(find_changed_checksums(&old_checksums, &reordered).is_empty());
+    }
+
+    #[test]
+    fn test_diff_blocks_identical_sources() {
+        let src = "import os\n\ndef f():\n    return os.sep\n";
+        let blocks = parse_module_internal(src).unwrap();
+        assert!(diff_blocks(&blocks, &blocks).is_empty());
+    }
 }
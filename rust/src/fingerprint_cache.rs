@@ -3,16 +3,17 @@
 // This module provides a thread-safe cache that stores parsed fingerprints
 // in memory, avoiding the need to re-parse the same files for every test.
 
-use anyhow::Result;
-use lru::LruCache;
+use anyhow::{bail, Context, Result};
 use parking_lot::RwLock;
 use pyo3::prelude::*;
-use std::num::NonZeroUsize;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 use std::sync::Arc;
 use std::time::UNIX_EPOCH;
 
-use crate::fingerprint::calculate_fingerprint_internal;
+use crate::cache::{approx_fingerprint_bytes, CacheCounters, CacheStats, SizedLru};
+use crate::fingerprint::{fingerprint_from_source, read_source_and_hash, relocate_fingerprint};
+use crate::options::FingerprintOptions;
 use crate::types::Fingerprint;
 
 /// Default maximum cache size (number of fingerprints)
@@ -20,6 +21,48 @@ use crate::types::Fingerprint;
 /// At ~5KB per fingerprint, this caps cache at ~500MB worst case
 const DEFAULT_MAX_SIZE: usize = 100_000;
 
+/// Version of the on-disk format written by `save_to_disk`
+///
+/// Version 1 stored float mtimes, which cannot be compared exactly, and version 2
+/// did not record the fingerprint options; such files load no entries.
+const PERSISTED_FORMAT_VERSION: u32 = 3;
+
+/// File modification time as `(seconds, nanoseconds)` since the Unix epoch
+type Mtime = (u64, u32);
+
+/// On-disk representation of the cache, entries ordered least- to most-recently used
+#[derive(Serialize, Deserialize)]
+struct PersistedCache {
+    format_version: u32,
+    /// `FingerprintOptions::checksum_scheme` of the cache that saved the entries
+    checksum_scheme: String,
+    /// Whether file hashes were computed with normalized line endings
+    normalize_line_endings: bool,
+    entries: Vec<PersistedEntry>,
+}
+
+/// Only the format version, read first so older layouts are not misparsed
+#[derive(Deserialize)]
+struct PersistedHeader {
+    format_version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedEntry {
+    path: String,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    fingerprint: Fingerprint,
+}
+
+/// Modification time of `path`, exact to the nanosecond
+fn file_mtime(path: &Path) -> Result<Mtime> {
+    let since_epoch = std::fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?;
+    Ok((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
 /// In-memory cache for fingerprints
 ///
 /// This cache stores parsed fingerprints to avoid re-parsing the same files
@@ -27,29 +70,56 @@ const DEFAULT_MAX_SIZE: usize = 100_000;
 /// touch the same source files.
 ///
 /// The cache uses LRU eviction: when the limit is reached, the least recently
-/// used entry is automatically evicted on insert.
+/// used entry is automatically evicted on insert. Besides the entry count, an
+/// approximate memory budget can bound it.
+///
+/// Entries are found by path first (valid while the mtime is unchanged), then
+/// by file hash, so identical copies of a file and files reverted to an
+/// earlier content are hashed but not parsed again.
 #[pyclass(unsendable)]
 pub struct FingerprintCache {
     // Cache: filepath -> (mtime, fingerprint)
-    cache: Arc<RwLock<LruCache<String, (f64, Fingerprint)>>>,
-    hits: Arc<RwLock<usize>>,
-    misses: Arc<RwLock<usize>>,
+    cache: Arc<RwLock<SizedLru<String, (Mtime, Fingerprint)>>>,
+    // Cache: file hash -> fingerprint of a file with that content
+    by_hash: Arc<RwLock<SizedLru<String, Fingerprint>>>,
+    counters: Arc<CacheCounters>,
     max_size: usize,
+    max_bytes: Option<usize>,
+    options: FingerprintOptions,
 }
 
 #[pymethods]
 impl FingerprintCache {
     /// Create a new cache with default maximum size
+    ///
+    /// Fingerprints are computed with `options` (default: `FingerprintOptions()`),
+    /// which must match the options the baseline was saved with.
+    ///
+    /// With `max_bytes`, least-recently-used fingerprints are also evicted once
+    /// their approximate memory footprint exceeds that many bytes. The budget
+    /// covers both the path and the content-hash index, half each.
     #[new]
-    #[pyo3(signature = (max_size=None))]
-    pub fn new(max_size: Option<usize>) -> Self {
+    #[pyo3(signature = (max_size=None, options=None, max_bytes=None))]
+    pub fn new(
+        max_size: Option<usize>,
+        options: Option<FingerprintOptions>,
+        max_bytes: Option<usize>,
+    ) -> Self {
         let size = max_size.unwrap_or(DEFAULT_MAX_SIZE);
-        let cap = NonZeroUsize::new(size).unwrap_or(NonZeroUsize::new(1).unwrap());
+        let index_bytes = max_bytes.map(|bytes| bytes / 2);
         Self {
-            cache: Arc::new(RwLock::new(LruCache::new(cap))),
-            hits: Arc::new(RwLock::new(0)),
-            misses: Arc::new(RwLock::new(0)),
+            cache: Arc::new(RwLock::new(SizedLru::new(size, index_bytes, |(_, fp)| {
+                approx_fingerprint_bytes(fp)
+            }))),
+            by_hash: Arc::new(RwLock::new(SizedLru::new(
+                size,
+                index_bytes,
+                approx_fingerprint_bytes,
+            ))),
+            counters: Arc::new(CacheCounters::default()),
             max_size: size,
+            max_bytes,
+            options: options.unwrap_or_default(),
         }
     }
 
@@ -66,21 +136,19 @@ impl FingerprintCache {
     /// Clear the cache
     pub fn clear(&self) {
         self.cache.write().clear();
-        *self.hits.write() = 0;
-        *self.misses.write() = 0;
+        self.by_hash.write().clear();
+        self.counters.reset();
     }
 
-    /// Get cache statistics
+    /// Get cache statistics as `(hits, misses, hit_rate)`
     pub fn stats(&self) -> (usize, usize, f64) {
-        let hits = *self.hits.read();
-        let misses = *self.misses.read();
-        let total = hits + misses;
-        let hit_rate = if total > 0 {
-            hits as f64 / total as f64
-        } else {
-            0.0
-        };
-        (hits, misses, hit_rate)
+        let stats = self.counters.snapshot();
+        (stats.hits as usize, stats.misses as usize, stats.hit_rate())
+    }
+
+    /// Get hit, miss, insertion and eviction counters
+    pub fn cache_stats(&self) -> CacheStats {
+        self.counters.snapshot()
     }
 
     /// Get number of cached entries
@@ -92,6 +160,41 @@ impl FingerprintCache {
     pub fn max_size(&self) -> usize {
         self.max_size
     }
+
+    /// Get the options fingerprints are computed with
+    pub fn options(&self) -> FingerprintOptions {
+        self.options.clone()
+    }
+
+    /// Get the memory budget in bytes, or None when only the entry count is bounded
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    /// Approximate memory held by cached fingerprints, in bytes
+    ///
+    /// Counts both the path and the content-hash index; never above `max_bytes`.
+    pub fn size_bytes(&self) -> usize {
+        self.cache.read().bytes() + self.by_hash.read().bytes()
+    }
+
+    /// Write the cached fingerprints to `path` so a later run can start warm
+    pub fn save_to_disk(&self, path: &str) -> PyResult<()> {
+        self.save_to_disk_internal(path).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!("Failed to save cache: {}", e))
+        })
+    }
+
+    /// Load fingerprints saved by `save_to_disk`, returning how many were kept
+    ///
+    /// Entries whose file is missing or whose mtime changed since the save are
+    /// dropped, so stale fingerprints are never trusted. A file saved by a cache
+    /// with different checksum options or line-ending normalization loads nothing.
+    pub fn load_from_disk(&self, path: &str) -> PyResult<usize> {
+        self.load_from_disk_internal(path).map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!("Failed to load cache: {}", e))
+        })
+    }
 }
 
 impl FingerprintCache {
@@ -99,35 +202,423 @@ impl FingerprintCache {
         let path_obj = Path::new(path);
 
         // Get current mtime
-        let metadata = std::fs::metadata(path_obj)?;
-        let current_mtime = metadata
-            .modified()?
-            .duration_since(UNIX_EPOCH)?
-            .as_secs_f64();
+        let current_mtime = file_mtime(path_obj)?;
 
         // Check cache (needs write lock for LRU promotion)
         {
             let mut cache = self.cache.write();
             if let Some((cached_mtime, cached_fp)) = cache.get(path) {
                 // Check if mtime matches (file hasn't changed)
-                if (current_mtime - cached_mtime).abs() < 0.001 {
+                if *cached_mtime == current_mtime {
                     // Cache hit!
-                    *self.hits.write() += 1;
+                    self.counters.record_hit();
                     return Ok(cached_fp.clone());
                 }
             }
         }
 
-        // Cache miss - calculate fingerprint
-        *self.misses.write() += 1;
-        let fingerprint = calculate_fingerprint_internal(path)?;
+        // Same content seen under another path (or before an edit was reverted)
+        let (bytes, file_hash) = read_source_and_hash(path, &self.options)?;
+        let known = self.by_hash.write().get(&file_hash).cloned();
+        let fingerprint = match known {
+            Some(fingerprint) => {
+                self.counters.record_hit();
+                relocate_fingerprint(fingerprint, path)?
+            }
+            None => {
+                // Cache miss - parse the file
+                self.counters.record_miss();
+                let (fingerprint, _) =
+                    fingerprint_from_source(path, &bytes, file_hash.clone(), &self.options)?;
+                self.by_hash.write().push(file_hash, fingerprint.clone());
+                fingerprint
+            }
+        };
 
         // Update cache — LruCache auto-evicts when full
+        let evicted = self
+            .cache
+            .write()
+            .push(path.to_string(), (current_mtime, fingerprint.clone()));
+        self.counters.record_insertion(evicted);
+
+        Ok(fingerprint)
+    }
+
+    pub(crate) fn save_to_disk_internal(&self, path: &str) -> Result<()> {
+        let entries = self
+            .cache
+            .read()
+            .iter()
+            .rev()
+            .map(|(file, ((secs, nanos), fingerprint))| PersistedEntry {
+                path: file.clone(),
+                mtime_secs: *secs,
+                mtime_nanos: *nanos,
+                fingerprint: fingerprint.clone(),
+            })
+            .collect();
+        let persisted = PersistedCache {
+            format_version: PERSISTED_FORMAT_VERSION,
+            checksum_scheme: self.options.checksum_scheme(),
+            normalize_line_endings: self.options.normalize_line_endings,
+            entries,
+        };
+
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create cache file {}", path))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), &persisted)
+            .context("Failed to write cache file")?;
+        Ok(())
+    }
+
+    pub(crate) fn load_from_disk_internal(&self, path: &str) -> Result<usize> {
+        let contents =
+            std::fs::read(path).with_context(|| format!("Failed to open cache file {}", path))?;
+        let header: PersistedHeader =
+            serde_json::from_slice(&contents).context("Failed to parse cache file")?;
+        if header.format_version > PERSISTED_FORMAT_VERSION {
+            bail!(
+                "cache file format version {} is newer than the supported version {}",
+                header.format_version,
+                PERSISTED_FORMAT_VERSION
+            );
+        }
+        if header.format_version < PERSISTED_FORMAT_VERSION {
+            return Ok(0);
+        }
+        let persisted: PersistedCache =
+            serde_json::from_slice(&contents).context("Failed to parse cache file")?;
+        // Fingerprints computed under other options would not match the baseline
+        if persisted.checksum_scheme != self.options.checksum_scheme()
+            || persisted.normalize_line_endings != self.options.normalize_line_endings
         {
-            let mut cache = self.cache.write();
-            cache.put(path.to_string(), (current_mtime, fingerprint.clone()));
+            return Ok(0);
         }
 
-        Ok(fingerprint)
+        let mut cache = self.cache.write();
+        let mut loaded = Vec::new();
+        for entry in persisted.entries {
+            let mtime = (entry.mtime_secs, entry.mtime_nanos);
+            let fresh = file_mtime(Path::new(&entry.path)).is_ok_and(|current| current == mtime);
+            if fresh {
+                self.by_hash.write().push(
+                    entry.fingerprint.file_hash.clone(),
+                    entry.fingerprint.clone(),
+                );
+                cache.push(entry.path.clone(), (mtime, entry.fingerprint));
+                loaded.push(entry.path);
+            }
+        }
+        // A cache smaller than the file keeps only the most recently used entries
+        Ok(loaded.iter().filter(|p| cache.contains(*p)).count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_save_and_load_round_trip_drops_stale_entries() {
+        let dir = TempDir::new().unwrap();
+        let write = |name: &str, body: &str| {
+            let p = dir.path().join(name);
+            std::fs::write(&p, body).unwrap();
+            p.to_string_lossy().into_owned()
+        };
+        let unchanged = write("unchanged.py", "def a():\n    return 1\n");
+        let touched = write("touched.py", "def b():\n    return 2\n");
+        let removed = write("removed.py", "def c():\n    return 3\n");
+
+        let cache = FingerprintCache::new(None, None, None);
+        for p in [&unchanged, &touched, &removed] {
+            cache.get_or_calculate_internal(p).unwrap();
+        }
+        let cache_file = dir.path().join("fingerprints.json");
+        let cache_file = cache_file.to_str().unwrap();
+        cache.save_to_disk_internal(cache_file).unwrap();
+
+        // Invalidate two of the three files after the save
+        std::fs::File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        std::fs::remove_file(&removed).unwrap();
+
+        let reloaded = FingerprintCache::new(None, None, None);
+        assert_eq!(reloaded.load_from_disk_internal(cache_file).unwrap(), 1);
+        assert_eq!(reloaded.size(), 1);
+
+        // The surviving entry is served from the cache without re-parsing
+        let fp = reloaded.get_or_calculate_internal(&unchanged).unwrap();
+        assert_eq!(
+            fp.checksums,
+            cache
+                .get_or_calculate_internal(&unchanged)
+                .unwrap()
+                .checksums
+        );
+        assert_eq!(reloaded.stats().0, 1);
+        reloaded.get_or_calculate_internal(&touched).unwrap();
+        assert_eq!(reloaded.stats().1, 1);
+    }
+
+    #[test]
+    fn test_identical_content_is_served_by_hash() {
+        let dir = TempDir::new().unwrap();
+        let source = "def helper():\n    return 1\n";
+        let original = dir.path().join("stubs_a.py");
+        let copy = dir.path().join("stubs_b.py");
+        std::fs::write(&original, source).unwrap();
+        std::fs::write(&copy, source).unwrap();
+
+        let cache = FingerprintCache::new(None, None, None);
+        let first = cache
+            .get_or_calculate_internal(original.to_str().unwrap())
+            .unwrap();
+        let second = cache
+            .get_or_calculate_internal(copy.to_str().unwrap())
+            .unwrap();
+
+        // The copy is a hit: hashed but not parsed again
+        assert_eq!(cache.stats(), (1, 1, 0.5));
+        assert_eq!(second.checksums, first.checksums);
+        assert_eq!(second.file_hash, first.file_hash);
+        assert!(second.filename.ends_with("stubs_b.py"));
+        assert_eq!(cache.size(), 2);
+
+        // Reverting an edit finds the earlier content by hash as well
+        std::fs::write(&original, "def helper():\n    return 2\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&original)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        cache
+            .get_or_calculate_internal(original.to_str().unwrap())
+            .unwrap();
+        std::fs::write(&original, source).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&original)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(120))
+            .unwrap();
+        let reverted = cache
+            .get_or_calculate_internal(original.to_str().unwrap())
+            .unwrap();
+        assert_eq!(reverted.checksums, first.checksums);
+        assert_eq!(cache.stats().0, 2);
+        assert_eq!(cache.stats().1, 2);
+    }
+
+    #[test]
+    fn test_sub_millisecond_mtime_change_is_not_a_hit() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("mod.py");
+        let path = file.to_str().unwrap();
+        let set_mtime = |when| {
+            std::fs::File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(when)
+                .unwrap()
+        };
+        let base = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 1_000);
+        std::fs::write(&file, "def f():\n    return 1\n").unwrap();
+        set_mtime(base);
+
+        let cache = FingerprintCache::new(None, None, None);
+        let before = cache.get_or_calculate_internal(path).unwrap();
+
+        // A rewrite landing 500ns later must not be served from the stale entry
+        std::fs::write(&file, "def f():\n    return 2\n").unwrap();
+        set_mtime(base + Duration::from_nanos(500));
+        let after = cache.get_or_calculate_internal(path).unwrap();
+        assert_ne!(after.checksums, before.checksums);
+        assert_eq!(cache.stats().1, 2);
+    }
+
+    #[test]
+    fn test_touch_keeps_entry_and_rewrite_misses() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("mod.py");
+        let path = file.to_str().unwrap();
+        let touch = |when| {
+            std::fs::File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(when)
+                .unwrap()
+        };
+        std::fs::write(&file, "def f():\n    return 1\n").unwrap();
+
+        let cache = FingerprintCache::new(None, None, None);
+        let original = cache.get_or_calculate_internal(path).unwrap();
+
+        // Touching the file without changing content is still a hit
+        touch(SystemTime::now() + Duration::from_secs(60));
+        let touched = cache.get_or_calculate_internal(path).unwrap();
+        assert_eq!(touched.checksums, original.checksums);
+        assert_eq!(cache.stats(), (1, 1, 0.5));
+
+        // Rewriting the content is a miss and replaces the stale entry
+        std::fs::write(&file, "def f():\n    return 2\n").unwrap();
+        touch(SystemTime::now() + Duration::from_secs(120));
+        let rewritten = cache.get_or_calculate_internal(path).unwrap();
+        assert_ne!(rewritten.checksums, original.checksums);
+        assert_eq!(cache.stats().1, 2);
+        assert_eq!(cache.size(), 1);
+    }
+
+    #[test]
+    fn test_load_reports_entries_actually_kept() {
+        let dir = TempDir::new().unwrap();
+        let files: Vec<String> = ["a.py", "b.py", "c.py"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                std::fs::write(&p, format!("X = {:?}\n", name)).unwrap();
+                p.to_string_lossy().into_owned()
+            })
+            .collect();
+
+        let cache = FingerprintCache::new(None, None, None);
+        for p in &files {
+            cache.get_or_calculate_internal(p).unwrap();
+        }
+        let cache_file = dir.path().join("fingerprints.json");
+        let cache_file = cache_file.to_str().unwrap();
+        cache.save_to_disk_internal(cache_file).unwrap();
+
+        // Only two of the three entries fit; the most recently used survive
+        let small = FingerprintCache::new(Some(2), None, None);
+        assert_eq!(small.load_from_disk_internal(cache_file).unwrap(), 2);
+        small.get_or_calculate_internal(&files[2]).unwrap();
+        assert_eq!(small.stats(), (1, 0, 1.0));
+
+        // Reloading into a warm cache reports the entries it stored again
+        let warm = FingerprintCache::new(None, None, None);
+        warm.get_or_calculate_internal(&files[0]).unwrap();
+        assert_eq!(warm.load_from_disk_internal(cache_file).unwrap(), 3);
+        assert_eq!(warm.size(), 3);
+    }
+
+    #[test]
+    fn test_load_ignores_float_mtime_format() {
+        let dir = TempDir::new().unwrap();
+        let cache_file = dir.path().join("fingerprints.json");
+        std::fs::write(
+            &cache_file,
+            r#"{"format_version": 1, "entries": [{"path": "a.py", "mtime": 1.5}]}"#,
+        )
+        .unwrap();
+
+        let cache = FingerprintCache::new(None, None, None);
+        assert_eq!(
+            cache
+                .load_from_disk_internal(cache_file.to_str().unwrap())
+                .unwrap(),
+            0
+        );
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_max_bytes_bounds_memory() {
+        let dir = TempDir::new().unwrap();
+        let files: Vec<String> = (0..20)
+            .map(|i| {
+                let p = dir.path().join(format!("m{i:02}.py"));
+                std::fs::write(&p, format!("def f{i}():\n    return {i}\n")).unwrap();
+                p.to_string_lossy().into_owned()
+            })
+            .collect();
+
+        let unbounded = FingerprintCache::new(None, None, None);
+        let one = unbounded.get_or_calculate_internal(&files[0]).unwrap();
+        let budget = 5 * approx_fingerprint_bytes(&one);
+
+        let cache = FingerprintCache::new(None, None, Some(budget));
+        assert_eq!(cache.max_bytes(), Some(budget));
+        for p in &files {
+            cache.get_or_calculate_internal(p).unwrap();
+            assert!(cache.size_bytes() <= budget);
+        }
+        assert!(cache.size() < files.len());
+        assert!(cache.cache_stats().evictions > 0);
+
+        // The most recently used file is still served from the cache
+        let misses = cache.stats().1;
+        cache.get_or_calculate_internal(&files[19]).unwrap();
+        assert_eq!(cache.stats().1, misses);
+
+        cache.clear();
+        assert_eq!(cache.size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_load_skips_entries_saved_with_other_options() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.py");
+        std::fs::write(&file, "def a():\n    # one\n    return 1\n").unwrap();
+        let file = file.to_str().unwrap();
+
+        let seeded = FingerprintOptions {
+            seed: 7,
+            ..FingerprintOptions::default()
+        };
+        let cache = FingerprintCache::new(None, Some(seeded.clone()), None);
+        let fingerprint = cache.get_or_calculate_internal(file).unwrap();
+        let cache_file = dir.path().join("fingerprints.json");
+        let cache_file = cache_file.to_str().unwrap();
+        cache.save_to_disk_internal(cache_file).unwrap();
+
+        let same = FingerprintCache::new(None, Some(seeded), None);
+        assert_eq!(same.load_from_disk_internal(cache_file).unwrap(), 1);
+        assert_eq!(
+            same.get_or_calculate_internal(file).unwrap().checksums,
+            fingerprint.checksums
+        );
+
+        // Other checksum options, or other line-ending handling, load nothing
+        let unseeded = FingerprintCache::new(None, None, None);
+        assert_eq!(unseeded.load_from_disk_internal(cache_file).unwrap(), 0);
+        assert_ne!(
+            unseeded.get_or_calculate_internal(file).unwrap().checksums,
+            fingerprint.checksums
+        );
+        let raw = FingerprintCache::new(
+            None,
+            Some(FingerprintOptions {
+                seed: 7,
+                normalize_line_endings: false,
+                ..FingerprintOptions::default()
+            }),
+            None,
+        );
+        assert_eq!(raw.load_from_disk_internal(cache_file).unwrap(), 0);
+        assert_eq!(raw.size(), 0);
+    }
+
+    #[test]
+    fn test_load_rejects_newer_format() {
+        let dir = TempDir::new().unwrap();
+        let cache_file = dir.path().join("fingerprints.json");
+        std::fs::write(&cache_file, r#"{"format_version": 99, "entries": []}"#).unwrap();
+
+        let cache = FingerprintCache::new(None, None, None);
+        let err = cache
+            .load_from_disk_internal(cache_file.to_str().unwrap())
+            .unwrap_err();
+        assert!(err.to_string().contains("newer"));
     }
 }
@@ -9,19 +9,33 @@
 use pyo3::prelude::*;
 
 mod cache;
+mod cancel;
 mod database;
 mod fingerprint;
 mod fingerprint_cache;
+mod notebook;
+mod options;
 mod parser;
 mod types;
 
-pub use database::{ImportResult, PytestDiffDatabase};
+pub use cache::CacheStats;
+pub use cancel::CancelToken;
+pub use database::{ImportResult, PytestDiffDatabase, SchemaVersionError, SCHEMA_VERSION};
 pub use fingerprint::{
-    calculate_fingerprint, detect_changes, process_coverage_data, save_baseline,
+    baseline_age, block_counts, blocks_for_line, calculate_fingerprint, detect_changes, diff_files,
+    inspect_file, process_coverage_data, save_baseline, select_tests, summarize_changes,
+    update_baseline_file, verify_baseline,
 };
 pub use fingerprint_cache::FingerprintCache;
-pub use parser::parse_module;
-pub use types::{Block, ChangedFiles, Fingerprint, TestExecution};
+pub use options::{FingerprintOptions, ScanOptions};
+pub use parser::{
+    compute_checksum, parse_module, parse_module_lenient, parse_module_lines,
+    parse_module_normalized, parse_module_tree, ChecksumAlgorithm,
+};
+pub use types::{
+    BaselineStats, BaselineVerification, Block, BlockChange, BlockNode, ChangeSummary,
+    ChangedFiles, FileInspection, Fingerprint, TestExecution,
+};
 
 /// Python module initialization
 #[pymodule]
@@ -30,21 +44,46 @@ fn _core(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Block>()?;
     m.add_class::<Fingerprint>()?;
     m.add_class::<ChangedFiles>()?;
+    m.add_class::<BlockChange>()?;
+    m.add_class::<BlockNode>()?;
+    m.add_class::<ChangeSummary>()?;
+    m.add_class::<FileInspection>()?;
+    m.add_class::<BaselineStats>()?;
+    m.add_class::<BaselineVerification>()?;
     m.add_class::<TestExecution>()?;
     m.add_class::<PytestDiffDatabase>()?;
     m.add_class::<ImportResult>()?;
     m.add_class::<FingerprintCache>()?;
+    m.add_class::<CacheStats>()?;
+    m.add_class::<CancelToken>()?;
+    m.add_class::<FingerprintOptions>()?;
+    m.add_class::<ScanOptions>()?;
 
     // Register functions
     m.add_function(wrap_pyfunction!(parse_module, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_module_normalized, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_module_tree, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_module_lines, m)?)?;
+    m.add_function(wrap_pyfunction!(compute_checksum, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_module_lenient, m)?)?;
     m.add_function(wrap_pyfunction!(calculate_fingerprint, m)?)?;
     m.add_function(wrap_pyfunction!(detect_changes, m)?)?;
+    m.add_function(wrap_pyfunction!(diff_files, m)?)?;
+    m.add_function(wrap_pyfunction!(summarize_changes, m)?)?;
+    m.add_function(wrap_pyfunction!(select_tests, m)?)?;
     m.add_function(wrap_pyfunction!(save_baseline, m)?)?;
+    m.add_function(wrap_pyfunction!(baseline_age, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_baseline, m)?)?;
+    m.add_function(wrap_pyfunction!(block_counts, m)?)?;
+    m.add_function(wrap_pyfunction!(update_baseline_file, m)?)?;
     m.add_function(wrap_pyfunction!(process_coverage_data, m)?)?;
+    m.add_function(wrap_pyfunction!(blocks_for_line, m)?)?;
+    m.add_function(wrap_pyfunction!(inspect_file, m)?)?;
 
     // Module metadata
     m.add("__version__", env!("CARGO_PKG_VERSION"))?;
     m.add("__author__", "Paul Milesi")?;
+    m.add("SCHEMA_VERSION", SCHEMA_VERSION)?;
 
     Ok(())
 }
@@ -0,0 +1,143 @@
+// Jupyter notebook support
+//
+// This module turns the code cells of a `.ipynb` file into Python source
+// that can be parsed like a module, and describes each cell as a block.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::parser::{calculate_checksum_with, parse_module_with_options, ParseOptions};
+use crate::types::Block;
+
+/// Block type of a notebook code cell
+const CELL_BLOCK_TYPE: &str = "cell";
+
+/// Whether `path` is a Jupyter notebook
+pub(crate) fn is_notebook(path: &std::path::Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some("ipynb")
+}
+
+/// Source of every code cell in a notebook, in order
+///
+/// IPython magics are not Python: line magics (`%time`, `!pip ...`) are
+/// commented out, and cells starting with a cell magic (`%%bash`) are
+/// commented out entirely, so the rest of the notebook still parses.
+pub(crate) fn extract_code_cells(notebook_json: &str) -> Result<Vec<String>> {
+    let notebook: Value = serde_json::from_str(notebook_json).context("Invalid notebook JSON")?;
+    let cells = notebook
+        .get("cells")
+        .and_then(Value::as_array)
+        .context("Notebook has no cells list")?;
+
+    let mut sources = Vec::new();
+    for cell in cells {
+        if cell.get("cell_type").and_then(Value::as_str) != Some("code") {
+            continue;
+        }
+        // nbformat stores source either as one string or as a list of lines
+        let source = match cell.get("source") {
+            Some(Value::String(source)) => source.clone(),
+            Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+            _ => String::new(),
+        };
+        sources.push(comment_out_magics(&source));
+    }
+    Ok(sources)
+}
+
+fn comment_out_magics(source: &str) -> String {
+    let cell_magic = source.trim_start().starts_with("%%");
+    source
+        .lines()
+        .map(|line| {
+            let code = line.trim_start();
+            if cell_magic || code.starts_with('%') || code.starts_with('!') {
+                format!("# {}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parse a notebook's code cells as one module
+///
+/// Returns the blocks of the concatenated cells followed by one `"cell"`
+/// block per code cell, named `<cell N>` (1-based, counting code cells only).
+/// Line numbers refer to the concatenated source, where cells are separated
+/// by a blank line. `options` apply to the blocks of the concatenated cells;
+/// cell blocks are hashed with its checksum algorithm.
+pub(crate) fn parse_notebook(notebook_json: &str, options: &ParseOptions) -> Result<Vec<Block>> {
+    let cells = extract_code_cells(notebook_json)?;
+
+    let mut source = String::new();
+    let mut cell_blocks = Vec::new();
+    let mut next_line = 1;
+    for (index, cell) in cells.iter().enumerate() {
+        let line_count = cell.lines().count().max(1);
+        let name = format!("<cell {}>", index + 1);
+        cell_blocks.push(Block {
+            start_line: next_line,
+            end_line: next_line + line_count - 1,
+            checksum: calculate_checksum_with(cell, options.algorithm),
+            qualified_name: name.clone(),
+            name,
+            block_type: CELL_BLOCK_TYPE.to_string(),
+            body_start_line: next_line,
+        });
+        source.push_str(cell);
+        source.push_str("\n\n");
+        next_line += line_count + 1;
+    }
+
+    let mut blocks = parse_module_with_options(&source, options)?;
+    blocks.extend(cell_blocks);
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTEBOOK: &str = r##"{
+        "cells": [
+            {"cell_type": "markdown", "source": ["# Analysis\n", "def not_code(): pass"]},
+            {"cell_type": "code", "source": ["import math\n", "%matplotlib inline\n", "def area(r):\n", "    return math.pi * r * r"]},
+            {"cell_type": "code", "source": "print(area(2))"}
+        ],
+        "metadata": {},
+        "nbformat": 4,
+        "nbformat_minor": 5
+    }"##;
+
+    #[test]
+    fn test_only_code_cells_are_fingerprinted() {
+        let blocks = parse_notebook(NOTEBOOK, &ParseOptions::default()).unwrap();
+
+        let cells: Vec<&Block> = blocks.iter().filter(|b| b.block_type == "cell").collect();
+        assert_eq!(cells.len(), 2);
+        assert_eq!((cells[0].start_line, cells[0].end_line), (1, 4));
+        assert_eq!((cells[1].start_line, cells[1].end_line), (6, 6));
+
+        let functions: Vec<&str> = blocks
+            .iter()
+            .filter(|b| b.block_type == "function")
+            .map(|b| b.name.as_str())
+            .collect();
+        assert_eq!(functions, vec!["area"]);
+    }
+
+    #[test]
+    fn test_extract_code_cells_comments_out_magics() {
+        let json = r#"{"cells": [
+            {"cell_type": "code", "source": "!pip install numpy\nx = 1"},
+            {"cell_type": "code", "source": "%%bash\necho hi"}
+        ]}"#;
+        assert_eq!(
+            extract_code_cells(json).unwrap(),
+            vec!["# !pip install numpy\nx = 1", "# %%bash\n# echo hi"]
+        );
+        assert!(extract_code_cells("{}").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+// Options shared by the fingerprinting and scanning entry points
+//
+// FingerprintOptions and ScanOptions are built once in Python and passed to
+// save_baseline, detect_changes and the other functions that fingerprint or
+// scan files, so every call uses the same settings.
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use pyo3::prelude::*;
+
+use crate::parser::ChecksumAlgorithm;
+
+/// Options controlling how file contents are fingerprinted
+///
+/// The options that change block checksums (see `checksum_scheme`) must be
+/// the same when saving a baseline and when detecting changes against it.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct FingerprintOptions {
+    /// Convert CRLF line endings to LF before hashing, so Windows and Unix
+    /// checkouts of the same file get the same file hash
+    #[pyo3(get)]
+    pub normalize_line_endings: bool,
+    /// Compute block checksums without comments and docstrings, so editing
+    /// them doesn't count as a change
+    #[pyo3(get)]
+    pub ignore_comments: bool,
+    /// Compute block checksums from the syntax tree, so reformatting doesn't
+    /// count as a change
+    #[pyo3(get)]
+    pub ast_checksum: bool,
+    /// Block types (e.g. `"module"`, `"import"`) left out of fingerprints, so
+    /// edits confined to them don't count as a change
+    #[pyo3(get)]
+    pub ignore_block_types: Vec<String>,
+    /// How precisely change detection compares files
+    pub granularity: Granularity,
+    /// When change detection finds a file touched without being edited, store
+    /// its new mtime in the baseline so the next run skips hashing it
+    #[pyo3(get)]
+    pub refresh_mtimes: bool,
+    /// Mixed into every block checksum (see `seed_checksum`), so databases
+    /// using different seeds never share checksums; 0 leaves them unchanged
+    #[pyo3(get)]
+    pub seed: u32,
+    /// Hash behind block checksums; xxHash64 checksums take 8 bytes each in
+    /// the database instead of 4
+    pub checksum_algorithm: ChecksumAlgorithm,
+}
+
+#[pymethods]
+impl FingerprintOptions {
+    /// # Arguments
+    /// * `normalize_line_endings` - Hash CRLF files as if they used LF (default: true)
+    /// * `ignore_comments` - Leave comments and docstrings out of block checksums
+    ///   (default: false)
+    /// * `ast_checksum` - Hash block syntax trees instead of text, so reformatting
+    ///   (e.g. with `black`) isn't a change (default: false)
+    /// * `ignore_block_types` - Block types to leave out of fingerprints, e.g.
+    ///   `["module", "import"]` (default: None)
+    /// * `granularity` - `"block"` compares block checksums; `"file"` makes change
+    ///   detection stop at the file hash (default: "block")
+    /// * `refresh_mtimes` - Let change detection store the mtime of files touched
+    ///   without being edited (default: false)
+    /// * `seed` - Mixed into block checksums to namespace them (default: 0, no seeding)
+    /// * `checksum_algorithm` - `"crc32"` (testmon-compatible) or `"xxhash64"`, which
+    ///   makes collisions between unrelated blocks unlikely on large codebases
+    ///   (default: "crc32")
+    #[new]
+    #[pyo3(signature = (
+        normalize_line_endings=true,
+        ignore_comments=false,
+        ast_checksum=false,
+        ignore_block_types=None,
+        granularity="block",
+        refresh_mtimes=false,
+        seed=0,
+        checksum_algorithm="crc32",
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        normalize_line_endings: bool,
+        ignore_comments: bool,
+        ast_checksum: bool,
+        ignore_block_types: Option<Vec<String>>,
+        granularity: &str,
+        refresh_mtimes: bool,
+        seed: u32,
+        checksum_algorithm: &str,
+    ) -> PyResult<Self> {
+        let granularity = Granularity::from_name(granularity)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let checksum_algorithm: ChecksumAlgorithm = checksum_algorithm
+            .parse()
+            .map_err(|e: anyhow::Error| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(Self {
+            normalize_line_endings,
+            ignore_comments,
+            ast_checksum,
+            ignore_block_types: ignore_block_types.unwrap_or_default(),
+            granularity,
+            refresh_mtimes,
+            seed,
+            checksum_algorithm,
+        })
+    }
+
+    #[getter(granularity)]
+    fn granularity_name(&self) -> &'static str {
+        self.granularity.name()
+    }
+
+    #[getter(checksum_algorithm)]
+    fn checksum_algorithm_name(&self) -> &'static str {
+        self.checksum_algorithm.name()
+    }
+
+    /// The checksum algorithm plus every option that changes block checksums,
+    /// e.g. `crc32`, `xxhash64` or `crc32+ast+seed=7`
+    ///
+    /// Two option sets with the same scheme compute the same checksums.
+    pub fn checksum_scheme(&self) -> String {
+        let mut scheme = self.checksum_algorithm.name().to_string();
+        if self.ignore_comments {
+            scheme.push_str("+ignore-comments");
+        }
+        if self.ast_checksum {
+            scheme.push_str("+ast");
+        }
+        if !self.ignore_block_types.is_empty() {
+            let mut types = self.ignore_block_types.clone();
+            types.sort();
+            types.dedup();
+            scheme.push_str(&format!("+ignore={}", types.join(",")));
+        }
+        if self.seed != 0 {
+            scheme.push_str(&format!("+seed={}", self.seed));
+        }
+        scheme
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "FingerprintOptions(scheme='{}', normalize_line_endings={}, granularity='{}', refresh_mtimes={})",
+            self.checksum_scheme(),
+            self.normalize_line_endings,
+            self.granularity.name(),
+            self.refresh_mtimes
+        )
+    }
+}
+
+impl Default for FingerprintOptions {
+    fn default() -> Self {
+        Self {
+            normalize_line_endings: true,
+            ignore_comments: false,
+            ast_checksum: false,
+            ignore_block_types: Vec::new(),
+            granularity: Granularity::default(),
+            refresh_mtimes: false,
+            seed: 0,
+            checksum_algorithm: ChecksumAlgorithm::default(),
+        }
+    }
+}
+
+/// Level at which change detection stops comparing a file with its baseline
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Granularity {
+    /// Stop at the file hash: a file is modified when its content differs,
+    /// and it is never parsed
+    File,
+    /// Compare block checksums and report which blocks changed
+    #[default]
+    Block,
+}
+
+impl Granularity {
+    pub(crate) fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "file" => Ok(Self::File),
+            "block" => Ok(Self::Block),
+            _ => anyhow::bail!(
+                "Unknown granularity '{}' (expected 'file' or 'block')",
+                name
+            ),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// Options controlling which files `find_python_files` returns
+#[pyclass]
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    /// Skip files matched by `.gitignore` files under the project root
+    #[pyo3(get)]
+    pub respect_gitignore: bool,
+    /// The patterns `exclude` was built from
+    #[pyo3(get)]
+    pub exclude_globs: Vec<String>,
+    /// Skip files whose path relative to the project root matches one of these globs
+    pub exclude: GlobSet,
+    /// Skip files larger than this many bytes (no limit when `None`)
+    #[pyo3(get)]
+    pub max_file_bytes: Option<u64>,
+    /// Extra directory names to prune, on top of hidden directories,
+    /// `__pycache__`, `node_modules` and virtual environments
+    #[pyo3(get)]
+    pub ignore_dirs: Vec<String>,
+    /// Descend into symlinked directories; symlink cycles are skipped and a
+    /// file reachable through several links is returned once
+    #[pyo3(get)]
+    pub follow_symlinks: bool,
+    /// Also return Jupyter notebooks (`.ipynb`), fingerprinted by their code cells
+    #[pyo3(get)]
+    pub include_notebooks: bool,
+}
+
+impl ScanOptions {
+    pub(crate) fn new(respect_gitignore: bool, exclude_globs: &[String]) -> Result<Self> {
+        Ok(Self {
+            respect_gitignore,
+            exclude_globs: exclude_globs.to_vec(),
+            exclude: build_exclude_set(exclude_globs)?,
+            ..Self::default()
+        })
+    }
+}
+
+#[pymethods]
+impl ScanOptions {
+    /// # Arguments
+    /// * `respect_gitignore` - Skip files ignored by `.gitignore` (default: false)
+    /// * `exclude_globs` - Glob patterns (relative to the project root) of files to skip;
+    ///   a trailing `/` is accepted for directories (default: None)
+    /// * `max_file_bytes` - Skip files larger than this many bytes (default: no limit)
+    /// * `ignore_dirs` - Directory names to skip, e.g. `build` or `vendor` (default: None)
+    /// * `follow_symlinks` - Descend into symlinked directories (default: false)
+    /// * `include_notebooks` - Also return Jupyter notebooks (default: false)
+    ///
+    /// Raises `ValueError` for an invalid glob pattern.
+    #[new]
+    #[pyo3(signature = (
+        respect_gitignore=false,
+        exclude_globs=None,
+        max_file_bytes=None,
+        ignore_dirs=None,
+        follow_symlinks=false,
+        include_notebooks=false,
+    ))]
+    fn py_new(
+        respect_gitignore: bool,
+        exclude_globs: Option<Vec<String>>,
+        max_file_bytes: Option<u64>,
+        ignore_dirs: Option<Vec<String>>,
+        follow_symlinks: bool,
+        include_notebooks: bool,
+    ) -> PyResult<Self> {
+        let scan = Self::new(respect_gitignore, &exclude_globs.unwrap_or_default())
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{:#}", e)))?;
+        Ok(Self {
+            max_file_bytes,
+            ignore_dirs: ignore_dirs
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| d.trim_end_matches('/').to_string())
+                .collect(),
+            follow_symlinks,
+            include_notebooks,
+            ..scan
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ScanOptions(respect_gitignore={}, exclude_globs={:?}, max_file_bytes={:?}, ignore_dirs={:?}, follow_symlinks={}, include_notebooks={})",
+            self.respect_gitignore,
+            self.exclude_globs,
+            self.max_file_bytes,
+            self.ignore_dirs,
+            self.follow_symlinks,
+            self.include_notebooks
+        )
+    }
+}
+
+/// Compile exclude patterns once; a trailing `/` is accepted for directories
+pub(crate) fn build_exclude_set(patterns: &[String]) -> Result<GlobSet> {
+    build_glob_set(patterns, "exclude")
+}
+
+pub(crate) fn build_glob_set(patterns: &[String], kind: &str) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern.trim_end_matches('/'))
+            .with_context(|| format!("Invalid {} pattern: {}", kind, pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_scheme() {
+        assert_eq!(FingerprintOptions::default().checksum_scheme(), "crc32");
+        let options = FingerprintOptions {
+            ignore_comments: true,
+            ast_checksum: true,
+            ignore_block_types: vec!["module".to_string(), "import".to_string()],
+            seed: 3,
+            ..FingerprintOptions::default()
+        };
+        assert_eq!(
+            options.checksum_scheme(),
+            "crc32+ignore-comments+ast+ignore=import,module+seed=3"
+        );
+        // Options that don't affect checksums don't affect the scheme
+        let file_level = FingerprintOptions {
+            granularity: Granularity::File,
+            refresh_mtimes: true,
+            ..FingerprintOptions::default()
+        };
+        assert_eq!(file_level.checksum_scheme(), "crc32");
+
+        let xxhash = FingerprintOptions {
+            checksum_algorithm: ChecksumAlgorithm::XxHash64,
+            seed: 3,
+            ..FingerprintOptions::default()
+        };
+        assert_eq!(xxhash.checksum_scheme(), "xxhash64+seed=3");
+    }
+
+    #[test]
+    fn test_constructors_match_defaults() {
+        let options =
+            FingerprintOptions::py_new(true, false, false, None, "block", false, 0, "crc32")
+                .unwrap();
+        assert_eq!(
+            options.checksum_scheme(),
+            FingerprintOptions::default().checksum_scheme()
+        );
+        assert_eq!(options.granularity, Granularity::Block);
+        assert!(
+            FingerprintOptions::py_new(true, false, false, None, "line", false, 0, "crc32")
+                .is_err()
+        );
+        let xxhash =
+            FingerprintOptions::py_new(true, false, false, None, "block", false, 0, "xxhash64")
+                .unwrap();
+        assert_eq!(xxhash.checksum_algorithm, ChecksumAlgorithm::XxHash64);
+        assert!(
+            FingerprintOptions::py_new(true, false, false, None, "block", false, 0, "md5").is_err()
+        );
+
+        let scan = ScanOptions::py_new(
+            false,
+            Some(vec!["migrations/".to_string()]),
+            Some(10),
+            Some(vec!["build/".to_string()]),
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(scan.exclude_globs, vec!["migrations/"]);
+        assert!(scan.exclude.is_match("migrations"));
+        assert_eq!(scan.ignore_dirs, vec!["build"]);
+        assert_eq!(scan.max_file_bytes, Some(10));
+        assert!(ScanOptions::py_new(
+            false,
+            Some(vec!["src/[".to_string()]),
+            None,
+            None,
+            false,
+            false
+        )
+        .is_err());
+    }
+}
@@ -8,8 +8,9 @@ use crc32fast::Hasher;
 use pyo3::prelude::*;
 use rustpython_parser::{ast, Parse};
 use rustpython_parser_core::source_code::RandomLocator;
+use rustpython_parser_core::text_size::{TextRange, TextSize};
 
-use crate::types::Block;
+use crate::types::{Block, BlockNode};
 
 /// Parse a Python module and extract all code blocks
 ///
@@ -33,6 +34,177 @@ pub fn parse_module(source: &str) -> PyResult<Vec<Block>> {
     Ok(blocks)
 }
 
+/// Parse a Python module into a tree of blocks
+///
+/// Same blocks as `parse_module`, but methods and nested functions are
+/// children of their enclosing class or function instead of siblings. The
+/// roots are the `<module>` block, module-level imports and top-level
+/// definitions, in source order.
+#[pyfunction]
+pub fn parse_module_tree(source: &str) -> PyResult<Vec<BlockNode>> {
+    let blocks = parse_module_internal(source).map_err(|e| {
+        pyo3::exceptions::PySyntaxError::new_err(format!("Failed to parse Python code: {}", e))
+    })?;
+
+    Ok(build_block_tree(blocks))
+}
+
+/// Nest blocks by line range: each definition becomes a child of the
+/// innermost class or function that encloses it
+pub(crate) fn build_block_tree(mut blocks: Vec<Block>) -> Vec<BlockNode> {
+    // Parents sort before their children, as they start no later and end no earlier
+    blocks.sort_by_key(|b| (b.start_line, std::cmp::Reverse(b.end_line)));
+
+    fn attach(open: &mut [BlockNode], roots: &mut Vec<BlockNode>, node: BlockNode) {
+        match open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+
+    let mut roots = Vec::new();
+    // Chain of definitions enclosing the current block, outermost first
+    let mut open: Vec<BlockNode> = Vec::new();
+    for block in blocks {
+        while open
+            .last()
+            .is_some_and(|parent| parent.block.end_line < block.end_line)
+        {
+            let done = open.pop().expect("checked non-empty");
+            attach(&mut open, &mut roots, done);
+        }
+        let can_nest = !matches!(
+            block.block_type.as_str(),
+            "module" | "import" | "line" | "cell"
+        );
+        let node = BlockNode {
+            block,
+            children: Vec::new(),
+        };
+        if can_nest {
+            open.push(node);
+        } else {
+            attach(&mut open, &mut roots, node);
+        }
+    }
+    while let Some(done) = open.pop() {
+        attach(&mut open, &mut roots, done);
+    }
+    roots
+}
+
+/// Parse a Python module with comments and docstrings excluded from checksums
+///
+/// With `strip_comments=True`, editing a comment or docstring leaves every
+/// block checksum unchanged, so such edits don't re-run dependent tests.
+/// With `strip_comments=False` this is equivalent to `parse_module`.
+#[pyfunction]
+pub fn parse_module_normalized(source: &str, strip_comments: bool) -> PyResult<Vec<Block>> {
+    let options = ParseOptions {
+        strip_comments,
+        ..ParseOptions::default()
+    };
+    let blocks = parse_module_with_options(source, &options).map_err(|e| {
+        pyo3::exceptions::PySyntaxError::new_err(format!("Failed to parse Python code: {}", e))
+    })?;
+
+    Ok(blocks)
+}
+
+/// Parse a Python module, recovering what it can from files with syntax errors
+///
+/// Valid sources give the same result as `parse_module`. On a syntax error,
+/// instead of raising, this returns the `<module>` block (checksum over the
+/// raw text, so any edit to the file is noticed) plus the blocks of the
+/// complete top-level statements preceding the error.
+///
+/// Lenient mode trades precision for resilience: blocks at or after the error
+/// are missing, so their dependents are only covered by the module checksum.
+#[pyfunction]
+pub fn parse_module_lenient(source: &str) -> Vec<Block> {
+    parse_module_lenient_internal(source)
+}
+
+pub(crate) fn parse_module_lenient_internal(source: &str) -> Vec<Block> {
+    let error_offset = match ast::Suite::parse(source, "<string>") {
+        Ok(_) => match parse_module_internal(source) {
+            Ok(blocks) => return blocks,
+            Err(_) => TextSize::default(),
+        },
+        Err(e) => e.offset,
+    };
+
+    let line_count = source.lines().count();
+    let module_block = Block {
+        start_line: 1,
+        end_line: line_count.max(1),
+        checksum: calculate_checksum(source),
+        name: "<module>".to_string(),
+        qualified_name: "<module>".to_string(),
+        block_type: "module".to_string(),
+        body_start_line: 1,
+    };
+
+    let error_line = RandomLocator::new(source).locate(error_offset).row.get() as usize;
+    let lines: Vec<&str> = source.lines().collect();
+
+    // Cut before the top-level statement containing the error: try each line
+    // starting at column 0 (walking backwards) until the prefix parses.
+    let recovered = (0..error_line.min(lines.len()))
+        .rev()
+        .filter(|&i| lines[i].starts_with(|c: char| !c.is_whitespace() && c != '#'))
+        .find_map(|cut| parse_module_internal(&lines[..cut].join("\n")).ok());
+
+    match recovered {
+        Some(mut blocks) => {
+            blocks[0] = module_block;
+            blocks
+        }
+        None => vec![module_block],
+    }
+}
+
+/// Split a Python module into one block per meaningful source line
+///
+/// Finer-grained companion to `parse_module`: every non-blank line that is not
+/// a comment becomes a `"line"` block with `start_line == end_line` and a
+/// checksum of its whitespace-trimmed content. This is a purely textual pass,
+/// so continuation lines of multi-line statements get their own blocks.
+///
+/// # Example
+/// ```python
+/// blocks = parse_module_lines("x = 1\n\n# note\ny = 2\n")
+/// assert [b.start_line for b in blocks] == [1, 4]
+/// ```
+#[pyfunction]
+pub fn parse_module_lines(source: &str) -> Vec<Block> {
+    extract_line_blocks(source)
+}
+
+pub(crate) fn extract_line_blocks(source: &str) -> Vec<Block> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let content = line.trim();
+            if content.is_empty() || content.starts_with('#') {
+                return None;
+            }
+            let line_number = index + 1;
+            let name = format!("<line {}>", line_number);
+            Some(Block {
+                start_line: line_number,
+                end_line: line_number,
+                checksum: calculate_checksum(content),
+                qualified_name: name.clone(),
+                name,
+                block_type: "line".to_string(),
+                body_start_line: line_number,
+            })
+        })
+        .collect()
+}
+
 /// Extract module-level skeleton (excludes function/class bodies)
 ///
 /// This creates a simplified version of the source that includes:
@@ -42,7 +214,10 @@ pub fn parse_module(source: &str) -> PyResult<Vec<Block>> {
 /// - Function/class signatures (but not their bodies)
 ///
 /// This ensures the module checksum only changes when module-level code changes,
-/// not when individual function implementations change.
+/// not when individual function implementations change. Signatures of adjacent
+/// definitions are sorted, so reordering functions without touching the
+/// statements between them leaves the module checksum unchanged. The
+/// `if __name__ == "__main__":` guard is left out; its "main" block covers it.
 fn extract_module_skeleton(
     source: &str,
     parsed: &[ast::Stmt],
@@ -52,6 +227,8 @@ fn extract_module_skeleton(
 
     let source_lines: Vec<&str> = source.lines().collect();
     let mut skeleton_parts = Vec::new();
+    // Signatures of the current run of adjacent definitions
+    let mut definitions: Vec<String> = Vec::new();
 
     for stmt in parsed {
         match stmt {
@@ -62,10 +239,13 @@ fn extract_module_skeleton(
 
                 if start <= source_lines.len() {
                     let def_lines = extract_signature_lines(&source_lines, start, end);
-                    skeleton_parts.push(def_lines.join("\n"));
+                    definitions.push(def_lines.join("\n"));
                 }
             }
 
+            // Script entry point: tracked by its own "main" block
+            _ if is_main_guard(stmt) => {}
+
             // All other statements: include completely
             // This includes: imports, assignments, expressions, etc.
             _ => {
@@ -73,16 +253,102 @@ fn extract_module_skeleton(
                 let end = get_line_number(locator, stmt.end());
 
                 if start <= source_lines.len() {
+                    definitions.sort();
+                    skeleton_parts.append(&mut definitions);
                     let stmt_source = extract_source_lines(source, start, end)?;
                     skeleton_parts.push(stmt_source);
                 }
             }
         }
     }
+    definitions.sort();
+    skeleton_parts.append(&mut definitions);
 
     Ok(skeleton_parts.join("\n"))
 }
 
+/// `extract_module_skeleton` over the syntax tree, for `ast_checksum`
+///
+/// Definitions contribute their signature (decorators, arguments, bases)
+/// with the body left out; runs of adjacent definitions are sorted as in the
+/// text skeleton.
+fn ast_module_skeleton(parsed: &[ast::Stmt], options: &ParseOptions) -> String {
+    let mut skeleton_parts = Vec::new();
+    let mut definitions: Vec<String> = Vec::new();
+
+    for stmt in parsed {
+        let mut signature = stmt.clone();
+        match &mut signature {
+            ast::Stmt::FunctionDef(def) => def.body.clear(),
+            ast::Stmt::AsyncFunctionDef(def) => def.body.clear(),
+            ast::Stmt::ClassDef(def) => def.body.clear(),
+            _ if is_main_guard(stmt) => continue,
+            _ => {
+                definitions.sort();
+                skeleton_parts.append(&mut definitions);
+                skeleton_parts.push(canonical_ast(stmt, options));
+                continue;
+            }
+        }
+        definitions.push(canonical_ast(&signature, options));
+    }
+    definitions.sort();
+    skeleton_parts.append(&mut definitions);
+
+    skeleton_parts.join("\n")
+}
+
+/// Formatting-independent text of a statement: its syntax tree without
+/// source positions. Identifiers and literal values are kept, layout is not.
+fn canonical_ast(stmt: &ast::Stmt, options: &ParseOptions) -> String {
+    use ast::Fold;
+
+    let mut stmt = stmt.clone();
+    if options.strip_comments {
+        remove_docstrings(&mut stmt);
+    }
+    match StripRanges.fold_stmt(stmt) {
+        Ok(stripped) => format!("{:?}", stripped),
+        Err(never) => match never {},
+    }
+}
+
+/// Drop docstrings from a definition and the definitions nested in it
+fn remove_docstrings(stmt: &mut ast::Stmt) {
+    let body = match stmt {
+        ast::Stmt::FunctionDef(def) => &mut def.body,
+        ast::Stmt::AsyncFunctionDef(def) => &mut def.body,
+        ast::Stmt::ClassDef(def) => &mut def.body,
+        _ => return,
+    };
+    if let Some(ast::Stmt::Expr(expr)) = body.first() {
+        if matches!(&*expr.value, ast::Expr::Constant(c) if matches!(c.value, ast::Constant::Str(_)))
+        {
+            body.remove(0);
+        }
+    }
+    body.iter_mut().for_each(remove_docstrings);
+}
+
+/// Folds a syntax tree into one without source ranges
+struct StripRanges;
+
+impl ast::Fold<TextRange> for StripRanges {
+    type TargetU = ();
+    type Error = std::convert::Infallible;
+    type UserContext = ();
+
+    fn will_map_user(&mut self, _user: &TextRange) -> Self::UserContext {}
+
+    fn map_user(
+        &mut self,
+        _user: TextRange,
+        _context: Self::UserContext,
+    ) -> Result<Self::TargetU, Self::Error> {
+        Ok(())
+    }
+}
+
 /// Strip a trailing comment from a line of Python code.
 ///
 /// Scans the line tracking string literal state (`'`, `"`) and returns the
@@ -154,6 +420,81 @@ fn extract_signature_lines<'a>(source_lines: &[&'a str], start: usize, end: usiz
     def_lines
 }
 
+/// Options controlling how block checksums are computed
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ParseOptions {
+    /// Ignore `#` comments and docstrings when computing block checksums
+    pub strip_comments: bool,
+    /// Hash each block's syntax tree instead of its text, so reformatting
+    /// (e.g. with `black`) leaves checksums unchanged. Comments never reach
+    /// the tree; docstrings do, unless `strip_comments` is also set.
+    pub ast_checksum: bool,
+    /// Hash behind block checksums
+    pub algorithm: ChecksumAlgorithm,
+}
+
+/// State shared while walking the AST of a single module
+struct ParseContext<'a> {
+    /// Text that block checksums are computed over. Always has the same line
+    /// layout as the parsed source, so AST line numbers index into it directly.
+    source: &'a str,
+    locator: RandomLocator<'a>,
+    options: &'a ParseOptions,
+    blocks: Vec<Block>,
+    /// Names of the enclosing classes/functions, outermost first
+    scope: Vec<String>,
+}
+
+impl ParseContext<'_> {
+    /// Dotted name of a definition nested in the current scope
+    fn qualified_name(&self, name: &str) -> String {
+        if self.scope.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.scope.join("."), name)
+        }
+    }
+
+    /// Extract blocks from a definition body with `name` pushed onto the scope
+    fn extract_nested(&mut self, name: &str, body: &[ast::Stmt]) -> Result<()> {
+        self.scope.push(name.to_string());
+        let result = extract_blocks_from_statements(body, self);
+        self.scope.pop();
+        result
+    }
+
+    /// Checksum of a block for `stmt`, spanning lines `start..=end` (1-indexed)
+    fn block_checksum(&self, stmt: &ast::Stmt, start: usize, end: usize) -> Result<i64> {
+        if self.options.ast_checksum {
+            Ok(self.checksum(&canonical_ast(stmt, self.options)))
+        } else {
+            Ok(self.checksum(&self.block_text(start, end)?))
+        }
+    }
+
+    fn checksum(&self, text: &str) -> i64 {
+        calculate_checksum_with(text, self.options.algorithm)
+    }
+
+    /// Extract the text hashed for a block spanning `start..=end` (1-indexed)
+    fn block_text(&self, start: usize, end: usize) -> Result<String> {
+        let text = extract_source_lines(self.source, start, end)?;
+        Ok(self.normalize(text))
+    }
+
+    /// Drop blank lines and trailing whitespace left behind by comment stripping
+    fn normalize(&self, text: String) -> String {
+        if !self.options.strip_comments {
+            return text;
+        }
+        text.lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
 /// Internal implementation that returns anyhow::Result
 ///
 /// This must be used instead of `parse_module` for any code running inside
@@ -161,44 +502,122 @@ fn extract_signature_lines<'a>(source_lines: &[&'a str], start: usize, end: usiz
 /// objects which require the GIL — causing a deadlock when called from worker
 /// threads while the main Python thread holds the GIL.
 pub(crate) fn parse_module_internal(source: &str) -> Result<Vec<Block>> {
+    parse_module_with_options(source, &ParseOptions::default())
+}
+
+/// Parse a module, computing block checksums according to `options`
+pub(crate) fn parse_module_with_options(
+    source: &str,
+    options: &ParseOptions,
+) -> Result<Vec<Block>> {
     // Parse the source code with RustPython's parser
     let parsed =
         ast::Suite::parse(source, "<string>").map_err(|e| anyhow::anyhow!("Parse error: {}", e))?;
 
-    // Build a RandomLocator once for efficient offset-to-line lookups
-    let mut locator = RandomLocator::new(source);
-
-    let mut blocks = Vec::new();
+    let stripped;
+    let checksum_source = if options.strip_comments {
+        stripped = strip_comments_and_docstrings(source);
+        stripped.as_str()
+    } else {
+        source
+    };
+
+    let mut ctx = ParseContext {
+        source: checksum_source,
+        // Build a RandomLocator once for efficient offset-to-line lookups
+        locator: RandomLocator::new(source),
+        options,
+        blocks: Vec::new(),
+        scope: Vec::new(),
+    };
 
     // Add module-level block (skeleton only - excludes function/class bodies)
     // This ensures that changing a function body doesn't invalidate the module checksum
-    let module_skeleton = extract_module_skeleton(source, &parsed, &mut locator)?;
-    let module_checksum = calculate_checksum(&module_skeleton);
+    let module_checksum = if options.ast_checksum {
+        calculate_checksum_with(&ast_module_skeleton(&parsed, options), options.algorithm)
+    } else {
+        let module_skeleton = extract_module_skeleton(ctx.source, &parsed, &mut ctx.locator)?;
+        ctx.checksum(&ctx.normalize(module_skeleton))
+    };
     let line_count = source.lines().count();
-    blocks.push(Block {
+    ctx.blocks.push(Block {
         start_line: 1,
         end_line: line_count.max(1),
         checksum: module_checksum,
         name: "<module>".to_string(),
+        qualified_name: "<module>".to_string(),
         block_type: "module".to_string(),
         body_start_line: 1,
     });
 
     // Extract blocks from AST
-    extract_blocks_from_statements(&parsed, source, &mut blocks, &mut locator)?;
+    extract_blocks_from_statements(&parsed, &mut ctx)?;
 
-    Ok(blocks)
+    Ok(ctx.blocks)
+}
+
+/// Blank out comments and docstrings while preserving byte offsets and lines
+///
+/// Uses the tokenizer so `#` inside string literals is left untouched. A
+/// docstring is any plain string literal forming a whole statement on its own
+/// (module/class/function docstrings, or stray string expressions), which is
+/// a no-op at runtime. F-strings are kept since they may evaluate expressions.
+fn strip_comments_and_docstrings(source: &str) -> String {
+    use rustpython_parser::lexer::lex;
+    use rustpython_parser::{Mode, StringKind, Tok};
+
+    let mut ranges = Vec::new();
+    // Candidate string statement: (range start, range end) of consecutive
+    // string tokens that started a logical line
+    let mut pending: Option<(usize, usize)> = None;
+    let mut at_line_start = true;
+
+    for (tok, range) in lex(source, Mode::Module).flatten() {
+        let (tok_start, tok_end) = (range.start().to_usize(), range.end().to_usize());
+        match tok {
+            Tok::Comment(_) => {
+                ranges.push((tok_start, tok_end));
+                continue;
+            }
+            Tok::NonLogicalNewline => continue,
+            Tok::String { kind, .. } if kind != StringKind::FString => {
+                if let Some((_, end)) = pending.as_mut() {
+                    *end = tok_end; // implicit concatenation
+                } else if at_line_start {
+                    pending = Some((tok_start, tok_end));
+                }
+                at_line_start = false;
+                continue;
+            }
+            Tok::Newline => {
+                if let Some(range) = pending.take() {
+                    ranges.push(range);
+                }
+            }
+            _ => pending = None,
+        }
+        at_line_start = matches!(tok, Tok::Newline | Tok::Indent | Tok::Dedent);
+    }
+    if let Some(range) = pending {
+        ranges.push(range);
+    }
+
+    let mut bytes = source.as_bytes().to_vec();
+    for (start, end) in ranges {
+        for b in &mut bytes[start..end] {
+            if *b != b'\n' && *b != b'\r' {
+                *b = b' ';
+            }
+        }
+    }
+    // Only whole tokens were blanked, so the result is still valid UTF-8
+    String::from_utf8(bytes).unwrap_or_else(|_| source.to_string())
 }
 
 /// Recursively extract blocks from a list of statements
-fn extract_blocks_from_statements(
-    statements: &[ast::Stmt],
-    source: &str,
-    blocks: &mut Vec<Block>,
-    locator: &mut RandomLocator,
-) -> Result<()> {
+fn extract_blocks_from_statements(statements: &[ast::Stmt], ctx: &mut ParseContext) -> Result<()> {
     for stmt in statements {
-        extract_block_from_statement(stmt, source, blocks, locator)?;
+        extract_block_from_statement(stmt, ctx)?;
     }
     Ok(())
 }
@@ -207,142 +626,303 @@ fn extract_blocks_from_statements(
 ///
 /// Shared logic for FunctionDef and AsyncFunctionDef: both use decorator_list
 /// for start_line and body.first() for body_start_line.
-#[allow(clippy::too_many_arguments)]
 fn extract_callable_block(
     name: &str,
     block_type: &str,
     decorator_list: &[ast::Expr],
     body: &[ast::Stmt],
     stmt: &ast::Stmt,
-    source: &str,
-    blocks: &mut Vec<Block>,
-    locator: &mut RandomLocator,
+    ctx: &mut ParseContext,
 ) -> Result<()> {
     use ast::Ranged;
 
-    let def_line = get_line_number(locator, stmt.start());
+    let def_line = get_line_number(&mut ctx.locator, stmt.start());
     // Include decorators in start_line so the checksum covers them
-    let start = decorator_list
-        .first()
-        .map(|d| get_line_number(locator, d.start()))
-        .unwrap_or(def_line);
-    let end = get_line_number(locator, stmt.end());
+    let start = decorated_start_line(decorator_list, def_line, &mut ctx.locator);
+    let end = get_line_number(&mut ctx.locator, stmt.end());
 
-    let block_source = extract_source_lines(source, start, end)?;
-    let checksum = calculate_checksum(&block_source);
+    let checksum = ctx.block_checksum(stmt, start, end)?;
 
     // body_start_line = first line of the function body (skipping decorators + def)
     let body_start_line = body
         .first()
-        .map(|s| get_line_number(locator, s.start()))
+        .map(|s| get_line_number(&mut ctx.locator, s.start()))
         .unwrap_or(def_line);
 
-    blocks.push(Block {
+    ctx.blocks.push(Block {
         start_line: start,
         end_line: end,
         checksum,
         name: name.to_string(),
+        qualified_name: ctx.qualified_name(name),
         block_type: block_type.to_string(),
         body_start_line,
     });
 
     // Extract nested blocks
-    extract_blocks_from_statements(body, source, blocks, locator)?;
-    Ok(())
+    ctx.extract_nested(name, body)
 }
 
-/// Extract a block from a single statement
-fn extract_block_from_statement(
+/// Extract a block for a module-level import statement
+///
+/// `name` lists the imported names and `qualified_name` their full targets
+/// (e.g. `foo` and `pkg.mod.foo`). The checksum covers the statement text, so
+/// it changes when the import source changes.
+fn extract_import_block(
     stmt: &ast::Stmt,
-    source: &str,
-    blocks: &mut Vec<Block>,
-    locator: &mut RandomLocator,
+    name: String,
+    qualified_name: String,
+    ctx: &mut ParseContext,
 ) -> Result<()> {
+    use ast::Ranged;
+
+    let start = get_line_number(&mut ctx.locator, stmt.start());
+    let end = get_line_number(&mut ctx.locator, stmt.end());
+    let checksum = ctx.block_checksum(stmt, start, end)?;
+
+    ctx.blocks.push(Block {
+        start_line: start,
+        end_line: end,
+        checksum,
+        name,
+        qualified_name,
+        block_type: "import".to_string(),
+        body_start_line: start,
+    });
+    Ok(())
+}
+
+/// Extract a block from a single statement
+fn extract_block_from_statement(stmt: &ast::Stmt, ctx: &mut ParseContext) -> Result<()> {
     use ast::Ranged; // Import trait to use range() method
 
     match stmt {
         ast::Stmt::FunctionDef(func_def) => {
+            let block_type =
+                callable_block_type(&func_def.decorator_list, &func_def.body, "function");
             extract_callable_block(
                 &func_def.name,
-                "function",
+                block_type,
                 &func_def.decorator_list,
                 &func_def.body,
                 stmt,
-                source,
-                blocks,
-                locator,
+                ctx,
             )?;
         }
         ast::Stmt::AsyncFunctionDef(async_func_def) => {
+            let block_type = callable_block_type(
+                &async_func_def.decorator_list,
+                &async_func_def.body,
+                "async_function",
+            );
             extract_callable_block(
                 &async_func_def.name,
-                "async_function",
+                block_type,
                 &async_func_def.decorator_list,
                 &async_func_def.body,
                 stmt,
-                source,
-                blocks,
-                locator,
+                ctx,
             )?;
         }
         ast::Stmt::ClassDef(class_def) => {
-            let def_line = get_line_number(locator, stmt.start());
-            let start = class_def
-                .decorator_list
-                .first()
-                .map(|d| get_line_number(locator, d.start()))
-                .unwrap_or(def_line);
-            let end = get_line_number(locator, stmt.end());
+            let def_line = get_line_number(&mut ctx.locator, stmt.start());
+            let start = decorated_start_line(&class_def.decorator_list, def_line, &mut ctx.locator);
+            let end = get_line_number(&mut ctx.locator, stmt.end());
 
-            let block_source = extract_source_lines(source, start, end)?;
-            let checksum = calculate_checksum(&block_source);
+            let checksum = ctx.block_checksum(stmt, start, end)?;
 
             // Class body IS executed at import time, so body_start_line = class def
             // line (skip decorators only, keep the `class` line).
-            blocks.push(Block {
+            ctx.blocks.push(Block {
                 start_line: start,
                 end_line: end,
                 checksum,
                 name: class_def.name.to_string(),
+                qualified_name: ctx.qualified_name(&class_def.name),
                 block_type: "class".to_string(),
                 body_start_line: def_line,
             });
 
-            extract_blocks_from_statements(&class_def.body, source, blocks, locator)?;
+            ctx.extract_nested(&class_def.name, &class_def.body)?;
+        }
+        // Script entry point, kept out of the module skeleton so edits to it
+        // do not invalidate everything that imports the module
+        ast::Stmt::If(if_stmt) if ctx.scope.is_empty() && is_main_guard(stmt) => {
+            let start = get_line_number(&mut ctx.locator, stmt.start());
+            let end = get_line_number(&mut ctx.locator, stmt.end());
+            let checksum = ctx.block_checksum(stmt, start, end)?;
+            let body_start_line = if_stmt
+                .body
+                .first()
+                .map(|s| get_line_number(&mut ctx.locator, s.start()))
+                .unwrap_or(start);
+
+            ctx.blocks.push(Block {
+                start_line: start,
+                end_line: end,
+                checksum,
+                name: "__main__".to_string(),
+                qualified_name: "__main__".to_string(),
+                block_type: "main".to_string(),
+                body_start_line,
+            });
+
+            extract_blocks_from_statements(&if_stmt.body, ctx)?;
+            extract_blocks_from_statements(&if_stmt.orelse, ctx)?;
         }
         // Handle other statement types that may contain nested blocks
         ast::Stmt::If(if_stmt) => {
-            extract_blocks_from_statements(&if_stmt.body, source, blocks, locator)?;
-            extract_blocks_from_statements(&if_stmt.orelse, source, blocks, locator)?;
+            extract_blocks_from_statements(&if_stmt.body, ctx)?;
+            extract_blocks_from_statements(&if_stmt.orelse, ctx)?;
         }
         ast::Stmt::For(for_stmt) => {
-            extract_blocks_from_statements(&for_stmt.body, source, blocks, locator)?;
-            extract_blocks_from_statements(&for_stmt.orelse, source, blocks, locator)?;
+            extract_blocks_from_statements(&for_stmt.body, ctx)?;
+            extract_blocks_from_statements(&for_stmt.orelse, ctx)?;
         }
         ast::Stmt::While(while_stmt) => {
-            extract_blocks_from_statements(&while_stmt.body, source, blocks, locator)?;
-            extract_blocks_from_statements(&while_stmt.orelse, source, blocks, locator)?;
+            extract_blocks_from_statements(&while_stmt.body, ctx)?;
+            extract_blocks_from_statements(&while_stmt.orelse, ctx)?;
         }
         ast::Stmt::With(with_stmt) => {
-            extract_blocks_from_statements(&with_stmt.body, source, blocks, locator)?;
+            extract_blocks_from_statements(&with_stmt.body, ctx)?;
         }
         ast::Stmt::Try(try_stmt) => {
-            extract_blocks_from_statements(&try_stmt.body, source, blocks, locator)?;
+            extract_blocks_from_statements(&try_stmt.body, ctx)?;
             for handler in &try_stmt.handlers {
                 match handler {
                     ast::ExceptHandler::ExceptHandler(h) => {
-                        extract_blocks_from_statements(&h.body, source, blocks, locator)?;
+                        extract_blocks_from_statements(&h.body, ctx)?;
                     }
                 }
             }
-            extract_blocks_from_statements(&try_stmt.orelse, source, blocks, locator)?;
-            extract_blocks_from_statements(&try_stmt.finalbody, source, blocks, locator)?;
+            extract_blocks_from_statements(&try_stmt.orelse, ctx)?;
+            extract_blocks_from_statements(&try_stmt.finalbody, ctx)?;
+        }
+        ast::Stmt::Match(match_stmt) => {
+            for case in &match_stmt.cases {
+                extract_blocks_from_statements(&case.body, ctx)?;
+            }
+        }
+        // Module-level imports get their own block so import churn can be told
+        // apart from other top-level edits (the module block still covers them)
+        ast::Stmt::Import(import) if ctx.scope.is_empty() => {
+            let targets: Vec<&str> = import.names.iter().map(|a| a.name.as_str()).collect();
+            extract_import_block(stmt, targets.join(", "), targets.join(", "), ctx)?;
+        }
+        ast::Stmt::ImportFrom(import) if ctx.scope.is_empty() => {
+            let dots = ".".repeat(import.level.as_ref().map_or(0, |l| l.to_usize()));
+            let module = import.module.as_ref().map_or("", |m| m.as_str());
+            let names: Vec<&str> = import.names.iter().map(|a| a.name.as_str()).collect();
+            let targets: Vec<String> = names
+                .iter()
+                .map(|name| match module {
+                    "" => format!("{}{}", dots, name),
+                    _ => format!("{}{}.{}", dots, module, name),
+                })
+                .collect();
+            extract_import_block(stmt, names.join(", "), targets.join(", "), ctx)?;
         }
         _ => {}
     }
     Ok(())
 }
 
+/// Builtin decorators that change how a function is bound, mapped to block types
+const METHOD_KIND_DECORATORS: &[&str] =
+    &["property", "staticmethod", "classmethod", "cached_property"];
+
+/// Classify a function by its decorators
+///
+/// Returns the decorator name for `@property`, `@staticmethod`, `@classmethod`
+/// and `@cached_property` (also matched as `@functools.cached_property`),
+/// falling back to "function".
+fn function_block_type(decorator_list: &[ast::Expr]) -> &'static str {
+    decorator_list
+        .iter()
+        .filter_map(|decorator| match decorator {
+            ast::Expr::Name(name) => Some(name.id.as_str()),
+            ast::Expr::Attribute(attr) => Some(attr.attr.as_str()),
+            _ => None,
+        })
+        .find_map(|name| METHOD_KIND_DECORATORS.iter().find(|&&kind| kind == name))
+        .copied()
+        .unwrap_or("function")
+}
+
+/// Block type of a `def` or `async def` (`plain` is the type of an undecorated one)
+///
+/// A method-kind decorator wins over a stub body, so the
+/// `@property def x(self): ...` of a Protocol stays a property.
+fn callable_block_type(
+    decorator_list: &[ast::Expr],
+    body: &[ast::Stmt],
+    plain: &'static str,
+) -> &'static str {
+    match function_block_type(decorator_list) {
+        "function" if is_stub_body(body) => "stub_function",
+        "function" => plain,
+        kind => kind,
+    }
+}
+
+/// Check whether a function body is a stub: a lone `...` (optionally preceded
+/// by a docstring), as found in `.pyi` files, protocols and overloads
+fn is_stub_body(body: &[ast::Stmt]) -> bool {
+    let is_constant = |stmt: &ast::Stmt, pred: fn(&ast::Constant) -> bool| match stmt {
+        ast::Stmt::Expr(expr) => matches!(&*expr.value, ast::Expr::Constant(c) if pred(&c.value)),
+        _ => false,
+    };
+    match body {
+        [only] => is_constant(only, |c| matches!(c, ast::Constant::Ellipsis)),
+        [doc, last] => {
+            is_constant(doc, |c| matches!(c, ast::Constant::Str(_)))
+                && is_constant(last, |c| matches!(c, ast::Constant::Ellipsis))
+        }
+        _ => false,
+    }
+}
+
+/// Whether a statement is an `if __name__ == "__main__":` guard (either operand order)
+fn is_main_guard(stmt: &ast::Stmt) -> bool {
+    let ast::Stmt::If(if_stmt) = stmt else {
+        return false;
+    };
+    let ast::Expr::Compare(compare) = &*if_stmt.test else {
+        return false;
+    };
+    let is_name =
+        |expr: &ast::Expr| matches!(expr, ast::Expr::Name(n) if n.id.as_str() == "__name__");
+    let is_main = |expr: &ast::Expr| matches!(expr, ast::Expr::Constant(c) if matches!(&c.value, ast::Constant::Str(s) if s == "__main__"));
+    match (
+        &*compare.left,
+        compare.ops.as_slice(),
+        compare.comparators.as_slice(),
+    ) {
+        (left, [ast::CmpOp::Eq], [right]) => {
+            (is_name(left) && is_main(right)) || (is_main(left) && is_name(right))
+        }
+        _ => false,
+    }
+}
+
+/// Compute the first line of a (possibly decorated) definition
+///
+/// RustPython reports a definition's start at the `def`/`class` keyword, so
+/// decorators fall outside the statement range. Take the minimum of the
+/// decorator lines and the keyword line so decorator edits change the checksum.
+fn decorated_start_line(
+    decorator_list: &[ast::Expr],
+    def_line: usize,
+    locator: &mut RandomLocator,
+) -> usize {
+    use ast::Ranged;
+
+    decorator_list
+        .iter()
+        .map(|d| get_line_number(locator, d.start()))
+        .fold(def_line, usize::min)
+}
+
 /// Convert TextSize to 1-indexed line number
 fn get_line_number(
     locator: &mut RandomLocator,
@@ -365,13 +945,99 @@ fn extract_source_lines(source: &str, start: usize, end: usize) -> Result<String
     Ok(lines[(start - 1)..end].join("\n"))
 }
 
+/// Hash algorithm used for block checksums
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC32 as a signed i32 (testmon-compatible)
+    #[default]
+    Crc32,
+    /// 64-bit xxHash, far fewer collisions on large codebases
+    XxHash64,
+}
+
+impl ChecksumAlgorithm {
+    /// Name used in Python APIs and database metadata
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "crc32",
+            ChecksumAlgorithm::XxHash64 => "xxhash64",
+        }
+    }
+}
+
+impl std::str::FromStr for ChecksumAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "crc32" => Ok(ChecksumAlgorithm::Crc32),
+            "xxhash64" => Ok(ChecksumAlgorithm::XxHash64),
+            _ => anyhow::bail!(
+                "Unknown checksum algorithm '{}' (expected 'crc32' or 'xxhash64')",
+                name
+            ),
+        }
+    }
+}
+
+/// Compute the checksum of a source string with the given algorithm
+///
+/// # Example
+/// ```python
+/// assert compute_checksum("def foo(): pass") == parse_module("def foo(): pass")[1].checksum
+/// wide = compute_checksum("def foo(): pass", "xxhash64")
+/// ```
+#[pyfunction]
+#[pyo3(signature = (source, algorithm="crc32"))]
+pub fn compute_checksum(source: &str, algorithm: &str) -> PyResult<i64> {
+    let algorithm: ChecksumAlgorithm = algorithm
+        .parse()
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("{}", e)))?;
+    Ok(calculate_checksum_with(source, algorithm))
+}
+
 /// Calculate CRC32 checksum for a string
 ///
-/// Returns a signed i32 checksum
-pub fn calculate_checksum(source: &str) -> i32 {
-    let mut hasher = Hasher::new();
-    hasher.update(source.as_bytes());
-    hasher.finalize() as i32
+/// Returns the signed i32 CRC32, widened to i64
+pub fn calculate_checksum(source: &str) -> i64 {
+    calculate_checksum_with(source, ChecksumAlgorithm::Crc32)
+}
+
+/// Calculate a checksum with a selectable algorithm
+///
+/// CRC32 values fit in an i32; xxHash64 values use the full i64 range.
+pub fn calculate_checksum_with(source: &str, algorithm: ChecksumAlgorithm) -> i64 {
+    match algorithm {
+        ChecksumAlgorithm::Crc32 => {
+            let mut hasher = Hasher::new();
+            hasher.update(source.as_bytes());
+            hasher.finalize() as i32 as i64
+        }
+        ChecksumAlgorithm::XxHash64 => xxhash_rust::xxh64::xxh64(source.as_bytes(), 0) as i64,
+    }
+}
+
+/// Mix `seed` into a block checksum computed with `algorithm`
+///
+/// Seed 0 returns the checksum unchanged, so unseeded databases keep their
+/// values. For CRC32 any other seed is a bijection on checksums: seeding adds
+/// no collisions, but blocks agree across seeds only by chance. xxHash64
+/// rehashes the checksum with the seed, keeping the full 64-bit range.
+pub fn seed_checksum(checksum: i64, seed: u32, algorithm: ChecksumAlgorithm) -> i64 {
+    if seed == 0 {
+        return checksum;
+    }
+    match algorithm {
+        ChecksumAlgorithm::Crc32 => {
+            let mut hasher = Hasher::new();
+            hasher.update(&seed.to_le_bytes());
+            hasher.update(&(checksum as i32).to_le_bytes());
+            hasher.finalize() as i32 as i64
+        }
+        ChecksumAlgorithm::XxHash64 => {
+            xxhash_rust::xxh64::xxh64(&checksum.to_le_bytes(), seed as u64) as i64
+        }
+    }
 }
 
 #[cfg(test)]
@@ -420,6 +1086,323 @@ class Calculator:
             .any(|b| b.name == "subtract" && b.block_type == "function"));
     }
 
+    #[test]
+    fn test_block_tree_nests_methods_in_classes() {
+        let source = "import os\n\nclass Calculator:\n    def add(self, a, b):\n        return a + b\n\n    @property\n    def zero(self):\n        return 0\n\ndef main():\n    return Calculator()\n";
+        let tree = build_block_tree(parse_module_internal(source).unwrap());
+
+        let roots: Vec<&str> = tree
+            .iter()
+            .map(|node| node.block.qualified_name.as_str())
+            .collect();
+        assert_eq!(roots, vec!["<module>", "os", "Calculator", "main"]);
+
+        let calculator = &tree[2];
+        let methods: Vec<&str> = calculator
+            .children
+            .iter()
+            .map(|node| node.block.qualified_name.as_str())
+            .collect();
+        assert_eq!(methods, vec!["Calculator.add", "Calculator.zero"]);
+        assert!(calculator.children.iter().all(|m| m.children.is_empty()));
+        assert!(tree[3].children.is_empty());
+    }
+
+    #[test]
+    fn test_block_tree_nests_inner_functions() {
+        let source = "def outer():\n    import json\n\n    def middle():\n        def inner():\n            return 1\n        return inner\n\n    class Local:\n        pass\n\n    return middle\n\ndef after():\n    pass\n";
+        let tree = build_block_tree(parse_module_internal(source).unwrap());
+
+        let names = |nodes: &[BlockNode]| -> Vec<String> {
+            nodes
+                .iter()
+                .map(|n| n.block.qualified_name.clone())
+                .collect()
+        };
+        assert_eq!(names(&tree), vec!["<module>", "outer", "after"]);
+        let outer = &tree[1];
+        // Imports inside functions are not blocks of their own
+        assert_eq!(names(&outer.children), vec!["outer.middle", "outer.Local"]);
+        assert_eq!(
+            names(&outer.children[0].children),
+            vec!["outer.middle.inner"]
+        );
+    }
+
+    #[test]
+    fn test_qualified_names() {
+        let source = r#"
+class Calculator:
+    def add(self, a, b):
+        def helper():
+            pass
+        return a + b
+
+    def subtract(self, a, b):
+        return a - b
+
+def add(a, b):
+    return a + b
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+        let qualified: Vec<&str> = blocks.iter().map(|b| b.qualified_name.as_str()).collect();
+
+        assert_eq!(
+            qualified,
+            vec![
+                "<module>",
+                "Calculator",
+                "Calculator.add",
+                "Calculator.add.helper",
+                "Calculator.subtract",
+                "add",
+            ]
+        );
+        // `name` stays the bare identifier
+        let helper = blocks.iter().find(|b| b.name == "helper").unwrap();
+        assert_eq!(helper.qualified_name, "Calculator.add.helper");
+    }
+
+    #[test]
+    fn test_method_kind_block_types() {
+        let source = r#"
+import functools
+
+class Account:
+    @property
+    def balance(self):
+        return self._balance
+
+    @staticmethod
+    def currency():
+        return "EUR"
+
+    @classmethod
+    def empty(cls):
+        return cls()
+
+    @functools.cached_property
+    def history(self):
+        return []
+
+    @other_decorator
+    def deposit(self, amount):
+        self._balance += amount
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+        let block_type = |name: &str| {
+            blocks
+                .iter()
+                .find(|b| b.name == name)
+                .unwrap()
+                .block_type
+                .clone()
+        };
+
+        assert_eq!(block_type("balance"), "property");
+        assert_eq!(block_type("currency"), "staticmethod");
+        assert_eq!(block_type("empty"), "classmethod");
+        assert_eq!(block_type("history"), "cached_property");
+        assert_eq!(block_type("deposit"), "function");
+    }
+
+    #[test]
+    fn test_decorated_stub_keeps_method_kind() {
+        let source = r#"
+class Account(Protocol):
+    @property
+    def balance(self) -> int: ...
+
+    @staticmethod
+    def currency() -> str: ...
+
+    def deposit(self, amount: int) -> None: ...
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+        let block_type = |name: &str| {
+            blocks
+                .iter()
+                .find(|b| b.name == name)
+                .unwrap()
+                .block_type
+                .clone()
+        };
+
+        assert_eq!(block_type("balance"), "property");
+        assert_eq!(block_type("currency"), "staticmethod");
+        assert_eq!(block_type("deposit"), "stub_function");
+    }
+
+    #[test]
+    fn test_decorated_async_method_keeps_method_kind() {
+        let source = r#"
+class Client:
+    @staticmethod
+    async def connect(url):
+        return url
+
+    @classmethod
+    async def create(cls):
+        return cls()
+
+    @property
+    async def status(self): ...
+
+    async def fetch(self):
+        return 1
+
+    async def close(self): ...
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+        let block_type = |name: &str| {
+            blocks
+                .iter()
+                .find(|b| b.name == name)
+                .unwrap()
+                .block_type
+                .clone()
+        };
+
+        assert_eq!(block_type("connect"), "staticmethod");
+        assert_eq!(block_type("create"), "classmethod");
+        assert_eq!(block_type("status"), "property");
+        assert_eq!(block_type("fetch"), "async_function");
+        assert_eq!(block_type("close"), "stub_function");
+    }
+
+    #[test]
+    fn test_parse_stub_file() {
+        let source = r#"
+from typing import overload
+
+VERSION: str
+
+class Client:
+    timeout: float
+    def __init__(self, url: str, timeout: float = ...) -> None: ...
+    @overload
+    def get(self, key: str) -> str: ...
+    @overload
+    def get(self, key: int) -> bytes: ...
+    async def fetch(self) -> bytes:
+        """Fetch the payload."""
+        ...
+
+def connect(url: str) -> Client: ...
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+
+        let stubs: Vec<&str> = blocks
+            .iter()
+            .filter(|b| b.block_type == "stub_function")
+            .map(|b| b.qualified_name.as_str())
+            .collect();
+        assert_eq!(
+            stubs,
+            vec![
+                "Client.__init__",
+                "Client.get",
+                "Client.get",
+                "Client.fetch",
+                "connect"
+            ]
+        );
+        assert!(blocks
+            .iter()
+            .any(|b| b.name == "Client" && b.block_type == "class"));
+
+        // Overloads have distinct checksums, so editing one is trackable
+        let gets: Vec<i64> = blocks
+            .iter()
+            .filter(|b| b.name == "get")
+            .map(|b| b.checksum)
+            .collect();
+        assert_ne!(gets[0], gets[1]);
+    }
+
+    #[test]
+    fn test_import_blocks() {
+        let source = r#"
+import os, sys
+from a import foo
+from .models import User as U
+
+def run():
+    import json
+    return foo()
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+        let imports: Vec<(&str, &str)> = blocks
+            .iter()
+            .filter(|b| b.block_type == "import")
+            .map(|b| (b.name.as_str(), b.qualified_name.as_str()))
+            .collect();
+
+        // Only module-level imports, one block per statement
+        assert_eq!(
+            imports,
+            vec![
+                ("os, sys", "os, sys"),
+                ("foo", "a.foo"),
+                ("User", ".models.User"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_main_guard_block() {
+        let source = |call: &str| {
+            format!(
+                "def main():\n    return 0\n\nif __name__ == \"__main__\":\n    {}\n",
+                call
+            )
+        };
+        let blocks = parse_module_internal(&source("main()")).unwrap();
+        let guard = blocks.iter().find(|b| b.block_type == "main").unwrap();
+        assert_eq!(guard.name, "__main__");
+        assert_eq!(
+            (guard.start_line, guard.body_start_line, guard.end_line),
+            (4, 5, 5)
+        );
+
+        // Edits inside the guard change its block but not the module skeleton
+        let edited = parse_module_internal(&source("raise SystemExit(main())")).unwrap();
+        let checksum = |blocks: &[Block], block_type: &str| {
+            blocks
+                .iter()
+                .find(|b| b.block_type == block_type)
+                .unwrap()
+                .checksum
+        };
+        assert_eq!(checksum(&blocks, "module"), checksum(&edited, "module"));
+        assert_ne!(checksum(&blocks, "main"), checksum(&edited, "main"));
+
+        // Reversed operands are recognized; other `__name__` checks are not
+        let reversed = parse_module_internal("if '__main__' == __name__:\n    pass\n").unwrap();
+        assert!(reversed.iter().any(|b| b.block_type == "main"));
+        let other = parse_module_internal("if __name__ != \"__main__\":\n    pass\n").unwrap();
+        assert!(!other.iter().any(|b| b.block_type == "main"));
+    }
+
+    #[test]
+    fn test_import_block_checksum_tracks_import_source() {
+        let import_checksum = |source: &str| {
+            parse_module_internal(source)
+                .unwrap()
+                .into_iter()
+                .find(|b| b.block_type == "import")
+                .unwrap()
+                .checksum
+        };
+
+        let original = import_checksum("from a import foo\n\ndef run():\n    return foo()\n");
+        let body_edit = import_checksum("from a import foo\n\ndef run():\n    return foo(1)\n");
+        let moved = import_checksum("from b import foo\n\ndef run():\n    return foo()\n");
+
+        assert_eq!(original, body_edit);
+        assert_ne!(original, moved);
+    }
+
     #[test]
     fn test_parse_async_function() {
         let source = r#"
@@ -453,6 +1436,76 @@ async def fetch_data():
         assert_ne!(checksum1, checksum2);
     }
 
+    #[test]
+    fn test_seeded_checksums_differ_per_seed() {
+        let source = "def foo(): pass";
+        for algorithm in [ChecksumAlgorithm::Crc32, ChecksumAlgorithm::XxHash64] {
+            let unseeded = calculate_checksum_with(source, algorithm);
+            assert_eq!(seed_checksum(unseeded, 0, algorithm), unseeded);
+
+            let seeded: std::collections::HashSet<i64> = (1..=100)
+                .map(|seed| seed_checksum(unseeded, seed, algorithm))
+                .collect();
+            assert_eq!(seeded.len(), 100);
+            assert!(!seeded.contains(&unseeded));
+            assert_eq!(
+                seed_checksum(unseeded, 7, algorithm),
+                seed_checksum(unseeded, 7, algorithm)
+            );
+        }
+        // Seeded CRC32 checksums stay in the i32 range
+        let crc = seed_checksum(calculate_checksum(source), 7, ChecksumAlgorithm::Crc32);
+        assert_eq!(crc, crc as i32 as i64);
+    }
+
+    #[test]
+    fn test_checksum_algorithm_crc32_matches_default() {
+        let source = "def foo(): pass";
+        assert_eq!(
+            calculate_checksum_with(source, ChecksumAlgorithm::Crc32),
+            calculate_checksum(source)
+        );
+        assert_eq!(ChecksumAlgorithm::default(), ChecksumAlgorithm::Crc32);
+        let blocks = parse_module_internal(source).unwrap();
+        assert_eq!(blocks[1].checksum, calculate_checksum(source));
+    }
+
+    #[test]
+    fn test_checksum_algorithm_from_name() {
+        for algorithm in [ChecksumAlgorithm::Crc32, ChecksumAlgorithm::XxHash64] {
+            assert_eq!(
+                algorithm.name().parse::<ChecksumAlgorithm>().unwrap(),
+                algorithm
+            );
+        }
+        assert!("md5".parse::<ChecksumAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_xxhash_block_checksums() {
+        let source = "def foo():\n    return 1\n";
+        let options = ParseOptions {
+            algorithm: ChecksumAlgorithm::XxHash64,
+            ..ParseOptions::default()
+        };
+        let blocks = parse_module_with_options(source, &options).unwrap();
+        let foo = blocks.iter().find(|b| b.name == "foo").unwrap();
+        assert_eq!(
+            foo.checksum,
+            calculate_checksum_with("def foo():\n    return 1", ChecksumAlgorithm::XxHash64)
+        );
+    }
+
+    #[test]
+    fn test_xxhash_no_collisions_on_similar_sources() {
+        let mut seen = std::collections::HashSet::new();
+        for i in 0..100_000 {
+            let source = format!("def func_{}(x):\n    return x + {}\n", i % 1000, i);
+            let checksum = calculate_checksum_with(&source, ChecksumAlgorithm::XxHash64);
+            assert!(seen.insert(checksum), "collision for {:?}", source);
+        }
+    }
+
     #[test]
     fn test_parse_nested_functions() {
         let source = r#"
@@ -469,6 +1522,27 @@ def outer():
         assert!(blocks.iter().any(|b| b.name == "inner"));
     }
 
+    #[test]
+    fn test_parse_function_inside_match_case() {
+        let source = r#"
+match backend:
+    case "sqlite":
+        def connect():
+            return sqlite3.connect()
+    case _:
+        class Fallback:
+            pass
+"#;
+        let blocks = parse_module_internal(source).unwrap();
+
+        let func = blocks.iter().find(|b| b.name == "connect").unwrap();
+        assert_eq!(func.block_type, "function");
+        assert_eq!(func.start_line, 4);
+        assert!(blocks
+            .iter()
+            .any(|b| b.name == "Fallback" && b.block_type == "class"));
+    }
+
     #[test]
     fn test_multiline_signature_with_comment_colon() {
         // A multi-line signature where an intermediate line has a trailing
@@ -628,6 +1702,344 @@ def foo(
         assert_eq!(func.body_start_line, 3);
     }
 
+    /// Source mixing 2-, 3- and 4-byte UTF-8 characters before and inside
+    /// definitions, so byte offsets drift far from character offsets
+    const MULTIBYTE_SOURCE: &str = "# Grüße — 日本語のコメント 🎉🎉🎉\nGRUSS = \"héllo wörld ✓\"\n\"\"\"Docstring über\nmehrere Zeilen 🚀\"\"\"\n\ndef grüße(名前):\n    # コメント ✨\n    return f\"Hallo {名前} 👋\"\n\nclass Café:\n    \"\"\"Ein Café ☕.\"\"\"\n\n    @property\n    def größe(self):\n        return \"😀😀\"\n\n    def ñame(self):\n        return 1\n";
+
+    fn multibyte_lines(blocks: &[Block]) -> Vec<(&str, usize, usize, usize)> {
+        blocks
+            .iter()
+            .filter(|b| b.block_type != "module")
+            .map(|b| {
+                (
+                    b.qualified_name.as_str(),
+                    b.start_line,
+                    b.end_line,
+                    b.body_start_line,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_multibyte_characters_keep_physical_line_numbers() {
+        let expected = vec![
+            ("grüße", 6, 8, 8),
+            ("Café", 10, 18, 10),
+            ("Café.größe", 13, 15, 15),
+            ("Café.ñame", 17, 18, 18),
+        ];
+        // Check the expectations against the source itself
+        let lines: Vec<&str> = MULTIBYTE_SOURCE.lines().collect();
+        assert!(lines[5].starts_with("def grüße"));
+        assert!(lines[12].trim_start().starts_with("@property"));
+        assert!(lines[16].trim_start().starts_with("def ñame"));
+
+        let blocks = parse_module_internal(MULTIBYTE_SOURCE).unwrap();
+        assert_eq!(multibyte_lines(&blocks), expected);
+
+        // Blanking multibyte comments and docstrings keeps every line in place
+        let stripped = ParseOptions {
+            strip_comments: true,
+            ..ParseOptions::default()
+        };
+        let ast = ParseOptions {
+            ast_checksum: true,
+            ..ParseOptions::default()
+        };
+        for options in [&stripped, &ast] {
+            let blocks = parse_module_with_options(MULTIBYTE_SOURCE, options).unwrap();
+            assert_eq!(multibyte_lines(&blocks), expected);
+        }
+
+        // Line blocks skip the comment-only lines 1 and 7, whatever they contain
+        let line_blocks = extract_line_blocks(MULTIBYTE_SOURCE);
+        let line_numbers: Vec<usize> = line_blocks.iter().map(|b| b.start_line).collect();
+        assert!(line_numbers.contains(&8));
+        assert!(!line_numbers.contains(&1) && !line_numbers.contains(&7));
+    }
+
+    #[test]
+    fn test_multibyte_block_text_is_the_whole_definition() {
+        let blocks = parse_module_internal(MULTIBYTE_SOURCE).unwrap();
+        let checksum = |name: &str| {
+            blocks
+                .iter()
+                .find(|b| b.qualified_name == name)
+                .unwrap()
+                .checksum
+        };
+        let lines: Vec<&str> = MULTIBYTE_SOURCE.lines().collect();
+        assert_eq!(
+            checksum("grüße"),
+            calculate_checksum(&lines[5..8].join("\n"))
+        );
+        assert_eq!(
+            checksum("Café.größe"),
+            calculate_checksum(&lines[12..15].join("\n"))
+        );
+    }
+
+    #[test]
+    fn test_multibyte_lenient_parse_reports_blocks_before_error() {
+        let source = format!("{}\ndef kaputt(:\n    pass\n", MULTIBYTE_SOURCE);
+        let blocks = parse_module_lenient_internal(&source);
+        assert_eq!(
+            multibyte_lines(&blocks),
+            vec![
+                ("grüße", 6, 8, 8),
+                ("Café", 10, 18, 10),
+                ("Café.größe", 13, 15, 15),
+                ("Café.ñame", 17, 18, 18),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decorator_change_changes_checksum() {
+        let cached = "@cache\ndef foo():\n    return 1\n";
+        let lru = "@lru_cache\ndef foo():\n    return 1\n";
+        let cached_blocks = parse_module_internal(cached).unwrap();
+        let lru_blocks = parse_module_internal(lru).unwrap();
+        let cached_func = cached_blocks.iter().find(|b| b.name == "foo").unwrap();
+        let lru_func = lru_blocks.iter().find(|b| b.name == "foo").unwrap();
+
+        assert_eq!(cached_func.start_line, 1);
+        assert_ne!(cached_func.checksum, lru_func.checksum);
+    }
+
+    #[test]
+    fn test_decorator_argument_change_changes_checksum() {
+        let source = "import retry\n\nclass Client:\n    @retry(times=3, delay=0.5)\n    def fetch(self):\n        return 1\n\n@retry(\n    times=3,\n)\ndef ping():\n    return 2\n";
+        let checksum = |source: &str, name: &str, options: &ParseOptions| {
+            parse_module_with_options(source, options)
+                .unwrap()
+                .into_iter()
+                .find(|b| b.qualified_name == name)
+                .unwrap()
+                .checksum
+        };
+
+        let ast = ParseOptions {
+            ast_checksum: true,
+            ..ParseOptions::default()
+        };
+        let stripped = ParseOptions {
+            strip_comments: true,
+            ..ParseOptions::default()
+        };
+        for options in [&ParseOptions::default(), &ast, &stripped] {
+            // Single-line decorator on a method
+            let edited = source.replacen("times=3", "times=5", 1);
+            assert_ne!(
+                checksum(source, "Client.fetch", options),
+                checksum(&edited, "Client.fetch", options)
+            );
+            assert_eq!(
+                checksum(source, "ping", options),
+                checksum(&edited, "ping", options)
+            );
+
+            // Argument on a continuation line of a multi-line decorator
+            let edited = source.replace("    times=3,\n", "    times=5,\n");
+            assert_ne!(
+                checksum(source, "ping", options),
+                checksum(&edited, "ping", options)
+            );
+            assert_eq!(
+                checksum(source, "Client.fetch", options),
+                checksum(&edited, "Client.fetch", options)
+            );
+        }
+    }
+
+    #[test]
+    fn test_decorated_class_range_includes_decorators() {
+        let source = "@register\n@dataclass\nclass Foo:\n    x: int = 1\n";
+        let blocks = parse_module_internal(source).unwrap();
+        let cls = blocks.iter().find(|b| b.name == "Foo").unwrap();
+        assert_eq!(cls.start_line, 1);
+        assert_eq!(cls.end_line, 4);
+    }
+
+    #[test]
+    fn test_strip_comments_comment_only_change_is_stable() {
+        let options = ParseOptions {
+            strip_comments: true,
+            ..ParseOptions::default()
+        };
+        let original = "import os\n\ndef foo():\n    \"\"\"Return one.\"\"\"\n    return 1\n";
+        let edited = "import os  # needed\n\ndef foo():\n    \"\"\"Return the number one.\n\n    Longer.\n    \"\"\"\n    # explain\n    return 1  # one\n";
+
+        let before = parse_module_with_options(original, &options).unwrap();
+        let after = parse_module_with_options(edited, &options).unwrap();
+        let checksums = |blocks: &[Block]| blocks.iter().map(|b| b.checksum).collect::<Vec<_>>();
+        assert_eq!(checksums(&before), checksums(&after));
+
+        // Without stripping, the same edit changes checksums
+        let raw_before = parse_module_internal(original).unwrap();
+        let raw_after = parse_module_internal(edited).unwrap();
+        assert_ne!(checksums(&raw_before), checksums(&raw_after));
+    }
+
+    #[test]
+    fn test_strip_comments_logic_change_detected() {
+        let options = ParseOptions {
+            strip_comments: true,
+            ..ParseOptions::default()
+        };
+        let before =
+            parse_module_with_options("def foo():\n    # c\n    return 1\n", &options).unwrap();
+        let after =
+            parse_module_with_options("def foo():\n    # c\n    return 2\n", &options).unwrap();
+        let foo_before = before.iter().find(|b| b.name == "foo").unwrap();
+        let foo_after = after.iter().find(|b| b.name == "foo").unwrap();
+        assert_ne!(foo_before.checksum, foo_after.checksum);
+    }
+
+    #[test]
+    fn test_ast_checksum_ignores_reformatting() {
+        let options = ParseOptions {
+            ast_checksum: true,
+            ..ParseOptions::default()
+        };
+        let original = "import os\n\ndef foo(a, b):\n    return {'x': a, 'y': b}\n\nclass C:\n    def m(self):\n        return foo(1, 2)\n";
+        let reformatted = "import os\n\n\ndef foo(\n    a,\n    b,\n):\n    return {\"x\": a, \"y\": b}  # pair\n\n\nclass C:\n\n    def m(self):\n        return foo(\n            1, 2\n        )\n";
+
+        let before = parse_module_with_options(original, &options).unwrap();
+        let after = parse_module_with_options(reformatted, &options).unwrap();
+        let checksums = |blocks: &[Block]| blocks.iter().map(|b| b.checksum).collect::<Vec<_>>();
+        assert_eq!(checksums(&before), checksums(&after));
+
+        // Text checksums see the reformatting
+        let raw_before = parse_module_internal(original).unwrap();
+        let raw_after = parse_module_internal(reformatted).unwrap();
+        assert_ne!(checksums(&raw_before), checksums(&raw_after));
+    }
+
+    #[test]
+    fn test_ast_checksum_logic_change_detected() {
+        let options = ParseOptions {
+            ast_checksum: true,
+            ..ParseOptions::default()
+        };
+        let source = "X = 1\n\ndef foo(a):\n    return a + 1\n\ndef bar():\n    return 0\n";
+        let before = parse_module_with_options(source, &options).unwrap();
+        let checksum =
+            |blocks: &[Block], name: &str| blocks.iter().find(|b| b.name == name).unwrap().checksum;
+
+        let body = parse_module_with_options(&source.replace("a + 1", "a - 1"), &options).unwrap();
+        assert_ne!(checksum(&before, "foo"), checksum(&body, "foo"));
+        assert_eq!(checksum(&before, "<module>"), checksum(&body, "<module>"));
+        assert_eq!(checksum(&before, "bar"), checksum(&body, "bar"));
+
+        let signature =
+            parse_module_with_options(&source.replace("foo(a)", "foo(b)"), &options).unwrap();
+        assert_ne!(
+            checksum(&before, "<module>"),
+            checksum(&signature, "<module>")
+        );
+
+        let constant =
+            parse_module_with_options(&source.replace("X = 1", "X = 2"), &options).unwrap();
+        assert_ne!(
+            checksum(&before, "<module>"),
+            checksum(&constant, "<module>")
+        );
+    }
+
+    #[test]
+    fn test_ast_checksum_with_strip_comments_ignores_docstrings() {
+        let options = ParseOptions {
+            strip_comments: true,
+            ast_checksum: true,
+        };
+        let before =
+            parse_module_with_options("def foo():\n    '''Doc.'''\n    return 1\n", &options)
+                .unwrap();
+        let after =
+            parse_module_with_options("def foo():\n    '''Other doc.'''\n    return 1\n", &options)
+                .unwrap();
+        let foo = |blocks: &[Block]| blocks.iter().find(|b| b.name == "foo").unwrap().checksum;
+        assert_eq!(foo(&before), foo(&after));
+    }
+
+    #[test]
+    fn test_strip_comments_keeps_hash_inside_strings() {
+        let source = "x = '# not a comment'  # comment\ny = \"a\" \"b\"\nprint(\"doc\")\n";
+        let stripped = strip_comments_and_docstrings(source);
+        assert_eq!(stripped.len(), source.len());
+        assert!(stripped.contains("'# not a comment'"));
+        assert!(!stripped.contains("# comment"));
+        // Strings that are part of an expression are not docstrings
+        assert!(stripped.contains("\"a\" \"b\""));
+        assert!(stripped.contains("print(\"doc\")"));
+    }
+
+    #[test]
+    fn test_line_blocks_one_per_meaningful_line() {
+        let source = "def total(items):\n    # sum prices\n    result = 0\n\n    for item in items:\n        result += item.price\n    return result\n";
+        let blocks = extract_line_blocks(source);
+
+        let lines: Vec<usize> = blocks.iter().map(|b| b.start_line).collect();
+        assert_eq!(lines, vec![1, 3, 5, 6, 7]);
+        assert!(blocks
+            .iter()
+            .all(|b| b.block_type == "line" && b.start_line == b.end_line));
+        // Indentation does not affect the checksum, content does
+        assert_eq!(blocks[1].checksum, calculate_checksum("result = 0"));
+    }
+
+    #[test]
+    fn test_line_blocks_only_edited_line_changes() {
+        let before = extract_line_blocks("a = 1\nb = 2\nc = 3\n");
+        let after = extract_line_blocks("a = 1\nb = 20\nc = 3\n");
+
+        let changed: Vec<usize> = before
+            .iter()
+            .zip(&after)
+            .filter(|(old, new)| old.checksum != new.checksum)
+            .map(|(old, _)| old.start_line)
+            .collect();
+        assert_eq!(changed, vec![2]);
+    }
+
+    #[test]
+    fn test_lenient_recovers_blocks_before_syntax_error() {
+        let source = "import os\n\ndef valid():\n    return 1\n\ndef broken(:\n    return 2\n\ndef after():\n    return 3\n";
+        let blocks = parse_module_lenient_internal(source);
+
+        assert_eq!(blocks[0].name, "<module>");
+        assert_eq!(blocks[0].end_line, 10);
+        assert_eq!(blocks[0].checksum, calculate_checksum(source));
+        assert!(blocks.iter().any(|b| b.name == "valid"));
+        assert!(!blocks
+            .iter()
+            .any(|b| b.name == "broken" || b.name == "after"));
+
+        // Recovered blocks keep their normal checksums
+        let valid = blocks.iter().find(|b| b.name == "valid").unwrap();
+        let clean = parse_module_internal("def valid():\n    return 1\n").unwrap();
+        assert_eq!(valid.checksum, clean[1].checksum);
+    }
+
+    #[test]
+    fn test_lenient_error_inside_function_body() {
+        let source = "def valid():\n    return 1\n\ndef broken():\n    x = (\n";
+        let blocks = parse_module_lenient_internal(source);
+        let names: Vec<&str> = blocks.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["<module>", "valid"]);
+    }
+
+    #[test]
+    fn test_lenient_matches_strict_on_valid_source() {
+        let source = "class A:\n    def f(self):\n        return 1\n";
+        assert_eq!(
+            parse_module_lenient_internal(source),
+            parse_module_internal(source).unwrap()
+        );
+    }
+
     #[test]
     fn test_parse_invalid_syntax() {
         let source = "def foo(";
@@ -4,6 +4,7 @@
 // the fundamental data structures used throughout the system.
 
 use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyType};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -12,7 +13,7 @@ use std::collections::HashMap;
 /// Blocks are the granularity at which we track code changes.
 /// Each block has a checksum calculated from its source code.
 #[pyclass]
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Block {
     /// Starting line number (1-indexed, inclusive)
     #[pyo3(get)]
@@ -22,18 +23,27 @@ pub struct Block {
     #[pyo3(get)]
     pub end_line: usize,
 
-    /// CRC32 checksum of the block's source code (as signed i32)
+    /// Checksum of the block's source code: CRC32 as a signed i32 by default,
+    /// or a 64-bit xxHash (see `ChecksumAlgorithm`)
     #[pyo3(get)]
-    pub checksum: i32,
+    pub checksum: i64,
 
     /// Name of the block (function/class name, or "module" for top-level)
     #[pyo3(get)]
     pub name: String,
 
-    /// Block type: "module", "class", "function", "async_function"
+    /// Block type: "module", "class", "function", "async_function", "import", "line",
+    /// "cell" (notebook code cell), "stub_function" (body is only `...`), "main" (the
+    /// `if __name__ == "__main__":` guard), or for decorated methods
+    /// "property", "staticmethod", "classmethod", "cached_property"
     #[pyo3(get)]
     pub block_type: String,
 
+    /// Dotted name including enclosing classes/functions (e.g. "Calculator.add").
+    /// Equal to `name` for top-level definitions and the module block.
+    #[pyo3(get)]
+    pub qualified_name: String,
+
     /// First line of the function/class body (skipping decorators and def/class line).
     /// Used for execution detection: decorator and `def` lines are executed at import
     /// time, so we only check body lines to determine if a function was actually called.
@@ -44,19 +54,21 @@ pub struct Block {
 #[pymethods]
 impl Block {
     #[new]
-    #[pyo3(signature = (start_line, end_line, checksum, name, block_type, body_start_line=None))]
+    #[pyo3(signature = (start_line, end_line, checksum, name, block_type, body_start_line=None, qualified_name=None))]
     fn new(
         start_line: usize,
         end_line: usize,
-        checksum: i32,
+        checksum: i64,
         name: String,
         block_type: String,
         body_start_line: Option<usize>,
+        qualified_name: Option<String>,
     ) -> Self {
         Self {
             start_line,
             end_line,
             checksum,
+            qualified_name: qualified_name.unwrap_or_else(|| name.clone()),
             name,
             block_type,
             body_start_line: body_start_line.unwrap_or(start_line),
@@ -78,6 +90,47 @@ impl Block {
     fn __str__(&self) -> String {
         self.__repr__()
     }
+
+    /// Whether `line` (1-indexed) lies within `start_line..=end_line`
+    pub fn contains_line(&self, line: usize) -> bool {
+        (self.start_line..=self.end_line).contains(&line)
+    }
+
+    /// All fields as a plain dict, the inverse of `from_dict`
+    fn as_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("start_line", self.start_line)?;
+        dict.set_item("end_line", self.end_line)?;
+        dict.set_item("checksum", self.checksum)?;
+        dict.set_item("name", &self.name)?;
+        dict.set_item("block_type", &self.block_type)?;
+        dict.set_item("qualified_name", &self.qualified_name)?;
+        dict.set_item("body_start_line", self.body_start_line)?;
+        Ok(dict)
+    }
+
+    /// Build a block from a dict as returned by `as_dict`
+    ///
+    /// `qualified_name` and `body_start_line` are optional and default as in
+    /// the constructor. Raises KeyError for missing keys and TypeError for
+    /// values of the wrong type.
+    #[classmethod]
+    fn from_dict(_cls: &Bound<'_, PyType>, dict: &Bound<'_, PyDict>) -> PyResult<Self> {
+        Self::from_py_dict(dict)
+    }
+
+    /// Blocks are equal when all their fields are
+    fn __eq__(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    /// Consistent with `__eq__`, so blocks can be used in sets and as dict keys
+    fn __hash__(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
 }
 
 /// File fingerprint containing block checksums and metadata
@@ -85,7 +138,7 @@ impl Block {
 /// This represents the "signature" of a file at a point in time,
 /// allowing us to detect when the file has changed.
 #[pyclass]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Fingerprint {
     /// Path to the file (relative to project root when stored in DB)
     #[pyo3(get)]
@@ -93,7 +146,7 @@ pub struct Fingerprint {
 
     /// List of block checksums in the file
     #[pyo3(get)]
-    pub checksums: Vec<i32>,
+    pub checksums: Vec<i64>,
 
     /// Blake3 hash of entire file content
     #[pyo3(get)]
@@ -103,6 +156,12 @@ pub struct Fingerprint {
     #[pyo3(get)]
     pub mtime: f64,
 
+    /// Exact modification time as `(seconds, nanoseconds)` since the Unix epoch.
+    /// Change detection compares this when present instead of the rounded `mtime`.
+    #[pyo3(get)]
+    #[serde(default)]
+    pub mtime_exact: Option<(u64, u32)>,
+
     /// List of blocks (optional, for detailed inspection)
     #[pyo3(get)]
     pub blocks: Option<Vec<Block>>,
@@ -111,19 +170,21 @@ pub struct Fingerprint {
 #[pymethods]
 impl Fingerprint {
     #[new]
-    #[pyo3(signature = (filename, checksums, file_hash, mtime, blocks=None))]
+    #[pyo3(signature = (filename, checksums, file_hash, mtime, blocks=None, mtime_exact=None))]
     fn new(
         filename: String,
-        checksums: Vec<i32>,
+        checksums: Vec<i64>,
         file_hash: String,
         mtime: f64,
         blocks: Option<Vec<Block>>,
+        mtime_exact: Option<(u64, u32)>,
     ) -> Self {
         Self {
             filename,
             checksums,
             file_hash,
             mtime,
+            mtime_exact,
             blocks,
         }
     }
@@ -136,13 +197,143 @@ impl Fingerprint {
             &self.file_hash[..8]
         )
     }
+
+    /// All fields as a plain dict (blocks as dicts too), the inverse of `from_dict`
+    fn as_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("filename", &self.filename)?;
+        dict.set_item("checksums", &self.checksums)?;
+        dict.set_item("file_hash", &self.file_hash)?;
+        dict.set_item("mtime", self.mtime)?;
+        dict.set_item("mtime_exact", self.mtime_exact)?;
+        let blocks = match &self.blocks {
+            Some(blocks) => Some(
+                blocks
+                    .iter()
+                    .map(|block| block.as_dict(py))
+                    .collect::<PyResult<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+        dict.set_item("blocks", blocks)?;
+        Ok(dict)
+    }
+
+    /// Build a fingerprint from a dict as returned by `as_dict`
+    ///
+    /// `blocks` and `mtime_exact` are optional; blocks may be dicts or `Block`
+    /// objects. Raises KeyError for missing keys and TypeError for values of
+    /// the wrong type.
+    #[classmethod]
+    fn from_dict(_cls: &Bound<'_, PyType>, dict: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let blocks = match dict.get_item("blocks")? {
+            Some(value) if !value.is_none() => Some(
+                value
+                    .try_iter()?
+                    .map(|item| {
+                        let item = item?;
+                        match item.downcast::<PyDict>() {
+                            Ok(block) => Block::from_py_dict(block),
+                            Err(_) => item.extract::<Block>().map_err(|_| {
+                                pyo3::exceptions::PyTypeError::new_err(
+                                    "'blocks' items must be dicts or Block objects",
+                                )
+                            }),
+                        }
+                    })
+                    .collect::<PyResult<Vec<_>>>()?,
+            ),
+            _ => None,
+        };
+
+        Ok(Self {
+            filename: required_item(dict, "filename")?,
+            checksums: required_item(dict, "checksums")?,
+            file_hash: required_item(dict, "file_hash")?,
+            mtime: required_item(dict, "mtime")?,
+            mtime_exact: optional_item(dict, "mtime_exact")?,
+            blocks,
+        })
+    }
+
+    /// Serialize to a JSON string, including blocks when present
+    fn to_json(&self) -> PyResult<String> {
+        self.to_json_string().map_err(|e| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "Failed to serialize fingerprint: {}",
+                e
+            ))
+        })
+    }
+
+    /// Build a fingerprint from the output of `to_json`
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        Self::from_json_str(json).map_err(|e| {
+            pyo3::exceptions::PyValueError::new_err(format!("Invalid fingerprint JSON: {}", e))
+        })
+    }
+}
+
+impl Block {
+    fn from_py_dict(dict: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let start_line: usize = required_item(dict, "start_line")?;
+        let name: String = required_item(dict, "name")?;
+        Ok(Self {
+            start_line,
+            end_line: required_item(dict, "end_line")?,
+            checksum: required_item(dict, "checksum")?,
+            qualified_name: optional_item(dict, "qualified_name")?.unwrap_or_else(|| name.clone()),
+            name,
+            block_type: required_item(dict, "block_type")?,
+            body_start_line: optional_item(dict, "body_start_line")?.unwrap_or(start_line),
+        })
+    }
+}
+
+/// Extract a required dict value, raising KeyError if absent
+fn required_item<'py, T: FromPyObject<'py>>(dict: &Bound<'py, PyDict>, key: &str) -> PyResult<T> {
+    match dict.get_item(key)? {
+        Some(value) => extract_item(&value, key),
+        None => Err(pyo3::exceptions::PyKeyError::new_err(format!(
+            "missing required key '{}'",
+            key
+        ))),
+    }
+}
+
+/// Extract an optional dict value; absent keys and None both give `None`
+fn optional_item<'py, T: FromPyObject<'py>>(
+    dict: &Bound<'py, PyDict>,
+    key: &str,
+) -> PyResult<Option<T>> {
+    match dict.get_item(key)? {
+        Some(value) if !value.is_none() => extract_item(&value, key).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn extract_item<'py, T: FromPyObject<'py>>(value: &Bound<'py, PyAny>, key: &str) -> PyResult<T> {
+    value.extract().map_err(|e| {
+        pyo3::exceptions::PyTypeError::new_err(format!("invalid value for '{}': {}", key, e))
+    })
+}
+
+impl Fingerprint {
+    pub fn to_json_string(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json_str(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
 }
 
 /// Result of change detection
 ///
 /// Contains lists of modified files and the specific blocks that changed.
 #[pyclass]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ChangedFiles {
     /// List of file paths that were modified
     #[pyo3(get)]
@@ -150,30 +341,129 @@ pub struct ChangedFiles {
 
     /// Map of filename -> list of changed block checksums
     #[pyo3(get)]
-    pub changed_blocks: HashMap<String, Vec<i32>>,
+    pub changed_blocks: HashMap<String, Vec<i64>>,
+
+    /// List of file paths that no longer exist on disk
+    #[pyo3(get)]
+    #[serde(default)]
+    pub deleted: Vec<String>,
+
+    /// List of file paths that have no baseline fingerprint yet
+    #[pyo3(get)]
+    #[serde(default)]
+    pub added: Vec<String>,
+
+    /// `(old_path, new_path)` pairs for files moved with unchanged content
+    #[pyo3(get)]
+    #[serde(default)]
+    pub renamed: Vec<(String, String)>,
+
+    /// Files under the directory of a changed `conftest.py`, whose fixtures
+    /// may resolve differently even if none of their own blocks changed
+    #[pyo3(get)]
+    #[serde(default)]
+    pub conftest_affected: Vec<String>,
+
+    /// `(path, error)` pairs for files that could not be read or parsed.
+    /// Their baseline blocks count as changed so dependent tests re-run.
+    #[pyo3(get)]
+    #[serde(default)]
+    pub errored: Vec<(String, String)>,
+
+    /// The number of scanned files differs from the number of baseline files
+    /// by more than half, suggesting the baseline comes from another checkout
+    #[pyo3(get)]
+    #[serde(default)]
+    pub baseline_mismatch: bool,
+
+    /// Human-readable problems found along the way: files skipped or not
+    /// checked, and a baseline from another checkout, checksum algorithm or
+    /// Python version. Not printed; callers such as the plugin log them.
+    #[pyo3(get)]
+    #[serde(default)]
+    pub warnings: Vec<String>,
+
+    /// Modified or deleted files that no recorded test depends on: they changed,
+    /// but none of their changed blocks was executed by a test. Only filled in
+    /// when `detect_changes` is called with `report_unreferenced`.
+    #[pyo3(get)]
+    #[serde(default)]
+    pub unreferenced_changes: Vec<String>,
+
+    /// The result can't be trusted (no baseline, unreadable database, too many
+    /// errored files, ...) and the full test suite should run. Only set by
+    /// `detect_changes` in `safe_mode`; the reason is in `warnings`.
+    #[pyo3(get)]
+    #[serde(default)]
+    pub run_all: bool,
 }
 
 #[pymethods]
 impl ChangedFiles {
     #[new]
-    fn new(modified: Vec<String>, changed_blocks: HashMap<String, Vec<i32>>) -> Self {
+    #[pyo3(signature = (
+        modified,
+        changed_blocks,
+        deleted=None,
+        added=None,
+        renamed=None,
+        conftest_affected=None,
+        errored=None,
+        baseline_mismatch=false,
+        warnings=None,
+        unreferenced_changes=None,
+        run_all=false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        modified: Vec<String>,
+        changed_blocks: HashMap<String, Vec<i64>>,
+        deleted: Option<Vec<String>>,
+        added: Option<Vec<String>>,
+        renamed: Option<Vec<(String, String)>>,
+        conftest_affected: Option<Vec<String>>,
+        errored: Option<Vec<(String, String)>>,
+        baseline_mismatch: bool,
+        warnings: Option<Vec<String>>,
+        unreferenced_changes: Option<Vec<String>>,
+        run_all: bool,
+    ) -> Self {
         Self {
             modified,
             changed_blocks,
+            deleted: deleted.unwrap_or_default(),
+            added: added.unwrap_or_default(),
+            renamed: renamed.unwrap_or_default(),
+            conftest_affected: conftest_affected.unwrap_or_default(),
+            errored: errored.unwrap_or_default(),
+            baseline_mismatch,
+            warnings: warnings.unwrap_or_default(),
+            unreferenced_changes: unreferenced_changes.unwrap_or_default(),
+            run_all,
         }
     }
 
     fn __repr__(&self) -> String {
         format!(
-            "ChangedFiles(modified={} files, changed_blocks={} files)",
+            "ChangedFiles(modified={} files, added={} files, deleted={} files, renamed={} files, errored={} files, changed_blocks={} files)",
             self.modified.len(),
+            self.added.len(),
+            self.deleted.len(),
+            self.renamed.len(),
+            self.errored.len(),
             self.changed_blocks.len()
         )
     }
 
-    /// Check if any files were modified
+    /// Check if any files were modified, added, deleted, renamed or failed to
+    /// parse, or if everything should run
     fn has_changes(&self) -> bool {
-        !self.modified.is_empty()
+        self.run_all
+            || !self.modified.is_empty()
+            || !self.added.is_empty()
+            || !self.deleted.is_empty()
+            || !self.renamed.is_empty()
+            || !self.errored.is_empty()
     }
 
     /// Get total number of changed blocks across all files
@@ -182,6 +472,248 @@ impl ChangedFiles {
     }
 }
 
+/// A single block-level difference between two versions of a file
+///
+/// Blocks are matched across versions by qualified name, so a renamed
+/// function shows up as one `"removed"` and one `"added"` change. Line blocks
+/// are matched by content, so a line that only shifted is `"moved"`.
+#[pyclass]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockChange {
+    /// `"added"`, `"removed"`, `"modified"` or `"moved"` (line blocks only)
+    #[pyo3(get)]
+    pub kind: String,
+
+    /// Qualified name of the block (e.g. "Calculator.add")
+    #[pyo3(get)]
+    pub name: String,
+
+    /// Block type, as in `Block.block_type`
+    #[pyo3(get)]
+    pub block_type: String,
+
+    /// Starting line in the new version, or in the old one for removed blocks
+    #[pyo3(get)]
+    pub start_line: usize,
+
+    /// Starting line in the old version (None for added blocks)
+    #[pyo3(get)]
+    pub old_start_line: Option<usize>,
+
+    /// Checksum in the old version (None for added blocks)
+    #[pyo3(get)]
+    pub old_checksum: Option<i64>,
+
+    /// Checksum in the new version (None for removed blocks)
+    #[pyo3(get)]
+    pub new_checksum: Option<i64>,
+}
+
+#[pymethods]
+impl BlockChange {
+    fn __repr__(&self) -> String {
+        format!(
+            "BlockChange(kind='{}', name='{}', type='{}', line={})",
+            self.kind, self.name, self.block_type, self.start_line
+        )
+    }
+}
+
+/// Project-wide counts of what changed since the baseline
+///
+/// Block counts come from comparing checksums, since the baseline doesn't
+/// keep block names: within a file, a vanished checksum paired with a new
+/// one counts as one modified block, and the rest as removed or added.
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangeSummary {
+    /// Number of Python files scanned
+    #[pyo3(get)]
+    pub files_scanned: usize,
+
+    #[pyo3(get)]
+    pub files_modified: usize,
+
+    #[pyo3(get)]
+    pub files_added: usize,
+
+    #[pyo3(get)]
+    pub files_deleted: usize,
+
+    #[pyo3(get)]
+    pub files_renamed: usize,
+
+    /// Files that could not be read or parsed
+    #[pyo3(get)]
+    pub files_errored: usize,
+
+    #[pyo3(get)]
+    pub blocks_added: usize,
+
+    #[pyo3(get)]
+    pub blocks_removed: usize,
+
+    #[pyo3(get)]
+    pub blocks_modified: usize,
+
+    /// Warnings from the scan, plus modified files whose blocks could not be counted
+    #[pyo3(get)]
+    pub warnings: Vec<String>,
+}
+
+#[pymethods]
+impl ChangeSummary {
+    fn __repr__(&self) -> String {
+        format!(
+            "ChangeSummary(files_scanned={}, modified={}, added={}, deleted={}, renamed={}, errored={}, blocks_added={}, blocks_removed={}, blocks_modified={})",
+            self.files_scanned,
+            self.files_modified,
+            self.files_added,
+            self.files_deleted,
+            self.files_renamed,
+            self.files_errored,
+            self.blocks_added,
+            self.blocks_removed,
+            self.blocks_modified
+        )
+    }
+}
+
+/// Baseline files sorted by whether they still match disk, from `verify_baseline`
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BaselineVerification {
+    /// Files whose content hashes to the stored file hash
+    #[pyo3(get)]
+    pub matching: Vec<String>,
+
+    /// Files whose hash differs from the stored one, or that can't be read
+    #[pyo3(get)]
+    pub mismatched: Vec<String>,
+
+    /// Files in the baseline that no longer exist on disk
+    #[pyo3(get)]
+    pub missing: Vec<String>,
+}
+
+#[pymethods]
+impl BaselineVerification {
+    /// Whether every baseline file still matches disk
+    pub fn is_consistent(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "BaselineVerification(matching={}, mismatched={}, missing={})",
+            self.matching.len(),
+            self.mismatched.len(),
+            self.missing.len()
+        )
+    }
+}
+
+/// Result of `save_baseline`
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BaselineStats {
+    /// Files covered by the baseline (or that would be, with `dry_run`)
+    #[pyo3(get)]
+    pub count: usize,
+
+    /// `(path, seconds)` spent fingerprinting each file that was (re)computed;
+    /// `None` unless `collect_timings` was set
+    #[pyo3(get)]
+    pub timings: Option<Vec<(String, f64)>>,
+}
+
+#[pymethods]
+impl BaselineStats {
+    fn __repr__(&self) -> String {
+        match &self.timings {
+            Some(timings) => format!(
+                "BaselineStats(count={}, timed_files={})",
+                self.count,
+                timings.len()
+            ),
+            None => format!("BaselineStats(count={})", self.count),
+        }
+    }
+}
+
+/// A block with the blocks defined inside it, from `parse_module_tree`
+#[pyclass]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockNode {
+    #[pyo3(get)]
+    pub block: Block,
+
+    /// Classes and functions defined directly inside the block, in source order
+    #[pyo3(get)]
+    pub children: Vec<BlockNode>,
+}
+
+#[pymethods]
+impl BlockNode {
+    fn __repr__(&self) -> String {
+        format!(
+            "BlockNode(name='{}', type='{}', children={})",
+            self.block.qualified_name,
+            self.block.block_type,
+            self.children.len()
+        )
+    }
+}
+
+/// Stored versus current checksums of a single file, from `inspect_file`
+///
+/// A debugging aid for unexpected re-runs: shows what the baseline holds,
+/// what the file parses to now, and which baseline checksums count as changed.
+#[pyclass]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileInspection {
+    /// Path relative to the project root, as stored in the baseline
+    #[pyo3(get)]
+    pub filename: String,
+
+    /// Checksums in the baseline, or None if the file has no baseline
+    #[pyo3(get)]
+    pub baseline_checksums: Option<Vec<i64>>,
+
+    /// Checksums of the file as it is now
+    #[pyo3(get)]
+    pub current_checksums: Vec<i64>,
+
+    /// Checksums change detection reports for this file: baseline checksums
+    /// that are gone, or every current checksum for a file without baseline
+    #[pyo3(get)]
+    pub changed_checksums: Vec<i64>,
+
+    /// Blocks of the file as it is now, with their names and line ranges
+    #[pyo3(get)]
+    pub blocks: Vec<Block>,
+}
+
+#[pymethods]
+impl FileInspection {
+    /// Whether change detection would consider the file's blocks changed
+    pub fn has_changes(&self) -> bool {
+        !self.changed_checksums.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "FileInspection(filename='{}', baseline={}, current={}, changed={})",
+            self.filename,
+            self.baseline_checksums
+                .as_ref()
+                .map_or("None".to_string(), |checksums| checksums.len().to_string()),
+            self.current_checksums.len(),
+            self.changed_checksums.len()
+        )
+    }
+}
+
 /// Test execution record
 ///
 /// Stores information about a single test run, including which
@@ -253,16 +785,64 @@ mod tests {
             "test_func".to_string(),
             "function".to_string(),
             None,
+            None,
         );
 
         assert_eq!(block.start_line, 1);
         assert_eq!(block.end_line, 10);
         assert_eq!(block.checksum, 0x12345678);
         assert_eq!(block.name, "test_func");
+        // qualified_name defaults to name when None
+        assert_eq!(block.qualified_name, "test_func");
         // body_start_line defaults to start_line when None
         assert_eq!(block.body_start_line, 1);
     }
 
+    #[test]
+    fn test_block_eq_and_hash() {
+        let block = |checksum| {
+            Block::new(
+                2,
+                3,
+                checksum,
+                "add".to_string(),
+                "function".to_string(),
+                None,
+                None,
+            )
+        };
+
+        let a = block(42);
+        let b = block(42);
+        assert!(a.__eq__(&b));
+        assert_eq!(a.__hash__(), b.__hash__());
+
+        let c = block(43);
+        assert!(!a.__eq__(&c));
+        assert_ne!(a.__hash__(), c.__hash__());
+
+        let mut d = block(42);
+        d.qualified_name = "Calculator.add".to_string();
+        assert!(!a.__eq__(&d));
+    }
+
+    #[test]
+    fn test_block_contains_line() {
+        let block = Block::new(
+            3,
+            5,
+            1,
+            "add".to_string(),
+            "function".to_string(),
+            None,
+            None,
+        );
+        assert!(!block.contains_line(2));
+        assert!(block.contains_line(3));
+        assert!(block.contains_line(5));
+        assert!(!block.contains_line(6));
+    }
+
     #[test]
     fn test_block_creation_with_body_start_line() {
         let block = Block::new(
@@ -272,6 +852,7 @@ mod tests {
             "test_func".to_string(),
             "function".to_string(),
             Some(3),
+            None,
         );
 
         assert_eq!(block.start_line, 1);
@@ -285,6 +866,15 @@ mod tests {
             [(String::from("file1.py"), vec![123, 456])]
                 .into_iter()
                 .collect(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+            false,
         );
 
         assert!(changed.has_changes());
@@ -293,9 +883,111 @@ mod tests {
 
     #[test]
     fn test_changed_files_no_changes() {
-        let changed = ChangedFiles::new(vec![], HashMap::new());
+        let changed = ChangedFiles::new(
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+            false,
+        );
 
         assert!(!changed.has_changes());
         assert_eq!(changed.total_changed_blocks(), 0);
     }
+
+    #[test]
+    fn test_changed_files_deleted_only() {
+        let changed = ChangedFiles::new(
+            vec![],
+            HashMap::new(),
+            Some(vec!["old.py".to_string()]),
+            None,
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+            false,
+        );
+
+        assert!(changed.has_changes());
+    }
+
+    #[test]
+    fn test_changed_files_added_only() {
+        let changed = ChangedFiles::new(
+            vec![],
+            HashMap::new(),
+            None,
+            Some(vec!["new.py".to_string()]),
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+            false,
+        );
+
+        assert!(changed.has_changes());
+    }
+
+    #[test]
+    fn test_changed_files_renamed_only() {
+        let changed = ChangedFiles::new(
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            Some(vec![("utils.py".to_string(), "helpers.py".to_string())]),
+            None,
+            None,
+            false,
+            None,
+            None,
+            false,
+        );
+
+        assert!(changed.has_changes());
+        assert_eq!(changed.total_changed_blocks(), 0);
+    }
+
+    #[test]
+    fn test_fingerprint_json_round_trip() {
+        let block = Block::new(
+            1,
+            2,
+            -17,
+            "add".to_string(),
+            "function".to_string(),
+            Some(2),
+            None,
+        );
+        let fp = Fingerprint::new(
+            "src/calc.py".to_string(),
+            vec![-17, 99],
+            "abcdef0123456789".to_string(),
+            1700000000.25,
+            Some(vec![block]),
+            Some((1700000000, 250_000_000)),
+        );
+
+        let json = fp.to_json_string().unwrap();
+        assert_eq!(Fingerprint::from_json_str(&json).unwrap(), fp);
+
+        // Fingerprints without blocks round-trip too
+        let bare = Fingerprint {
+            blocks: None,
+            ..fp.clone()
+        };
+        let json = bare.to_json_string().unwrap();
+        assert_eq!(Fingerprint::from_json_str(&json).unwrap(), bare);
+    }
 }